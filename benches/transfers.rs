@@ -0,0 +1,176 @@
+// Copyright (c) 2023 Espresso Systems (espressosys.com)
+// This file is part of the Discord Faucet library.
+//
+// You should have received a copy of the MIT License
+// along with the Discord Faucet library. If not, see <https://mit-license.org/>.
+
+//! Benchmarks of the transfer pipeline against a local anvil.
+//!
+//! Run with `cargo bench`. Anvil must be installed.
+use async_std::{
+    channel::{unbounded, Receiver, Sender},
+    task::{block_on, spawn},
+};
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+use discord_faucet::{Faucet, FaucetEvent, FaucetRequest, Options, RequestId};
+use ethers::types::Address;
+use futures::StreamExt;
+use sequencer_utils::{Anvil, AnvilOptions};
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+/// Requests sent at once when measuring the throughput of the queue.
+const BATCH_SIZE: usize = 50;
+
+/// A running faucet with its queue.
+struct Setup {
+    _anvil: Anvil,
+    faucet: Faucet,
+    queue: Sender<FaucetRequest>,
+    events: Receiver<FaucetEvent>,
+}
+
+impl Setup {
+    async fn new() -> Self {
+        let anvil = AnvilOptions::default().spawn().await;
+        let options = Options {
+            // The wallets funded by anvil.
+            num_clients: 10,
+            provider_url_ws: None,
+            provider_url_http: anvil.url(),
+            ..Default::default()
+        };
+        let (queue, receiver) = unbounded();
+        let faucet = Faucet::create(options, receiver).await.unwrap();
+        let events = faucet.events().subscribe().await;
+        // The pipeline keeps running after its handle is dropped.
+        let _ = faucet.clone().start().await;
+        while !faucet.is_ready().await {
+            async_std::task::sleep(Duration::from_millis(100)).await;
+        }
+        Self {
+            _anvil: anvil,
+            faucet,
+            queue,
+            events,
+        }
+    }
+
+    async fn request(&self) -> RequestId {
+        let request = FaucetRequest::new(Address::random(), None);
+        let id = request.id;
+        self.queue.send(request).await.unwrap();
+        id
+    }
+
+    /// Wait until `count` events matching `f` were published.
+    async fn wait_for(&mut self, mut count: usize, f: impl Fn(&FaucetEvent) -> bool) {
+        while count > 0 {
+            if f(&self.events.next().await.unwrap()) {
+                count -= 1;
+            }
+        }
+    }
+}
+
+fn is_submitted(event: &FaucetEvent) -> bool {
+    matches!(event, FaucetEvent::TransferSubmitted { .. })
+}
+
+fn is_confirmed(event: &FaucetEvent) -> bool {
+    matches!(event, FaucetEvent::TransferConfirmed { .. })
+}
+
+/// The time from a request entering the queue until its transaction is sent.
+fn request_to_submission(c: &mut Criterion) {
+    let mut setup = block_on(Setup::new());
+    c.bench_function("request_to_submission", |b| {
+        b.iter_custom(|iters| {
+            block_on(async {
+                let mut total = Duration::ZERO;
+                for _ in 0..iters {
+                    let start = Instant::now();
+                    setup.request().await;
+                    setup.wait_for(1, is_submitted).await;
+                    total += start.elapsed();
+                    // Let the wallet become available again before the next request.
+                    setup.wait_for(1, is_confirmed).await;
+                }
+                total
+            })
+        })
+    });
+}
+
+/// The time to serve a batch of requests, which queue up waiting for wallets.
+fn queue_throughput(c: &mut Criterion) {
+    let mut setup = block_on(Setup::new());
+    let mut group = c.benchmark_group("queue_throughput");
+    group.throughput(criterion::Throughput::Elements(BATCH_SIZE as u64));
+    group.sample_size(10);
+    group.bench_function("batch", |b| {
+        b.iter_custom(|iters| {
+            block_on(async {
+                let mut total = Duration::ZERO;
+                for _ in 0..iters {
+                    let start = Instant::now();
+                    for _ in 0..BATCH_SIZE {
+                        setup.request().await;
+                    }
+                    setup.wait_for(BATCH_SIZE, is_confirmed).await;
+                    total += start.elapsed();
+                }
+                total
+            })
+        })
+    });
+    group.finish();
+}
+
+/// The latency of reading the state of the faucet while transfers are being processed, which
+/// measures the contention on its lock.
+fn state_contention(c: &mut Criterion) {
+    let setup = block_on(Setup::new());
+    let busy = Arc::new(AtomicBool::new(true));
+    // Keep the pipeline writing to the state in the background.
+    let load = spawn({
+        let busy = busy.clone();
+        let queue = setup.queue.clone();
+        async move {
+            while busy.load(Ordering::Relaxed) {
+                for _ in 0..BATCH_SIZE {
+                    queue
+                        .send(FaucetRequest::new(Address::random(), None))
+                        .await
+                        .unwrap();
+                }
+                async_std::task::sleep(Duration::from_millis(100)).await;
+            }
+        }
+    });
+
+    let id = block_on(setup.request());
+    c.bench_function("request_status_under_load", |b| {
+        b.iter_batched(
+            || (),
+            |()| block_on(setup.faucet.request_status(id)),
+            BatchSize::SmallInput,
+        )
+    });
+
+    busy.store(false, Ordering::Relaxed);
+    block_on(load);
+}
+
+criterion_group!(
+    benches,
+    request_to_submission,
+    queue_throughput,
+    state_contention
+);
+criterion_main!(benches);
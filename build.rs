@@ -0,0 +1,10 @@
+// Copyright (c) 2023 Espresso Systems (espressosys.com)
+// This file is part of the Discord Faucet library.
+//
+// You should have received a copy of the MIT License
+// along with the Discord Faucet library. If not, see <https://mit-license.org/>.
+
+fn main() {
+    #[cfg(feature = "grpc")]
+    tonic_build::compile_protos("proto/faucet.proto").expect("Failed to compile protobufs");
+}
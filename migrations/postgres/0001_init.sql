@@ -0,0 +1,32 @@
+CREATE TABLE requests (
+    id TEXT PRIMARY KEY,
+    recipient TEXT NOT NULL,
+    token TEXT,
+    amount TEXT NOT NULL,
+    queued_at BIGINT NOT NULL
+);
+
+CREATE TABLE grants (
+    request_id TEXT PRIMARY KEY,
+    recipient TEXT NOT NULL,
+    token TEXT,
+    amount TEXT NOT NULL,
+    tx_hash TEXT NOT NULL,
+    block_number BIGINT,
+    confirmed_at BIGINT NOT NULL
+);
+CREATE INDEX grants_recipient ON grants (recipient, confirmed_at);
+
+CREATE TABLE cooldowns (
+    key TEXT PRIMARY KEY,
+    started_at BIGINT NOT NULL
+);
+
+CREATE TABLE dead_letters (
+    id BIGSERIAL PRIMARY KEY,
+    request_id TEXT,
+    recipient TEXT NOT NULL,
+    tx_hash TEXT,
+    reason TEXT NOT NULL,
+    failed_at BIGINT NOT NULL
+);
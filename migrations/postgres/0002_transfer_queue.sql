@@ -0,0 +1,7 @@
+CREATE TABLE transfer_queue (
+    seq BIGSERIAL PRIMARY KEY,
+    request_id TEXT,
+    payload TEXT NOT NULL,
+    queued_at BIGINT NOT NULL
+);
+CREATE INDEX transfer_queue_request_id ON transfer_queue (request_id);
@@ -0,0 +1,3 @@
+-- The chain of each grant, empty for the default chain, so that lifetime totals are kept per chain.
+ALTER TABLE grants ADD COLUMN chain TEXT NOT NULL DEFAULT '';
+CREATE INDEX grants_chain ON grants (chain, recipient);
@@ -0,0 +1,12 @@
+-- Requests taken from the shared queue stay in it until they are submitted, so that they are
+-- delivered again if the instance which took them stops before submitting them.
+ALTER TABLE transfer_queue ADD COLUMN claimed_at BIGINT;
+
+-- The results of the requests of the shared queue, for the instances which received them.
+CREATE TABLE shared_completions (
+    request_id TEXT PRIMARY KEY,
+    tx_hash TEXT NOT NULL,
+    block_number BIGINT,
+    completed_at BIGINT NOT NULL
+);
+CREATE INDEX shared_completions_completed_at ON shared_completions (completed_at);
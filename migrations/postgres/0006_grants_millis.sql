@@ -0,0 +1,2 @@
+-- Grants were confirmed in seconds, while every other timestamp is in milliseconds.
+UPDATE grants SET confirmed_at = confirmed_at * 1000;
@@ -0,0 +1,9 @@
+-- Funds sent back to the return address, which are credited against the lifetime totals.
+CREATE TABLE returns (
+    tx_hash TEXT PRIMARY KEY,
+    chain TEXT NOT NULL,
+    sender TEXT NOT NULL,
+    amount TEXT NOT NULL,
+    returned_at BIGINT NOT NULL
+);
+CREATE INDEX returns_chain ON returns (chain, sender);
@@ -0,0 +1,6 @@
+-- The address registered by each Discord user with `/register`.
+CREATE TABLE registrations (
+    user_id BIGINT PRIMARY KEY,
+    address TEXT NOT NULL,
+    registered_at BIGINT NOT NULL
+);
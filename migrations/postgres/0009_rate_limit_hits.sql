@@ -0,0 +1,8 @@
+-- The hits of the counters of the `--rate-limit` rules, shared by the instances using the database.
+CREATE TABLE rate_limit_hits (
+    key TEXT NOT NULL,
+    hit_at BIGINT NOT NULL,
+    expires_at BIGINT NOT NULL
+);
+CREATE INDEX rate_limit_hits_key ON rate_limit_hits (key, hit_at);
+CREATE INDEX rate_limit_hits_expires_at ON rate_limit_hits (expires_at);
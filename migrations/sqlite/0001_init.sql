@@ -0,0 +1,33 @@
+-- Databases created before migrations were versioned already have these tables.
+CREATE TABLE IF NOT EXISTS requests (
+    id TEXT PRIMARY KEY,
+    recipient TEXT NOT NULL,
+    token TEXT,
+    amount TEXT NOT NULL,
+    queued_at INTEGER NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS grants (
+    request_id TEXT PRIMARY KEY,
+    recipient TEXT NOT NULL,
+    token TEXT,
+    amount TEXT NOT NULL,
+    tx_hash TEXT NOT NULL,
+    block_number INTEGER,
+    confirmed_at INTEGER NOT NULL
+);
+CREATE INDEX IF NOT EXISTS grants_recipient ON grants (recipient, confirmed_at);
+
+CREATE TABLE IF NOT EXISTS cooldowns (
+    key TEXT PRIMARY KEY,
+    started_at INTEGER NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS dead_letters (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    request_id TEXT,
+    recipient TEXT NOT NULL,
+    tx_hash TEXT,
+    reason TEXT NOT NULL,
+    failed_at INTEGER NOT NULL
+);
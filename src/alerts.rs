@@ -0,0 +1,320 @@
+// Copyright (c) 2023 Espresso Systems (espressosys.com)
+// This file is part of the Discord Faucet library.
+//
+// You should have received a copy of the MIT License
+// along with the Discord Faucet library. If not, see <https://mit-license.org/>.
+
+//! Alerts for the operators of the faucet.
+//!
+//! The state of the faucet is checked periodically, and an alert is raised whenever it crosses one
+//! of the configured thresholds, and again once it recovers. Conditions which persist do not raise
+//! further alerts.
+use crate::{FaucetStats, Options, TransferRequest, VelocitySpike};
+use ethers::{types::U256, utils::format_ether};
+use std::fmt::{self, Display, Formatter};
+
+/// A change in the state of the faucet which operators should know about.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Alert {
+    /// The total balance of the faucet fell below `threshold`.
+    LowBalance { balance: U256, threshold: U256 },
+    /// The total balance of the faucet is above all thresholds again.
+    BalanceRecovered { balance: U256 },
+    /// More than the maximum number of transfers are waiting for a wallet.
+    LongQueue { queue_length: usize },
+    /// The queue is below the maximum length again.
+    QueueRecovered { queue_length: usize },
+    /// The RPC failed for several checks in a row.
+    RpcFailing { failures: usize, error: String },
+    /// The RPC works again.
+    RpcRecovered,
+    /// A transfer failed for good and was recorded as a dead letter.
+    DeadLetter {
+        request: TransferRequest,
+        reason: String,
+    },
+    /// A task of the faucet panicked.
+    TaskPanicked { message: String },
+    /// The rate of requests exceeded its baseline, and requests are throttled.
+    VelocitySpike { spike: VelocitySpike },
+    /// Requests are no longer throttled.
+    VelocityRecovered,
+}
+
+impl Alert {
+    /// The condition this alert is about, shared by an alert and its recovery.
+    ///
+    /// Repeated alerts with the same key are only reported once.
+    pub fn key(&self) -> String {
+        match self {
+            Self::LowBalance { .. } | Self::BalanceRecovered { .. } => "low_balance".into(),
+            Self::LongQueue { .. } | Self::QueueRecovered { .. } => "long_queue".into(),
+            Self::RpcFailing { .. } | Self::RpcRecovered => "rpc_failing".into(),
+            Self::DeadLetter { request, .. } => match request.id() {
+                Some(id) => format!("dead_letter:{id}"),
+                None => format!("dead_letter:{:?}", request.to()),
+            },
+            Self::TaskPanicked { message } => format!("task_panicked:{message}"),
+            Self::VelocitySpike { .. } | Self::VelocityRecovered => "velocity_spike".into(),
+        }
+    }
+
+    /// Whether this alert reports the end of a previous alert.
+    pub fn is_recovery(&self) -> bool {
+        matches!(
+            self,
+            Self::BalanceRecovered { .. }
+                | Self::QueueRecovered { .. }
+                | Self::RpcRecovered
+                | Self::VelocityRecovered
+        )
+    }
+
+    /// Whether this alert needs immediate attention.
+    pub fn is_critical(&self) -> bool {
+        matches!(self, Self::RpcFailing { .. } | Self::TaskPanicked { .. })
+    }
+}
+
+impl Display for Alert {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Self::LowBalance { balance, threshold } => write!(
+                f,
+                "The faucet balance is {} ETH, below {} ETH",
+                format_ether(*balance),
+                format_ether(*threshold)
+            ),
+            Self::BalanceRecovered { balance } => {
+                write!(
+                    f,
+                    "The faucet balance is back to {} ETH",
+                    format_ether(*balance)
+                )
+            }
+            Self::LongQueue { queue_length } => {
+                write!(f, "{queue_length} requests are waiting for a wallet")
+            }
+            Self::QueueRecovered { queue_length } => {
+                write!(f, "The queue is down to {queue_length} requests")
+            }
+            Self::RpcFailing { failures, error } => {
+                write!(f, "The RPC failed {failures} times in a row: {error}")
+            }
+            Self::RpcRecovered => write!(f, "The RPC works again"),
+            Self::DeadLetter { request, reason } => write!(
+                f,
+                "A transfer to {:?} failed for good: {reason}",
+                request.to()
+            ),
+            Self::TaskPanicked { message } => write!(f, "A task panicked: {message}"),
+            Self::VelocitySpike { spike } => write!(
+                f,
+                "{} made {} requests in a window, against a baseline of {}; requests are throttled",
+                spike.key, spike.requests, spike.baseline
+            ),
+            Self::VelocityRecovered => write!(f, "Requests are no longer throttled"),
+        }
+    }
+}
+
+/// Tracks the state of the faucet between checks, to raise alerts when it changes.
+#[derive(Clone, Debug)]
+pub struct Alerts {
+    /// The balance thresholds, from highest to lowest.
+    balance_thresholds: Vec<U256>,
+    max_queue_length: Option<usize>,
+    max_rpc_failures: usize,
+
+    /// The lowest threshold the balance is below, if any.
+    balance_below: Option<U256>,
+    queue_too_long: bool,
+    rpc_failures: usize,
+    spiking: bool,
+}
+
+impl Alerts {
+    pub fn new(opt: &Options) -> Self {
+        let mut alerts = Self {
+            balance_thresholds: vec![],
+            max_queue_length: None,
+            max_rpc_failures: 1,
+            balance_below: None,
+            queue_too_long: false,
+            rpc_failures: 0,
+            spiking: false,
+        };
+        alerts.configure(opt);
+        alerts
+    }
+
+    /// Update the thresholds from `opt`, keeping the state of the previous checks.
+    pub fn configure(&mut self, opt: &Options) {
+        let mut balance_thresholds = opt.discord_alert_balance.clone();
+        balance_thresholds.sort_by(|a, b| b.cmp(a));
+        self.balance_thresholds = balance_thresholds;
+        self.max_queue_length = opt.discord_alert_queue_length;
+        self.max_rpc_failures = opt.discord_alert_rpc_failures.max(1);
+    }
+
+    /// The alerts raised by a new check of the faucet.
+    pub fn check(&mut self, stats: Result<&FaucetStats, String>) -> Vec<Alert> {
+        let stats = match stats {
+            Ok(stats) => stats,
+            Err(error) => {
+                self.rpc_failures += 1;
+                if self.rpc_failures == self.max_rpc_failures {
+                    return vec![Alert::RpcFailing {
+                        failures: self.rpc_failures,
+                        error,
+                    }];
+                }
+                return vec![];
+            }
+        };
+
+        let mut alerts = vec![];
+        if self.rpc_failures >= self.max_rpc_failures {
+            alerts.push(Alert::RpcRecovered);
+        }
+        self.rpc_failures = 0;
+
+        let balance = stats.total_balance;
+        let below = self
+            .balance_thresholds
+            .iter()
+            .rev()
+            .find(|threshold| balance < **threshold)
+            .copied();
+        match (below, self.balance_below) {
+            // Only alert when the balance crosses a lower threshold than before.
+            (Some(threshold), Some(previous)) if threshold >= previous => {}
+            (Some(threshold), _) => alerts.push(Alert::LowBalance { balance, threshold }),
+            (None, Some(_)) => alerts.push(Alert::BalanceRecovered { balance }),
+            (None, None) => {}
+        }
+        self.balance_below = below;
+
+        if let Some(max) = self.max_queue_length {
+            let queue_length = stats.queue_length;
+            let too_long = queue_length > max;
+            if too_long && !self.queue_too_long {
+                alerts.push(Alert::LongQueue { queue_length });
+            } else if !too_long && self.queue_too_long {
+                alerts.push(Alert::QueueRecovered { queue_length });
+            }
+            self.queue_too_long = too_long;
+        }
+
+        match (stats.velocity_spike, self.spiking) {
+            (Some(spike), false) => alerts.push(Alert::VelocitySpike { spike }),
+            (None, true) => alerts.push(Alert::VelocityRecovered),
+            _ => {}
+        }
+        self.spiking = stats.velocity_spike.is_some();
+
+        alerts
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::VelocityKey;
+    use ethers::types::U64;
+
+    fn stats(balance: u64, queue_length: usize) -> FaucetStats {
+        FaucetStats {
+            queue_length,
+            inflight: 0,
+            completed: 0,
+            tarpitted: 0,
+            drips: 0,
+            relays: 0,
+            queue_keys: 0,
+            available_wallets: 1,
+            total_balance: balance.into(),
+            block_number: U64::zero(),
+            paused: false,
+            velocity_spike: None,
+        }
+    }
+
+    #[test]
+    fn test_alerts() {
+        let mut opt = Options::default();
+        opt.discord_alert_balance = vec![10.into(), 100.into()];
+        opt.discord_alert_queue_length = Some(5);
+        opt.discord_alert_rpc_failures = 2;
+        let mut alerts = Alerts::new(&opt);
+
+        assert_eq!(alerts.check(Ok(&stats(1000, 0))), vec![]);
+
+        // Each threshold is only reported once.
+        assert_eq!(
+            alerts.check(Ok(&stats(50, 0))),
+            vec![Alert::LowBalance {
+                balance: 50.into(),
+                threshold: 100.into()
+            }]
+        );
+        assert_eq!(alerts.check(Ok(&stats(40, 0))), vec![]);
+        assert_eq!(
+            alerts.check(Ok(&stats(5, 0))),
+            vec![Alert::LowBalance {
+                balance: 5.into(),
+                threshold: 10.into()
+            }]
+        );
+        assert_eq!(alerts.check(Ok(&stats(50, 0))), vec![]);
+        assert_eq!(
+            alerts.check(Ok(&stats(500, 0))),
+            vec![Alert::BalanceRecovered {
+                balance: 500.into()
+            }]
+        );
+
+        assert_eq!(
+            alerts.check(Ok(&stats(500, 6))),
+            vec![Alert::LongQueue { queue_length: 6 }]
+        );
+        assert_eq!(alerts.check(Ok(&stats(500, 7))), vec![]);
+        assert_eq!(
+            alerts.check(Ok(&stats(500, 5))),
+            vec![Alert::QueueRecovered { queue_length: 5 }]
+        );
+
+        // A single RPC failure is tolerated.
+        assert_eq!(alerts.check(Err("error".into())), vec![]);
+        assert_eq!(alerts.check(Ok(&stats(500, 0))), vec![]);
+        assert_eq!(alerts.check(Err("error".into())), vec![]);
+        assert_eq!(
+            alerts.check(Err("error".into())),
+            vec![Alert::RpcFailing {
+                failures: 2,
+                error: "error".into()
+            }]
+        );
+        assert_eq!(alerts.check(Err("error".into())), vec![]);
+        assert_eq!(alerts.check(Ok(&stats(500, 0))), vec![Alert::RpcRecovered]);
+
+        let spike = VelocitySpike {
+            key: VelocityKey::Global,
+            requests: 100,
+            baseline: 10,
+        };
+        let spiking = FaucetStats {
+            velocity_spike: Some(spike),
+            ..stats(500, 0)
+        };
+        assert_eq!(
+            alerts.check(Ok(&spiking)),
+            vec![Alert::VelocitySpike { spike }]
+        );
+        assert_eq!(alerts.check(Ok(&spiking)), vec![]);
+        assert_eq!(
+            alerts.check(Ok(&stats(500, 0))),
+            vec![Alert::VelocityRecovered]
+        );
+    }
+}
@@ -0,0 +1,163 @@
+// Copyright (c) 2023 Espresso Systems (espressosys.com)
+// This file is part of the Discord Faucet library.
+//
+// You should have received a copy of the MIT License
+// along with the Discord Faucet library. If not, see <https://mit-license.org/>.
+
+//! Discord users banned from the faucet.
+//!
+//! Bans are managed by the faucet admins on Discord and saved to a JSON file, so that they survive
+//! restarts.
+use anyhow::{Context, Result};
+use async_std::{fs, sync::RwLock};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    io,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// A banned Discord user.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct Ban {
+    pub user: u64,
+    pub reason: Option<String>,
+    /// The admin who banned the user.
+    pub banned_by: u64,
+    /// When the user was banned, in seconds since the Unix epoch.
+    pub timestamp: u64,
+}
+
+impl Ban {
+    pub fn new(user: u64, reason: Option<String>, banned_by: u64) -> Self {
+        Self {
+            user,
+            reason,
+            banned_by,
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct BanList {
+    /// The file the bans are saved to, or `None` to keep them in memory only.
+    path: Option<PathBuf>,
+    bans: Arc<RwLock<BTreeMap<u64, Ban>>>,
+}
+
+impl BanList {
+    /// Load the bans saved at `path`, which is created on the first ban if it does not exist.
+    pub fn load(path: PathBuf) -> Result<Self> {
+        let bans = if path.exists() { read(&path)? } else { vec![] };
+        Ok(Self {
+            path: Some(path),
+            bans: Arc::new(RwLock::new(
+                bans.into_iter().map(|ban| (ban.user, ban)).collect(),
+            )),
+        })
+    }
+
+    /// Read the bans again from the file, to pick up changes made by hand.
+    pub async fn reload(&self) -> Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let bans = match fs::read_to_string(path).await {
+            Ok(contents) => parse(path, &contents)?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => vec![],
+            Err(err) => return Err(err).with_context(|| format!("reading {}", path.display())),
+        };
+        *self.bans.write().await = bans.into_iter().map(|ban| (ban.user, ban)).collect();
+        Ok(())
+    }
+
+    /// The ban of `user`, if they are banned.
+    pub async fn get(&self, user: u64) -> Option<Ban> {
+        self.bans.read().await.get(&user).cloned()
+    }
+
+    /// All the bans, ordered by user.
+    pub async fn list(&self) -> Vec<Ban> {
+        self.bans.read().await.values().cloned().collect()
+    }
+
+    /// Ban a user, replacing any previous ban of the same user.
+    pub async fn ban(&self, ban: Ban) -> Result<()> {
+        let mut bans = self.bans.write().await;
+        let mut updated = bans.clone();
+        updated.insert(ban.user, ban);
+        self.save(&updated).await?;
+        *bans = updated;
+        Ok(())
+    }
+
+    /// Lift the ban of `user`, returning whether they were banned.
+    pub async fn unban(&self, user: u64) -> Result<bool> {
+        let mut bans = self.bans.write().await;
+        if !bans.contains_key(&user) {
+            return Ok(false);
+        }
+        let mut updated = bans.clone();
+        updated.remove(&user);
+        self.save(&updated).await?;
+        *bans = updated;
+        Ok(true)
+    }
+
+    /// Save `bans`, without blocking the executor while the list is locked.
+    async fn save(&self, bans: &BTreeMap<u64, Ban>) -> Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let contents = serde_json::to_string_pretty(&bans.values().collect::<Vec<_>>())?;
+        // Write to a temporary file first, so that the list is never left half written.
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, contents)
+            .await
+            .with_context(|| format!("writing {}", tmp.display()))?;
+        fs::rename(&tmp, path)
+            .await
+            .with_context(|| format!("writing {}", path.display()))
+    }
+}
+
+fn read(path: &Path) -> Result<Vec<Ban>> {
+    let contents =
+        std::fs::read_to_string(path).with_context(|| format!("reading {}", path.display()))?;
+    parse(path, &contents)
+}
+
+fn parse(path: &Path, contents: &str) -> Result<Vec<Ban>> {
+    serde_json::from_str(contents).with_context(|| format!("parsing {}", path.display()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[async_std::test]
+    async fn test_ban_list() {
+        let path = std::env::temp_dir().join(format!("faucet-bans-{}.json", rand::random::<u64>()));
+        let bans = BanList::load(path.clone()).unwrap();
+        assert_eq!(bans.get(1).await, None);
+
+        let ban = Ban::new(1, Some("spam".into()), 100);
+        bans.ban(ban.clone()).await.unwrap();
+        bans.ban(Ban::new(2, None, 100)).await.unwrap();
+        assert_eq!(bans.get(1).await, Some(ban.clone()));
+
+        assert!(bans.unban(2).await.unwrap());
+        assert!(!bans.unban(2).await.unwrap());
+
+        // The bans are persisted.
+        let reloaded = BanList::load(path.clone()).unwrap();
+        assert_eq!(reloaded.list().await, vec![ban]);
+        std::fs::remove_file(path).unwrap();
+    }
+}
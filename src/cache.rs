@@ -0,0 +1,168 @@
+// Copyright (c) 2023 Espresso Systems (espressosys.com)
+// This file is part of the Discord Faucet library.
+//
+// You should have received a copy of the MIT License
+// along with the Discord Faucet library. If not, see <https://mit-license.org/>.
+
+//! A cache of the chain data the faucet reads repeatedly, to save RPC calls.
+//!
+//! Receipts are keyed by hash, so they do not change while they are cached, short of a reorg.
+//! Balances are keyed by address and dropped whenever a transaction from or to the address is
+//! seen in a block, so a cached balance only misses changes the faucet cannot see, like internal
+//! transfers, for at most `--cache-ttl`.
+use async_std::sync::Mutex;
+use ethers::types::{Address, TransactionReceipt, H256, U256};
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+/// The maximum number of entries of a cache, above which the oldest entries are dropped.
+const MAX_ENTRIES: usize = 1000;
+
+/// A map whose entries expire after a fixed time, counting the hits and misses of lookups.
+///
+/// A cache with a TTL of zero is disabled: it stores nothing and counts no lookups.
+#[derive(Clone, Debug)]
+pub struct TtlCache<K, V> {
+    ttl: Duration,
+    entries: Arc<Mutex<HashMap<K, (Instant, V)>>>,
+    hits: Arc<AtomicU64>,
+    misses: Arc<AtomicU64>,
+}
+
+impl<K: Clone + Eq + Hash, V: Clone> TtlCache<K, V> {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            entries: Default::default(),
+            hits: Default::default(),
+            misses: Default::default(),
+        }
+    }
+
+    /// The value of `key`, if it was inserted less than the TTL ago.
+    pub async fn get(&self, key: &K) -> Option<V> {
+        if self.ttl.is_zero() {
+            return None;
+        }
+        let entries = self.entries.lock().await;
+        match entries.get(key) {
+            Some((inserted, value)) if inserted.elapsed() < self.ttl => {
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                Some(value.clone())
+            }
+            _ => {
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+        }
+    }
+
+    pub async fn insert(&self, key: K, value: V) {
+        if self.ttl.is_zero() {
+            return;
+        }
+        let mut entries = self.entries.lock().await;
+        if entries.len() >= MAX_ENTRIES && !entries.contains_key(&key) {
+            entries.retain(|_, (inserted, _)| inserted.elapsed() < self.ttl);
+        }
+        while entries.len() >= MAX_ENTRIES && !entries.contains_key(&key) {
+            let Some(oldest) = entries
+                .iter()
+                .min_by_key(|(_, (inserted, _))| *inserted)
+                .map(|(key, _)| key.clone())
+            else {
+                break;
+            };
+            entries.remove(&oldest);
+        }
+        entries.insert(key, (Instant::now(), value));
+    }
+
+    /// Drop the value of `key`, so that the next lookup reads it again.
+    pub async fn invalidate(&self, key: &K) {
+        self.entries.lock().await.remove(key);
+    }
+
+    pub fn hits(&self) -> u64 {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    pub fn misses(&self) -> u64 {
+        self.misses.load(Ordering::Relaxed)
+    }
+}
+
+/// The hits and misses of one of the caches, for the metrics.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CacheStats {
+    pub cache: &'static str,
+    pub hits: u64,
+    pub misses: u64,
+}
+
+/// The caches of the chain data of a faucet.
+#[derive(Clone, Debug)]
+pub struct ChainCache {
+    pub receipts: TtlCache<H256, TransactionReceipt>,
+    pub balances: TtlCache<Address, U256>,
+}
+
+impl ChainCache {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            receipts: TtlCache::new(ttl),
+            balances: TtlCache::new(ttl),
+        }
+    }
+
+    pub fn stats(&self) -> Vec<CacheStats> {
+        vec![
+            CacheStats {
+                cache: "receipts",
+                hits: self.receipts.hits(),
+                misses: self.receipts.misses(),
+            },
+            CacheStats {
+                cache: "balances",
+                hits: self.balances.hits(),
+                misses: self.balances.misses(),
+            },
+        ]
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[async_std::test]
+    async fn test_ttl_cache() {
+        let cache = TtlCache::new(Duration::from_secs(3600));
+        assert_eq!(cache.get(&1).await, None);
+        cache.insert(1, "one").await;
+        assert_eq!(cache.get(&1).await, Some("one"));
+        cache.invalidate(&1).await;
+        assert_eq!(cache.get(&1).await, None);
+        assert_eq!((cache.hits(), cache.misses()), (1, 2));
+
+        // Entries are dropped when the cache is full.
+        for key in 0..=MAX_ENTRIES {
+            cache.insert(key, "entry").await;
+        }
+        assert_eq!(cache.entries.lock().await.len(), MAX_ENTRIES);
+        assert_eq!(cache.get(&MAX_ENTRIES).await, Some("entry"));
+
+        // A cache with a TTL of zero is disabled.
+        let cache = TtlCache::new(Duration::ZERO);
+        cache.insert(1, "one").await;
+        assert_eq!(cache.get(&1).await, None);
+        assert_eq!((cache.hits(), cache.misses()), (0, 0));
+    }
+}
@@ -0,0 +1,94 @@
+// Copyright (c) 2023 Espresso Systems (espressosys.com)
+// This file is part of the Discord Faucet library.
+//
+// You should have received a copy of the MIT License
+// along with the Discord Faucet library. If not, see <https://mit-license.org/>.
+
+//! CAPTCHA verification for the public web endpoint.
+//!
+//! Both hCaptcha and Cloudflare Turnstile use the same `siteverify` protocol: the server posts the
+//! token obtained by the client together with its secret key and receives a JSON verdict.
+use anyhow::Result;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum CaptchaProvider {
+    Hcaptcha,
+    Turnstile,
+}
+
+impl CaptchaProvider {
+    fn verify_url(&self) -> &'static str {
+        match self {
+            Self::Hcaptcha => "https://api.hcaptcha.com/siteverify",
+            Self::Turnstile => "https://challenges.cloudflare.com/turnstile/v0/siteverify",
+        }
+    }
+
+    /// The script which renders the provider's widget.
+    pub(crate) fn script_url(&self) -> &'static str {
+        match self {
+            Self::Hcaptcha => "https://js.hcaptcha.com/1/api.js",
+            Self::Turnstile => "https://challenges.cloudflare.com/turnstile/v0/api.js",
+        }
+    }
+
+    /// The class of the element the provider's widget is rendered into.
+    pub(crate) fn widget_class(&self) -> &'static str {
+        match self {
+            Self::Hcaptcha => "h-captcha",
+            Self::Turnstile => "cf-turnstile",
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct VerifyRequest<'a> {
+    secret: &'a str,
+    response: &'a str,
+}
+
+#[derive(Deserialize)]
+struct VerifyResponse {
+    success: bool,
+    #[serde(rename = "error-codes", default)]
+    error_codes: Vec<String>,
+}
+
+#[derive(Clone, Debug)]
+pub struct CaptchaVerifier {
+    provider: CaptchaProvider,
+    secret: String,
+}
+
+impl CaptchaVerifier {
+    pub fn new(provider: CaptchaProvider, secret: String) -> Self {
+        Self { provider, secret }
+    }
+
+    /// Check a CAPTCHA token with the provider.
+    ///
+    /// Returns `Ok(false)` if the provider rejected the token and `Err` if the provider could not
+    /// be reached.
+    pub async fn verify(&self, token: &str) -> Result<bool> {
+        let body = surf::Body::from_form(&VerifyRequest {
+            secret: &self.secret,
+            response: token,
+        })
+        .map_err(|err| err.into_inner())?;
+        let response: VerifyResponse = surf::post(self.provider.verify_url())
+            .body(body)
+            .recv_json()
+            .await
+            .map_err(|err| err.into_inner())?;
+        if !response.success {
+            tracing::info!(
+                "{:?} rejected CAPTCHA token: {:?}",
+                self.provider,
+                response.error_codes
+            );
+        }
+        Ok(response.success)
+    }
+}
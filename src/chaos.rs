@@ -0,0 +1,166 @@
+// Copyright (c) 2023 Espresso Systems (espressosys.com)
+// This file is part of the Discord Faucet library.
+//
+// You should have received a copy of the MIT License
+// along with the Discord Faucet library. If not, see <https://mit-license.org/>.
+
+//! Fault injection into the HTTP RPC of the faucet, for testing its recovery paths.
+//!
+//! Only built with the `chaos` feature. Faults are only injected into the calls made by the
+//! transfer pipeline, so that the faucet can still start up and follow the chain:
+//! - timeouts fail sending transactions and getting receipts,
+//! - dropped receipts are reported as not yet available,
+//! - reorgs report a transaction as sent but discard it, as if it was reorged out before being
+//!   mined, so that it times out,
+//! - nonce errors reject a transaction as if its nonce was already used.
+use crate::Options;
+use async_trait::async_trait;
+use ethers::{
+    providers::{Http, HttpClientError, JsonRpcClient, JsonRpcError},
+    types::{Bytes, H256},
+    utils::keccak256,
+};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+use std::{fmt::Debug, str::FromStr};
+use url::Url;
+
+/// The probability of each fault, between 0 and 1.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Faults {
+    pub timeout: f64,
+    pub dropped_receipt: f64,
+    pub reorg: f64,
+    pub nonce_error: f64,
+}
+
+impl Faults {
+    /// The faults configured in `opts`.
+    pub fn new(opts: &Options) -> Self {
+        Self {
+            timeout: opts.chaos_timeout_rate,
+            dropped_receipt: opts.chaos_dropped_receipt_rate,
+            reorg: opts.chaos_reorg_rate,
+            nonce_error: opts.chaos_nonce_error_rate,
+        }
+    }
+}
+
+/// An HTTP RPC client which injects faults.
+#[derive(Clone, Debug)]
+pub struct ChaosClient {
+    inner: Http,
+    faults: Faults,
+}
+
+impl ChaosClient {
+    pub fn new(url: &Url, faults: Faults) -> Result<Self, url::ParseError> {
+        Ok(Self {
+            inner: Http::from_str(url.as_str())?,
+            faults,
+        })
+    }
+}
+
+/// Whether a fault with probability `rate` happens.
+fn happens(rate: f64) -> bool {
+    rate > 0.0 && rand::random::<f64>() < rate
+}
+
+fn rpc_error(code: i64, message: &str) -> HttpClientError {
+    HttpClientError::JsonRpcError(JsonRpcError {
+        code,
+        message: message.into(),
+        data: None,
+    })
+}
+
+/// Deserialize a made up response.
+fn respond<R: DeserializeOwned>(value: Value) -> Result<R, HttpClientError> {
+    let text = value.to_string();
+    serde_json::from_value(value).map_err(|err| HttpClientError::SerdeJson { err, text })
+}
+
+/// The hash of the raw transaction in the parameters of `eth_sendRawTransaction`.
+fn raw_transaction_hash(params: impl Serialize) -> Option<H256> {
+    let params = serde_json::to_value(params).ok()?;
+    let raw: Bytes = serde_json::from_value(params.get(0)?.clone()).ok()?;
+    Some(keccak256(raw).into())
+}
+
+#[async_trait]
+impl JsonRpcClient for ChaosClient {
+    type Error = HttpClientError;
+
+    async fn request<T, R>(&self, method: &str, params: T) -> Result<R, Self::Error>
+    where
+        T: Debug + Serialize + Send + Sync,
+        R: DeserializeOwned + Send,
+    {
+        match method {
+            "eth_sendRawTransaction" | "eth_getTransactionReceipt"
+                if happens(self.faults.timeout) =>
+            {
+                tracing::warn!("Injecting a timeout into {method}");
+                return Err(rpc_error(-32603, "request timed out (injected)"));
+            }
+            "eth_sendRawTransaction" if happens(self.faults.nonce_error) => {
+                tracing::warn!("Injecting a nonce error");
+                return Err(rpc_error(-32000, "nonce too low (injected)"));
+            }
+            "eth_sendRawTransaction" if happens(self.faults.reorg) => {
+                if let Some(hash) = raw_transaction_hash(&params) {
+                    tracing::warn!("Injecting a reorg, discarding transaction {hash:?}");
+                    return respond(serde_json::to_value(hash).unwrap());
+                }
+            }
+            "eth_getTransactionReceipt" if happens(self.faults.dropped_receipt) => {
+                tracing::warn!("Injecting a dropped receipt");
+                return respond(Value::Null);
+            }
+            _ => {}
+        }
+        self.inner.request(method, params).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ethers::types::TransactionReceipt;
+
+    fn client(faults: Faults) -> ChaosClient {
+        // Nothing listens on this port, so any request which is not faulted fails.
+        ChaosClient::new(&"http://localhost:1".parse().unwrap(), faults).unwrap()
+    }
+
+    #[async_std::test]
+    async fn test_chaos_client() {
+        let raw = Bytes::from(vec![1, 2, 3]);
+        let client = client(Faults {
+            reorg: 1.0,
+            dropped_receipt: 1.0,
+            ..Default::default()
+        });
+        let hash: H256 = client
+            .request("eth_sendRawTransaction", [&raw])
+            .await
+            .unwrap();
+        assert_eq!(hash, H256::from(keccak256(&raw)));
+        let receipt: Option<TransactionReceipt> = client
+            .request("eth_getTransactionReceipt", [hash])
+            .await
+            .unwrap();
+        assert_eq!(receipt, None);
+
+        let client = self::client(Faults {
+            nonce_error: 1.0,
+            ..Default::default()
+        });
+        let err = client
+            .request::<_, H256>("eth_sendRawTransaction", [&raw])
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("nonce too low"), "{err}");
+    }
+}
@@ -0,0 +1,52 @@
+// Copyright (c) 2023 Espresso Systems (espressosys.com)
+// This file is part of the Discord Faucet library.
+//
+// You should have received a copy of the MIT License
+// along with the Discord Faucet library. If not, see <https://mit-license.org/>.
+
+//! Commands which check or inspect a deployment instead of running the faucet.
+//!
+//! The faucet runs when no command is given, so its options stay at the top level of the command
+//! line.
+use crate::{LoadTest, Status, ValidateConfig};
+use clap::{Parser, Subcommand};
+
+#[derive(Clone, Debug, Parser)]
+#[command(name = "discord-faucet")]
+pub enum Command {
+    /// Check the configuration, the RPC, the first faucet wallet and the Discord token, then exit.
+    ///
+    /// Exits with a non-zero status if any check fails.
+    ValidateConfig(ValidateConfig),
+    /// Print the state of a running faucet, its wallets and its Discord connection.
+    ///
+    /// Exits with a non-zero status if the faucet is unhealthy or cannot be reached.
+    Status(Status),
+    /// Request funds from a running faucet at a steady rate, then report throughput and latency.
+    ///
+    /// Each request grants funds to a new random address, so only run this against a test
+    /// deployment. Exits with a non-zero status if any request failed.
+    LoadTest(LoadTest),
+}
+
+impl Command {
+    /// Whether the command line starts with one of these commands.
+    pub fn requested() -> bool {
+        std::env::args()
+            .nth(1)
+            .is_some_and(|arg| <Self as Subcommand>::has_subcommand(&arg))
+    }
+
+    /// Run the command, returning whether it succeeded.
+    pub async fn run(self) -> bool {
+        match self {
+            Self::ValidateConfig(validate) => {
+                let report = validate.run().await;
+                println!("{report}");
+                report.passed()
+            }
+            Self::Status(status) => status.run().await,
+            Self::LoadTest(load_test) => load_test.run().await,
+        }
+    }
+}
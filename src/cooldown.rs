@@ -0,0 +1,517 @@
+// Copyright (c) 2023 Espresso Systems (espressosys.com)
+// This file is part of the Discord Faucet library.
+//
+// You should have received a copy of the MIT License
+// along with the Discord Faucet library. If not, see <https://mit-license.org/>.
+
+//! Cooldowns between faucet grants.
+//!
+//! Cooldowns are tracked in memory, and optionally in a [`CooldownStore`] shared by all the
+//! instances of the faucet, so that requests cannot bypass the cooldown by reaching another replica
+//! or by waiting for a restart. The same store counts the requests of the `--rate-limit` rules.
+use anyhow::{Context, Result};
+use async_std::sync::Mutex;
+use async_trait::async_trait;
+use redis::aio::MultiplexedConnection;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt::{self, Debug, Formatter},
+    hash::Hash,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// Persistent cooldowns, which may be shared by several instances of the faucet.
+#[async_trait]
+pub trait CooldownStore: Debug + Send + Sync {
+    /// When the cooldown named `key` was last started, if ever.
+    async fn cooldown(&self, key: &str) -> Result<Option<SystemTime>>;
+
+    /// Start the cooldown named `key` for `period`, unless it is still running.
+    ///
+    /// Returns the time left if the cooldown is still running. Checking and starting the cooldown is
+    /// atomic, so that concurrent requests to different instances cannot both start it.
+    async fn start_cooldown(&self, key: &str, period: Duration) -> Result<Option<Duration>>;
+
+    /// End the cooldown named `key`, if it is running.
+    async fn reset_cooldown(&self, key: &str) -> Result<()>;
+
+    /// The number of hits of the counter named `key` within the last `period`, and the time of the
+    /// oldest of them.
+    ///
+    /// A counter is always queried with the same period, and older hits may be forgotten.
+    async fn hits(&self, key: &str, period: Duration) -> Result<(usize, Option<SystemTime>)>;
+
+    /// Count a hit of the counter named `key`, which is forgotten after `period`.
+    async fn hit(&self, key: &str, period: Duration) -> Result<()>;
+}
+
+pub type SharedCooldownStore = Arc<dyn CooldownStore>;
+
+/// Connect to the cooldown store at `url`, e.g. `redis://localhost:6379`.
+pub async fn open_cooldown_store(url: &str) -> Result<SharedCooldownStore> {
+    Ok(Arc::new(RedisCooldownStore::connect(url).await?))
+}
+
+/// A cooldown saved in a [`MemoryCooldownStore`], in milliseconds since the Unix epoch.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CooldownEntry {
+    pub started_at: u64,
+    pub expires_at: u64,
+}
+
+/// Cooldowns kept in memory, which can be saved to and restored from a state snapshot.
+///
+/// The hits of counters are not saved in snapshots.
+#[derive(Clone, Debug, Default)]
+pub struct MemoryCooldownStore {
+    entries: Arc<Mutex<HashMap<String, CooldownEntry>>>,
+    /// The hits of each counter, oldest first, as the time of the hit and when it is forgotten.
+    hits: Arc<Mutex<HashMap<String, VecDeque<(u64, u64)>>>>,
+}
+
+impl MemoryCooldownStore {
+    pub fn new(entries: HashMap<String, CooldownEntry>) -> Self {
+        Self {
+            entries: Arc::new(Mutex::new(entries)),
+            hits: Default::default(),
+        }
+    }
+
+    /// The cooldowns which are still running.
+    pub async fn entries(&self) -> HashMap<String, CooldownEntry> {
+        let now = unix_millis();
+        let mut entries = self.entries.lock().await;
+        entries.retain(|_, entry| entry.expires_at > now);
+        entries.clone()
+    }
+}
+
+#[async_trait]
+impl CooldownStore for MemoryCooldownStore {
+    async fn cooldown(&self, key: &str) -> Result<Option<SystemTime>> {
+        let entries = self.entries.lock().await;
+        Ok(entries
+            .get(key)
+            .map(|entry| UNIX_EPOCH + Duration::from_millis(entry.started_at)))
+    }
+
+    async fn start_cooldown(&self, key: &str, period: Duration) -> Result<Option<Duration>> {
+        let now = unix_millis();
+        let mut entries = self.entries.lock().await;
+        if let Some(entry) = entries.get(key).filter(|entry| entry.expires_at > now) {
+            return Ok(Some(Duration::from_millis(entry.expires_at - now)));
+        }
+        // Forget cooldowns which ended, so the map does not grow forever.
+        entries.retain(|_, entry| entry.expires_at > now);
+        entries.insert(
+            key.to_string(),
+            CooldownEntry {
+                started_at: now,
+                expires_at: now + period.as_millis() as u64,
+            },
+        );
+        Ok(None)
+    }
+
+    async fn reset_cooldown(&self, key: &str) -> Result<()> {
+        self.entries.lock().await.remove(key);
+        Ok(())
+    }
+
+    async fn hits(&self, key: &str, period: Duration) -> Result<(usize, Option<SystemTime>)> {
+        let since = unix_millis().saturating_sub(period.as_millis() as u64);
+        let hits = self.hits.lock().await;
+        let mut recent = hits
+            .get(key)
+            .into_iter()
+            .flatten()
+            .filter(|(at, _)| *at > since)
+            .map(|(at, _)| *at);
+        let oldest = recent.next();
+        let count = recent.count() + oldest.is_some() as usize;
+        Ok((
+            count,
+            oldest.map(|at| UNIX_EPOCH + Duration::from_millis(at)),
+        ))
+    }
+
+    async fn hit(&self, key: &str, period: Duration) -> Result<()> {
+        let now = unix_millis();
+        let mut hits = self.hits.lock().await;
+        // Forget hits which expired, so the map does not grow forever.
+        hits.retain(|_, times| {
+            times.retain(|(_, expires_at)| *expires_at > now);
+            !times.is_empty()
+        });
+        hits.entry(key.to_string())
+            .or_default()
+            .push_back((now, now + period.as_millis() as u64));
+        Ok(())
+    }
+}
+
+fn unix_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+/// Cooldowns kept in Redis, as keys which expire when the cooldown ends.
+#[derive(Clone)]
+pub struct RedisCooldownStore {
+    conn: MultiplexedConnection,
+}
+
+impl Debug for RedisCooldownStore {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RedisCooldownStore").finish_non_exhaustive()
+    }
+}
+
+impl RedisCooldownStore {
+    /// The prefix of the keys of the cooldowns, so that they do not collide with other users of the
+    /// same Redis instance.
+    const PREFIX: &'static str = "discord-faucet:cooldown:";
+
+    /// The prefix of the keys of the counters, sorted sets of their hits scored by time.
+    const HITS_PREFIX: &'static str = "discord-faucet:hits:";
+
+    pub async fn connect(url: &str) -> Result<Self> {
+        let client = redis::Client::open(url).context("invalid Redis URL")?;
+        let conn = client
+            .get_multiplexed_async_std_connection()
+            .await
+            .context("connecting to Redis")?;
+        Ok(Self { conn })
+    }
+}
+
+#[async_trait]
+impl CooldownStore for RedisCooldownStore {
+    async fn cooldown(&self, key: &str) -> Result<Option<SystemTime>> {
+        let started: Option<u64> = redis::cmd("GET")
+            .arg(format!("{}{key}", Self::PREFIX))
+            .query_async(&mut self.conn.clone())
+            .await?;
+        Ok(started.map(|millis| UNIX_EPOCH + Duration::from_millis(millis)))
+    }
+
+    async fn start_cooldown(&self, key: &str, period: Duration) -> Result<Option<Duration>> {
+        let key = format!("{}{key}", Self::PREFIX);
+        let mut conn = self.conn.clone();
+        loop {
+            let now = unix_millis();
+            let started: Option<String> = redis::cmd("SET")
+                .arg(&key)
+                .arg(now)
+                .arg("NX")
+                .arg("PX")
+                .arg(period.as_millis().max(1) as u64)
+                .query_async(&mut conn)
+                .await?;
+            if started.is_some() {
+                return Ok(None);
+            }
+            let remaining: i64 = redis::cmd("PTTL").arg(&key).query_async(&mut conn).await?;
+            // Otherwise the key expired between the two commands, so try again.
+            if remaining > 0 {
+                return Ok(Some(Duration::from_millis(remaining as u64)));
+            }
+        }
+    }
+
+    async fn reset_cooldown(&self, key: &str) -> Result<()> {
+        redis::cmd("DEL")
+            .arg(format!("{}{key}", Self::PREFIX))
+            .query_async(&mut self.conn.clone())
+            .await?;
+        Ok(())
+    }
+
+    async fn hits(&self, key: &str, period: Duration) -> Result<(usize, Option<SystemTime>)> {
+        let key = format!("{}{key}", Self::HITS_PREFIX);
+        let mut conn = self.conn.clone();
+        let since = unix_millis().saturating_sub(period.as_millis() as u64);
+        let (count, oldest): (usize, Vec<(String, f64)>) = redis::pipe()
+            .atomic()
+            .cmd("ZREMRANGEBYSCORE")
+            .arg(&key)
+            .arg("-inf")
+            .arg(since)
+            .ignore()
+            .cmd("ZCARD")
+            .arg(&key)
+            .cmd("ZRANGE")
+            .arg(&key)
+            .arg(0)
+            .arg(0)
+            .arg("WITHSCORES")
+            .query_async(&mut conn)
+            .await?;
+        Ok((
+            count,
+            oldest
+                .first()
+                .map(|(_, at)| UNIX_EPOCH + Duration::from_millis(*at as u64)),
+        ))
+    }
+
+    async fn hit(&self, key: &str, period: Duration) -> Result<()> {
+        let key = format!("{}{key}", Self::HITS_PREFIX);
+        let now = unix_millis();
+        // Members must be unique, so that hits at the same millisecond all count.
+        let member = format!("{now}:{:016x}", rand::random::<u64>());
+        let () = redis::pipe()
+            .atomic()
+            .cmd("ZADD")
+            .arg(&key)
+            .arg(now)
+            .arg(member)
+            .ignore()
+            .cmd("PEXPIRE")
+            .arg(&key)
+            .arg(period.as_millis().max(1) as u64)
+            .ignore()
+            .query_async(&mut self.conn.clone())
+            .await?;
+        Ok(())
+    }
+}
+
+/// Tracks the time of the last grant for each key, to enforce a minimum period between grants.
+///
+/// Grants are timed with the wall clock, like the grants persisted in storage, which must still
+/// count after the host reboots.
+#[derive(Clone, Debug)]
+pub struct Cooldown<K> {
+    period: Duration,
+    last_grant: Arc<Mutex<HashMap<K, SystemTime>>>,
+    /// Where the grants are persisted, and the namespace of this cooldown's keys.
+    storage: Option<(SharedCooldownStore, String)>,
+}
+
+impl<K: Eq + Hash + Debug> Cooldown<K> {
+    pub fn new(period: Duration) -> Self {
+        Self {
+            period,
+            last_grant: Default::default(),
+            storage: None,
+        }
+    }
+
+    /// Persist grants in `storage`, so that the cooldown survives restarts and is shared by all the
+    /// instances using the same storage.
+    ///
+    /// `namespace` distinguishes the keys of this cooldown from those of other cooldowns sharing the
+    /// same storage.
+    pub fn with_storage(
+        mut self,
+        storage: SharedCooldownStore,
+        namespace: impl Into<String>,
+    ) -> Self {
+        self.storage = Some((storage, namespace.into()));
+        self
+    }
+
+    pub fn period(&self) -> Duration {
+        self.period
+    }
+
+    /// The time until `key` can be granted funds again, if it is still cooling down.
+    pub async fn remaining(&self, key: &K) -> Option<Duration> {
+        let last_grant = self.last_grant.lock().await;
+        match last_grant.get(key) {
+            Some(timestamp) => self.remaining_since(timestamp),
+            None => self.remaining_since(&self.load(key).await?),
+        }
+    }
+
+    /// Start the cooldown for `key`.
+    ///
+    /// Fails with the remaining time if `key` is still cooling down from a previous grant.
+    pub async fn start(&self, key: K) -> Result<(), Duration> {
+        let mut last_grant = self.last_grant.lock().await;
+        if let Some(remaining) = last_grant
+            .get(&key)
+            .and_then(|timestamp| self.remaining_since(timestamp))
+        {
+            return Err(remaining);
+        }
+        if let Some((storage, namespace)) = &self.storage {
+            let storage_key = format!("{namespace}:{key:?}");
+            match storage.start_cooldown(&storage_key, self.period).await {
+                Ok(Some(remaining)) => return Err(remaining),
+                Ok(None) => {}
+                // Fall back to the in-memory cooldown rather than refusing all grants.
+                Err(err) => tracing::warn!("Failed to persist cooldown {storage_key}: {err:#}"),
+            }
+        }
+        // Forget keys which are no longer cooling down, so the map does not grow forever.
+        last_grant.retain(|_, timestamp| self.remaining_since(timestamp).is_some());
+        last_grant.insert(key, SystemTime::now());
+        Ok(())
+    }
+
+    /// End the cooldown of `key`, so that it can be granted funds again right away.
+    pub async fn reset(&self, key: &K) {
+        let mut last_grant = self.last_grant.lock().await;
+        last_grant.remove(key);
+        if let Some((storage, namespace)) = &self.storage {
+            let storage_key = format!("{namespace}:{key:?}");
+            if let Err(err) = storage.reset_cooldown(&storage_key).await {
+                tracing::warn!("Failed to reset cooldown {storage_key}: {err:#}");
+            }
+        }
+    }
+
+    /// The time of the last grant to `key` recorded in storage, if any.
+    async fn load(&self, key: &K) -> Option<SystemTime> {
+        let (storage, namespace) = self.storage.as_ref()?;
+        let storage_key = format!("{namespace}:{key:?}");
+        match storage.cooldown(&storage_key).await {
+            Ok(started) => started,
+            Err(err) => {
+                tracing::warn!("Failed to load cooldown {storage_key}: {err:#}");
+                None
+            }
+        }
+    }
+
+    fn remaining_since(&self, timestamp: &SystemTime) -> Option<Duration> {
+        // A grant in the future, after the clock went back, has only just started.
+        let elapsed = timestamp.elapsed().unwrap_or_default();
+        self.period
+            .checked_sub(elapsed)
+            .filter(|remaining| !remaining.is_zero())
+    }
+}
+
+/// Check the behavior common to all the cooldown stores.
+#[cfg(test)]
+pub(crate) async fn check_cooldown_store(store: &dyn CooldownStore) {
+    let period = Duration::from_secs(3600);
+    let key = format!("user:{}", rand::random::<u64>());
+    assert_eq!(store.cooldown(&key).await.unwrap(), None);
+    assert_eq!(store.start_cooldown(&key, period).await.unwrap(), None);
+    assert!(store.cooldown(&key).await.unwrap().is_some());
+    let remaining = store.start_cooldown(&key, period).await.unwrap().unwrap();
+    assert!(remaining <= period && remaining > period / 2);
+
+    // Expired cooldowns can be started again.
+    let key = format!("user:{}", rand::random::<u64>());
+    let period = Duration::from_millis(10);
+    store.start_cooldown(&key, period).await.unwrap();
+    async_std::task::sleep(Duration::from_millis(20)).await;
+    assert_eq!(store.start_cooldown(&key, period).await.unwrap(), None);
+
+    // Reset cooldowns can be started again right away.
+    let period = Duration::from_secs(3600);
+    store.reset_cooldown(&key).await.unwrap();
+    assert_eq!(store.cooldown(&key).await.unwrap(), None);
+    assert_eq!(store.start_cooldown(&key, period).await.unwrap(), None);
+
+    // Counters only count the hits within the period.
+    let key = format!("rate:{}", rand::random::<u64>());
+    assert_eq!(store.hits(&key, period).await.unwrap(), (0, None));
+    for _ in 0..2 {
+        store.hit(&key, period).await.unwrap();
+    }
+    let (hits, oldest) = store.hits(&key, period).await.unwrap();
+    assert_eq!(hits, 2);
+    assert!(oldest.unwrap().elapsed().unwrap_or_default() < period);
+    async_std::task::sleep(Duration::from_millis(20)).await;
+    assert_eq!(
+        store.hits(&key, Duration::from_millis(10)).await.unwrap().0,
+        0
+    );
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{SharedStorage, SqliteStorage};
+
+    #[async_std::test]
+    async fn test_cooldown() {
+        let cooldown = Cooldown::new(Duration::from_secs(3600));
+        assert_eq!(cooldown.remaining(&1).await, None);
+
+        cooldown.start(1).await.unwrap();
+        assert!(cooldown.remaining(&1).await.is_some());
+        assert!(cooldown.start(1).await.is_err());
+
+        // Other keys are not affected.
+        cooldown.start(2).await.unwrap();
+
+        cooldown.reset(&1).await;
+        assert_eq!(cooldown.remaining(&1).await, None);
+        cooldown.start(1).await.unwrap();
+    }
+
+    #[async_std::test]
+    async fn test_cooldown_expires() {
+        let cooldown = Cooldown::new(Duration::from_millis(10));
+        cooldown.start(1).await.unwrap();
+        async_std::task::sleep(Duration::from_millis(20)).await;
+        assert_eq!(cooldown.remaining(&1).await, None);
+        cooldown.start(1).await.unwrap();
+    }
+
+    #[async_std::test]
+    async fn test_cooldown_storage() {
+        let dir = std::env::temp_dir().join(format!("cooldown-{}", rand::random::<u64>()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("faucet.db");
+        let period = Duration::from_secs(3600);
+
+        let storage: SharedStorage = Arc::new(SqliteStorage::open(&path).unwrap());
+        let cooldown = Cooldown::new(period).with_storage(storage, "test");
+        cooldown.start(1).await.unwrap();
+
+        // A new cooldown backed by the same database remembers the grant, as after a restart.
+        let storage: SharedStorage = Arc::new(SqliteStorage::open(&path).unwrap());
+        let cooldown = Cooldown::new(period).with_storage(storage.clone(), "test");
+        assert!(cooldown.remaining(&1).await.is_some());
+        assert!(cooldown.start(1).await.is_err());
+        cooldown.start(2).await.unwrap();
+
+        // Namespaces keep cooldowns sharing a database apart.
+        let other = Cooldown::new(period).with_storage(storage, "other");
+        other.start(1).await.unwrap();
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[async_std::test]
+    async fn test_memory_cooldown_store() {
+        let store = MemoryCooldownStore::default();
+        check_cooldown_store(&store).await;
+
+        // Running cooldowns survive a snapshot.
+        let entries = store.entries().await;
+        let (key, _) = entries
+            .iter()
+            .find(|(_, entry)| entry.expires_at - entry.started_at == 3_600_000)
+            .unwrap();
+        let key = key.clone();
+        let store = MemoryCooldownStore::new(entries);
+        assert!(store
+            .start_cooldown(&key, Duration::from_secs(3600))
+            .await
+            .unwrap()
+            .is_some());
+        check_cooldown_store(&store).await;
+    }
+
+    /// Runs against the Redis server given by `ESPRESSO_DISCORD_FAUCET_TEST_REDIS_URL`, with `cargo test -- --ignored`.
+    #[async_std::test]
+    #[ignore]
+    async fn test_redis_cooldown_store() {
+        let url = std::env::var("ESPRESSO_DISCORD_FAUCET_TEST_REDIS_URL")
+            .expect("ESPRESSO_DISCORD_FAUCET_TEST_REDIS_URL is not set");
+        check_cooldown_store(&RedisCooldownStore::connect(&url).await.unwrap()).await;
+    }
+}
@@ -0,0 +1,52 @@
+// Copyright (c) 2023 Espresso Systems (espressosys.com)
+// This file is part of the Discord Faucet library.
+//
+// You should have received a copy of the MIT License
+// along with the Discord Faucet library. If not, see <https://mit-license.org/>.
+
+//! A connection to the PostgreSQL database shared by several faucet instances.
+use crate::migrate_postgres;
+use anyhow::{Context, Result};
+use async_std::task::spawn_blocking;
+use std::sync::{Arc, Mutex};
+
+/// A PostgreSQL client which is replaced by a new connection when its connection drops.
+#[derive(Clone)]
+pub(crate) struct PgConnection {
+    url: String,
+    client: Arc<Mutex<postgres::Client>>,
+}
+
+impl PgConnection {
+    /// Connect to the database at `url` and apply any pending migrations.
+    pub(crate) fn connect(url: &str) -> Result<Self> {
+        let mut client = postgres::Client::connect(url, postgres::NoTls)?;
+        migrate_postgres(&mut client)?;
+        Ok(Self {
+            url: url.to_string(),
+            client: Arc::new(Mutex::new(client)),
+        })
+    }
+
+    /// Run `f` with the client, off the async executor.
+    ///
+    /// The client does not recover from a dropped connection, so a closed client is replaced by a
+    /// new connection first. The call which found the connection dropped still fails.
+    pub(crate) async fn run<T: Send + 'static>(
+        &self,
+        f: impl FnOnce(&mut postgres::Client) -> Result<T> + Send + 'static,
+    ) -> Result<T> {
+        let url = self.url.clone();
+        let client = self.client.clone();
+        spawn_blocking(move || {
+            let mut client = client.lock().unwrap();
+            if client.is_closed() {
+                tracing::warn!("Lost the connection to the database, reconnecting");
+                *client = postgres::Client::connect(&url, postgres::NoTls)
+                    .context("reconnecting to database")?;
+            }
+            f(&mut client)
+        })
+        .await
+    }
+}
@@ -8,86 +8,2166 @@
 //!
 //! Suggestions for improvements:
 //!   - After starting up, process messages sent since last online.
-use crate::serve;
-use crate::WebState;
-use crate::{Faucet, Options};
-use async_compatibility_layer::logging::{setup_backtrace, setup_logging};
-use async_std::task::spawn;
-use clap::Parser;
-use ethers::types::Address;
-use regex::Regex;
+use crate::await_transfer;
+use crate::{
+    AbuseSignal, Alert, Alerts, Ban, CoolingDown, ErrorCode, Faucet, FaucetEvent, FaucetRequest,
+    Gateway, GuildSettings, LimitKeys, RequestSource, Summary, VelocityKey,
+};
+use crate::{CorrelationId, Matcher, Messages, Options, Token};
+use crate::{QueuedRequest, Rejection, RequestId, TransferRequest, WebState};
+use async_std::{
+    future::timeout,
+    sync::RwLock,
+    task::{sleep, spawn},
+};
+use ethers::{
+    types::{Address, H256, U256},
+    utils::{format_ether, format_units, parse_ether},
+};
+use futures::{
+    future::{join_all, ready},
+    stream, StreamExt,
+};
 use serenity::{
     async_trait,
+    builder::CreateEmbed,
+    client::bridge::gateway::event::ShardStageUpdateEvent,
+    gateway::ConnectionStage,
     model::{
-        gateway::Ready,
+        channel::{Message, MessageFlags, MessageType},
+        gateway::{Activity, Ready},
+        guild::Member,
+        id::{ChannelId, RoleId, UserId},
         prelude::{
             command::{Command, CommandOptionType},
+            component::{ActionRowComponent, InputTextStyle},
             interaction::{
-                application_command::{CommandDataOption, CommandDataOptionValue},
+                application_command::{
+                    ApplicationCommandInteraction, CommandDataOption, CommandDataOptionValue,
+                },
+                autocomplete::AutocompleteInteraction,
+                modal::ModalSubmitInteraction,
                 Interaction, InteractionResponseType,
             },
         },
+        user::{OnlineStatus, User},
+        Timestamp,
     },
     prelude::{Context, EventHandler, GatewayIntents},
+    utils::Colour,
     Client,
 };
-use std::io;
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    sync::{atomic::Ordering, Arc},
+    time::{Duration, Instant},
+};
+use tracing::{info_span, Instrument, Span};
+
+/// How long to wait for a grant to be confirmed before updating the reply anyway.
+///
+/// Replies to interactions can only be edited for 15 minutes.
+const CONFIRMATION_TIMEOUT: Duration = Duration::from_secs(14 * 60);
+
+/// The most grants shown in one reply, leaving room for a note among Discord's 10 embeds.
+const MAX_GRANTS_PER_REPLY: usize = 9;
+
+/// The most addresses shown in each ranking of `/leaderboard`.
+const LEADERBOARD_SIZE: usize = 10;
+
+/// How often to update the bot's presence with the state of the faucet.
+const PRESENCE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How often to check the faucets for conditions the operators should be alerted about.
+const ALERT_INTERVAL: Duration = Duration::from_secs(60);
+
+/// The delay before restarting a failed Discord client, doubled after each failure.
+const MIN_RESTART_DELAY: Duration = Duration::from_secs(1);
+const MAX_RESTART_DELAY: Duration = Duration::from_secs(5 * 60);
+
+/// How often to check how long the bot has been disconnected.
+const DISCONNECTION_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+/// The progress of a grant requested on Discord, as shown in the bot's replies.
+#[derive(Clone, Debug)]
+enum GrantStatus {
+    Queued {
+        eta_secs: u64,
+    },
+    Confirmed {
+        tx_hash: H256,
+    },
+    /// Not confirmed while the reply could still be edited.
+    Delayed,
+    /// The transfer failed for good, and will not be sent again.
+    Failed {
+        reason: String,
+    },
+}
+
+impl GrantStatus {
+    fn colour(&self) -> Colour {
+        match self {
+            Self::Queued { .. } => Colour::GOLD,
+            Self::Confirmed { .. } => Colour::DARK_GREEN,
+            Self::Delayed => Colour::ORANGE,
+            Self::Failed { .. } => Colour::RED,
+        }
+    }
+}
+
+/// A reply to a request which could not be served.
+fn error_embed(messages: &Messages, message: impl ToString) -> CreateEmbed {
+    let mut embed = CreateEmbed::default();
+    embed
+        .title(messages.get("error_title", &[]))
+        .description(message)
+        .colour(Colour::RED);
+    embed
+}
+
+/// Reply to `command` with a message only its author can see.
+async fn reply_privately(
+    ctx: &Context,
+    command: &ApplicationCommandInteraction,
+    message: impl ToString,
+) {
+    if let Err(why) = command
+        .create_interaction_response(&ctx.http, |response| {
+            response
+                .kind(InteractionResponseType::ChannelMessageWithSource)
+                .interaction_response_data(|data| data.content(message).ephemeral(true))
+        })
+        .await
+    {
+        tracing::error!("Cannot respond to slash command: {}", why);
+    }
+}
+
+/// Reply to `command` with `embed`, returning whether the reply was sent.
+async fn reply_with_embeds(
+    ctx: &Context,
+    command: &ApplicationCommandInteraction,
+    embeds: Vec<CreateEmbed>,
+) -> bool {
+    if let Err(why) = command
+        .create_interaction_response(&ctx.http, |response| {
+            response
+                .kind(InteractionResponseType::ChannelMessageWithSource)
+                .interaction_response_data(|message| message.set_embeds(embeds))
+        })
+        .await
+    {
+        tracing::error!("Cannot respond to slash command: {}", why);
+        return false;
+    }
+    true
+}
+
+/// Start the cooldowns of `user` and `addresses` in a guild.
+///
+/// Fails with a reply if any of them is still cooling down.
+async fn start_cooldowns(
+    messages: &Messages,
+    settings: &GuildSettings,
+    user: UserId,
+    addresses: &[Address],
+) -> Result<(), String> {
+    settings
+        .start_cooldowns(user.0, addresses)
+        .await
+        .map_err(|cooling_down| match cooling_down {
+            CoolingDown::User(remaining) => messages.get(
+                "user_cooldown",
+                &[("remaining", &format_duration(messages, remaining))],
+            ),
+            CoolingDown::Address(address, remaining) => messages.get(
+                "address_cooldown",
+                &[
+                    ("address", &format!("{address:?}")),
+                    ("remaining", &format_duration(messages, remaining)),
+                ],
+            ),
+        })
+}
+
+/// Check that `user` holds the role required to request funds, if any.
+fn check_role(
+    messages: &Messages,
+    config: &Options,
+    user: &User,
+    member: Option<&Member>,
+) -> Result<(), String> {
+    let Some(role) = config.discord_required_role else {
+        return Ok(());
+    };
+    let has_role = member.is_some_and(|member| member.roles.contains(&RoleId(role)));
+    if has_role {
+        return Ok(());
+    }
+    tracing::info!(
+        "Rejecting request from {} without the required role",
+        user.tag()
+    );
+    Err(match config.discord_verification_channel {
+        Some(channel) => messages.get(
+            "missing_role_verify",
+            &[("role", &role), ("channel", &channel)],
+        ),
+        None => messages.get("missing_role", &[("role", &role)]),
+    })
+}
+
+/// Check that `user` passes the configured account age and membership checks.
+fn check_account(
+    messages: &Messages,
+    config: &Options,
+    user: &User,
+    member: Option<&Member>,
+) -> Result<(), String> {
+    let now = Timestamp::now().unix_timestamp();
+    let age_of = |timestamp: Timestamp| {
+        Duration::from_secs((now - timestamp.unix_timestamp()).max(0) as u64)
+    };
+
+    if let Some(min_age) = config.discord_min_account_age {
+        let age = age_of(user.created_at());
+        if age < min_age {
+            tracing::info!("Rejecting request from new account {}", user.tag());
+            return Err(messages.get(
+                "account_too_new",
+                &[("remaining", &format_duration(messages, min_age - age))],
+            ));
+        }
+    }
+    if let Some(min_membership) = config.discord_min_membership {
+        let joined_at = member.and_then(|member| member.joined_at);
+        if let Some(membership) = joined_at.map(age_of) {
+            if membership < min_membership {
+                tracing::info!("Rejecting request from new member {}", user.tag());
+                return Err(messages.get(
+                    "member_too_new",
+                    &[(
+                        "remaining",
+                        &format_duration(messages, min_membership - membership),
+                    )],
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Format a remaining wait in days, hours or minutes, rounded up to the minute.
+fn format_duration(messages: &Messages, duration: Duration) -> String {
+    let plural = |count: u64, unit: &str| {
+        if count == 1 {
+            messages.get(&format!("{unit}_one"), &[])
+        } else {
+            messages.get(&format!("{unit}_other"), &[("count", &count)])
+        }
+    };
+    let minutes = (duration.as_secs() + 59) / 60;
+    if minutes > 48 * 60 {
+        return plural((minutes + 24 * 60 - 1) / (24 * 60), "day");
+    }
+    match (minutes / 60, minutes % 60) {
+        (0, minutes) => plural(minutes, "minute"),
+        (hours, 0) => plural(hours, "hour"),
+        (hours, minutes) => messages.get(
+            "hours_minutes",
+            &[
+                ("hours", &plural(hours, "hour")),
+                ("minutes", &plural(minutes, "minute")),
+            ],
+        ),
+    }
+}
+
+/// Ask for an address in a form, for `/faucet` and `/faucet-both` commands without one.
+async fn open_address_modal(
+    messages: &Messages,
+    ctx: &Context,
+    command: &ApplicationCommandInteraction,
+) {
+    // The form of `/faucet-both` has its own ID, so that its submission is a dual-layer request.
+    let id = match command.data.name.as_str() {
+        "faucet-both" => "faucet-both-address",
+        _ => "faucet-address",
+    };
+    if let Err(why) = command
+        .create_interaction_response(&ctx.http, |response| {
+            response
+                .kind(InteractionResponseType::Modal)
+                .interaction_response_data(|data| {
+                    data.custom_id(id)
+                        .title(messages.get("modal_title", &[]))
+                        .components(|components| {
+                            components.create_action_row(|row| {
+                                row.create_input_text(|input| {
+                                    input
+                                        .custom_id("address")
+                                        .label(messages.get("modal_label", &[]))
+                                        .style(InputTextStyle::Short)
+                                        .placeholder("0x...")
+                                        .min_length(42)
+                                        .max_length(42)
+                                        .required(true)
+                                })
+                            })
+                        })
+                })
+        })
+        .await
+    {
+        tracing::error!("Cannot open address form: {}", why);
+    }
+}
+
+/// Find an ethereum address in the text of a command option.
+fn find_address(settings: Option<&GuildSettings>, input: &str) -> Option<Address> {
+    find_addresses(settings, input).into_iter().next()
+}
+
+/// Find the distinct ethereum addresses in a message, using the address pattern of the guild.
+fn find_addresses(settings: Option<&GuildSettings>, input: &str) -> Vec<Address> {
+    match settings {
+        Some(settings) => settings.matcher.addresses(input),
+        None => Matcher::default().addresses(input),
+    }
+}
+
+/// Why `msg` must not be served as a request for funds, if it must not.
+///
+/// Only messages typed by people are served, so that bots cannot trigger grants, or each other.
+/// Addresses are only taken from the text of a message, never from its embeds.
+fn ignore_reason(msg: &Message) -> Option<&'static str> {
+    if msg.author.bot {
+        Some("sent by a bot")
+    } else if msg.webhook_id.is_some() {
+        Some("sent by a webhook")
+    } else if msg.edited_timestamp.is_some() {
+        Some("replayed after an edit")
+    } else if !matches!(msg.kind, MessageType::Regular | MessageType::InlineReply) {
+        Some("of a special type")
+    } else if msg
+        .flags
+        .is_some_and(|flags| flags.contains(MessageFlags::IS_CROSSPOST))
+    {
+        Some("crossposted from another channel")
+    } else if msg.content.trim().is_empty() {
+        Some("without text")
+    } else {
+        None
+    }
+}
+
+/// The name of the network served by `faucet`, as shown in replies.
+fn network_name(messages: &Messages, faucet: &Faucet) -> String {
+    faucet
+        .config()
+        .network_name
+        .clone()
+        .unwrap_or_else(|| messages.get("chain_name", &[("chain_id", &faucet.chain_id())]))
+}
+
+/// A link to `tx_hash` on the block explorer of `faucet`, or just the hash if there is none.
+fn explorer_link(faucet: &Faucet, tx_hash: H256) -> String {
+    match &faucet.config().explorer_url {
+        Some(template) => format!(
+            "[{tx_hash:?}]({})",
+            template.replace("{tx_hash}", &format!("{tx_hash:?}"))
+        ),
+        None => format!("`{tx_hash:?}`"),
+    }
+}
+
+/// Format an amount of wei in ethers, without trailing zeros.
+fn format_amount(amount: U256) -> String {
+    trim_zeros(format_ether(amount))
+}
+
+/// Format an amount in the smallest unit of `token` in whole tokens, with the token symbol.
+fn format_token_amount(amount: U256, token: &Token) -> String {
+    let whole = format_units(amount, token.decimals as u32).unwrap_or_else(|_| amount.to_string());
+    format!("{} {}", trim_zeros(whole), token.symbol)
+}
+
+/// Remove the trailing zeros of a decimal amount.
+fn trim_zeros(amount: String) -> String {
+    if amount.contains('.') {
+        amount
+            .trim_end_matches('0')
+            .trim_end_matches('.')
+            .to_string()
+    } else {
+        amount
+    }
+}
+
+/// Post an alert about `faucet` in `channel`.
+async fn post_alert(
+    ctx: &Context,
+    channel: ChannelId,
+    messages: &Messages,
+    faucet: &Faucet,
+    alert: Alert,
+) {
+    let network = network_name(messages, faucet);
+    let (key, args): (_, Vec<(&str, String)>) = match alert {
+        Alert::LowBalance { balance, threshold } => (
+            "alert_low_balance",
+            vec![
+                ("balance", format_amount(balance)),
+                ("threshold", format_amount(threshold)),
+            ],
+        ),
+        Alert::BalanceRecovered { balance } => (
+            "alert_balance_recovered",
+            vec![("balance", format_amount(balance))],
+        ),
+        Alert::LongQueue { queue_length } => (
+            "alert_long_queue",
+            vec![("queue_length", queue_length.to_string())],
+        ),
+        Alert::QueueRecovered { queue_length } => (
+            "alert_queue_recovered",
+            vec![("queue_length", queue_length.to_string())],
+        ),
+        Alert::RpcFailing { failures, error } => (
+            "alert_rpc_failing",
+            vec![("failures", failures.to_string()), ("error", error)],
+        ),
+        Alert::RpcRecovered => ("alert_rpc_recovered", vec![]),
+        Alert::DeadLetter { request, reason } => (
+            "alert_dead_letter",
+            vec![
+                ("address", format!("{:?}", request.to())),
+                ("reason", reason),
+            ],
+        ),
+        Alert::TaskPanicked { message } => ("alert_task_panicked", vec![("message", message)]),
+        Alert::VelocitySpike { spike } => (
+            "alert_velocity_spike",
+            vec![
+                ("requester", spike.key.to_string()),
+                ("requests", spike.requests.to_string()),
+                ("baseline", spike.baseline.to_string()),
+            ],
+        ),
+        Alert::VelocityRecovered => ("alert_velocity_recovered", vec![]),
+    };
+    let mut args: Vec<(&str, &dyn Display)> = args
+        .iter()
+        .map(|(name, value)| (*name, value as &dyn Display))
+        .collect();
+    args.push(("network", &network));
+    let alert = messages.get(key, &args);
+    tracing::warn!("Alert: {alert}");
+    if let Err(err) = channel.say(&ctx.http, alert).await {
+        tracing::error!("Cannot post alert: {}", err);
+    }
+}
+
+/// The bot's reply to a request for funds, updated as the grant progresses.
+enum Reply {
+    Command(ApplicationCommandInteraction),
+    Modal(ModalSubmitInteraction),
+    /// A reply to a channel or direct message.
+    Message(Message),
+}
+
+impl Reply {
+    /// The message of the reply.
+    async fn message(&self, ctx: &Context) -> serenity::Result<Message> {
+        match self {
+            Self::Command(command) => command.get_interaction_response(&ctx.http).await,
+            Self::Modal(modal) => modal.get_interaction_response(&ctx.http).await,
+            Self::Message(message) => Ok(message.clone()),
+        }
+    }
+
+    /// Replace the embeds of the reply.
+    async fn edit(&mut self, ctx: &Context, embeds: Vec<CreateEmbed>) -> serenity::Result<()> {
+        match self {
+            Self::Command(command) => {
+                command
+                    .edit_original_interaction_response(&ctx.http, |response| {
+                        response.set_embeds(embeds)
+                    })
+                    .await?;
+            }
+            Self::Modal(modal) => {
+                modal
+                    .edit_original_interaction_response(&ctx.http, |response| {
+                        response.set_embeds(embeds)
+                    })
+                    .await?;
+            }
+            Self::Message(message) => {
+                message
+                    .edit(ctx, |message| message.set_embeds(embeds))
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The Discord user making a request for funds, and where.
+#[derive(Clone, Copy, Debug)]
+struct Requester<'a> {
+    user: &'a User,
+    /// The user as a member of the guild, if the request was made in one.
+    member: Option<&'a Member>,
+    guild: Option<u64>,
+    /// The ID of the interaction or message of the request.
+    correlation_id: CorrelationId,
+}
+
+impl Requester<'_> {
+    /// The span to handle the request in, so that its logs carry the correlation ID.
+    fn span(&self) -> Span {
+        info_span!(
+            "discord_request",
+            correlation_id = %self.correlation_id,
+            user = %self.user.tag()
+        )
+    }
+}
+
+/// A grant requested on Discord.
+#[derive(Clone, Debug)]
+struct Grant {
+    address: Address,
+    amount: U256,
+    /// The ERC-20 token granted, or `None` for the native currency.
+    token: Option<Token>,
+    id: RequestId,
+    correlation_id: CorrelationId,
+    /// The faucet serving the grant.
+    faucet: Faucet,
+    /// The messages in the locale of the requester.
+    messages: Messages,
+}
+
+impl Grant {
+    /// A reply showing the progress of the grant.
+    fn embed(&self, status: &GrantStatus) -> CreateEmbed {
+        let messages = &self.messages;
+        let text = |key| messages.get(key, &[]);
+        let network = network_name(messages, &self.faucet);
+        let mut embed = CreateEmbed::default();
+        embed
+            .title(text("grant_title"))
+            .field(
+                text("grant_recipient"),
+                format!("`{:?}`", self.address),
+                false,
+            )
+            .field(text("grant_amount"), self.formatted_amount(), true)
+            .field(text("grant_network"), network, true)
+            .colour(status.colour())
+            .footer(|footer| {
+                footer.text(messages.get(
+                    "grant_footer",
+                    &[("id", &self.id), ("correlation_id", &self.correlation_id)],
+                ))
+            });
+        let status_field = text("grant_status");
+        match status {
+            GrantStatus::Queued { eta_secs } => {
+                embed.field(
+                    status_field,
+                    messages.get("grant_queued", &[("eta_secs", eta_secs)]),
+                    false,
+                );
+            }
+            GrantStatus::Confirmed { tx_hash } => {
+                embed
+                    .field(status_field, text("grant_confirmed"), false)
+                    .field(
+                        text("grant_transaction"),
+                        explorer_link(&self.faucet, *tx_hash),
+                        false,
+                    );
+            }
+            GrantStatus::Delayed => {
+                embed.field(status_field, text("grant_delayed"), false);
+            }
+            GrantStatus::Failed { reason } => {
+                embed.field(
+                    status_field,
+                    messages.get("grant_failed", &[("reason", reason)]),
+                    false,
+                );
+            }
+        }
+        embed
+    }
+
+    /// Wait until the grant is mined or fails for good, or give up after `within`.
+    async fn await_confirmation(&self, within: Duration) -> GrantStatus {
+        let id = self.id;
+        // Subscribe before waiting for the grant, so that a failure in between is not missed.
+        let failed = self
+            .faucet
+            .events()
+            .subscribe()
+            .await
+            .filter_map(move |event| {
+                ready(match event {
+                    FaucetEvent::TransferFailed {
+                        request,
+                        reason,
+                        retried: false,
+                        ..
+                    } if request.id() == Some(id) => Some(GrantStatus::Failed { reason }),
+                    _ => None,
+                })
+            });
+        let faucet = self.faucet.clone();
+        let confirmed =
+            stream::once(async move { await_transfer(faucet, id).await.ok()?.next().await?.ok() })
+                .filter_map(|completed| {
+                    ready(completed.map(|completed| GrantStatus::Confirmed {
+                        tx_hash: completed.tx_hash,
+                    }))
+                });
+        let status = timeout(within, stream::select(confirmed, failed).next()).await;
+        match status {
+            Ok(Some(status)) => status,
+            _ => {
+                tracing::warn!("Request {id} was not confirmed within {within:?}");
+                GrantStatus::Delayed
+            }
+        }
+    }
+
+    /// The amount of the grant, in ethers or whole tokens.
+    fn formatted_amount(&self) -> String {
+        match &self.token {
+            Some(token) => format_token_amount(self.amount, token),
+            None => format_amount(self.amount),
+        }
+    }
+
+    /// Tell `user` in a reply to `message` whether the grant was mined.
+    async fn notify(&self, ctx: &Context, message: &Message, user: UserId, status: &GrantStatus) {
+        let user = format!("<@{user}>");
+        let address = format!("{:?}", self.address);
+        let notification = match status {
+            GrantStatus::Confirmed { tx_hash } => self.messages.get(
+                "notify_confirmed",
+                &[
+                    ("user", &user),
+                    ("amount", &self.formatted_amount()),
+                    ("address", &address),
+                    ("transaction", &explorer_link(&self.faucet, *tx_hash)),
+                ],
+            ),
+            GrantStatus::Failed { reason } => self.messages.get(
+                "notify_dead_letter",
+                &[
+                    ("user", &user),
+                    ("address", &address),
+                    ("reason", reason),
+                    ("id", &self.id),
+                    ("correlation_id", &self.correlation_id),
+                ],
+            ),
+            _ => self.messages.get(
+                "notify_failed",
+                &[
+                    ("user", &user),
+                    ("address", &address),
+                    ("id", &self.id),
+                    ("correlation_id", &self.correlation_id),
+                ],
+            ),
+        };
+        if let Err(err) = message.reply_ping(ctx, notification).await {
+            tracing::error!("Cannot notify {user} of request {}: {}", self.id, err);
+        }
+    }
+}
+
+/// The grants requested in a single Discord message.
+struct Grants {
+    /// The user who requested the grants.
+    user: UserId,
+    grants: Vec<(Grant, GrantStatus)>,
+    /// Remarks about the addresses which were not served.
+    notes: Vec<String>,
+}
+
+impl Grants {
+    /// A reply showing the progress of each grant.
+    fn embeds(&self) -> Vec<CreateEmbed> {
+        let mut embeds: Vec<_> = self
+            .grants
+            .iter()
+            .map(|(grant, status)| grant.embed(status))
+            .collect();
+        if !self.notes.is_empty() {
+            let mut embed = CreateEmbed::default();
+            embed
+                .description(self.notes.join("\n"))
+                .colour(Colour::GOLD);
+            embeds.push(embed);
+        }
+        embeds
+    }
+
+    /// Update `reply` once all the grants are mined or failed, and notify the requester of each of
+    /// them.
+    ///
+    /// The reply itself can only be edited while the interaction is valid. Grants which take
+    /// longer are reported in follow-up messages only.
+    async fn report_completion(mut self, ctx: Context, mut reply: Reply) {
+        let statuses = join_all(
+            self.grants
+                .iter()
+                .map(|(grant, _)| grant.await_confirmation(CONFIRMATION_TIMEOUT)),
+        )
+        .await;
+        for ((_, status), confirmed) in self.grants.iter_mut().zip(statuses) {
+            *status = confirmed;
+        }
+        if let Err(err) = reply.edit(&ctx, self.embeds()).await {
+            tracing::error!("Cannot edit reply to faucet request: {}", err);
+        }
+
+        let message = match reply.message(&ctx).await {
+            Ok(message) => message,
+            Err(err) => {
+                tracing::error!("Cannot notify requester, reply not found: {}", err);
+                return;
+            }
+        };
+        join_all(self.grants.iter().map(|(grant, status)| async {
+            let status = match status {
+                GrantStatus::Delayed => {
+                    let remaining = grant
+                        .faucet
+                        .config()
+                        .discord_notification_timeout
+                        .saturating_sub(CONFIRMATION_TIMEOUT);
+                    grant.await_confirmation(remaining).await
+                }
+                status => status.clone(),
+            };
+            grant.notify(&ctx, &message, self.user, &status).await;
+        }))
+        .await;
+    }
+}
 
 impl WebState {
-    async fn handle_faucet_request(&self, options: &[CommandDataOption]) -> String {
-        let option = options
-            .get(0)
-            .expect("Expected address option")
-            .resolved
+    /// The messages in the locale of a guild with `settings`.
+    fn messages(&self, settings: Option<&GuildSettings>) -> Messages {
+        self.catalog
+            .messages(settings.and_then(|settings| settings.locale.as_deref()))
+    }
+
+    /// The default faucet and the faucets of the chains in the guild settings.
+    fn faucets(&self) -> impl Iterator<Item = &Faucet> {
+        std::iter::once(&self.faucet).chain(self.guilds.chains().map(|(_, chain)| &chain.faucet))
+    }
+
+    /// Handle a `/faucet-admin` command, returning the reply.
+    ///
+    /// Pausing and resuming applies to the faucets of all chains, while the grant amount can only
+    /// be changed for the default faucet; guilds with their own amount keep it.
+    async fn handle_admin_command(
+        &self,
+        messages: &Messages,
+        command: &ApplicationCommandInteraction,
+    ) -> Result<String, String> {
+        let Some(role) = self.faucet.config().discord_admin_role else {
+            return Err(messages.get("admin_disabled", &[]));
+        };
+        let is_admin = command
+            .member
             .as_ref()
-            .expect("Expected user object");
-        match option {
-            CommandDataOptionValue::String(input) => {
-                // Try to find an ethereum address in the message body.
-                let re = Regex::new("0x[a-fA-F0-9]{40}").unwrap();
-
-                if let Some(matched) = re.captures(input) {
-                    let address = matched
-                        .get(0)
-                        .expect("At least one match")
-                        .as_str()
-                        .parse::<Address>()
-                        .expect("Address can be parsed after matching regex");
-                    if let Err(err) = self.request(address).await {
-                        tracing::error!("Failed make faucet request for {address:?}: {}", err);
-                        format!("Internal Error: Failed to send funds to {address:?}")
+            .is_some_and(|member| member.roles.contains(&RoleId(role)));
+        if !is_admin {
+            tracing::warn!(
+                "{} is not allowed to use admin commands",
+                command.user.tag()
+            );
+            return Err(messages.get("admin_forbidden", &[]));
+        }
+
+        let subcommand = command.data.options.get(0).expect("Expected subcommand");
+        tracing::info!(
+            "Admin command {} by {}",
+            subcommand.name,
+            command.user.tag()
+        );
+        match subcommand.name.as_str() {
+            "pause" => {
+                for faucet in self.faucets() {
+                    faucet.pause().await;
+                }
+                Ok(messages.get("admin_paused", &[]))
+            }
+            "resume" => {
+                for faucet in self.faucets() {
+                    faucet.resume().await;
+                }
+                Ok(messages.get("admin_resumed", &[]))
+            }
+            "balance" => {
+                let mut lines = vec![];
+                for faucet in self.faucets() {
+                    let balance = faucet.total_balance().await.map_err(|err| {
+                        tracing::error!("Failed to get the faucet balance: {err:#}");
+                        messages.get("admin_balance_failed", &[])
+                    })?;
+                    let key = if faucet.is_paused().await {
+                        "admin_balance_paused"
                     } else {
-                        format!("Sending funds to {address:?}")
-                    }
+                        "admin_balance"
+                    };
+                    lines.push(messages.get(
+                        key,
+                        &[
+                            ("network", &network_name(messages, faucet)),
+                            ("balance", &format_amount(balance)),
+                            ("wallets", &faucet.wallets().await.len()),
+                            ("amount", &format_amount(faucet.grant_amount().await)),
+                        ],
+                    ));
+                }
+                Ok(lines.join("\n"))
+            }
+            "set-amount" => {
+                let Some(CommandDataOptionValue::String(input)) = subcommand
+                    .options
+                    .get(0)
+                    .and_then(|option| option.resolved.as_ref())
+                else {
+                    unreachable!()
+                };
+                let amount = parse_ether(input.trim())
+                    .map_err(|_| messages.get("admin_invalid_amount", &[("input", &input)]))?;
+                self.faucet.set_grant_amount(amount).await;
+                Ok(messages.get("admin_amount_set", &[("amount", &format_amount(amount))]))
+            }
+            "ban" | "unban" => {
+                let option = |name| {
+                    subcommand
+                        .options
+                        .iter()
+                        .find(|option| option.name == name)
+                        .and_then(|option| option.resolved.as_ref())
+                };
+                let Some(CommandDataOptionValue::User(user, _)) = option("user") else {
+                    unreachable!()
+                };
+                let mention = format!("<@{}>", user.id);
+                let save_failed = |err: anyhow::Error| {
+                    tracing::error!("Failed to save the ban list: {err:#}");
+                    messages.get("admin_ban_failed", &[])
+                };
+                if subcommand.name == "ban" {
+                    let reason = match option("reason") {
+                        Some(CommandDataOptionValue::String(reason)) => Some(reason.clone()),
+                        _ => None,
+                    };
+                    self.bans
+                        .ban(Ban::new(user.id.0, reason, command.user.id.0))
+                        .await
+                        .map_err(save_failed)?;
+                    Ok(messages.get("admin_banned", &[("user", &mention)]))
+                } else if self.bans.unban(user.id.0).await.map_err(save_failed)? {
+                    Ok(messages.get("admin_unbanned", &[("user", &mention)]))
                 } else {
-                    "No address found!".to_string()
+                    Ok(messages.get("admin_not_banned", &[("user", &mention)]))
                 }
             }
             _ => unreachable!(),
         }
     }
+
+    /// Check that `user` holds the role required to request funds, if any.
+    async fn check_role(
+        &self,
+        messages: &Messages,
+        user: &User,
+        member: Option<&Member>,
+    ) -> Result<(), String> {
+        let result = check_role(messages, self.faucet.config(), user, member);
+        if result.is_err() {
+            self.discord_metrics.rejection(Rejection::Role).await;
+        }
+        result
+    }
+
+    /// Check that faucet commands are allowed in `channel`.
+    ///
+    /// Fails with a message pointing to the allowed channels otherwise.
+    fn check_channel(
+        &self,
+        messages: &Messages,
+        settings: Option<&GuildSettings>,
+        channel: ChannelId,
+    ) -> Result<(), String> {
+        let options = self.live_options.get();
+        let allowed = match settings {
+            Some(settings) => &settings.channels,
+            None => &options.discord_channels,
+        };
+        if allowed.is_empty() || allowed.contains(&channel.0) {
+            return Ok(());
+        }
+        let channels = allowed
+            .iter()
+            .map(|id| format!("<#{id}>"))
+            .collect::<Vec<_>>()
+            .join(", ");
+        Err(messages.get("wrong_channel", &[("channels", &channels)]))
+    }
+
+    /// Handle a request for funds by `user` to the address in `input`, returning the requested
+    /// grant and its expected wait time.
+    ///
+    /// With `dual`, the native currency is also granted on `--dual-layer-chain`.
+    async fn handle_faucet_request(
+        &self,
+        messages: &Messages,
+        settings: Option<&GuildSettings>,
+        requester: Requester<'_>,
+        input: &str,
+        token: Option<&Token>,
+        dual: bool,
+    ) -> Result<Grants, String> {
+        let Requester {
+            user,
+            member,
+            guild,
+            correlation_id,
+        } = requester;
+        if let Some(ban) = self.bans.get(user.id.0).await {
+            tracing::info!("Rejecting request from banned user {}", user.tag());
+            self.discord_metrics.rejection(Rejection::Ban).await;
+            return Err(match ban.reason {
+                Some(reason) => messages.get("banned_reason", &[("reason", &reason)]),
+                None => messages.get("banned", &[]),
+            });
+        }
+        let rollup = if dual {
+            Some(
+                self.dual_layer_chain()
+                    .map_err(|_| messages.get("dual_layer_disabled", &[]))?,
+            )
+        } else {
+            None
+        };
+        let mut addresses = find_addresses(settings, input);
+        if self.faucet.config().discord_require_registration {
+            let registered = self
+                .discord_registrations
+                .read()
+                .await
+                .get(&user.id.0)
+                .copied();
+            let Some(registered) = registered else {
+                self.discord_metrics
+                    .rejection(Rejection::Registration)
+                    .await;
+                return Err(messages.get("not_registered", &[]));
+            };
+            if addresses.iter().any(|address| *address != registered) {
+                self.discord_metrics
+                    .rejection(Rejection::Registration)
+                    .await;
+                return Err(messages.get(
+                    "registered_address_only",
+                    &[("address", &format!("{registered:?}"))],
+                ));
+            }
+            addresses = vec![registered];
+        }
+        if addresses.is_empty() {
+            return Err(messages.get("no_address", &[]));
+        }
+        let mut notes = vec![];
+        // Dual-layer requests make two grants per address.
+        let max_grants = if dual {
+            MAX_GRANTS_PER_REPLY / 2
+        } else {
+            MAX_GRANTS_PER_REPLY
+        };
+        let max = self
+            .faucet
+            .config()
+            .discord_max_addresses
+            .clamp(1, max_grants);
+        if addresses.len() > max {
+            let ignored = addresses.len() - max;
+            addresses.truncate(max);
+            let key = if ignored == 1 {
+                "ignored_addresses_one"
+            } else {
+                "ignored_addresses_other"
+            };
+            notes.push(messages.get(key, &[("count", &ignored), ("max", &max)]));
+        }
+
+        let mut delay = None;
+        if let Some(velocity) = self.faucet.velocity() {
+            if let Err(remaining) = velocity
+                .check(Some(VelocityKey::DiscordUser(user.id.0)))
+                .await
+            {
+                delay = self.faucet.tarpit_delay(AbuseSignal::Velocity).await;
+                if delay.is_none() {
+                    self.discord_metrics.rejection(Rejection::Cooldown).await;
+                    return Err(messages.get(
+                        "user_cooldown",
+                        &[("remaining", &format_duration(messages, remaining))],
+                    ));
+                }
+            }
+        }
+        if let Err(message) = check_account(messages, self.faucet.config(), user, member) {
+            self.discord_metrics.rejection(Rejection::Account).await;
+            return Err(message);
+        }
+        for address in &addresses {
+            let keys = LimitKeys {
+                ip: None,
+                address: Some(*address),
+                user: Some(user.id.0),
+            };
+            if let Err(remaining) = self.rate_limiter.check(keys).await {
+                if let Some(tarpitted) = self.faucet.tarpit_delay(AbuseSignal::RateLimit).await {
+                    self.rate_limiter.count(keys).await;
+                    delay = delay.max(Some(tarpitted));
+                    continue;
+                }
+                self.discord_metrics.rejection(Rejection::Cooldown).await;
+                return Err(messages.get(
+                    "address_cooldown",
+                    &[
+                        ("address", &format!("{address:?}")),
+                        ("remaining", &format_duration(messages, remaining)),
+                    ],
+                ));
+            }
+        }
+        if let Some(settings) = settings {
+            let quota_exhausted = |remaining| {
+                messages.get(
+                    "guild_quota_exhausted",
+                    &[("remaining", &format_duration(messages, remaining))],
+                )
+            };
+            let count = addresses.len() as u32;
+            // Check the guild quota before starting the cooldowns, so that users are not put on
+            // cooldown for requests which are not served, but only use it up once they are.
+            if let Some(quota) = &settings.quota {
+                if let Some(remaining) = quota.remaining(count).await {
+                    self.discord_metrics.rejection(Rejection::Quota).await;
+                    return Err(quota_exhausted(remaining));
+                }
+            }
+            if let Err(message) = start_cooldowns(messages, settings, user.id, &addresses).await {
+                self.discord_metrics.rejection(Rejection::Cooldown).await;
+                return Err(message);
+            }
+            if let Some(quota) = &settings.quota {
+                if let Err(remaining) = quota.take(count).await {
+                    settings.reset_cooldowns(Some(user.id.0), &addresses).await;
+                    self.discord_metrics.rejection(Rejection::Quota).await;
+                    return Err(quota_exhausted(remaining));
+                }
+            }
+        }
+
+        let (queue, faucet) = self.chain(settings);
+        let source = if self.is_verified(member) {
+            RequestSource::DiscordVerified
+        } else {
+            RequestSource::Discord
+        };
+        let mut grants = vec![];
+        let mut failed = vec![];
+        for address in addresses {
+            let mut request = FaucetRequest::new(address, token.cloned())
+                .with_correlation_id(correlation_id)
+                .with_delay(delay)
+                .with_requester(Some(crate::Requester::Discord(user.id.0)))
+                .with_source(source);
+            let amount = match token {
+                // The grant amount of the guild only applies to the native currency.
+                Some(token) => token.grant_amount,
+                None => {
+                    if let Some(amount) = settings.and_then(|settings| settings.grant_amount) {
+                        request = request.with_amount(amount);
+                    }
+                    match request.amount {
+                        Some(amount) => amount,
+                        None => faucet.grant_amount().await,
+                    }
+                }
+            };
+            // The rollup grant of a dual-layer request is served along with the L1 grant, or
+            // neither is.
+            let l1 = Self::submit(queue, faucet, request);
+            let result = match rollup {
+                Some(rollup) => {
+                    let request = FaucetRequest::new(address, None)
+                        .with_correlation_id(correlation_id)
+                        .with_delay(delay)
+                        .with_requester(Some(crate::Requester::Discord(user.id.0)))
+                        .with_source(source);
+                    Self::submit_dual(faucet, l1, rollup, request)
+                        .await
+                        .map(|dual| (dual.l1, Some(dual.rollup)))
+                }
+                None => l1.await.map(|l1| (l1, None)),
+            };
+            match result {
+                Ok((QueuedRequest { id, eta_secs, .. }, dual)) => {
+                    self.discord_addresses
+                        .write()
+                        .await
+                        .insert(user.id.0, address);
+                    if self.faucet.config().discord_audit_channel_id.is_some() {
+                        self.discord_requesters.write().await.insert(id, user.id.0);
+                    }
+                    grants.push((
+                        Grant {
+                            address,
+                            amount,
+                            token: token.cloned(),
+                            id,
+                            correlation_id,
+                            faucet: faucet.clone(),
+                            messages: messages.clone(),
+                        },
+                        GrantStatus::Queued { eta_secs },
+                    ));
+                    if let (Some(rollup), Some(queued)) = (rollup, dual) {
+                        match queued {
+                            Some(QueuedRequest { id, eta_secs, .. }) => {
+                                if self.faucet.config().discord_audit_channel_id.is_some() {
+                                    self.discord_requesters.write().await.insert(id, user.id.0);
+                                }
+                                grants.push((
+                                    Grant {
+                                        address,
+                                        amount: rollup.faucet.grant_amount().await,
+                                        token: None,
+                                        id,
+                                        correlation_id,
+                                        faucet: rollup.faucet.clone(),
+                                        messages: messages.clone(),
+                                    },
+                                    GrantStatus::Queued { eta_secs },
+                                ));
+                            }
+                            None => notes.push(
+                                messages
+                                    .get("request_failed", &[("address", &format!("{address:?}"))]),
+                            ),
+                        }
+                    }
+                }
+                Err(err) if err.code == ErrorCode::LifetimeCapReached => {
+                    failed.push(address);
+                    let max = faucet.config().max_lifetime_per_address.unwrap_or_default();
+                    notes.push(messages.get(
+                        "lifetime_cap_reached",
+                        &[
+                            ("address", &format!("{address:?}")),
+                            ("max", &format_amount(max)),
+                        ],
+                    ));
+                }
+                Err(err) => {
+                    failed.push(address);
+                    tracing::error!("Failed make faucet request for {address:?}: {}", err);
+                    notes.push(
+                        messages.get("request_failed", &[("address", &format!("{address:?}"))]),
+                    );
+                }
+            }
+        }
+        // Grants which are not served do not count against the guild.
+        if let (Some(settings), false) = (settings, failed.is_empty()) {
+            let user = grants.is_empty().then_some(user.id.0);
+            settings.reset_cooldowns(user, &failed).await;
+            if let Some(quota) = &settings.quota {
+                quota.refund(failed.len() as u32).await;
+            }
+        }
+        if grants.is_empty() {
+            return Err(notes.join("\n"));
+        }
+        self.discord_metrics.grants(guild, grants.len()).await;
+        Ok(Grants {
+            user: user.id,
+            grants,
+            notes,
+        })
+    }
+
+    /// Whether `member` holds `--discord-verified-role`.
+    fn is_verified(&self, member: Option<&Member>) -> bool {
+        self.faucet
+            .config()
+            .discord_verified_role
+            .is_some_and(|role| member.is_some_and(|member| member.roles.contains(&RoleId(role))))
+    }
+
+    /// Handle a `/faucet-web-token` command by `user` in `guild`, returning the reply.
+    ///
+    /// Tokens are only issued to users who could request funds with `/faucet`, since the web
+    /// requests made with a token are not subject to the role and account checks again.
+    async fn handle_web_token_command(
+        &self,
+        messages: &Messages,
+        guild: Option<u64>,
+        user: &User,
+        member: Option<&Member>,
+    ) -> Result<String, String> {
+        if let Some(ban) = self.bans.get(user.id.0).await {
+            self.discord_metrics.rejection(Rejection::Ban).await;
+            return Err(match ban.reason {
+                Some(reason) => messages.get("banned_reason", &[("reason", &reason)]),
+                None => messages.get("banned", &[]),
+            });
+        }
+        self.check_role(messages, user, member).await?;
+        if let Err(message) = check_account(messages, self.faucet.config(), user, member) {
+            self.discord_metrics.rejection(Rejection::Account).await;
+            return Err(message);
+        }
+        let token = self
+            .web_tokens
+            .issue(user.id.0, guild, self.is_verified(member));
+        Ok(messages.get(
+            "web_token",
+            &[
+                ("token", &token),
+                (
+                    "validity",
+                    &format_duration(messages, self.web_tokens.ttl()),
+                ),
+            ],
+        ))
+    }
+
+    /// Handle a `/balance` command by `user`, returning the reply.
+    ///
+    /// Without an address, reports the balance of the last address `user` requested funds to.
+    async fn handle_balance_command(
+        &self,
+        messages: &Messages,
+        settings: Option<&GuildSettings>,
+        user: UserId,
+        options: &[CommandDataOption],
+    ) -> Result<String, String> {
+        let address = match options.get(0).and_then(|option| option.resolved.as_ref()) {
+            Some(CommandDataOptionValue::String(input)) => {
+                find_address(settings, input).ok_or_else(|| messages.get("no_address", &[]))?
+            }
+            Some(_) => unreachable!(),
+            None => self
+                .discord_addresses
+                .read()
+                .await
+                .get(&user.0)
+                .copied()
+                .ok_or_else(|| messages.get("no_last_address", &[]))?,
+        };
+        let (_, faucet) = self.chain(settings);
+        let balance = faucet.balance(address).await.map_err(|err| {
+            tracing::error!("Failed to get the balance of {address:?}: {err:#}");
+            messages.get("balance_failed", &[("address", &format!("{address:?}"))])
+        })?;
+        Ok(messages.get(
+            "balance",
+            &[
+                ("address", &format!("{address:?}")),
+                ("amount", &format_amount(balance)),
+                ("network", &network_name(messages, faucet)),
+            ],
+        ))
+    }
+
+    /// Handle a `/register` command by `user`, returning the reply.
+    ///
+    /// Registering an address starts a cooldown before the user can register another one.
+    async fn handle_register_command(
+        &self,
+        messages: &Messages,
+        settings: Option<&GuildSettings>,
+        user: UserId,
+        options: &[CommandDataOption],
+    ) -> Result<String, String> {
+        let Some(CommandDataOptionValue::String(input)) =
+            options.get(0).and_then(|option| option.resolved.as_ref())
+        else {
+            unreachable!()
+        };
+        let address =
+            find_address(settings, input).ok_or_else(|| messages.get("no_address", &[]))?;
+        let address_arg = format!("{address:?}");
+        let mut registrations = self.discord_registrations.write().await;
+        if registrations.get(&user.0) == Some(&address) {
+            return Ok(messages.get("already_registered", &[("address", &address_arg)]));
+        }
+        self.registration_cooldown
+            .start(user.0)
+            .await
+            .map_err(|remaining| {
+                messages.get(
+                    "registration_cooldown",
+                    &[("remaining", &format_duration(messages, remaining))],
+                )
+            })?;
+        if let Some(storage) = &self.storage {
+            // The registration still holds until the faucet restarts.
+            if let Err(err) = storage.record_registration(user.0, address).await {
+                tracing::error!(
+                    "Failed to persist the registration of Discord user {user}: {err:#}"
+                );
+            }
+        }
+        registrations.insert(user.0, address);
+        tracing::info!("Discord user {user} registered {address:?}");
+        Ok(messages.get("registered", &[("address", &address_arg)]))
+    }
+
+    /// Handle a `/cooldown` command by `user`, returning the reply.
+    async fn handle_cooldown_command(
+        &self,
+        messages: &Messages,
+        settings: Option<&GuildSettings>,
+        user: UserId,
+        options: &[CommandDataOption],
+    ) -> Result<String, String> {
+        let address = match options.get(0).and_then(|option| option.resolved.as_ref()) {
+            Some(CommandDataOptionValue::String(input)) => {
+                Some(find_address(settings, input).ok_or_else(|| messages.get("no_address", &[]))?)
+            }
+            Some(_) => unreachable!(),
+            None => None,
+        };
+        let Some((cooldown, address_cooldown)) = settings.and_then(|settings| {
+            settings
+                .cooldown
+                .as_ref()
+                .zip(settings.address_cooldown.as_ref())
+        }) else {
+            return Ok(messages.get("no_cooldown", &[]));
+        };
+
+        let mut lines = vec![match cooldown.remaining(&user.0).await {
+            Some(remaining) => messages.get(
+                "user_cooldown",
+                &[("remaining", &format_duration(messages, remaining))],
+            ),
+            None => messages.get("user_ready", &[]),
+        }];
+        if let Some(address) = address {
+            let address_arg = format!("{address:?}");
+            lines.push(match address_cooldown.remaining(&address).await {
+                Some(remaining) => messages.get(
+                    "address_cooldown",
+                    &[
+                        ("address", &address_arg),
+                        ("remaining", &format_duration(messages, remaining)),
+                    ],
+                ),
+                None => messages.get("address_ready", &[("address", &address_arg)]),
+            });
+        }
+        Ok(lines.join("\n"))
+    }
+
+    /// Handle a `/faucet-status` command, returning the reply.
+    async fn handle_status_command(
+        &self,
+        messages: &Messages,
+        settings: Option<&GuildSettings>,
+    ) -> Result<CreateEmbed, String> {
+        let text = |key| messages.get(key, &[]);
+        let (_, faucet) = self.chain(settings);
+        let stats = faucet.stats().await.map_err(|err| {
+            tracing::error!("Failed to get the faucet status: {err:#}");
+            text("status_failed")
+        })?;
+        let (status, colour) = if stats.paused {
+            ("status_paused", Colour::ORANGE)
+        } else if stats.available_wallets == 0 {
+            ("status_busy", Colour::GOLD)
+        } else {
+            ("status_operational", Colour::DARK_GREEN)
+        };
+        let mut embed = CreateEmbed::default();
+        embed
+            .title(text("status_title"))
+            .field(text("status_status"), text(status), false)
+            .field(text("status_network"), network_name(messages, faucet), true)
+            .field(text("status_block_height"), stats.block_number, true)
+            .field(text("status_queue_length"), stats.queue_length, true)
+            .field(text("status_inflight"), stats.inflight, true)
+            .field(
+                text("status_available_wallets"),
+                stats.available_wallets,
+                true,
+            )
+            .field(
+                text("status_balance"),
+                format_amount(stats.total_balance),
+                true,
+            )
+            .colour(colour);
+        Ok(embed)
+    }
+
+    /// Handle a `/leaderboard` command, returning the reply.
+    async fn handle_leaderboard_command(&self, messages: &Messages) -> CreateEmbed {
+        // Show the Discord users behind the addresses, where known.
+        let users = self
+            .discord_addresses
+            .read()
+            .await
+            .iter()
+            .map(|(user, address)| (*address, *user))
+            .collect::<HashMap<_, _>>();
+        let name = |address: &Address| match users.get(address) {
+            Some(user) => format!("<@{user}>"),
+            None => format!("`{address:?}`"),
+        };
+        let ranking = |lines: Vec<String>| {
+            if lines.is_empty() {
+                messages.get("leaderboard_empty", &[])
+            } else {
+                lines.join("\n")
+            }
+        };
+
+        let returners = self
+            .leaderboard
+            .top_returners(LEADERBOARD_SIZE)
+            .await
+            .into_iter()
+            .enumerate()
+            .map(|(i, (address, amount))| {
+                messages.get(
+                    "leaderboard_returner",
+                    &[
+                        ("rank", &(i + 1)),
+                        ("user", &name(&address)),
+                        ("amount", &format_amount(amount)),
+                    ],
+                )
+            })
+            .collect();
+        let requesters = self
+            .leaderboard
+            .top_requesters(LEADERBOARD_SIZE)
+            .await
+            .into_iter()
+            .enumerate()
+            .map(|(i, (address, grants))| {
+                messages.get(
+                    "leaderboard_requester",
+                    &[
+                        ("rank", &(i + 1)),
+                        ("user", &name(&address)),
+                        ("grants", &grants),
+                    ],
+                )
+            })
+            .collect();
+
+        let mut embed = CreateEmbed::default();
+        embed
+            .title(messages.get(
+                "leaderboard_title",
+                &[(
+                    "period",
+                    &format_duration(messages, self.leaderboard.period()),
+                )],
+            ))
+            .field(
+                messages.get("leaderboard_returners", &[]),
+                ranking(returners),
+                false,
+            )
+            .field(
+                messages.get("leaderboard_requesters", &[]),
+                ranking(requesters),
+                false,
+            )
+            .colour(Colour::DARK_GREEN);
+        embed
+    }
+
+    /// Keep the presence of `shard` up to date with the state of the default faucet.
+    async fn update_presence(self, shard: u64) {
+        // The presence is shared by all guilds, so it is shown in the default locale.
+        let messages = self.messages(None);
+        loop {
+            let (activity, status) = match self.faucet.stats().await {
+                Ok(stats) if stats.paused => (
+                    messages.get("presence_paused", &[]),
+                    OnlineStatus::DoNotDisturb,
+                ),
+                Ok(stats) if stats.total_balance < self.faucet.grant_amount().await => (
+                    messages.get("presence_empty", &[]),
+                    OnlineStatus::DoNotDisturb,
+                ),
+                Ok(stats) => (
+                    messages.get(
+                        "presence_active",
+                        &[
+                            ("grants", &self.faucet.grants_today().await),
+                            ("queue_length", &stats.queue_length),
+                        ],
+                    ),
+                    OnlineStatus::Online,
+                ),
+                Err(err) => {
+                    tracing::warn!("Failed to get the faucet status for the presence: {err:#}");
+                    (
+                        messages.get("presence_unavailable", &[]),
+                        OnlineStatus::Idle,
+                    )
+                }
+            };
+            // Use the latest context of the shard, in case the client was restarted.
+            if let Some(ctx) = self.shard_contexts.read().await.get(&shard) {
+                ctx.set_presence(Some(Activity::watching(activity)), status)
+                    .await;
+            }
+            sleep(PRESENCE_INTERVAL).await;
+        }
+    }
+
+    /// Post alerts about `faucet` to the operators in `channel`.
+    async fn post_alerts(self, ctx: Context, channel: ChannelId, faucet: Faucet) {
+        // Alerts are for the operators, so they are posted in the default locale.
+        let messages = self.messages(None);
+
+        // Dead letters are reported as they happen, once per request rather than for every retry.
+        let mut events = faucet.events().subscribe().await;
+        spawn({
+            let ctx = ctx.clone();
+            let messages = messages.clone();
+            let faucet = faucet.clone();
+            async move {
+                while let Some(event) = events.next().await {
+                    if let FaucetEvent::TransferFailed {
+                        request,
+                        reason,
+                        retried: false,
+                        ..
+                    } = event
+                    {
+                        let alert = Alert::DeadLetter { request, reason };
+                        post_alert(&ctx, channel, &messages, &faucet, alert).await;
+                    }
+                }
+            }
+        });
+
+        let mut alerts = Alerts::new(faucet.config());
+        loop {
+            alerts.configure(&self.live_options.get());
+            let stats = faucet.stats().await.map_err(|err| format!("{err:#}"));
+            for alert in alerts.check(stats.as_ref().map_err(Clone::clone)) {
+                post_alert(&ctx, channel, &messages, &faucet, alert).await;
+            }
+            sleep(ALERT_INTERVAL).await;
+        }
+    }
+
+    /// Post a summary of the activity of `faucet` in `channel` every summary interval.
+    async fn post_summaries(self, ctx: Context, channel: ChannelId, faucet: Faucet) {
+        // Summaries are shared by all guilds, so they are posted in the default locale.
+        let messages = self.messages(None);
+        let network = network_name(&messages, &faucet);
+        let interval = faucet.config().discord_summary_interval;
+
+        let summary = Arc::new(RwLock::new(Summary::default()));
+        let mut events = faucet.events().subscribe().await;
+        spawn({
+            let summary = summary.clone();
+            async move {
+                while let Some(event) = events.next().await {
+                    summary.write().await.record(&event);
+                }
+            }
+        });
+
+        loop {
+            sleep(interval).await;
+            let summary = std::mem::take(&mut *summary.write().await);
+            let balance = faucet.stats().await.ok().map(|stats| stats.total_balance);
+            let runway = match balance.and_then(|balance| summary.runway(balance, interval)) {
+                Some(runway) => messages.get(
+                    "summary_runway",
+                    &[(
+                        "days",
+                        &format!("{:.1}", runway.as_secs_f64() / (24. * 3600.)),
+                    )],
+                ),
+                None => messages.get("summary_runway_unknown", &[]),
+            };
+            let balance = match balance {
+                Some(balance) => format_amount(balance),
+                None => messages.get("summary_balance_unknown", &[]),
+            };
+            let post = messages.get(
+                "summary",
+                &[
+                    ("network", &network),
+                    ("grants", &summary.grants),
+                    ("recipients", &summary.recipients.len()),
+                    ("value", &format_amount(summary.value)),
+                    ("failures", &summary.failures),
+                    ("balance", &balance),
+                    ("runway", &runway),
+                ],
+            );
+            if let Err(err) = channel.say(&ctx.http, post).await {
+                tracing::error!("Cannot post summary: {}", err);
+            }
+        }
+    }
+
+    /// Post every grant of `faucet` in `channel`.
+    async fn post_audit_log(self, ctx: Context, channel: ChannelId, faucet: Faucet) {
+        // The audit log is shared by all guilds, so it is posted in the default locale.
+        let messages = self.messages(None);
+        let network = network_name(&messages, &faucet);
+        let mut events = faucet.events().subscribe().await;
+        while let Some(event) = events.next().await {
+            let (request, tx_hash) = match event {
+                FaucetEvent::TransferConfirmed {
+                    request, tx_hash, ..
+                } => (request, tx_hash),
+                // Requests which are given up on or cancelled are never posted.
+                FaucetEvent::TransferFailed {
+                    request,
+                    retried: false,
+                    ..
+                }
+                | FaucetEvent::RequestCancelled { request } => {
+                    if let Some(id) = request.id() {
+                        self.discord_requesters.write().await.remove(&id);
+                    }
+                    continue;
+                }
+                _ => continue,
+            };
+            let (id, amount) = match request {
+                TransferRequest::Faucet { id, amount, .. }
+                | TransferRequest::Deposit { id, amount, .. } => (id, format_amount(amount)),
+                TransferRequest::Erc20 {
+                    id, token, amount, ..
+                } => match faucet.tokens().by_address(token) {
+                    Some(token) => (id, format_token_amount(amount, token)),
+                    None => (id, format!("{amount} of {token:?}")),
+                },
+                TransferRequest::Nft { id, contract, .. } => {
+                    (id, format!("an NFT of {contract:?}"))
+                }
+                TransferRequest::Erc1155 {
+                    id,
+                    token,
+                    token_id,
+                    amount,
+                    ..
+                } => match faucet.tokens().erc1155(token, token_id) {
+                    Some(token) => (id, format_token_amount(amount, token)),
+                    None => (id, format!("{amount} of token {token_id} of {token:?}")),
+                },
+                // Funding transfers between the faucet wallets and relays are not grants.
+                TransferRequest::Funding { .. } | TransferRequest::Relay { .. } => continue,
+            };
+            let source = match self.discord_requesters.write().await.remove(&id) {
+                Some(user) => {
+                    messages.get("audit_source_discord", &[("user", &format!("<@{user}>"))])
+                }
+                None => messages.get("audit_source_api", &[]),
+            };
+            let post = messages.get(
+                "audit_grant",
+                &[
+                    ("amount", &amount),
+                    ("address", &format!("{:?}", request.to())),
+                    ("network", &network),
+                    ("source", &source),
+                    ("transaction", &explorer_link(&faucet, tx_hash)),
+                ],
+            );
+            if let Err(err) = channel
+                .send_message(&ctx.http, |message| {
+                    // Mention the requesters without notifying them.
+                    message
+                        .content(post)
+                        .allowed_mentions(|mentions| mentions.empty_parse())
+                })
+                .await
+            {
+                tracing::error!("Cannot post grant {id} in the audit log: {}", err);
+            }
+        }
+    }
+
+    /// Thank the Discord users who send funds back to the return address of `faucet`.
+    ///
+    /// Users are recognized by the last address they requested funds for or registered.
+    async fn thank_returns(self, ctx: Context, faucet: Faucet) {
+        // Users are thanked by direct message, outside of any guild, so in the default locale.
+        let messages = self.messages(None);
+        let network = network_name(&messages, &faucet);
+        let mut events = faucet.events().subscribe().await;
+        while let Some(event) = events.next().await {
+            let FaucetEvent::FundsReturned {
+                from,
+                amount,
+                tx_hash,
+            } = event
+            else {
+                continue;
+            };
+            let users = self
+                .discord_addresses
+                .read()
+                .await
+                .iter()
+                .filter(|(_, address)| **address == from)
+                .map(|(user, _)| UserId(*user))
+                .collect::<Vec<_>>();
+            let thanks = messages.get(
+                "return_thanks",
+                &[
+                    ("amount", &format_amount(amount)),
+                    ("network", &network),
+                    ("transaction", &explorer_link(&faucet, tx_hash)),
+                ],
+            );
+            for user in users {
+                let result = match user.create_dm_channel(&ctx.http).await {
+                    Ok(channel) => channel.say(&ctx.http, &thanks).await.map(|_| ()),
+                    Err(err) => Err(err),
+                };
+                if let Err(err) = result {
+                    tracing::warn!("Cannot thank {user} for returning funds: {err}");
+                }
+            }
+        }
+    }
+
+    /// Serve a request for funds sent in a message by a member of `guild`.
+    ///
+    /// The message is either posted in a channel of `guild`, or sent to the bot directly. The reply
+    /// is sent in the same channel.
+    async fn message_request(&self, ctx: Context, msg: Message, guild: u64) {
+        let settings = self.guilds.get(guild).await;
+        let messages = self.messages(settings.as_ref());
+        let result = match ctx.http.get_member(guild, msg.author.id.0).await {
+            Ok(member) => match self.check_role(&messages, &msg.author, Some(&member)).await {
+                Ok(()) => {
+                    let requester = Requester {
+                        user: &msg.author,
+                        member: Some(&member),
+                        guild: Some(guild),
+                        correlation_id: msg.id.0.into(),
+                    };
+                    self.handle_faucet_request(
+                        &messages,
+                        settings.as_ref(),
+                        requester,
+                        &msg.content,
+                        None,
+                        false,
+                    )
+                    .instrument(requester.span())
+                    .await
+                }
+                Err(message) => Err(message),
+            },
+            Err(err) => {
+                tracing::info!("Cannot find {} in guild {guild}: {err}", msg.author.tag());
+                Err(messages.get("not_a_member", &[]))
+            }
+        };
+        let embeds = match &result {
+            Ok(grants) => grants.embeds(),
+            Err(message) => vec![error_embed(&messages, message)],
+        };
+
+        match msg
+            .channel_id
+            .send_message(&ctx.http, |message| message.set_embeds(embeds))
+            .await
+        {
+            Ok(reply) => {
+                if let Ok(grants) = result {
+                    spawn(grants.report_completion(ctx, Reply::Message(reply)));
+                }
+            }
+            Err(err) => tracing::error!("Cannot reply to message: {}", err),
+        }
+    }
+
+    /// Respond to a `/faucet` command and update the reply once the grant is confirmed.
+    ///
+    /// This serves both `/faucet` and `/faucet-token`, which requests an ERC-20 token instead of
+    /// the native currency.
+    async fn faucet_command(
+        &self,
+        ctx: Context,
+        command: ApplicationCommandInteraction,
+        messages: &Messages,
+        settings: Option<&GuildSettings>,
+    ) {
+        let member = command.member.as_ref();
+        if let Err(message) = self.check_role(messages, &command.user, member).await {
+            reply_privately(&ctx, &command, message).await;
+            return;
+        }
+
+        let option = |name| {
+            let option = command
+                .data
+                .options
+                .iter()
+                .find(|option| option.name == name)?;
+            match option.resolved.as_ref()? {
+                CommandDataOptionValue::String(value) => Some(value),
+                _ => unreachable!(),
+            }
+        };
+        let token = match option("token") {
+            Some(symbol) => {
+                let (_, faucet) = self.chain(settings);
+                match faucet.tokens().get(symbol) {
+                    Some(token) => Some(token.clone()),
+                    None => {
+                        let tokens = faucet.tokens().symbols().join(", ");
+                        let reply = messages
+                            .get("unknown_token", &[("token", symbol), ("tokens", &tokens)]);
+                        reply_privately(&ctx, &command, reply).await;
+                        return;
+                    }
+                }
+            }
+            None => None,
+        };
+        // With registration, the registered address is used when none is given.
+        let input = match option("address") {
+            Some(input) => input.as_str(),
+            None if self.faucet.config().discord_require_registration => "",
+            None => {
+                open_address_modal(messages, &ctx, &command).await;
+                return;
+            }
+        };
+        let requester = Requester {
+            user: &command.user,
+            member,
+            guild: command.guild_id.map(|guild| guild.0),
+            correlation_id: command.id.0.into(),
+        };
+        let result = self
+            .handle_faucet_request(
+                messages,
+                settings,
+                requester,
+                input,
+                token.as_ref(),
+                command.data.name == "faucet-both",
+            )
+            .instrument(requester.span())
+            .await;
+        let embeds = match &result {
+            Ok(grants) => grants.embeds(),
+            Err(message) => vec![error_embed(messages, message)],
+        };
+
+        if !reply_with_embeds(&ctx, &command, embeds).await {
+            return;
+        }
+        if let Ok(grants) = result {
+            spawn(grants.report_completion(ctx, Reply::Command(command)));
+        }
+    }
+
+    /// Suggest the configured tokens matching what the user typed in `/faucet-token`.
+    async fn complete_token(&self, ctx: Context, autocomplete: AutocompleteInteraction) {
+        let settings = match autocomplete.guild_id {
+            Some(guild) => self.guilds.get(guild.0).await,
+            None => None,
+        };
+        let (_, faucet) = self.chain(settings.as_ref());
+        let typed = autocomplete
+            .data
+            .options
+            .iter()
+            .find(|option| option.focused)
+            .and_then(|option| option.value.as_ref())
+            .and_then(|value| value.as_str())
+            .unwrap_or_default()
+            .to_lowercase();
+        let symbols = faucet
+            .tokens()
+            .symbols()
+            .into_iter()
+            .filter(|symbol| symbol.to_lowercase().starts_with(&typed))
+            // Discord shows at most 25 choices.
+            .take(25);
+        if let Err(why) = autocomplete
+            .create_autocomplete_response(&ctx.http, |response| {
+                for symbol in symbols {
+                    response.add_string_choice(&symbol, &symbol);
+                }
+                response
+            })
+            .await
+        {
+            tracing::error!("Cannot suggest tokens: {}", why);
+        }
+    }
+
+    /// Serve the address entered in the modal opened by [`open_address_modal`], on both layers if
+    /// `dual`.
+    async fn address_modal_submit(&self, ctx: Context, modal: ModalSubmitInteraction, dual: bool) {
+        let input = modal
+            .data
+            .components
+            .iter()
+            .flat_map(|row| &row.components)
+            .find_map(|component| match component {
+                ActionRowComponent::InputText(input) if input.custom_id == "address" => {
+                    Some(input.value.clone())
+                }
+                _ => None,
+            })
+            .unwrap_or_default();
+        let settings = match modal.guild_id {
+            Some(guild) => self.guilds.get(guild.0).await,
+            None => None,
+        };
+        let messages = self.messages(settings.as_ref());
+        let member = modal.member.as_ref();
+        let result = match self.check_role(&messages, &modal.user, member).await {
+            Ok(()) => {
+                let requester = Requester {
+                    user: &modal.user,
+                    member,
+                    guild: modal.guild_id.map(|guild| guild.0),
+                    correlation_id: modal.id.0.into(),
+                };
+                self.handle_faucet_request(
+                    &messages,
+                    settings.as_ref(),
+                    requester,
+                    &input,
+                    None,
+                    dual,
+                )
+                .instrument(requester.span())
+                .await
+            }
+            Err(message) => Err(message),
+        };
+
+        // Invalid addresses are only reported to the user, who can try again.
+        let (embeds, ephemeral) = match &result {
+            Ok(grants) => (grants.embeds(), false),
+            Err(message) => (vec![error_embed(&messages, message)], true),
+        };
+        if let Err(why) = modal
+            .create_interaction_response(&ctx.http, |response| {
+                response
+                    .kind(InteractionResponseType::ChannelMessageWithSource)
+                    .interaction_response_data(|message| {
+                        message.set_embeds(embeds).ephemeral(ephemeral)
+                    })
+            })
+            .await
+        {
+            tracing::error!("Cannot respond to modal submission: {}", why);
+            return;
+        }
+        if let Ok(grants) = result {
+            spawn(grants.report_completion(ctx, Reply::Modal(modal)));
+        }
+    }
 }
 
 #[async_trait]
 impl EventHandler for WebState {
     async fn interaction_create(&self, ctx: Context, interaction: Interaction) {
-        if let Interaction::ApplicationCommand(command) = interaction {
+        if let Interaction::ModalSubmit(modal) = interaction {
+            match modal.data.custom_id.as_str() {
+                "faucet-address" => self.address_modal_submit(ctx, modal, false).await,
+                "faucet-both-address" => self.address_modal_submit(ctx, modal, true).await,
+                _ => {}
+            }
+        } else if let Interaction::Autocomplete(autocomplete) = interaction {
+            if autocomplete.data.name == "faucet-token" {
+                self.complete_token(ctx, autocomplete).await;
+            }
+        } else if let Interaction::ApplicationCommand(command) = interaction {
             tracing::info!("Received command interaction: {:#?}", command);
+            self.discord_metrics.command(&command.data.name).await;
 
-            let content = match command.data.name.as_str() {
-                "faucet" => self.handle_faucet_request(&command.data.options).await,
-                _ => "not implemented".to_string(),
+            let settings = match command.guild_id {
+                Some(guild) => self.guilds.get(guild.0).await,
+                None => None,
             };
+            let messages = self.messages(settings.as_ref());
 
-            if let Err(why) = command
-                .create_interaction_response(&ctx.http, |response| {
-                    response
-                        .kind(InteractionResponseType::ChannelMessageWithSource)
-                        .interaction_response_data(|message| message.content(content))
-                })
-                .await
+            // Admin commands are not restricted to the faucet channels.
+            if command.data.name == "faucet-admin" {
+                let reply = self
+                    .handle_admin_command(&messages, &command)
+                    .await
+                    .unwrap_or_else(|err| err);
+                reply_privately(&ctx, &command, reply).await;
+                return;
+            }
+
+            if let Err(message) =
+                self.check_channel(&messages, settings.as_ref(), command.channel_id)
             {
-                tracing::error!("Cannot respond to slash command: {}", why);
+                reply_privately(&ctx, &command, message).await;
+                return;
+            }
+
+            match command.data.name.as_str() {
+                "faucet" | "faucet-token" | "faucet-both" => {
+                    self.faucet_command(ctx, command, &messages, settings.as_ref())
+                        .await
+                }
+                "balance" => {
+                    let reply = self
+                        .handle_balance_command(
+                            &messages,
+                            settings.as_ref(),
+                            command.user.id,
+                            &command.data.options,
+                        )
+                        .await
+                        .unwrap_or_else(|err| err);
+                    reply_privately(&ctx, &command, reply).await;
+                }
+                "register" => {
+                    let reply = self
+                        .handle_register_command(
+                            &messages,
+                            settings.as_ref(),
+                            command.user.id,
+                            &command.data.options,
+                        )
+                        .await
+                        .unwrap_or_else(|err| err);
+                    reply_privately(&ctx, &command, reply).await;
+                }
+                "cooldown" => {
+                    let reply = self
+                        .handle_cooldown_command(
+                            &messages,
+                            settings.as_ref(),
+                            command.user.id,
+                            &command.data.options,
+                        )
+                        .await
+                        .unwrap_or_else(|err| err);
+                    reply_privately(&ctx, &command, reply).await;
+                }
+                "faucet-web-token" => {
+                    let reply = self
+                        .handle_web_token_command(
+                            &messages,
+                            command.guild_id.map(|guild| guild.0),
+                            &command.user,
+                            command.member.as_ref(),
+                        )
+                        .await
+                        .unwrap_or_else(|err| err);
+                    reply_privately(&ctx, &command, reply).await;
+                }
+                "faucet-status" => match self
+                    .handle_status_command(&messages, settings.as_ref())
+                    .await
+                {
+                    Ok(embed) => {
+                        reply_with_embeds(&ctx, &command, vec![embed]).await;
+                    }
+                    Err(message) => reply_privately(&ctx, &command, message).await,
+                },
+                "leaderboard" => {
+                    let embed = self.handle_leaderboard_command(&messages).await;
+                    reply_with_embeds(&ctx, &command, vec![embed]).await;
+                }
+                _ => reply_privately(&ctx, &command, messages.get("unknown_command", &[])).await,
+            }
+        }
+    }
+
+    async fn shard_stage_update(&self, _ctx: Context, event: ShardStageUpdateEvent) {
+        tracing::info!(
+            "Discord shard {} is {} (was {})",
+            event.shard_id.0,
+            event.new,
+            event.old
+        );
+        if let Some(gateway) = &self.gateway {
+            gateway
+                .set_connected(event.shard_id.0, event.new == ConnectionStage::Connected)
+                .await;
+        }
+    }
+
+    // Edited messages are not handled, so that editing a message cannot request funds again.
+    async fn message(&self, ctx: Context, msg: Message) {
+        if let Some(reason) = ignore_reason(&msg) {
+            tracing::debug!("Ignoring message {} {reason}", msg.id);
+            return;
+        }
+        match msg.guild_id {
+            // Channel messages are requests if they match the trigger of the guild.
+            Some(guild) => {
+                let Some(settings) = self.guilds.get(guild.0).await else {
+                    return;
+                };
+                if !settings.matcher.is_request(&msg.content) {
+                    return;
+                }
+                let messages = self.messages(Some(&settings));
+                // Other messages in the wrong channels are not answered, to avoid noise.
+                if self
+                    .check_channel(&messages, Some(&settings), msg.channel_id)
+                    .is_err()
+                {
+                    return;
+                }
+                tracing::info!("Received request from {} in a channel", msg.author.tag());
+                self.message_request(ctx, msg, guild.0).await;
+            }
+            // Any direct message is a request.
+            None => {
+                let Some(guild) = self.faucet.config().discord_dm_guild else {
+                    return;
+                };
+                tracing::info!("Received direct message from {}", msg.author.tag());
+                self.message_request(ctx, msg, guild).await;
             }
         }
     }
@@ -99,10 +2179,93 @@ impl EventHandler for WebState {
     async fn ready(&self, ctx: Context, ready: Ready) {
         tracing::info!("{} is connected!", ready.user.name);
 
+        if let Some(gateway) = &self.gateway {
+            gateway.set_connected(ctx.shard_id, true).await;
+        }
+        // The ready event is sent again after reconnecting, when the presence is already updated.
+        // Each shard has its own presence.
+        if self
+            .shard_contexts
+            .write()
+            .await
+            .insert(ctx.shard_id, ctx.clone())
+            .is_none()
+        {
+            spawn(self.clone().update_presence(ctx.shard_id));
+        }
+        // Only one shard posts the alerts, even if the shards are run by different instances.
+        if ctx.shard_id == 0 && !self.posting_started.swap(true, Ordering::SeqCst) {
+            let config = self.faucet.config();
+            for faucet in self.faucets() {
+                if let Some(channel) = config.discord_alert_channel_id {
+                    spawn(self.clone().post_alerts(
+                        ctx.clone(),
+                        ChannelId(channel),
+                        faucet.clone(),
+                    ));
+                }
+                if let Some(channel) = config.discord_audit_channel_id {
+                    spawn(self.clone().post_audit_log(
+                        ctx.clone(),
+                        ChannelId(channel),
+                        faucet.clone(),
+                    ));
+                }
+                if config.discord_thank_returns && config.return_address.is_some() {
+                    spawn(self.clone().thank_returns(ctx.clone(), faucet.clone()));
+                }
+                if let Some(channel) = config.discord_summary_channel_id {
+                    spawn(self.clone().post_summaries(
+                        ctx.clone(),
+                        ChannelId(channel),
+                        faucet.clone(),
+                    ));
+                }
+            }
+        }
+
         Command::create_global_application_command(&ctx.http, |command| {
             command
                 .name("faucet")
                 .description("Request funds from the faucet")
+                .create_option(|option| {
+                    option
+                        .name("address")
+                        .description("Your ethereum address, or leave empty to enter it in a form")
+                        .kind(CommandOptionType::String)
+                        .required(false)
+                })
+        })
+        .await
+        .expect("Command creation succeeds");
+
+        Command::create_global_application_command(&ctx.http, |command| {
+            command
+                .name("faucet-both")
+                .description("Request funds on both the L1 and the rollup")
+                .create_option(|option| {
+                    option
+                        .name("address")
+                        .description("Your ethereum address, or leave empty to enter it in a form")
+                        .kind(CommandOptionType::String)
+                        .required(false)
+                })
+        })
+        .await
+        .expect("Command creation succeeds");
+
+        Command::create_global_application_command(&ctx.http, |command| {
+            command
+                .name("faucet-token")
+                .description("Request an ERC-20 token from the faucet")
+                .create_option(|option| {
+                    option
+                        .name("token")
+                        .description("The symbol of the token")
+                        .kind(CommandOptionType::String)
+                        .required(true)
+                        .set_autocomplete(true)
+                })
                 .create_option(|option| {
                     option
                         .name("address")
@@ -113,49 +2276,216 @@ impl EventHandler for WebState {
         })
         .await
         .expect("Command creation succeeds");
-    }
-}
 
-#[async_std::main]
-pub async fn main() -> io::Result<()> {
-    // Configure the client with your Discord bot token in the environment.
-    setup_logging();
-    setup_backtrace();
+        Command::create_global_application_command(&ctx.http, |command| {
+            command
+                .name("balance")
+                .description("Show the balance of an address")
+                .create_option(|option| {
+                    option
+                        .name("address")
+                        .description("The address, by default the last one you requested funds to")
+                        .kind(CommandOptionType::String)
+                        .required(false)
+                })
+        })
+        .await
+        .expect("Command creation succeeds");
+
+        Command::create_global_application_command(&ctx.http, |command| {
+            command
+                .name("cooldown")
+                .description("Show how long until you can request funds again")
+                .create_option(|option| {
+                    option
+                        .name("address")
+                        .description("Also show when this address can receive funds again")
+                        .kind(CommandOptionType::String)
+                        .required(false)
+                })
+        })
+        .await
+        .expect("Command creation succeeds");
+
+        Command::create_global_application_command(&ctx.http, |command| {
+            command
+                .name("faucet-web-token")
+                .description("Get a token to request funds on the website as your Discord user")
+        })
+        .await
+        .expect("Command creation succeeds");
 
-    let opts = Options::parse();
+        Command::create_global_application_command(&ctx.http, |command| {
+            command
+                .name("leaderboard")
+                .description("Show who returned and requested the most funds recently")
+        })
+        .await
+        .expect("Command creation succeeds");
 
-    // Create a new instance of the Client, logging in as a bot. This will
-    // automatically prepend your bot token with "Bot ", which is a requirement
-    // by Discord for bot users.
-    let (sender, receiver) = async_std::channel::unbounded();
-    let state = WebState::new(sender);
-    let faucet = Faucet::create(opts.clone(), receiver)
+        Command::create_global_application_command(&ctx.http, |command| {
+            command
+                .name("faucet-status")
+                .description("Show the queue and health of the faucet")
+        })
         .await
-        .expect("Failed to create faucet");
-
-    // Do not attempt to start the discord bot if the token is missing or empty.
-    let discord_client = if let Some(token) = opts.discord_token.filter(|token| !token.is_empty()) {
-        // Set gateway intents, which decides what events the bot will be notified about
-        let intents = GatewayIntents::GUILD_MESSAGES
-            | GatewayIntents::DIRECT_MESSAGES
-            | GatewayIntents::MESSAGE_CONTENT;
-        let client = Client::builder(token, intents)
-            .event_handler(state.clone())
+        .expect("Command creation succeeds");
+
+        if self.faucet.config().discord_require_registration {
+            Command::create_global_application_command(&ctx.http, |command| {
+                command
+                    .name("register")
+                    .description("Register the address the faucet sends your funds to")
+                    .create_option(|option| {
+                        option
+                            .name("address")
+                            .description("Your ethereum address")
+                            .kind(CommandOptionType::String)
+                            .required(true)
+                    })
+            })
             .await
-            .expect("Err creating discord client");
-        Some(client)
-    } else {
-        tracing::warn!("Discord bot disabled. For local testing this is fine.");
-        None
-    };
+            .expect("Command creation succeeds");
+        }
 
-    let faucet_handle = spawn(faucet.start());
-    let api_handle = spawn(serve(opts.port, state));
+        if self.faucet.config().discord_admin_role.is_some() {
+            Command::create_global_application_command(&ctx.http, |command| {
+                command
+                    .name("faucet-admin")
+                    .description("Manage the faucet")
+                    .create_option(|option| {
+                        option
+                            .name("pause")
+                            .description("Stop accepting requests")
+                            .kind(CommandOptionType::SubCommand)
+                    })
+                    .create_option(|option| {
+                        option
+                            .name("resume")
+                            .description("Accept requests again")
+                            .kind(CommandOptionType::SubCommand)
+                    })
+                    .create_option(|option| {
+                        option
+                            .name("balance")
+                            .description("Show the balance of the faucet wallets")
+                            .kind(CommandOptionType::SubCommand)
+                    })
+                    .create_option(|option| {
+                        option
+                            .name("set-amount")
+                            .description("Change the amount granted per request")
+                            .kind(CommandOptionType::SubCommand)
+                            .create_sub_option(|option| {
+                                option
+                                    .name("amount")
+                                    .description("The amount in ethers")
+                                    .kind(CommandOptionType::String)
+                                    .required(true)
+                            })
+                    })
+                    .create_option(|option| {
+                        option
+                            .name("ban")
+                            .description("Ban a user from the faucet")
+                            .kind(CommandOptionType::SubCommand)
+                            .create_sub_option(|option| {
+                                option
+                                    .name("user")
+                                    .description("The user to ban")
+                                    .kind(CommandOptionType::User)
+                                    .required(true)
+                            })
+                            .create_sub_option(|option| {
+                                option
+                                    .name("reason")
+                                    .description("The reason shown to the user")
+                                    .kind(CommandOptionType::String)
+                                    .required(false)
+                            })
+                    })
+                    .create_option(|option| {
+                        option
+                            .name("unban")
+                            .description("Lift the ban of a user")
+                            .kind(CommandOptionType::SubCommand)
+                            .create_sub_option(|option| {
+                                option
+                                    .name("user")
+                                    .description("The user to unban")
+                                    .kind(CommandOptionType::User)
+                                    .required(true)
+                            })
+                    })
+            })
+            .await
+            .expect("Command creation succeeds");
+        }
+    }
+}
 
-    if let Some(mut discord) = discord_client {
-        let _result = futures::join!(faucet_handle, api_handle, discord.start());
-    } else {
-        let _result = futures::join!(faucet_handle, api_handle);
+/// Run the gateway shards of the bot assigned to this instance.
+async fn start_shards(client: &mut Client, opts: &Options) -> serenity::Result<()> {
+    let Some(count) = opts.discord_shard_count else {
+        return client.start().await;
     };
-    Ok(())
+    let ids = &opts.discord_shard_ids;
+    if ids.is_empty() {
+        return client.start_shards(count).await;
+    }
+    if let Err(err) = opts.check_discord_shards() {
+        tracing::error!("Invalid Discord shards: {err:#}");
+        return Err(serenity::Error::Other("invalid Discord shards"));
+    }
+    let (first, last) = (ids.iter().min().unwrap(), ids.iter().max().unwrap());
+    tracing::info!("Running Discord shards {first} to {last} of {count}");
+    client.start_shard_range([*first, *last], count).await
+}
+
+/// Run the Discord bot, restarting the client with exponential backoff whenever it fails.
+pub(crate) async fn run_discord(token: String, state: WebState, opts: &Options) {
+    // Set gateway intents, which decides what events the bot will be notified about
+    let intents = GatewayIntents::GUILD_MESSAGES
+        | GatewayIntents::DIRECT_MESSAGES
+        | GatewayIntents::MESSAGE_CONTENT;
+    let mut delay = MIN_RESTART_DELAY;
+    loop {
+        let started = Instant::now();
+        match Client::builder(&token, intents)
+            .event_handler(state.clone())
+            .await
+        {
+            Ok(mut client) => {
+                match start_shards(&mut client, opts).await {
+                    Ok(()) => tracing::error!("Discord client stopped"),
+                    Err(err) => tracing::error!("Discord client failed: {err}"),
+                }
+                client.shard_manager.lock().await.shutdown_all().await;
+            }
+            Err(err) => tracing::error!("Failed to create Discord client: {err}"),
+        }
+        if let Some(gateway) = &state.gateway {
+            gateway.reconnecting().await;
+        }
+        // A client which ran for a while failed for a new reason, so it is restarted quickly.
+        if started.elapsed() > MAX_RESTART_DELAY {
+            delay = MIN_RESTART_DELAY;
+        }
+        tracing::info!("Restarting the Discord client in {delay:?}");
+        sleep(delay).await;
+        delay = (delay * 2).min(MAX_RESTART_DELAY);
+    }
+}
+
+/// Exit the process if the Discord bot stays disconnected for `max`, so it can be restarted.
+pub(crate) async fn exit_when_disconnected(gateway: Gateway, max: Duration) {
+    loop {
+        sleep(DISCONNECTION_CHECK_INTERVAL).await;
+        if let Some(disconnected) = gateway.disconnected_for().await {
+            if disconnected >= max {
+                tracing::error!("Discord bot disconnected for {disconnected:?}, exiting");
+                std::process::exit(1);
+            }
+        }
+    }
 }
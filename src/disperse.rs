@@ -0,0 +1,54 @@
+// Copyright (c) 2023 Espresso Systems (espressosys.com)
+// This file is part of the Discord Faucet library.
+//
+// You should have received a copy of the MIT License
+// along with the Discord Faucet library. If not, see <https://mit-license.org/>.
+
+//! Paying several grants with a single transaction.
+//!
+//! With `--disperse-contract`, the grants of the native currency queued together are merged into a
+//! single call of `disperseEther` on a [Disperse](https://disperse.app) contract, which forwards
+//! each recipient its share of the value of the transaction. This saves a transaction and a wallet
+//! per grant when requests arrive in bursts, at the cost of the grants of a batch succeeding or
+//! failing together. The grants of a batch which reverts, e.g. because a recipient is a contract
+//! rejecting payments, are sent again on their own.
+use crate::{Middleware, TransferRequest};
+use ethers::{
+    contract::abigen,
+    types::{transaction::eip2718::TypedTransaction, Address, U256},
+};
+use std::sync::Arc;
+
+abigen!(
+    Disperse,
+    r#"[
+        function disperseEther(address[] recipients, uint256[] values) external payable
+    ]"#
+);
+
+/// Whether `transfer` can be paid in a batch with other grants.
+pub fn is_batchable(transfer: &TransferRequest) -> bool {
+    matches!(transfer, TransferRequest::Faucet { .. })
+}
+
+/// The transaction of `sender` paying all the `grants` through the contract `disperse`.
+pub fn disperse_tx(
+    disperse: Address,
+    sender: Arc<Middleware>,
+    grants: impl IntoIterator<Item = TransferRequest>,
+) -> TypedTransaction {
+    let (recipients, values): (Vec<_>, Vec<_>) = grants
+        .into_iter()
+        .filter_map(|grant| match grant {
+            TransferRequest::Faucet { to, amount, .. } => Some((to, amount)),
+            _ => None,
+        })
+        .unzip();
+    let total = values
+        .iter()
+        .fold(U256::zero(), |total, value| total + value);
+    Disperse::new(disperse, sender)
+        .disperse_ether(recipients, values)
+        .value(total)
+        .tx
+}
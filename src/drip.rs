@@ -0,0 +1,136 @@
+// Copyright (c) 2023 Espresso Systems (espressosys.com)
+// This file is part of the Discord Faucet library.
+//
+// You should have received a copy of the MIT License
+// along with the Discord Faucet library. If not, see <https://mit-license.org/>.
+
+//! Drip grants, paid in installments spread over time.
+//!
+//! A drip request is granted the same amount as any other request, but in `--drip-installments`
+//! transfers, `--drip-interval` apart. The first installment is queued like any other grant, and
+//! tracks the status of the request. The remaining installments are saved in the state snapshot, so
+//! that they survive restarts.
+use crate::{CorrelationId, RequestId};
+use ethers::types::{Address, U256};
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// The installments of a drip request which are still to be paid.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct Drip {
+    /// The drip request.
+    pub id: RequestId,
+    pub correlation_id: CorrelationId,
+    pub to: Address,
+    /// The amount of each remaining installment.
+    pub amount: U256,
+    /// The number of installments still to be paid.
+    pub remaining: usize,
+    /// When the next installment is due, in milliseconds since the Unix epoch.
+    pub next_at: u64,
+}
+
+impl Drip {
+    /// The installments following the first one of a drip request for `amount` in `installments`.
+    ///
+    /// Returns the amount of the first installment, which also pays the rounding remainder, and the
+    /// remaining installments, if any.
+    pub fn split(
+        id: RequestId,
+        correlation_id: CorrelationId,
+        to: Address,
+        amount: U256,
+        installments: usize,
+        interval: Duration,
+    ) -> (U256, Option<Self>) {
+        let installments = installments.max(1);
+        let each = amount / installments;
+        let first = amount - each * (installments - 1);
+        let rest = (installments > 1).then(|| Self {
+            id,
+            correlation_id,
+            to,
+            amount: each,
+            remaining: installments - 1,
+            next_at: now_millis() + interval.as_millis() as u64,
+        });
+        (first, rest)
+    }
+
+    /// Whether the next installment is due.
+    pub fn is_due(&self) -> bool {
+        self.next_at <= now_millis()
+    }
+
+    /// Account for the payment of the next installment.
+    ///
+    /// The following installment is due `interval` after this one is paid, rather than after it
+    /// was due, so that installments which fell overdue, e.g. while the faucet was down, are
+    /// rescheduled instead of paid in a burst. Returns `false` once all the installments have been
+    /// paid.
+    pub fn advance(&mut self, interval: Duration) -> bool {
+        self.remaining -= 1;
+        self.next_at = self.next_at.max(now_millis()) + interval.as_millis() as u64;
+        self.remaining > 0
+    }
+}
+
+/// The current time, in milliseconds since the Unix epoch.
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_drip_split() {
+        let interval = Duration::from_secs(3600);
+        let (first, rest) = Drip::split(
+            RequestId::random(),
+            CorrelationId::random(),
+            Address::random(),
+            10.into(),
+            3,
+            interval,
+        );
+        let mut rest = rest.unwrap();
+        assert_eq!(first, 4.into());
+        assert_eq!(rest.amount, 3.into());
+        assert_eq!(rest.remaining, 2);
+        assert!(!rest.is_due());
+
+        assert!(rest.advance(interval));
+        assert!(!rest.advance(interval));
+
+        // Overdue installments are rescheduled rather than paid in a burst.
+        let (_, rest) = Drip::split(
+            RequestId::random(),
+            CorrelationId::random(),
+            Address::random(),
+            10.into(),
+            3,
+            interval,
+        );
+        let mut rest = rest.unwrap();
+        rest.next_at = 0;
+        assert!(rest.is_due());
+        assert!(rest.advance(interval));
+        assert!(!rest.is_due());
+
+        let (first, rest) = Drip::split(
+            RequestId::random(),
+            CorrelationId::random(),
+            Address::random(),
+            10.into(),
+            1,
+            interval,
+        );
+        assert_eq!(first, 10.into());
+        assert_eq!(rest, None);
+    }
+}
@@ -0,0 +1,240 @@
+// Copyright (c) 2023 Espresso Systems (espressosys.com)
+// This file is part of the Discord Faucet library.
+//
+// You should have received a copy of the MIT License
+// along with the Discord Faucet library. If not, see <https://mit-license.org/>.
+
+//! Errors returned by the web API.
+//!
+//! Every error is serialized in the same envelope,
+//! `{"code": "COOLDOWN", "status": 429, "message": "...", "retry_after_secs": 3600}`, so clients
+//! can branch on `code` instead of parsing `message`.
+use crate::QuotaError;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use thiserror::Error;
+use tide_disco::{http::StatusCode, RequestError};
+
+/// A stable, machine-readable error code.
+///
+/// Codes are part of the API. New codes may be added, but existing codes must not be renamed or
+/// removed.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq, Hash)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ErrorCode {
+    /// The request is malformed.
+    BadRequest,
+    /// The recipient address is not a valid Ethereum address.
+    BadAddress,
+    /// The request ID is not a valid request ID.
+    BadRequestId,
+    /// The requested token is not configured.
+    UnknownToken,
+    /// The requested resource or feature does not exist.
+    NotFound,
+    /// The CAPTCHA token is missing or invalid.
+    CaptchaFailed,
+    /// The proof of work solution is missing or invalid.
+    ProofOfWorkFailed,
+    /// The proof of ownership of the recipient address is missing or invalid.
+    OwnershipProofFailed,
+    /// The recipient address was flagged by the Sybil heuristics.
+    Suspicious,
+    /// The requester is not logged in.
+    Unauthorized,
+    /// The requester must wait before requesting funds again.
+    Cooldown,
+    /// The API key has used up its quota.
+    QuotaExceeded,
+    /// The recipient address has received the most funds an address can receive.
+    LifetimeCapReached,
+    /// The faucet has too many pending requests.
+    QueueFull,
+    /// The faucet does not have enough funds to serve the request.
+    FaucetEmpty,
+    /// The faucet has been paused by an operator.
+    Paused,
+    /// The faucet or one of its dependencies is temporarily unavailable.
+    Unavailable,
+    /// An unexpected error.
+    Internal,
+}
+
+impl ErrorCode {
+    /// The code for a generic error with the HTTP status `status`.
+    fn from_status(status: StatusCode) -> Self {
+        match status {
+            StatusCode::BadRequest => Self::BadRequest,
+            StatusCode::Unauthorized => Self::Unauthorized,
+            StatusCode::NotFound => Self::NotFound,
+            StatusCode::TooManyRequests => Self::Cooldown,
+            StatusCode::ServiceUnavailable => Self::Unavailable,
+            _ => Self::Internal,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, Error, PartialEq, Eq)]
+#[error("{code:?} ({status}): {message}")]
+pub struct FaucetError {
+    pub code: ErrorCode,
+    pub status: StatusCode,
+    /// A human readable description of the error.
+    pub message: String,
+    /// How long to wait before retrying, for errors which are only temporary.
+    pub retry_after_secs: Option<u64>,
+}
+
+impl FaucetError {
+    pub fn new(code: ErrorCode, status: StatusCode, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            status,
+            message: message.into(),
+            retry_after_secs: None,
+        }
+    }
+
+    pub fn bad_address(input: &str) -> Self {
+        Self::new(
+            ErrorCode::BadAddress,
+            StatusCode::BadRequest,
+            format!("unable to parse Ethereum address: {input}"),
+        )
+    }
+
+    pub fn bad_request_id(input: &str) -> Self {
+        Self::new(
+            ErrorCode::BadRequestId,
+            StatusCode::BadRequest,
+            format!("unable to parse request ID: {input}"),
+        )
+    }
+
+    pub fn unknown_token(input: &str, available: &[String]) -> Self {
+        Self::new(
+            ErrorCode::UnknownToken,
+            StatusCode::NotFound,
+            format!(
+                "unknown token {input}, available tokens: {}",
+                available.join(", ")
+            ),
+        )
+    }
+
+    /// An error for a feature which is not enabled in this faucet.
+    pub fn not_enabled(feature: &str) -> Self {
+        Self::new(
+            ErrorCode::NotFound,
+            StatusCode::NotFound,
+            format!("{feature} is not enabled"),
+        )
+    }
+
+    pub fn unauthorized(message: impl Into<String>) -> Self {
+        Self::new(ErrorCode::Unauthorized, StatusCode::Unauthorized, message)
+    }
+
+    pub fn cooldown(remaining: Duration) -> Self {
+        // Round up, so that retrying after `retry_after_secs` always succeeds.
+        let secs = remaining.as_secs() + u64::from(remaining.subsec_nanos() > 0);
+        Self {
+            retry_after_secs: Some(secs),
+            ..Self::new(
+                ErrorCode::Cooldown,
+                StatusCode::TooManyRequests,
+                format!("too many requests, try again in {secs} seconds"),
+            )
+        }
+    }
+
+    /// An error for a quota shared by several requesters, such as a Discord guild, which is used
+    /// up for `remaining`.
+    pub fn quota_exhausted(message: impl Into<String>, remaining: Duration) -> Self {
+        Self {
+            retry_after_secs: Some(remaining.as_secs() + 1),
+            ..Self::new(
+                ErrorCode::QuotaExceeded,
+                StatusCode::TooManyRequests,
+                message,
+            )
+        }
+    }
+
+    /// An error for a temporary condition, such as too many outstanding challenges.
+    pub fn unavailable(message: impl Into<String>) -> Self {
+        Self::new(
+            ErrorCode::Unavailable,
+            StatusCode::ServiceUnavailable,
+            message,
+        )
+    }
+}
+
+impl tide_disco::Error for FaucetError {
+    fn catch_all(status: StatusCode, msg: String) -> Self {
+        Self::new(ErrorCode::from_status(status), status, msg)
+    }
+
+    fn status(&self) -> StatusCode {
+        self.status
+    }
+}
+
+impl From<QuotaError> for FaucetError {
+    fn from(err: QuotaError) -> Self {
+        match err {
+            QuotaError::UnknownKey => Self::unauthorized(err.to_string()),
+            QuotaError::RateExceeded { retry_after, .. } => Self {
+                retry_after_secs: Some(retry_after.as_secs() + 1),
+                ..Self::new(
+                    ErrorCode::QuotaExceeded,
+                    StatusCode::TooManyRequests,
+                    err.to_string(),
+                )
+            },
+            QuotaError::TotalExceeded { .. } => Self::new(
+                ErrorCode::QuotaExceeded,
+                StatusCode::TooManyRequests,
+                err.to_string(),
+            ),
+        }
+    }
+}
+
+impl From<RequestError> for FaucetError {
+    fn from(err: RequestError) -> Self {
+        Self::new(
+            ErrorCode::BadRequest,
+            StatusCode::BadRequest,
+            err.to_string(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_error_envelope() {
+        let err = FaucetError::cooldown(Duration::from_millis(1500));
+        assert_eq!(
+            serde_json::to_value(&err).unwrap(),
+            json!({
+                "code": "COOLDOWN",
+                "status": 429,
+                "message": "too many requests, try again in 2 seconds",
+                "retry_after_secs": 2,
+            })
+        );
+
+        let err = FaucetError::bad_address("0x1234");
+        assert_eq!(serde_json::to_value(&err).unwrap()["code"], "BAD_ADDRESS");
+        assert_eq!(
+            serde_json::to_value(&err).unwrap()["retry_after_secs"],
+            json!(null)
+        );
+    }
+}
@@ -0,0 +1,130 @@
+// Copyright (c) 2023 Espresso Systems (espressosys.com)
+// This file is part of the Discord Faucet library.
+//
+// You should have received a copy of the MIT License
+// along with the Discord Faucet library. If not, see <https://mit-license.org/>.
+
+//! Faucet activity events.
+//!
+//! The faucet publishes an event whenever a transfer moves through its lifecycle. Subscribers (for
+//! example the `events` endpoint of the web server) receive every event published after they
+//! subscribed.
+use crate::TransferRequest;
+use async_std::{
+    channel::{Receiver, Sender, TrySendError},
+    sync::RwLock,
+};
+use ethers::types::{Address, H256, U256, U64};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+/// Number of events buffered per subscriber before events are dropped for that subscriber.
+const EVENT_BUFFER_SIZE: usize = 1024;
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum FaucetEvent {
+    /// A transfer was added to the queue.
+    RequestQueued { request: TransferRequest },
+    /// A transfer was submitted to the RPC.
+    TransferSubmitted {
+        request: TransferRequest,
+        sender: Address,
+        tx_hash: H256,
+    },
+    /// A transfer was mined successfully.
+    TransferConfirmed {
+        request: TransferRequest,
+        tx_hash: H256,
+        block_number: Option<U64>,
+    },
+    /// A transfer failed.
+    TransferFailed {
+        request: TransferRequest,
+        tx_hash: Option<H256>,
+        reason: String,
+        /// Whether the transfer is sent again, rather than given up on.
+        retried: bool,
+    },
+    /// A faucet wallet received enough funds to serve requests.
+    WalletFunded { wallet: Address, balance: U256 },
+    /// A queued transfer was cancelled by the requester.
+    RequestCancelled { request: TransferRequest },
+    /// An address sent funds back to the return address.
+    FundsReturned {
+        from: Address,
+        amount: U256,
+        tx_hash: H256,
+    },
+}
+
+impl FaucetEvent {
+    /// The name of this kind of event, as in its `event` field.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::RequestQueued { .. } => "request_queued",
+            Self::TransferSubmitted { .. } => "transfer_submitted",
+            Self::TransferConfirmed { .. } => "transfer_confirmed",
+            Self::TransferFailed { .. } => "transfer_failed",
+            Self::WalletFunded { .. } => "wallet_funded",
+            Self::RequestCancelled { .. } => "request_cancelled",
+            Self::FundsReturned { .. } => "funds_returned",
+        }
+    }
+
+    /// The transfer this event is about, if any.
+    pub fn request(&self) -> Option<&TransferRequest> {
+        match self {
+            Self::RequestQueued { request }
+            | Self::TransferSubmitted { request, .. }
+            | Self::TransferConfirmed { request, .. }
+            | Self::TransferFailed { request, .. }
+            | Self::RequestCancelled { request } => Some(request),
+            Self::WalletFunded { .. } | Self::FundsReturned { .. } => None,
+        }
+    }
+
+    /// The transaction this event is about, if any.
+    pub fn tx_hash(&self) -> Option<H256> {
+        match self {
+            Self::TransferSubmitted { tx_hash, .. } | Self::TransferConfirmed { tx_hash, .. } => {
+                Some(*tx_hash)
+            }
+            Self::TransferFailed { tx_hash, .. } => *tx_hash,
+            Self::FundsReturned { tx_hash, .. } => Some(*tx_hash),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct EventBus {
+    subscribers: Arc<RwLock<Vec<Sender<FaucetEvent>>>>,
+}
+
+impl EventBus {
+    /// Subscribe to all events published from now on.
+    pub async fn subscribe(&self) -> Receiver<FaucetEvent> {
+        let (sender, receiver) = async_std::channel::bounded(EVENT_BUFFER_SIZE);
+        self.subscribers.write().await.push(sender);
+        receiver
+    }
+
+    /// Send an event to all subscribers.
+    ///
+    /// This never blocks: if a subscriber is not keeping up the event is dropped for that
+    /// subscriber only. Subscribers that have gone away are removed.
+    pub async fn publish(&self, event: FaucetEvent) {
+        tracing::debug!("Publishing event {event:?}");
+        self.subscribers.write().await.retain(|subscriber| {
+            match subscriber.try_send(event.clone()) {
+                Ok(()) => true,
+                Err(TrySendError::Full(_)) => {
+                    tracing::warn!("Event subscriber is lagging, dropping event");
+                    true
+                }
+                Err(TrySendError::Closed(_)) => false,
+            }
+        });
+    }
+}
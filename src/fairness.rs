@@ -0,0 +1,154 @@
+// Copyright (c) 2023 Espresso Systems (espressosys.com)
+// This file is part of the Discord Faucet library.
+//
+// You should have received a copy of the MIT License
+// along with the Discord Faucet library. If not, see <https://mit-license.org/>.
+
+//! Sharing the throughput of the faucet fairly between requesters.
+//!
+//! The queue of a faucet is ordered by the priority of the source of each request, with
+//! `--source-priority`, so that requests from operators or trusted users are served ahead of a
+//! flood of anonymous requests.
+//!
+//! Within a priority, the queue is served in rounds: a requester with `n` requests already queued
+//! joins round `n`, behind every request of the earlier rounds. The faucet keeps the number of
+//! requests queued by each requester as requests are queued and dequeued, and the queue stays
+//! sorted by priority and round, so a request is placed by scanning from the back of the queue only
+//! past the requests of later rounds, rather than by going over the whole queue. A burst of
+//! requests from one requester is thus interleaved with the requests of everyone else, as if each
+//! requester had a queue of its own and the queues were drained round-robin, while the faucet still
+//! serves a single queue, in which the position of a request is how many transfers are ahead of it.
+//!
+//! Transfers without a source or a requester, such as funding transfers or drip installments, have
+//! the highest priority and are all in the first round.
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, net::IpAddr};
+
+/// Who made a faucet request.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Requester {
+    /// A request from the web API, by the IP of the client.
+    Ip(IpAddr),
+    /// A request from Discord, or from the web API with a Discord token, by the Discord user ID.
+    Discord(u64),
+}
+
+/// Where a faucet request comes from, to prioritize some sources over others.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, ValueEnum, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RequestSource {
+    /// Requests made on behalf of the operators: top-ups registered by admins and self-tests.
+    Admin,
+    /// Discord members holding `--discord-verified-role`, including their web requests with a
+    /// Discord token and their top-ups.
+    DiscordVerified,
+    /// Other Discord members, likewise.
+    Discord,
+    /// The web and gRPC APIs.
+    Web,
+}
+
+/// What decides where a request is queued.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct QueueKey {
+    /// The rank of the source of the request in `--source-priority`, lower ranks first.
+    pub priority: usize,
+    pub requester: Option<Requester>,
+}
+
+/// The index at which to insert a request with `key` in a queue whose requests have the keys
+/// `queue`, in order, where `queued` is the number of requests of each requester in the queue.
+pub fn fair_position<I>(queue: I, queued: &HashMap<Requester, usize>, key: QueueKey) -> usize
+where
+    I: IntoIterator<Item = QueueKey>,
+    I::IntoIter: DoubleEndedIterator + ExactSizeIterator,
+{
+    let count = |requester| queued.get(&requester).copied().unwrap_or_default();
+    let round = key.requester.map_or(0, count);
+    // The round of a queued request is the number of requests of its requester ahead of it.
+    let mut behind = HashMap::<Requester, usize>::new();
+    let mut queue = queue.into_iter();
+    let mut position = queue.len();
+    while let Some(QueueKey {
+        priority,
+        requester,
+    }) = queue.next_back()
+    {
+        let slot = match requester {
+            Some(requester) => {
+                let behind = behind.entry(requester).or_default();
+                *behind += 1;
+                (priority, count(requester).saturating_sub(*behind))
+            }
+            None => (priority, 0),
+        };
+        if slot <= (key.priority, round) {
+            break;
+        }
+        position -= 1;
+    }
+    position
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn enqueue(queue: &mut Vec<Option<Requester>>, requester: Option<Requester>) {
+        let key = |requester| QueueKey {
+            priority: 0,
+            requester,
+        };
+        let mut queued = HashMap::new();
+        for requester in queue.iter().flatten() {
+            *queued.entry(*requester).or_default() += 1;
+        }
+        let position = fair_position(queue.iter().copied().map(key), &queued, key(requester));
+        queue.insert(position, requester);
+    }
+
+    #[test]
+    fn test_fair_position() {
+        let a = Some(Requester::Discord(1));
+        let b = Some(Requester::Ip("127.0.0.1".parse().unwrap()));
+        let c = Some(Requester::Discord(2));
+
+        // A burst from one requester is queued in order.
+        let mut queue = vec![];
+        for _ in 0..3 {
+            enqueue(&mut queue, a);
+        }
+        assert_eq!(queue, [a, a, a]);
+
+        // Other requesters are served between the requests of the burst.
+        enqueue(&mut queue, b);
+        assert_eq!(queue, [a, b, a, a]);
+        enqueue(&mut queue, c);
+        assert_eq!(queue, [a, b, c, a, a]);
+        enqueue(&mut queue, b);
+        assert_eq!(queue, [a, b, c, a, b, a]);
+
+        // Requests without a requester are in the first round.
+        enqueue(&mut queue, None);
+        assert_eq!(queue, [a, b, c, None, a, b, a]);
+    }
+
+    #[test]
+    fn test_fair_position_priority() {
+        let a = Some(Requester::Discord(1));
+        let b = Some(Requester::Discord(2));
+        let key = |priority, requester| QueueKey {
+            priority,
+            requester,
+        };
+        let queue = [key(1, a), key(2, b), key(2, a)];
+        let queued = [(a.unwrap(), 2), (b.unwrap(), 1)].into_iter().collect();
+
+        // Requests are queued behind those of higher priorities, whatever their round.
+        assert_eq!(fair_position(queue, &queued, key(1, a)), 1);
+        assert_eq!(fair_position(queue, &queued, key(0, b)), 0);
+        assert_eq!(fair_position(queue, &queued, key(2, b)), 3);
+    }
+}
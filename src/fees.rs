@@ -0,0 +1,117 @@
+// Copyright (c) 2023 Espresso Systems (espressosys.com)
+// This file is part of the Discord Faucet library.
+//
+// You should have received a copy of the MIT License
+// along with the Discord Faucet library. If not, see <https://mit-license.org/>.
+
+//! Components of transaction fees which the gas price of the chain does not account for.
+//!
+//! On rollups, every transaction also pays for publishing its data on the L1, which can be most of
+//! its cost. With `--fee-estimator`, the faucet periodically estimates this L1 data fee and reserves
+//! it on top of the funds each transfer requires, and of the balance a wallet needs to be funded.
+use crate::RpcClient;
+use anyhow::Result;
+use async_std::task::sleep;
+use clap::ValueEnum;
+use ethers::{
+    contract::abigen,
+    providers::Provider,
+    types::{Address, Bytes, U256},
+};
+use rand::Rng;
+use serde::Deserialize;
+use std::{sync::Arc, time::Duration};
+
+abigen!(
+    GasPriceOracle,
+    r#"[
+        function getL1Fee(bytes data) external view returns (uint256)
+    ]"#
+);
+
+/// The size of the largest unbatched transaction of the faucet.
+///
+/// This is larger than a signed token transfer or mint, so the estimate covers every transfer of
+/// the faucet.
+const L1_DATA_SIZE: usize = 256;
+
+/// The size each grant adds to a batch: an address and an amount, ABI-encoded.
+const BATCH_GRANT_SIZE: usize = 64;
+
+/// How many times the L1 data fee is estimated on startup before falling back to
+/// [`DEFAULT_L1_FEE`].
+const L1_FEE_ATTEMPTS: usize = 3;
+
+const L1_FEE_RETRY_DELAY: Duration = Duration::from_secs(2);
+
+/// The L1 data fee assumed on startup if it cannot be estimated, until it is estimated again.
+///
+/// 0.001 ETH is well above the L1 data fee of a faucet transaction on OP-stack chains, so that
+/// wallets are not underfunded meanwhile.
+pub const DEFAULT_L1_FEE: U256 = U256([1_000_000_000_000_000, 0, 0, 0]);
+
+/// The size of the largest transaction of the faucet, paying batches of up to `batch_size` grants.
+pub fn max_tx_size(batch_size: usize) -> usize {
+    L1_DATA_SIZE + BATCH_GRANT_SIZE * batch_size
+}
+
+/// How the fees of a chain are estimated.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum FeeEstimator {
+    /// The gas price covers the whole fee.
+    #[default]
+    Standard,
+    /// An OP-stack rollup, whose L1 data fee is quoted by the `GasPriceOracle` predeploy.
+    OpStack,
+}
+
+impl FeeEstimator {
+    /// The fee a transaction of up to `size` bytes pays in addition to its gas, for publishing its
+    /// data on the L1.
+    pub async fn l1_fee(self, provider: &Provider<RpcClient>, size: usize) -> Result<U256> {
+        match self {
+            Self::Standard => Ok(U256::zero()),
+            Self::OpStack => {
+                let oracle =
+                    GasPriceOracle::new(op_stack_gas_price_oracle(), Arc::new(provider.clone()));
+                // Since Fjord, the fee is charged for the compressed size of the data, so the
+                // estimate is made for random data, which does not compress.
+                let mut data = vec![0; size];
+                rand::thread_rng().fill(&mut data[..]);
+                Ok(oracle.get_l1_fee(Bytes::from(data)).call().await?)
+            }
+        }
+    }
+
+    /// The L1 data fee to start the faucet with, retrying a few times if the RPC fails, then
+    /// falling back to [`DEFAULT_L1_FEE`].
+    pub async fn initial_l1_fee(self, provider: &Provider<RpcClient>, size: usize) -> U256 {
+        for attempt in 1..=L1_FEE_ATTEMPTS {
+            match self.l1_fee(provider, size).await {
+                Ok(fee) => return fee,
+                Err(err) => tracing::warn!(
+                    "Failed to estimate the L1 data fee (attempt {attempt}/{L1_FEE_ATTEMPTS}): \
+                     {err:#}"
+                ),
+            }
+            if attempt < L1_FEE_ATTEMPTS {
+                sleep(L1_FEE_RETRY_DELAY).await;
+            }
+        }
+        tracing::warn!("Assuming an L1 data fee of {DEFAULT_L1_FEE} wei until it can be estimated");
+        DEFAULT_L1_FEE
+    }
+
+    /// Whether the fees of the chain have a component the gas price does not account for.
+    pub fn has_l1_fee(self) -> bool {
+        self != Self::Standard
+    }
+}
+
+/// The address of the `GasPriceOracle` predeploy of OP-stack chains.
+fn op_stack_gas_price_oracle() -> Address {
+    "0x420000000000000000000000000000000000000F"
+        .parse()
+        .unwrap()
+}
@@ -0,0 +1,106 @@
+// Copyright (c) 2023 Espresso Systems (espressosys.com)
+// This file is part of the Discord Faucet library.
+//
+// You should have received a copy of the MIT License
+// along with the Discord Faucet library. If not, see <https://mit-license.org/>.
+
+//! Waiting for grants to be final, on chains where inclusion in a block is not finality.
+//!
+//! By default a grant is confirmed as soon as its transaction is mined. With `--finality`, it is
+//! only confirmed once its block is final: either at or below the block with the `finalized` tag, or
+//! at or below the block number reported by `--finality-contract`, e.g. a light client contract
+//! tracking the blocks sequenced by HotShot. A grant whose transaction is reorged out before its
+//! block is final is sent again.
+use crate::{Options, RpcClient, TransferRequest};
+use anyhow::{anyhow, Context, Result};
+use clap::ValueEnum;
+use ethers::{
+    contract::abigen,
+    providers::{Http, Middleware, Provider},
+    types::{Address, BlockNumber, H256, U256, U64},
+};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+abigen!(
+    FinalityOracle,
+    r#"[
+        function finalizedBlockNumber() external view returns (uint256)
+    ]"#
+);
+
+/// When a grant is considered confirmed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum FinalityMode {
+    /// As soon as its transaction is mined.
+    #[default]
+    Inclusion,
+    /// Once its block is at or below the block with the `finalized` tag.
+    FinalizedTag,
+    /// Once its block is at or below the `finalizedBlockNumber()` of `--finality-contract`.
+    Contract,
+}
+
+/// A grant which was mined, waiting for its block to be final.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct IncludedTransfer {
+    pub request: TransferRequest,
+    pub tx_hash: H256,
+    pub block_number: U64,
+    /// The wallet which sent the transaction, and the nonce of the transaction, to tell whether it
+    /// can still be mined after a reorg. Unknown for the transfers of older snapshots.
+    #[serde(default)]
+    pub sent_with: Option<(Address, U256)>,
+}
+
+/// Where the faucet learns which blocks are final.
+#[derive(Clone, Debug)]
+pub enum FinalitySource {
+    Tag(Provider<RpcClient>),
+    Contract(FinalityOracle<Provider<Http>>),
+}
+
+impl FinalitySource {
+    /// The source of finality configured in `options` for the chain of `provider`, if any.
+    ///
+    /// The contract is queried through `--finality-provider-url`, or through the RPC of the faucet
+    /// if it is on the same chain.
+    pub fn new(options: &Options, provider: &Provider<RpcClient>) -> Result<Option<Self>> {
+        match options.finality {
+            FinalityMode::Inclusion => Ok(None),
+            FinalityMode::FinalizedTag => Ok(Some(Self::Tag(provider.clone()))),
+            FinalityMode::Contract => {
+                let contract = options
+                    .finality_contract
+                    .context("--finality contract requires --finality-contract")?;
+                let url = options
+                    .finality_provider_url
+                    .as_ref()
+                    .unwrap_or(&options.provider_url_http);
+                let provider = Provider::<Http>::try_from(url.as_str())?;
+                Ok(Some(Self::Contract(FinalityOracle::new(
+                    contract,
+                    Arc::new(provider),
+                ))))
+            }
+        }
+    }
+
+    /// The number of the latest final block.
+    pub async fn finalized_block(&self) -> Result<U64> {
+        match self {
+            Self::Tag(provider) => provider
+                .get_block(BlockNumber::Finalized)
+                .await?
+                .and_then(|block| block.number)
+                .context("no finalized block"),
+            Self::Contract(oracle) => {
+                let number = oracle.finalized_block_number().call().await?;
+                let number = u64::try_from(number)
+                    .map_err(|_| anyhow!("invalid finalized block number {number}"))?;
+                Ok(number.into())
+            }
+        }
+    }
+}
@@ -0,0 +1,160 @@
+// Copyright (c) 2023 Espresso Systems (espressosys.com)
+// This file is part of the Discord Faucet library.
+//
+// You should have received a copy of the MIT License
+// along with the Discord Faucet library. If not, see <https://mit-license.org/>.
+
+//! Connection state of the Discord gateway, for health reporting.
+use async_std::sync::RwLock;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+/// The connection state of a shard, as reported by `/healthcheck/deep`.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct ShardHealth {
+    pub id: u64,
+    pub connected: bool,
+    /// How long the shard has been in its current state, in seconds.
+    pub since_secs: u64,
+}
+
+/// The connection state of the Discord bot, as reported by `/healthcheck/deep`.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct GatewayHealth {
+    pub connected: bool,
+    /// How long the bot has been disconnected, in seconds, if it is.
+    pub disconnected_secs: Option<u64>,
+    /// The number of times a shard connected to the gateway again after losing its connection,
+    /// including when the client was restarted.
+    pub reconnects: u64,
+    pub shards: Vec<ShardHealth>,
+}
+
+/// Tracks the connection state of the shards run by this process.
+#[derive(Clone, Debug)]
+pub struct Gateway {
+    /// Whether each shard is connected, and since when.
+    shards: Arc<RwLock<BTreeMap<u64, (bool, Instant)>>>,
+    /// When the bot started, which counts as disconnected until a shard connects.
+    started: Instant,
+    reconnects: Arc<AtomicU64>,
+}
+
+impl Default for Gateway {
+    fn default() -> Self {
+        Self {
+            shards: Default::default(),
+            started: Instant::now(),
+            reconnects: Default::default(),
+        }
+    }
+}
+
+impl Gateway {
+    /// Record that `shard` is connected or not.
+    ///
+    /// A shard connecting after it was disconnected counts as a reconnect, while its first
+    /// connection does not.
+    pub async fn set_connected(&self, shard: u64, connected: bool) {
+        let mut shards = self.shards.write().await;
+        let previous = shards.get(&shard).map(|(state, _)| *state);
+        if previous != Some(connected) {
+            if connected && previous.is_some() {
+                self.reconnects.fetch_add(1, Ordering::Relaxed);
+            }
+            shards.insert(shard, (connected, Instant::now()));
+        }
+    }
+
+    /// Record that the client failed, disconnecting all shards, and is about to restart.
+    ///
+    /// The shards count as reconnected once the new client connects them again.
+    pub async fn reconnecting(&self) {
+        let now = Instant::now();
+        for (connected, since) in self.shards.write().await.values_mut() {
+            if *connected {
+                *connected = false;
+                *since = now;
+            }
+        }
+    }
+
+    pub fn reconnects(&self) -> u64 {
+        self.reconnects.load(Ordering::Relaxed)
+    }
+
+    /// How long any shard has been disconnected, if one is.
+    pub async fn disconnected_for(&self) -> Option<Duration> {
+        let shards = self.shards.read().await;
+        if shards.is_empty() {
+            return Some(self.started.elapsed());
+        }
+        shards
+            .values()
+            .filter(|(connected, _)| !connected)
+            .map(|(_, since)| since.elapsed())
+            .max()
+    }
+
+    pub async fn health(&self) -> GatewayHealth {
+        let disconnected = self.disconnected_for().await;
+        let shards = self
+            .shards
+            .read()
+            .await
+            .iter()
+            .map(|(id, (connected, since))| ShardHealth {
+                id: *id,
+                connected: *connected,
+                since_secs: since.elapsed().as_secs(),
+            })
+            .collect();
+        GatewayHealth {
+            connected: disconnected.is_none(),
+            disconnected_secs: disconnected.map(|duration| duration.as_secs()),
+            reconnects: self.reconnects(),
+            shards,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[async_std::test]
+    async fn test_gateway_health() {
+        let gateway = Gateway::default();
+        assert!(!gateway.health().await.connected);
+
+        gateway.set_connected(0, true).await;
+        gateway.set_connected(1, true).await;
+        assert_eq!(gateway.disconnected_for().await, None);
+
+        assert_eq!(gateway.reconnects(), 0);
+
+        gateway.set_connected(1, false).await;
+        let health = gateway.health().await;
+        assert!(!health.connected);
+        assert_eq!(health.shards.len(), 2);
+
+        gateway.set_connected(1, true).await;
+        assert_eq!(gateway.reconnects(), 1);
+        gateway.reconnecting().await;
+        let health = gateway.health().await;
+        assert!(!health.connected);
+        assert!(health.shards.iter().all(|shard| !shard.connected));
+
+        // Both shards reconnect with the new client.
+        gateway.set_connected(0, true).await;
+        gateway.set_connected(1, true).await;
+        assert_eq!(gateway.reconnects(), 3);
+    }
+}
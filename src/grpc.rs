@@ -0,0 +1,184 @@
+// Copyright (c) 2023 Espresso Systems (espressosys.com)
+// This file is part of the Discord Faucet library.
+//
+// You should have received a copy of the MIT License
+// along with the Discord Faucet library. If not, see <https://mit-license.org/>.
+
+//! gRPC API for internal services.
+//!
+//! The gRPC API does not support bot protection, ownership proofs or OAuth login, so it must only
+//! be reachable from trusted networks. Requests carrying an API key in the `x-api-key` metadata are
+//! charged to the quotas of the key.
+use crate::{ErrorCode, FaucetError, FaucetRequest, RequestSource, RequestStatus, WebState};
+use futures::{stream::BoxStream, StreamExt};
+use proto::{
+    faucet_server::{Faucet, FaucetServer},
+    status_response, Confirmed, Event, GrantRequest, GrantResponse, Included, Queued,
+    StatusRequest, StatusResponse, StreamEventsRequest, Submitted,
+};
+use std::net::{Ipv4Addr, SocketAddr};
+use tonic::{transport::Server, Code, Request, Response, Status};
+
+pub mod proto {
+    tonic::include_proto!("faucet.v1");
+}
+
+impl From<FaucetError> for Status {
+    fn from(err: FaucetError) -> Self {
+        let code = match err.code {
+            ErrorCode::BadRequest | ErrorCode::BadAddress | ErrorCode::BadRequestId => {
+                Code::InvalidArgument
+            }
+            ErrorCode::UnknownToken | ErrorCode::NotFound => Code::NotFound,
+            ErrorCode::CaptchaFailed
+            | ErrorCode::ProofOfWorkFailed
+            | ErrorCode::OwnershipProofFailed
+            | ErrorCode::Suspicious => Code::PermissionDenied,
+            ErrorCode::Unauthorized => Code::Unauthenticated,
+            ErrorCode::Cooldown
+            | ErrorCode::QuotaExceeded
+            | ErrorCode::LifetimeCapReached
+            | ErrorCode::QueueFull => Code::ResourceExhausted,
+            ErrorCode::FaucetEmpty | ErrorCode::Paused => Code::FailedPrecondition,
+            ErrorCode::Unavailable => Code::Unavailable,
+            ErrorCode::Internal => Code::Internal,
+        };
+        Status::new(code, err.message)
+    }
+}
+
+#[derive(Clone, Debug)]
+struct GrpcService {
+    state: WebState,
+}
+
+#[tonic::async_trait]
+impl Faucet for GrpcService {
+    async fn request_grant(
+        &self,
+        req: Request<GrantRequest>,
+    ) -> Result<Response<GrantResponse>, Status> {
+        let api_key = req
+            .metadata()
+            .get("x-api-key")
+            .map(|key| {
+                key.to_str()
+                    .map(str::to_string)
+                    .map_err(|_| FaucetError::unauthorized("invalid x-api-key metadata"))
+            })
+            .transpose()?;
+        let req = req.into_inner();
+        let address = req
+            .address
+            .parse()
+            .map_err(|_| FaucetError::bad_address(&req.address))?;
+        let token = req
+            .token
+            .map(|symbol| self.state.token(&symbol))
+            .transpose()?;
+        tracing::info!("Received gRPC faucet request for {:?} {:?}", address, token);
+
+        if let Some(key) = api_key {
+            self.state.charge_api_key(&key, 1, &token).await?;
+        }
+        let queued = match self
+            .state
+            .request(FaucetRequest::new(address, token.clone()).with_source(RequestSource::Web))
+            .await
+        {
+            Ok(queued) => queued,
+            Err(err) => {
+                // Requests which are not served are not charged.
+                if let Some(key) = api_key {
+                    self.state.refund_api_key(&key, 1, &token).await;
+                }
+                return Err(err.into());
+            }
+        };
+        Ok(Response::new(GrantResponse {
+            request_id: queued.id.to_string(),
+            eta_secs: queued.eta_secs,
+            correlation_id: queued.correlation_id.to_string(),
+        }))
+    }
+
+    async fn get_status(
+        &self,
+        req: Request<StatusRequest>,
+    ) -> Result<Response<StatusResponse>, Status> {
+        let input = req.into_inner().request_id;
+        let id = input
+            .parse()
+            .map_err(|_| FaucetError::bad_request_id(&input))?;
+        let status = self
+            .state
+            .faucet
+            .request_status(id)
+            .await
+            .ok_or_else(|| Status::not_found(format!("unknown request {id}")))?;
+        let status = match status {
+            RequestStatus::Queued { position, eta_secs } => {
+                status_response::Status::Queued(Queued {
+                    position: position as u64,
+                    eta_secs,
+                })
+            }
+            RequestStatus::Submitted { tx_hash, eta_secs } => {
+                status_response::Status::Submitted(Submitted {
+                    tx_hash: format!("{tx_hash:?}"),
+                    eta_secs,
+                })
+            }
+            RequestStatus::Included {
+                tx_hash,
+                block_number,
+            } => status_response::Status::Included(Included {
+                tx_hash: format!("{tx_hash:?}"),
+                block_number: block_number.as_u64(),
+            }),
+            RequestStatus::Confirmed {
+                tx_hash,
+                block_number,
+            } => status_response::Status::Confirmed(Confirmed {
+                tx_hash: format!("{tx_hash:?}"),
+                block_number: block_number.map(|number| number.as_u64()),
+            }),
+        };
+        Ok(Response::new(StatusResponse {
+            status: Some(status),
+        }))
+    }
+
+    type StreamEventsStream = BoxStream<'static, Result<Event, Status>>;
+
+    async fn stream_events(
+        &self,
+        _req: Request<StreamEventsRequest>,
+    ) -> Result<Response<Self::StreamEventsStream>, Status> {
+        let events = self.state.faucet.events().subscribe().await;
+        Ok(Response::new(
+            events
+                .map(|event| {
+                    let json = serde_json::to_value(&event)
+                        .map_err(|err| Status::internal(err.to_string()))?;
+                    Ok(Event {
+                        event: json["event"].as_str().unwrap_or_default().to_string(),
+                        request_id: event
+                            .request()
+                            .and_then(|request| request.id())
+                            .map(|id| id.to_string()),
+                        tx_hash: event.tx_hash().map(|hash| format!("{hash:?}")),
+                        json: json.to_string(),
+                    })
+                })
+                .boxed(),
+        ))
+    }
+}
+
+pub(crate) async fn serve_grpc(port: u16, state: WebState) -> Result<(), tonic::transport::Error> {
+    Server::builder()
+        .add_service(FaucetServer::new(GrpcService { state }))
+        .serve(SocketAddr::from((Ipv4Addr::UNSPECIFIED, port)))
+        .await
+}
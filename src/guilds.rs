@@ -0,0 +1,587 @@
+// Copyright (c) 2023 Espresso Systems (espressosys.com)
+// This file is part of the Discord Faucet library.
+//
+// You should have received a copy of the MIT License
+// along with the Discord Faucet library. If not, see <https://mit-license.org/>.
+
+//! Settings for each Discord guild served by the bot.
+//!
+//! Guilds without settings are served according to the command line options.
+use crate::{
+    check_fee_multiplier, Cooldown, Faucet, FaucetEvent, FaucetRequest, FeeEstimator, Matcher,
+    Options, SharedCooldownStore, TokenBucket,
+};
+use anyhow::{bail, Context, Result};
+use async_std::{channel::Sender, sync::RwLock, task::sleep};
+use ethers::{
+    types::{Address, U256},
+    utils::{parse_ether, parse_units},
+};
+use futures::StreamExt;
+use serde::Deserialize;
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Debug,
+    fs,
+    hash::Hash,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+use url::Url;
+
+/// How often to check the settings file for changes.
+const RELOAD_INTERVAL: Duration = Duration::from_secs(10);
+
+#[derive(Clone, Debug, Default, Deserialize)]
+struct GuildsFile {
+    #[serde(default, rename = "chain")]
+    chains: BTreeMap<String, ChainConfig>,
+    #[serde(default, rename = "guild")]
+    guilds: Vec<GuildConfig>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct ChainConfig {
+    provider_url_http: String,
+    provider_url_ws: Option<String>,
+    /// Block explorer URL template, as for `--explorer-url`.
+    explorer_url: Option<String>,
+    /// The gas limit of transfers of the native currency, as for `--gas-limit`.
+    gas_limit: Option<u64>,
+    /// A factor applied to the fees suggested by the provider, as for `--fee-multiplier`.
+    fee_multiplier: Option<f64>,
+    /// The minimum priority fee, in gwei, as for `--min-priority-fee`.
+    min_priority_fee: Option<String>,
+    /// How to estimate the fees of the chain, as for `--fee-estimator`.
+    fee_estimator: Option<FeeEstimator>,
+}
+
+impl ChainConfig {
+    fn min_priority_fee(&self) -> Result<Option<U256>> {
+        self.min_priority_fee
+            .as_deref()
+            .map(|fee| Ok(parse_units(fee, "gwei")?.into()))
+            .transpose()
+    }
+
+    fn fee_multiplier(&self) -> Result<Option<f64>> {
+        self.fee_multiplier.map(check_fee_multiplier).transpose()
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct GuildConfig {
+    id: u64,
+    #[serde(default)]
+    channels: Vec<u64>,
+    /// The minimum time between two grants to the same user or address, e.g. `24h`.
+    cooldown: Option<String>,
+    /// The amount to grant, in ethers.
+    grant_amount: Option<String>,
+    /// The name of a chain defined in the same file.
+    chain: Option<String>,
+    /// The locale of the bot's replies.
+    locale: Option<String>,
+    /// Which channel messages are requests for funds: `prefix:WORD`, `command:COMMAND` or
+    /// `address`. By default, only commands are.
+    trigger: Option<String>,
+    /// The regular expression matching addresses in requests.
+    address_pattern: Option<String>,
+    /// The maximum number of grants per hour to all the members of the guild.
+    grants_per_hour: Option<u32>,
+    /// The number of grants the guild can make at once, by default `grants_per_hour`.
+    grants_burst: Option<u32>,
+}
+
+/// The settings of a guild.
+#[derive(Clone, Debug)]
+pub struct GuildSettings {
+    /// The channels in which the bot serves faucet commands, or all channels if empty.
+    pub channels: Vec<u64>,
+    /// The cooldown between grants to the same user.
+    pub cooldown: Option<Cooldown<u64>>,
+    /// The cooldown between grants to the same address, with the same period as `cooldown`.
+    pub address_cooldown: Option<Cooldown<Address>>,
+    pub grant_amount: Option<U256>,
+    pub chain: Option<String>,
+    pub locale: Option<String>,
+    /// Recognizes requests in the messages of the guild.
+    pub matcher: Matcher,
+    /// Limits the grants to all the members of the guild.
+    pub quota: Option<TokenBucket>,
+}
+
+/// A cooldown preventing a grant in a guild.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CoolingDown {
+    /// The requesting user is cooling down for the given time.
+    User(Duration),
+    /// The address is cooling down for the given time.
+    Address(Address, Duration),
+}
+
+impl CoolingDown {
+    /// The time until the cooldown ends.
+    pub fn remaining(&self) -> Duration {
+        match self {
+            Self::User(remaining) | Self::Address(_, remaining) => *remaining,
+        }
+    }
+}
+
+impl GuildSettings {
+    /// Start the cooldowns of `user` and `addresses`.
+    ///
+    /// This is the single place where grants in a guild are charged against its cooldowns, for
+    /// requests from Discord and web requests with a Discord token alike.
+    pub async fn start_cooldowns(
+        &self,
+        user: u64,
+        addresses: &[Address],
+    ) -> Result<(), CoolingDown> {
+        if let Some(cooldown) = &self.address_cooldown {
+            for address in addresses {
+                if let Some(remaining) = cooldown.remaining(address).await {
+                    return Err(CoolingDown::Address(*address, remaining));
+                }
+            }
+        }
+        if let Some(cooldown) = &self.cooldown {
+            cooldown.start(user).await.map_err(CoolingDown::User)?;
+        }
+        if let Some(cooldown) = &self.address_cooldown {
+            for (i, address) in addresses.iter().enumerate() {
+                if let Err(remaining) = cooldown.start(*address).await {
+                    // Another request started the cooldown in the meantime.
+                    self.reset_cooldowns(Some(user), &addresses[..i]).await;
+                    return Err(CoolingDown::Address(*address, remaining));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// End the cooldowns started by [`start_cooldowns`](Self::start_cooldowns) for grants which
+    /// were not served: those of `addresses`, and that of `user` if none of its grants were.
+    pub async fn reset_cooldowns(&self, user: Option<u64>, addresses: &[Address]) {
+        if let (Some(cooldown), Some(user)) = (&self.cooldown, user) {
+            cooldown.reset(&user).await;
+        }
+        if let Some(cooldown) = &self.address_cooldown {
+            for address in addresses {
+                cooldown.reset(address).await;
+            }
+        }
+    }
+}
+
+/// A faucet for a chain other than the default one, and the queue through which it is requested.
+#[derive(Clone, Debug)]
+pub struct ChainFaucet {
+    pub queue: Sender<FaucetRequest>,
+    pub faucet: Faucet,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Guilds {
+    path: Option<PathBuf>,
+    settings: Arc<RwLock<HashMap<u64, GuildSettings>>>,
+    chains: Arc<BTreeMap<String, ChainFaucet>>,
+    /// Where the cooldowns of the guilds are persisted, if anywhere.
+    cooldowns: Option<SharedCooldownStore>,
+}
+
+impl Guilds {
+    /// Load the settings file at `path` and create a faucet for each chain it defines.
+    ///
+    /// The faucets are not started. The cooldowns of the guilds are persisted in `cooldowns`, if
+    /// any.
+    pub async fn load(
+        path: PathBuf,
+        options: &Options,
+        cooldowns: Option<SharedCooldownStore>,
+    ) -> Result<Self> {
+        let file = read(&path)?;
+        let mut chains = BTreeMap::new();
+        for (name, chain) in &file.chains {
+            let options = Options {
+                provider_url_http: chain
+                    .provider_url_http
+                    .parse()
+                    .context("invalid provider_url_http")?,
+                provider_url_ws: chain
+                    .provider_url_ws
+                    .as_deref()
+                    .map(Url::parse)
+                    .transpose()
+                    .context("invalid provider_url_ws")?,
+                network_name: Some(name.clone()),
+                explorer_url: chain.explorer_url.clone(),
+                gas_limit: chain.gas_limit.or(options.gas_limit),
+                fee_multiplier: chain
+                    .fee_multiplier()
+                    .context("invalid fee_multiplier")?
+                    .unwrap_or(options.fee_multiplier),
+                min_priority_fee: chain
+                    .min_priority_fee()
+                    .context("invalid min_priority_fee")?
+                    .or(options.min_priority_fee),
+                fee_estimator: chain.fee_estimator.unwrap_or(options.fee_estimator),
+                ..options.clone()
+            };
+            let (queue, receiver) =
+                async_std::channel::bounded(options.request_channel_capacity.get());
+            let faucet = Faucet::create(options, receiver)
+                .await
+                .with_context(|| format!("creating faucet for chain {name}"))?;
+            tracing::info!("Created faucet for chain {name} ({})", faucet.chain_id());
+            chains.insert(name.clone(), ChainFaucet { queue, faucet });
+        }
+
+        let guilds = Self {
+            path: Some(path),
+            settings: Default::default(),
+            chains: Arc::new(chains),
+            cooldowns,
+        };
+        guilds.apply(file).await?;
+        Ok(guilds)
+    }
+
+    /// The faucets of the chains defined in the settings file.
+    pub fn chains(&self) -> impl Iterator<Item = (&String, &ChainFaucet)> {
+        self.chains.iter()
+    }
+
+    pub fn chain(&self, name: &str) -> Option<&ChainFaucet> {
+        self.chains.get(name)
+    }
+
+    /// The settings of `guild`, if it has any.
+    pub async fn get(&self, guild: u64) -> Option<GuildSettings> {
+        self.settings.read().await.get(&guild).cloned()
+    }
+
+    /// The largest grant amount of the guilds using `chain`, if any of them sets one.
+    ///
+    /// The faucet of the chain grants this amount where no guild applies, such as dual grants, and
+    /// judges returns and low balances against it.
+    pub async fn grant_amount(&self, chain: &str) -> Option<U256> {
+        self.settings
+            .read()
+            .await
+            .values()
+            .filter(|settings| settings.chain.as_deref() == Some(chain))
+            .filter_map(|settings| settings.grant_amount)
+            .max()
+    }
+
+    /// End the address cooldowns of `address` in all the guilds.
+    pub async fn reset_address_cooldowns(&self, address: Address) {
+        let settings = self.settings.read().await.clone();
+        for settings in settings.values() {
+            if let Some(cooldown) = &settings.address_cooldown {
+                cooldown.reset(&address).await;
+            }
+        }
+    }
+
+    /// End the address cooldowns of every address which sends at least a grant back to the return
+    /// address of `faucet`.
+    pub async fn credit_returns(self, faucet: Faucet) {
+        let mut events = faucet.events().subscribe().await;
+        while let Some(event) = events.next().await {
+            if let FaucetEvent::FundsReturned { from, amount, .. } = event {
+                if amount >= faucet.grant_amount().await {
+                    tracing::info!(
+                        "Ending the address cooldowns of {from:?}, which returned a grant"
+                    );
+                    self.reset_address_cooldowns(from).await;
+                }
+            }
+        }
+    }
+
+    /// Reload the settings whenever the settings file changes.
+    pub async fn watch(self) {
+        let Some(path) = self.path.clone() else {
+            return;
+        };
+        let mut last_modified = modified(&path);
+        loop {
+            sleep(RELOAD_INTERVAL).await;
+            let modified = modified(&path);
+            if modified == last_modified {
+                continue;
+            }
+            last_modified = modified;
+            match self.reload().await {
+                Ok(()) => tracing::info!("Reloaded guild settings from {}", path.display()),
+                Err(err) => tracing::error!(
+                    "Failed to reload guild settings, keeping the old ones: {err:#}"
+                ),
+            }
+        }
+    }
+
+    /// Reload the settings file.
+    pub async fn reload(&self) -> Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let file = read(path)?;
+        for name in file.chains.keys() {
+            if !self.chains.contains_key(name) {
+                tracing::warn!("Chain {name} was added to the guild settings, restart to serve it");
+            }
+        }
+        self.apply(file).await
+    }
+
+    /// Check the settings file at `path` without creating the faucets of its chains.
+    ///
+    /// Returns the number of guilds and the HTTP RPC of each chain, so that they can be checked.
+    pub async fn validate(path: &Path) -> Result<(usize, Vec<(String, Url)>)> {
+        let file = read(path)?;
+        let mut chains = vec![];
+        for (name, chain) in &file.chains {
+            let url = chain
+                .provider_url_http
+                .parse()
+                .with_context(|| format!("invalid provider_url_http for chain {name}"))?;
+            if let Some(url) = &chain.provider_url_ws {
+                Url::parse(url)
+                    .with_context(|| format!("invalid provider_url_ws for chain {name}"))?;
+            }
+            chain
+                .min_priority_fee()
+                .with_context(|| format!("invalid min_priority_fee for chain {name}"))?;
+            chain
+                .fee_multiplier()
+                .with_context(|| format!("invalid fee_multiplier for chain {name}"))?;
+            chains.push((name.clone(), url));
+        }
+        let guilds = file.guilds.len();
+        Self::default()
+            .apply_guilds(file.guilds, |chain| file.chains.contains_key(chain))
+            .await?;
+        Ok((guilds, chains))
+    }
+
+    /// Apply the settings of `file`, and the grant amounts of its guilds to their chains.
+    ///
+    /// Chains whose guilds set no grant amount keep their current one.
+    async fn apply(&self, file: GuildsFile) -> Result<()> {
+        self.apply_guilds(file.guilds, |chain| self.chains.contains_key(chain))
+            .await?;
+        for (name, chain) in self.chains() {
+            if let Some(amount) = self.grant_amount(name).await {
+                chain.faucet.set_grant_amount(amount).await;
+            }
+        }
+        Ok(())
+    }
+
+    async fn apply_guilds(
+        &self,
+        guilds: Vec<GuildConfig>,
+        known_chain: impl Fn(&str) -> bool,
+    ) -> Result<()> {
+        let mut settings = self.settings.write().await;
+        let mut new_settings = HashMap::new();
+        for guild in guilds {
+            if let Some(chain) = &guild.chain {
+                if !known_chain(chain) {
+                    bail!("guild {} uses unknown chain {chain}", guild.id);
+                }
+            }
+            let period = guild
+                .cooldown
+                .as_deref()
+                .map(duration_str::parse)
+                .transpose()
+                .with_context(|| format!("invalid cooldown for guild {}", guild.id))?;
+            // Keep the cooldowns of users and addresses if the period did not change.
+            let old = settings
+                .get(&guild.id)
+                .filter(|old| old.cooldown.as_ref().map(Cooldown::period) == period);
+            let (cooldown, address_cooldown) = match (period, old) {
+                (Some(_), Some(old)) => (old.cooldown.clone(), old.address_cooldown.clone()),
+                (Some(period), None) => (
+                    Some(self.cooldown(period, format!("guild:{}:user", guild.id))),
+                    Some(self.cooldown(period, format!("guild:{}:address", guild.id))),
+                ),
+                (None, _) => (None, None),
+            };
+            let grant_amount = guild
+                .grant_amount
+                .as_deref()
+                .map(parse_ether)
+                .transpose()
+                .with_context(|| format!("invalid grant amount for guild {}", guild.id))?;
+            let trigger = guild
+                .trigger
+                .as_deref()
+                .map(str::parse)
+                .transpose()
+                .with_context(|| format!("invalid trigger for guild {}", guild.id))?;
+            let matcher = Matcher::new(trigger, guild.address_pattern.as_deref())
+                .with_context(|| format!("invalid address pattern for guild {}", guild.id))?;
+            let quota = guild.grants_per_hour.map(|per_hour| {
+                let capacity = guild.grants_burst.unwrap_or(per_hour);
+                // Keep the remaining quota if the limits did not change.
+                settings
+                    .get(&guild.id)
+                    .and_then(|old| old.quota.clone())
+                    .filter(|old| old.per_hour() == per_hour && old.capacity() == capacity)
+                    .unwrap_or_else(|| TokenBucket::new(per_hour, capacity))
+            });
+            new_settings.insert(
+                guild.id,
+                GuildSettings {
+                    channels: guild.channels,
+                    cooldown,
+                    address_cooldown,
+                    grant_amount,
+                    chain: guild.chain,
+                    locale: guild.locale,
+                    matcher,
+                    quota,
+                },
+            );
+        }
+        *settings = new_settings;
+        Ok(())
+    }
+
+    /// A cooldown persisted under `namespace`, if the guilds have a cooldown store.
+    fn cooldown<K: Eq + Hash + Debug>(&self, period: Duration, namespace: String) -> Cooldown<K> {
+        let cooldown = Cooldown::new(period);
+        match &self.cooldowns {
+            Some(storage) => cooldown.with_storage(storage.clone(), namespace),
+            None => cooldown,
+        }
+    }
+}
+
+fn read(path: &Path) -> Result<GuildsFile> {
+    let contents =
+        fs::read_to_string(path).with_context(|| format!("reading {}", path.display()))?;
+    toml::from_str(&contents).with_context(|| format!("parsing {}", path.display()))
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[async_std::test]
+    async fn test_guild_settings() {
+        let file = toml::from_str::<GuildsFile>(
+            r#"
+            [[guild]]
+            id = 1
+            channels = [10, 11]
+            cooldown = "1h"
+            grant_amount = "0.5"
+            trigger = "command:!faucet"
+            grants_per_hour = 10
+            grants_burst = 1
+
+            [[guild]]
+            id = 2
+            "#,
+        )
+        .unwrap();
+        let guilds = Guilds::default();
+        guilds.apply(file.clone()).await.unwrap();
+
+        let settings = guilds.get(1).await.unwrap();
+        assert_eq!(settings.channels, vec![10, 11]);
+        assert_eq!(settings.grant_amount, Some(parse_ether("0.5").unwrap()));
+        assert!(settings
+            .matcher
+            .is_request("!faucet 0x1234567890123456789012345678901234567890"));
+        let cooldown = settings.cooldown.unwrap();
+        cooldown.start(100).await.unwrap();
+        let address = Address::random();
+        let address_cooldown = settings.address_cooldown.unwrap();
+        address_cooldown.start(address).await.unwrap();
+        assert!(guilds.get(2).await.unwrap().cooldown.is_none());
+        assert!(guilds.get(3).await.is_none());
+
+        let quota = settings.quota.unwrap();
+        assert_eq!(quota.per_hour(), 10);
+        quota.take(1).await.unwrap();
+
+        // Reloading the same settings keeps the cooldowns and quotas.
+        guilds.apply(file).await.unwrap();
+        let settings = guilds.get(1).await.unwrap();
+        assert!(settings.cooldown.unwrap().remaining(&100).await.is_some());
+        assert!(settings
+            .address_cooldown
+            .unwrap()
+            .remaining(&address)
+            .await
+            .is_some());
+        assert!(settings.quota.unwrap().remaining(1).await.is_some());
+
+        guilds.reset_address_cooldowns(address).await;
+        assert!(guilds
+            .get(1)
+            .await
+            .unwrap()
+            .address_cooldown
+            .unwrap()
+            .remaining(&address)
+            .await
+            .is_none());
+
+        // The cooldowns of grants which were not served are ended.
+        let settings = guilds.get(1).await.unwrap();
+        settings.start_cooldowns(100, &[address]).await.unwrap_err();
+        settings.reset_cooldowns(Some(100), &[address]).await;
+        settings.start_cooldowns(100, &[address]).await.unwrap();
+
+        // Invalid triggers are rejected.
+        let file = toml::from_str::<GuildsFile>("[[guild]]\nid = 1\ntrigger = \"x\"").unwrap();
+        assert!(guilds.apply(file).await.is_err());
+
+        // Guilds can only use known chains.
+        let file = toml::from_str::<GuildsFile>("[[guild]]\nid = 1\nchain = \"other\"").unwrap();
+        assert!(guilds.apply(file).await.is_err());
+        assert!(guilds.get(1).await.is_some());
+
+        // A chain grants the largest amount of its guilds.
+        let file = toml::from_str::<GuildsFile>(
+            r#"
+            [[guild]]
+            id = 1
+            chain = "rollup"
+            grant_amount = "0.5"
+
+            [[guild]]
+            id = 2
+            chain = "rollup"
+            grant_amount = "2"
+
+            [[guild]]
+            id = 3
+            grant_amount = "5"
+            "#,
+        )
+        .unwrap();
+        guilds.apply_guilds(file.guilds, |_| true).await.unwrap();
+        assert_eq!(
+            guilds.grant_amount("rollup").await,
+            Some(parse_ether(2).unwrap())
+        );
+        assert_eq!(guilds.grant_amount("other").await, None);
+    }
+}
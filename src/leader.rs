@@ -0,0 +1,157 @@
+// Copyright (c) 2023 Espresso Systems (espressosys.com)
+// This file is part of the Discord Faucet library.
+//
+// You should have received a copy of the MIT License
+// along with the Discord Faucet library. If not, see <https://mit-license.org/>.
+
+//! Leader election between instances sharing a wallet set, for high availability.
+//!
+//! With `--leader-lock-url`, a primary and one or more standbys run with the same mnemonic and
+//! wallets. Only the instance holding the lease submits transactions. The leader renews the lease
+//! continuously, and a standby takes it over once it expires, within `--leader-lease` of the
+//! primary dying.
+//!
+//! An instance stops submitting transactions once half of the lease has passed without renewing
+//! it, well before a standby can take the lease over, so that two instances never submit from the
+//! same wallets at once. Nonces are read from the chain, so a new leader continues from the
+//! transactions of the old one.
+use crate::PgConnection;
+use anyhow::{Context, Result};
+use async_std::{sync::RwLock, task::sleep};
+use std::{
+    fmt::{self, Debug, Formatter},
+    sync::Arc,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+/// Elects a single leader among the instances using the same lease.
+#[derive(Clone)]
+pub struct LeaderElection {
+    conn: PgConnection,
+    /// The name of the lease, shared by the instances taking part in the election.
+    name: String,
+    /// The identifier of this instance.
+    holder: String,
+    lease: Duration,
+    /// Until when this instance may act as the leader.
+    leader_until: Arc<RwLock<Option<Instant>>>,
+}
+
+impl Debug for LeaderElection {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LeaderElection")
+            .field("name", &self.name)
+            .field("holder", &self.holder)
+            .field("lease", &self.lease)
+            .finish_non_exhaustive()
+    }
+}
+
+impl LeaderElection {
+    /// Take part in the election for the lease `name`, in the database at `url`.
+    pub fn connect(url: &str, name: impl Into<String>, lease: Duration) -> Result<Self> {
+        Ok(Self {
+            conn: PgConnection::connect(url).context("connecting to lock")?,
+            name: name.into(),
+            holder: format!("{:016x}", rand::random::<u64>()),
+            lease,
+            leader_until: Default::default(),
+        })
+    }
+
+    /// Whether this instance is currently the leader.
+    pub async fn is_leader(&self) -> bool {
+        self.leader_until
+            .read()
+            .await
+            .map_or(false, |until| Instant::now() < until)
+    }
+
+    /// Acquire or renew the lease, if it is free or already held by this instance.
+    ///
+    /// Returns whether this instance is the leader. A dropped connection to the database is
+    /// reopened by the next attempt.
+    pub async fn try_acquire(&self) -> Result<bool> {
+        let started = Instant::now();
+        let name = self.name.clone();
+        let holder = self.holder.clone();
+        let lease = self.lease;
+        let acquired = self
+            .conn
+            .run(move |client| {
+                let now = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_millis() as i64;
+                let expires_at = now + lease.as_millis() as i64;
+                let updated = client.execute(
+                    "INSERT INTO leases (name, holder, expires_at) VALUES ($1, $2, $3)
+                 ON CONFLICT (name) DO UPDATE SET
+                    holder = excluded.holder,
+                    expires_at = excluded.expires_at
+                 WHERE leases.holder = excluded.holder OR leases.expires_at < $4",
+                    &[&name, &holder, &expires_at, &now],
+                )?;
+                Ok(updated > 0)
+            })
+            .await?;
+
+        let mut leader_until = self.leader_until.write().await;
+        let was_leader = leader_until.map_or(false, |until| Instant::now() < until);
+        // Measure the lease from before the request, and only use half of it, to leave a margin
+        // for the latency of the database and the drift between clocks.
+        *leader_until = acquired.then(|| started + self.lease / 2);
+        if acquired && !was_leader {
+            tracing::warn!("Became the leader for {}", self.name);
+        } else if !acquired && was_leader {
+            tracing::warn!("Lost the leadership for {}", self.name);
+        }
+        Ok(acquired)
+    }
+
+    /// Keep taking part in the election as long as the process runs.
+    pub async fn run(self) {
+        loop {
+            if let Err(err) = self.try_acquire().await {
+                // Until the lease is renewed, `is_leader` turns false by itself.
+                tracing::error!("Failed to renew the lease {}: {err:#}", self.name);
+            }
+            sleep(self.lease / 6).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Runs against the database given by `ESPRESSO_DISCORD_FAUCET_TEST_DATABASE_URL`, with `cargo test -- --ignored`.
+    #[async_std::test]
+    #[ignore]
+    async fn test_leader_election() {
+        let url = std::env::var("ESPRESSO_DISCORD_FAUCET_TEST_DATABASE_URL")
+            .expect("ESPRESSO_DISCORD_FAUCET_TEST_DATABASE_URL is not set");
+        let name = format!("test-{}", rand::random::<u64>());
+        let lease = Duration::from_millis(500);
+        let primary = LeaderElection::connect(&url, &name, lease).unwrap();
+        let standby = LeaderElection::connect(&url, &name, lease).unwrap();
+        assert!(!primary.is_leader().await);
+
+        assert!(primary.try_acquire().await.unwrap());
+        assert!(primary.is_leader().await);
+        assert!(!standby.try_acquire().await.unwrap());
+        assert!(!standby.is_leader().await);
+
+        // The leader keeps the lease as long as it renews it.
+        assert!(primary.try_acquire().await.unwrap());
+
+        // Once the primary stops renewing the lease, it stops acting as the leader, and the standby
+        // takes over when the lease expires.
+        sleep(lease / 2).await;
+        assert!(!primary.is_leader().await);
+        sleep(lease / 2 + Duration::from_millis(50)).await;
+        assert!(standby.try_acquire().await.unwrap());
+        assert!(standby.is_leader().await);
+        assert!(!primary.try_acquire().await.unwrap());
+    }
+}
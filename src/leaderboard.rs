@@ -0,0 +1,167 @@
+// Copyright (c) 2023 Espresso Systems (espressosys.com)
+// This file is part of the Discord Faucet library.
+//
+// You should have received a copy of the MIT License
+// along with the Discord Faucet library. If not, see <https://mit-license.org/>.
+
+//! The addresses which returned and requested the most funds recently, for `/leaderboard`.
+//!
+//! The returns and grants of the faucets are kept in memory for `--discord-leaderboard-period`, so
+//! the leaderboard covers a rolling window and starts empty when the faucet restarts.
+use crate::{Faucet, FaucetEvent, TransferRequest};
+use async_std::sync::RwLock;
+use ethers::types::{Address, U256};
+use futures::StreamExt;
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+#[derive(Clone, Debug, Default)]
+pub struct Leaderboard {
+    period: Duration,
+    /// When each return was seen, its sender and its amount, oldest first.
+    returns: Arc<RwLock<VecDeque<(Instant, Address, U256)>>>,
+    /// When each grant was confirmed and its recipient, oldest first.
+    grants: Arc<RwLock<VecDeque<(Instant, Address)>>>,
+}
+
+impl Leaderboard {
+    pub fn new(period: Duration) -> Self {
+        Self {
+            period,
+            ..Default::default()
+        }
+    }
+
+    /// The period covered by the leaderboard.
+    pub fn period(&self) -> Duration {
+        self.period
+    }
+
+    /// Account for `event` in the leaderboard.
+    pub async fn record(&self, event: &FaucetEvent) {
+        match event {
+            FaucetEvent::FundsReturned { from, amount, .. } => {
+                let mut returns = self.returns.write().await;
+                returns.push_back((Instant::now(), *from, *amount));
+                self.prune(&mut returns, |(timestamp, ..)| *timestamp);
+            }
+            FaucetEvent::TransferConfirmed { request, .. } => {
+                // Funding transfers between the faucet wallets, relays and paymaster top-ups are not
+                // grants.
+                if request.is_top_up()
+                    || matches!(
+                        request,
+                        TransferRequest::Funding { .. } | TransferRequest::Relay { .. }
+                    )
+                {
+                    return;
+                }
+                let mut grants = self.grants.write().await;
+                grants.push_back((Instant::now(), request.to()));
+                self.prune(&mut grants, |(timestamp, _)| *timestamp);
+            }
+            _ => {}
+        }
+    }
+
+    /// Record the returns and grants of `faucet`.
+    pub async fn watch(self, faucet: Faucet) {
+        let mut events = faucet.events().subscribe().await;
+        while let Some(event) = events.next().await {
+            self.record(&event).await;
+        }
+    }
+
+    /// The `n` addresses which returned the most native currency during the period, with the
+    /// amount each returned.
+    pub async fn top_returners(&self, n: usize) -> Vec<(Address, U256)> {
+        let mut returns = self.returns.write().await;
+        self.prune(&mut returns, |(timestamp, ..)| *timestamp);
+        let mut totals = HashMap::<Address, U256>::new();
+        for (_, from, amount) in returns.iter() {
+            *totals.entry(*from).or_default() += *amount;
+        }
+        top(totals, n)
+    }
+
+    /// The `n` addresses which received the most grants during the period, with the number of
+    /// grants each received.
+    pub async fn top_requesters(&self, n: usize) -> Vec<(Address, usize)> {
+        let mut grants = self.grants.write().await;
+        self.prune(&mut grants, |(timestamp, _)| *timestamp);
+        let mut counts = HashMap::<Address, usize>::new();
+        for (_, to) in grants.iter() {
+            *counts.entry(*to).or_default() += 1;
+        }
+        top(counts, n)
+    }
+
+    /// Forget the entries older than the period.
+    fn prune<T>(&self, entries: &mut VecDeque<T>, timestamp: impl Fn(&T) -> Instant) {
+        while entries
+            .front()
+            .is_some_and(|entry| timestamp(entry).elapsed() > self.period)
+        {
+            entries.pop_front();
+        }
+    }
+}
+
+/// The `n` largest totals, largest first, with ties ordered by address.
+fn top<T: Ord + Copy>(totals: HashMap<Address, T>, n: usize) -> Vec<(Address, T)> {
+    let mut totals = totals.into_iter().collect::<Vec<_>>();
+    totals.sort_by(|(a, x), (b, y)| y.cmp(x).then(a.cmp(b)));
+    totals.truncate(n);
+    totals
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::RequestId;
+    use ethers::types::H256;
+
+    #[async_std::test]
+    async fn test_leaderboard() {
+        let leaderboard = Leaderboard::new(Duration::from_secs(3600));
+        let (a, b) = (Address::random(), Address::random());
+        for (from, amount) in [(a, 1), (b, 3), (a, 1)] {
+            leaderboard
+                .record(&FaucetEvent::FundsReturned {
+                    from,
+                    amount: amount.into(),
+                    tx_hash: H256::random(),
+                })
+                .await;
+        }
+        for to in [a, a, b] {
+            leaderboard
+                .record(&FaucetEvent::TransferConfirmed {
+                    request: TransferRequest::faucet(RequestId::random(), to, 1.into()),
+                    tx_hash: H256::random(),
+                    block_number: None,
+                })
+                .await;
+        }
+        assert_eq!(
+            leaderboard.top_returners(10).await,
+            vec![(b, 3.into()), (a, 2.into())]
+        );
+        assert_eq!(leaderboard.top_requesters(1).await, vec![(a, 2)]);
+
+        // Entries older than the period are forgotten.
+        let leaderboard = Leaderboard::new(Duration::ZERO);
+        leaderboard
+            .record(&FaucetEvent::FundsReturned {
+                from: a,
+                amount: 1.into(),
+                tx_hash: H256::random(),
+            })
+            .await;
+        async_std::task::sleep(Duration::from_millis(1)).await;
+        assert_eq!(leaderboard.top_returners(10).await, vec![]);
+    }
+}
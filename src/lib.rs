@@ -4,11 +4,209 @@
 // You should have received a copy of the MIT License
 // along with the Discord Faucet library. If not, see <https://mit-license.org/>.
 
+mod alerts;
+pub use alerts::*;
+
+mod bans;
+pub use bans::*;
+
+mod cache;
+pub use cache::*;
+
+mod captcha;
+pub use captcha::*;
+
+#[cfg(feature = "chaos")]
+mod chaos;
+#[cfg(feature = "chaos")]
+pub use chaos::*;
+
+mod cli;
+pub use cli::*;
+
+mod cooldown;
+pub use cooldown::*;
+
+mod database;
+pub(crate) use database::*;
+
+mod disperse;
+pub use disperse::*;
+
+mod drip;
+pub use drip::*;
+
+mod error;
+pub use error::*;
+
+mod events;
+pub use events::*;
+
+mod fairness;
+pub use fairness::*;
+
 mod faucet;
-pub(crate) use crate::faucet::*;
+pub use crate::faucet::*;
+
+mod fees;
+pub use fees::*;
+
+mod finality;
+pub use finality::*;
+
+#[cfg(feature = "grpc")]
+mod grpc;
+#[cfg(feature = "grpc")]
+pub(crate) use grpc::*;
+
+mod gateway;
+pub use gateway::*;
+
+mod guilds;
+pub use guilds::*;
+
+mod leader;
+pub use leader::*;
+
+mod leaderboard;
+pub use leaderboard::*;
+
+mod limits;
+pub use limits::*;
+
+mod load_test;
+pub use load_test::*;
+
+mod log_file;
+pub use log_file::*;
+
+mod matcher;
+pub use matcher::*;
+
+mod messages;
+pub use messages::*;
+
+mod metrics;
+pub use metrics::*;
+
+mod migrations;
+pub(crate) use migrations::*;
+
+#[cfg(test)]
+mod mock;
+#[cfg(test)]
+pub use mock::*;
+
+mod nft;
+pub use nft::*;
+
+mod nonces;
+pub use nonces::*;
+
+mod oauth;
+pub use oauth::*;
+
+mod openapi;
+
+mod ownership;
+pub use ownership::*;
+
+mod paymaster;
+pub use paymaster::*;
+
+mod pow;
+pub use pow::*;
+
+mod queue;
+pub use queue::*;
+
+mod quota;
+pub use quota::*;
+
+mod rate_limit;
+pub use rate_limit::*;
+
+mod relay;
+pub use relay::*;
+
+mod reload;
+pub use reload::*;
+
+mod run;
+pub use run::*;
+
+mod rpc;
+pub use rpc::*;
+
+mod self_test;
+pub use self_test::*;
+
+mod shard;
+pub use shard::*;
+
+mod snapshot;
+pub use snapshot::*;
+
+mod sse;
+pub(crate) use sse::*;
+
+mod statsd;
+pub use statsd::*;
+
+mod status;
+pub use status::*;
+
+mod storage;
+pub use storage::*;
+
+mod submit;
+pub use submit::*;
+
+mod summary;
+pub use summary::*;
+
+mod sybil;
+pub use sybil::*;
+
+mod systemd;
+pub use systemd::*;
+
+mod tarpit;
+pub use tarpit::*;
+
+mod telemetry;
+pub use telemetry::*;
+
+#[cfg(feature = "test_utils")]
+mod test_utils;
+#[cfg(feature = "test_utils")]
+pub use test_utils::*;
+
+mod tokens;
+pub use tokens::*;
+
+mod top_ups;
+pub use top_ups::*;
+
+mod ui;
+pub(crate) use ui::*;
+
+mod validate;
+pub use validate::*;
+
+mod velocity;
+pub use velocity::*;
 
 mod web;
 pub(crate) use web::*;
 
+mod web_tokens;
+pub use web_tokens::*;
+
+mod webhook;
+pub use webhook::*;
+
+#[cfg(feature = "discord")]
 mod discord;
-pub use discord::*;
+#[cfg(feature = "discord")]
+pub(crate) use discord::*;
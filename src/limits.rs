@@ -0,0 +1,397 @@
+// Copyright (c) 2023 Espresso Systems (espressosys.com)
+// This file is part of the Discord Faucet library.
+//
+// You should have received a copy of the MIT License
+// along with the Discord Faucet library. If not, see <https://mit-license.org/>.
+
+//! Rate limits over combinations of the client IP, the recipient address and the Discord user.
+//!
+//! A limit on a single key is easily evaded, by rotating addresses or IPs. Each `--rate-limit` rule
+//! counts the requests sharing the same values of its fields, so a rule on `ip+address` limits each
+//! address from each IP, while separate rules on `ip` and `address` limit each of them on their own.
+//! A rule applies to a request only if all its fields are known, e.g. rules on `ip` do not apply to
+//! Discord commands, and a request must pass every rule which applies, so the strictest one wins.
+//!
+//! Requests are counted in memory, or in the shared cooldown store if there is one, so that
+//! replicas behind a load balancer enforce the rules together.
+use crate::{CooldownStore, SharedCooldownStore};
+use anyhow::{anyhow, bail, Context, Error, Result};
+use async_std::sync::Mutex;
+use ethers::types::Address;
+use std::{
+    collections::{BTreeSet, HashMap, VecDeque},
+    fmt::{self, Display, Formatter},
+    net::IpAddr,
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime},
+};
+
+/// A field of a request which limits can be keyed on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum LimitField {
+    Ip,
+    Address,
+    User,
+}
+
+impl FromStr for LimitField {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "ip" => Ok(Self::Ip),
+            "address" => Ok(Self::Address),
+            "user" => Ok(Self::User),
+            _ => bail!("unknown field {s}, expected ip, address or user"),
+        }
+    }
+}
+
+impl Display for LimitField {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Self::Ip => write!(f, "ip"),
+            Self::Address => write!(f, "address"),
+            Self::User => write!(f, "user"),
+        }
+    }
+}
+
+/// At most `max` requests per `period` sharing the same values of `fields`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LimitRule {
+    pub fields: BTreeSet<LimitField>,
+    pub max: usize,
+    pub period: Duration,
+}
+
+impl FromStr for LimitRule {
+    type Err = Error;
+
+    /// Parse a rule from `FIELDS:MAX:PERIOD`, where `FIELDS` joins `ip`, `address` and `user`
+    /// with `+`, e.g. `ip+address:3:1d`.
+    fn from_str(s: &str) -> Result<Self> {
+        let [fields, max, period] = s.split(':').collect::<Vec<_>>()[..] else {
+            bail!("expected FIELDS:MAX:PERIOD, got {s}");
+        };
+        let fields = fields
+            .split('+')
+            .map(LimitField::from_str)
+            .collect::<Result<BTreeSet<_>>>()?;
+        Ok(Self {
+            fields,
+            max: max.parse().context("invalid maximum number of requests")?,
+            period: duration_str::parse(period).map_err(|err| anyhow!("invalid period: {err}"))?,
+        })
+    }
+}
+
+impl Display for LimitRule {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let fields = self
+            .fields
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>();
+        write!(
+            f,
+            "{}:{}:{}s",
+            fields.join("+"),
+            self.max,
+            self.period.as_secs()
+        )
+    }
+}
+
+/// The known fields of a request.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct LimitKeys {
+    pub ip: Option<IpAddr>,
+    pub address: Option<Address>,
+    pub user: Option<u64>,
+}
+
+/// The value of a field, to key the request counts on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+enum LimitValue {
+    Ip(IpAddr),
+    Address(Address),
+    User(u64),
+}
+
+impl LimitKeys {
+    /// The values of `fields`, or `None` if one of them is not known.
+    fn values(&self, fields: &BTreeSet<LimitField>) -> Option<Vec<LimitValue>> {
+        fields
+            .iter()
+            .map(|field| match field {
+                LimitField::Ip => self.ip.map(LimitValue::Ip),
+                LimitField::Address => self.address.map(LimitValue::Address),
+                LimitField::User => self.user.map(LimitValue::User),
+            })
+            .collect()
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct RateLimiter {
+    rules: Arc<Vec<LimitRule>>,
+    /// The times of the recent requests of each rule, by the values of its fields.
+    requests: Arc<Mutex<Vec<HashMap<Vec<LimitValue>, VecDeque<Instant>>>>>,
+    /// Where the requests are counted instead, if anywhere.
+    storage: Option<SharedCooldownStore>,
+}
+
+impl RateLimiter {
+    pub fn new(rules: Vec<LimitRule>) -> Self {
+        let requests = rules.iter().map(|_| HashMap::new()).collect();
+        Self {
+            rules: Arc::new(rules),
+            requests: Arc::new(Mutex::new(requests)),
+            storage: None,
+        }
+    }
+
+    /// Count the requests in `storage`, so that the rules are shared by all the instances using
+    /// the same storage.
+    pub fn with_storage(mut self, storage: SharedCooldownStore) -> Self {
+        self.storage = Some(storage);
+        self
+    }
+
+    /// Count a request with `keys`, if it is within every rule which applies to it.
+    ///
+    /// Fails with the longest time until the request would be within all the rules, in which case
+    /// it is not counted.
+    pub async fn check(&self, keys: LimitKeys) -> Result<(), Duration> {
+        if self.rules.is_empty() {
+            return Ok(());
+        }
+        if let Some(storage) = &self.storage {
+            match self.check_stored(&**storage, keys).await {
+                Ok(result) => return result,
+                // Fall back to counting in memory rather than refusing all requests.
+                Err(err) => tracing::warn!("Failed to count request {keys:?} in storage: {err:#}"),
+            }
+        }
+        let now = Instant::now();
+        let mut requests = self.requests.lock().await;
+        let mut retry_after = None;
+        let mut counted = vec![];
+        for (index, rule) in self.rules.iter().enumerate() {
+            let Some(values) = keys.values(&rule.fields) else {
+                continue;
+            };
+            let requests = &mut requests[index];
+            requests.retain(|_, times| {
+                while times
+                    .front()
+                    .is_some_and(|time| now.duration_since(*time) >= rule.period)
+                {
+                    times.pop_front();
+                }
+                !times.is_empty()
+            });
+            let times = requests.get(&values);
+            if times.map_or(0, VecDeque::len) >= rule.max {
+                // The oldest request expires first, or never if no request is allowed.
+                let remaining = match times.and_then(VecDeque::front) {
+                    Some(oldest) => rule.period - now.duration_since(*oldest),
+                    None => rule.period,
+                };
+                tracing::info!("Request {keys:?} exceeds the rate limit {rule}");
+                retry_after = retry_after.max(Some(remaining));
+            } else {
+                counted.push((index, values));
+            }
+        }
+        if let Some(remaining) = retry_after {
+            return Err(remaining);
+        }
+        for (index, values) in counted {
+            requests[index].entry(values).or_default().push_back(now);
+        }
+        Ok(())
+    }
+
+    /// Count a request with `keys` in every rule which applies to it, even if it is over the limit.
+    pub async fn count(&self, keys: LimitKeys) {
+        if let Some(storage) = &self.storage {
+            match self.count_stored(&**storage, keys).await {
+                Ok(()) => return,
+                Err(err) => tracing::warn!("Failed to count request {keys:?} in storage: {err:#}"),
+            }
+        }
+        let now = Instant::now();
+        let mut requests = self.requests.lock().await;
+        for (index, rule) in self.rules.iter().enumerate() {
+            if let Some(values) = keys.values(&rule.fields) {
+                requests[index].entry(values).or_default().push_back(now);
+            }
+        }
+    }
+
+    /// [`check`](Self::check) a request against the counts in `storage`.
+    ///
+    /// Concurrent requests to different instances may both pass the last request of a rule.
+    async fn check_stored(
+        &self,
+        storage: &dyn CooldownStore,
+        keys: LimitKeys,
+    ) -> Result<Result<(), Duration>> {
+        let now = SystemTime::now();
+        let mut retry_after = None;
+        let mut counted = vec![];
+        for rule in self.rules.iter() {
+            let Some(values) = keys.values(&rule.fields) else {
+                continue;
+            };
+            let key = storage_key(rule, &values);
+            let (hits, oldest) = storage.hits(&key, rule.period).await?;
+            if hits >= rule.max {
+                let remaining = match oldest {
+                    Some(oldest) => rule
+                        .period
+                        .saturating_sub(now.duration_since(oldest).unwrap_or_default()),
+                    None => rule.period,
+                };
+                tracing::info!("Request {keys:?} exceeds the rate limit {rule}");
+                retry_after = retry_after.max(Some(remaining));
+            } else {
+                counted.push((key, rule.period));
+            }
+        }
+        if let Some(remaining) = retry_after {
+            return Ok(Err(remaining));
+        }
+        for (key, period) in counted {
+            storage.hit(&key, period).await?;
+        }
+        Ok(Ok(()))
+    }
+
+    /// [`count`](Self::count) a request in `storage`.
+    async fn count_stored(&self, storage: &dyn CooldownStore, keys: LimitKeys) -> Result<()> {
+        for rule in self.rules.iter() {
+            if let Some(values) = keys.values(&rule.fields) {
+                storage
+                    .hit(&storage_key(rule, &values), rule.period)
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The name of the counter of `rule` for the requests with `values`.
+fn storage_key(rule: &LimitRule, values: &[LimitValue]) -> String {
+    let values = values
+        .iter()
+        .map(|value| match value {
+            LimitValue::Ip(ip) => ip.to_string(),
+            LimitValue::Address(address) => format!("{address:?}"),
+            LimitValue::User(user) => user.to_string(),
+        })
+        .collect::<Vec<_>>();
+    format!("rate-limit:{rule}:{}", values.join("+"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::MemoryCooldownStore;
+
+    #[test]
+    fn test_limit_rule() {
+        let rule = "ip+address:3:1d".parse::<LimitRule>().unwrap();
+        assert_eq!(
+            rule.fields,
+            [LimitField::Ip, LimitField::Address].into_iter().collect()
+        );
+        assert_eq!(rule.max, 3);
+        assert_eq!(rule.period, Duration::from_secs(24 * 3600));
+        assert_eq!(rule.to_string(), "ip+address:3:86400s");
+
+        "ip+email:3:1d".parse::<LimitRule>().unwrap_err();
+        "ip:3".parse::<LimitRule>().unwrap_err();
+    }
+
+    #[async_std::test]
+    async fn test_rate_limiter() {
+        let limiter = RateLimiter::new(vec![
+            "address:2:1h".parse().unwrap(),
+            "ip+user:1:1h".parse().unwrap(),
+        ]);
+        let address = Address::random();
+        let ip = Some("10.0.0.1".parse().unwrap());
+
+        // Only the address rule applies without a user.
+        for _ in 0..2 {
+            limiter
+                .check(LimitKeys {
+                    ip,
+                    address: Some(address),
+                    user: None,
+                })
+                .await
+                .unwrap();
+        }
+        limiter
+            .check(LimitKeys {
+                ip: None,
+                address: Some(address),
+                user: Some(1),
+            })
+            .await
+            .unwrap_err();
+
+        // The strictest rule wins, and rejected requests are not counted.
+        let other = Some(Address::random());
+        let keys = LimitKeys {
+            ip,
+            address: other,
+            user: Some(1),
+        };
+        limiter.check(keys).await.unwrap();
+        limiter.check(keys).await.unwrap_err();
+        limiter
+            .check(LimitKeys {
+                ip: Some("10.0.0.2".parse().unwrap()),
+                address: other,
+                user: Some(1),
+            })
+            .await
+            .unwrap();
+    }
+
+    #[async_std::test]
+    async fn test_shared_rate_limiter() {
+        let rules = vec!["address:2:1h".parse::<LimitRule>().unwrap()];
+        let store: SharedCooldownStore = Arc::new(MemoryCooldownStore::default());
+        let replicas = [
+            RateLimiter::new(rules.clone()).with_storage(store.clone()),
+            RateLimiter::new(rules).with_storage(store),
+        ];
+        let keys = LimitKeys {
+            address: Some(Address::random()),
+            ..Default::default()
+        };
+
+        // The replicas count the requests together.
+        replicas[0].check(keys).await.unwrap();
+        replicas[1].check(keys).await.unwrap();
+        let remaining = replicas[0].check(keys).await.unwrap_err();
+        assert!(remaining <= Duration::from_secs(3600) && remaining > Duration::from_secs(1800));
+        replicas[1].check(keys).await.unwrap_err();
+
+        // Other addresses are not affected.
+        replicas[1]
+            .check(LimitKeys {
+                address: Some(Address::random()),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+    }
+}
@@ -0,0 +1,206 @@
+// Copyright (c) 2023 Espresso Systems (espressosys.com)
+// This file is part of the Discord Faucet library.
+//
+// You should have received a copy of the MIT License
+// along with the Discord Faucet library. If not, see <https://mit-license.org/>.
+
+//! The `load-test` command, which drives the HTTP API of a faucet at a steady request rate.
+//!
+//! Each request grants funds to a new random address. Only the time to queue the request is
+//! measured, not the time to mine the transfer, so the report shows how many requests the faucet
+//! accepts before the queue fills up.
+use crate::{ErrorCode, FaucetError, QueuedRequest};
+use async_std::task::{sleep, spawn};
+use clap::Args;
+use ethers::types::Address;
+use futures::future::join_all;
+use std::{
+    collections::BTreeMap,
+    fmt::{self, Display, Formatter},
+    time::{Duration, Instant},
+};
+use surf_disco::Client;
+use url::Url;
+
+#[derive(Args, Clone, Debug)]
+pub struct LoadTest {
+    /// The URL of the API of the faucet under test.
+    #[arg(
+        long,
+        env = "ESPRESSO_DISCORD_FAUCET_LOAD_TEST_URL",
+        default_value = "http://localhost:8111"
+    )]
+    url: Url,
+
+    /// Requests per second.
+    #[arg(long, default_value = "10")]
+    rate: f64,
+
+    /// How long to send requests for.
+    #[arg(long, default_value = "1m", value_parser = duration_str::parse)]
+    duration: Duration,
+
+    /// An API key sent with each request, so that the cooldown of the faucet does not apply.
+    #[arg(long, env = "ESPRESSO_DISCORD_FAUCET_LOAD_TEST_API_KEY")]
+    api_key: Option<String>,
+}
+
+/// The outcome of a load test.
+#[derive(Debug, Default)]
+pub struct LoadReport {
+    elapsed: Duration,
+    /// The latency of each successful request.
+    latencies: Vec<Duration>,
+    /// The number of failed requests, by error code.
+    failures: BTreeMap<String, usize>,
+}
+
+impl LoadReport {
+    fn record(&mut self, result: Result<Duration, FaucetError>) {
+        match result {
+            Ok(latency) => self.latencies.push(latency),
+            Err(err) => *self.failures.entry(failure(&err)).or_default() += 1,
+        }
+    }
+
+    /// The latency under which `p` percent of the successful requests completed.
+    fn percentile(&self, p: usize) -> Option<Duration> {
+        let mut latencies = self.latencies.clone();
+        latencies.sort();
+        let index = (latencies.len() * p).div_ceil(100).checked_sub(1)?;
+        latencies.get(index).copied()
+    }
+
+    /// Whether every request succeeded.
+    pub fn passed(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+/// The name of the failure of a request in the report.
+fn failure(err: &FaucetError) -> String {
+    match err.code {
+        // Errors which did not come from the faucet, e.g. connection errors, have no code of
+        // their own.
+        ErrorCode::Internal => format!("{:?} ({})", err.code, err.status),
+        code => format!("{code:?}"),
+    }
+}
+
+impl Display for LoadReport {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let failed: usize = self.failures.values().sum();
+        let total = self.latencies.len() + failed;
+        let secs = self.elapsed.as_secs_f64().max(f64::EPSILON);
+        writeln!(
+            f,
+            "Requests:   {total} in {:.1}s",
+            self.elapsed.as_secs_f64()
+        )?;
+        writeln!(
+            f,
+            "Throughput: {:.1} req/s, {:.1} successful req/s",
+            total as f64 / secs,
+            self.latencies.len() as f64 / secs
+        )?;
+        writeln!(f, "Succeeded:  {}", self.latencies.len())?;
+        writeln!(f, "Failed:     {failed}")?;
+        for (reason, count) in &self.failures {
+            writeln!(f, "  {reason}: {count}")?;
+        }
+        write!(f, "Latency:")?;
+        for p in [50, 90, 99] {
+            match self.percentile(p) {
+                Some(latency) => write!(f, "  p{p} {}ms", latency.as_millis())?,
+                None => write!(f, "  p{p} -")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl LoadTest {
+    /// Send requests at `--rate` for `--duration`, then print the report.
+    ///
+    /// Returns whether every request succeeded.
+    pub async fn run(self) -> bool {
+        if self.rate.is_nan() || self.rate <= 0.0 {
+            println!("The rate must be positive");
+            return false;
+        }
+        let client = Client::<FaucetError>::new(self.url.clone());
+        let interval = Duration::from_secs_f64(1.0 / self.rate);
+        let count = (self.duration.as_secs_f64() * self.rate).ceil() as u32;
+        println!(
+            "Sending {count} requests to {} over {:?}",
+            self.url, self.duration
+        );
+
+        let start = Instant::now();
+        let mut requests = vec![];
+        for i in 0..count {
+            // Schedule each request from the start, so that slow requests do not lower the rate.
+            if let Some(delay) = (interval * i).checked_sub(start.elapsed()) {
+                sleep(delay).await;
+            }
+            requests.push(spawn(request(client.clone(), self.api_key.clone())));
+        }
+        let mut report = LoadReport::default();
+        for result in join_all(requests).await {
+            report.record(result);
+        }
+        report.elapsed = start.elapsed();
+
+        println!("{report}");
+        report.passed()
+    }
+}
+
+/// Request funds for a random address, returning how long the request took.
+async fn request(
+    client: Client<FaucetError>,
+    api_key: Option<String>,
+) -> Result<Duration, FaucetError> {
+    let start = Instant::now();
+    let mut req = client.post::<QueuedRequest>(&format!("v1/request/{:?}", Address::random()));
+    if let Some(key) = api_key {
+        req = req.header("X-Api-Key", key);
+    }
+    req.send().await?;
+    Ok(start.elapsed())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tide_disco::http::StatusCode;
+
+    #[test]
+    fn test_load_report() {
+        let mut report = LoadReport {
+            elapsed: Duration::from_secs(2),
+            ..Default::default()
+        };
+        assert_eq!(report.percentile(50), None);
+        for ms in 1..=10 {
+            report.record(Ok(Duration::from_millis(ms)));
+        }
+        report.record(Err(FaucetError::new(
+            ErrorCode::QueueFull,
+            StatusCode::ServiceUnavailable,
+            "full",
+        )));
+        assert!(!report.passed());
+        assert_eq!(report.percentile(50), Some(Duration::from_millis(5)));
+        assert_eq!(report.percentile(99), Some(Duration::from_millis(10)));
+        assert_eq!(
+            report.to_string(),
+            "Requests:   11 in 2.0s\n\
+             Throughput: 5.5 req/s, 5.0 successful req/s\n\
+             Succeeded:  10\n\
+             Failed:     1\n  \
+             QueueFull: 1\n\
+             Latency:  p50 5ms  p90 9ms  p99 10ms"
+        );
+    }
+}
@@ -0,0 +1,144 @@
+// Copyright (c) 2023 Espresso Systems (espressosys.com)
+// This file is part of the Discord Faucet library.
+//
+// You should have received a copy of the MIT License
+// along with the Discord Faucet library. If not, see <https://mit-license.org/>.
+
+//! A log file which is rotated by size and age.
+//!
+//! When the file is rotated, `faucet.log` is renamed to `faucet.log.1`, `faucet.log.1` to
+//! `faucet.log.2` and so on, and the oldest file beyond the number of files to keep is deleted, so
+//! the logs never take more than about `(keep + 1) * max_size` on disk.
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+#[derive(Debug)]
+struct State {
+    file: File,
+    size: u64,
+    opened: Instant,
+}
+
+/// A log file, shared by all the writers of the logs.
+#[derive(Clone, Debug)]
+pub struct RotatingFile {
+    path: PathBuf,
+    /// The size above which the file is rotated, if any.
+    max_size: Option<u64>,
+    /// The age above which the file is rotated, if any.
+    max_age: Option<Duration>,
+    /// How many rotated files to keep.
+    keep: usize,
+    state: Arc<Mutex<State>>,
+}
+
+impl RotatingFile {
+    /// Append to the log file at `path`.
+    pub fn open(
+        path: impl AsRef<Path>,
+        max_size: Option<u64>,
+        max_age: Option<Duration>,
+        keep: usize,
+    ) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let state = Self::open_state(&path)?;
+        Ok(Self {
+            path,
+            max_size,
+            max_age,
+            keep,
+            state: Arc::new(Mutex::new(state)),
+        })
+    }
+
+    fn open_state(path: &Path) -> io::Result<State> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let size = file.metadata()?.len();
+        Ok(State {
+            file,
+            size,
+            opened: Instant::now(),
+        })
+    }
+
+    fn rotated(&self, n: usize) -> PathBuf {
+        let mut name = self.path.clone().into_os_string();
+        name.push(format!(".{n}"));
+        name.into()
+    }
+
+    fn rotate(&self, state: &mut State) -> io::Result<()> {
+        state.file.flush()?;
+        if self.keep == 0 {
+            fs::remove_file(&self.path)?;
+        } else {
+            let oldest = self.rotated(self.keep);
+            if oldest.exists() {
+                fs::remove_file(oldest)?;
+            }
+            for n in (1..self.keep).rev() {
+                let from = self.rotated(n);
+                if from.exists() {
+                    fs::rename(from, self.rotated(n + 1))?;
+                }
+            }
+            fs::rename(&self.path, self.rotated(1))?;
+        }
+        *state = Self::open_state(&self.path)?;
+        Ok(())
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut state = self.state.lock().unwrap();
+        let too_big = self
+            .max_size
+            .is_some_and(|max| state.size > 0 && state.size + buf.len() as u64 > max);
+        let too_old = self
+            .max_age
+            .is_some_and(|max| state.opened.elapsed() >= max);
+        if too_big || too_old {
+            self.rotate(&mut state)?;
+        }
+        let written = state.file.write(buf)?;
+        state.size += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.state.lock().unwrap().file.flush()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_rotation() {
+        let dir = std::env::temp_dir().join(format!("faucet-logs-{}", rand::random::<u64>()));
+        fs::create_dir(&dir).unwrap();
+        let path = dir.join("faucet.log");
+
+        let mut file = RotatingFile::open(&path, Some(10), None, 2).unwrap();
+        for line in ["first\n", "second\n", "third\n", "fourth\n"] {
+            file.write_all(line.as_bytes()).unwrap();
+        }
+        file.flush().unwrap();
+
+        // Each line exceeds the size of the file with the previous one, and only 2 rotated files
+        // are kept.
+        assert_eq!(fs::read_to_string(&path).unwrap(), "fourth\n");
+        assert_eq!(fs::read_to_string(file.rotated(1)).unwrap(), "third\n");
+        assert_eq!(fs::read_to_string(file.rotated(2)).unwrap(), "second\n");
+        assert!(!file.rotated(3).exists());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,163 @@
+// Copyright (c) 2023 Espresso Systems (espressosys.com)
+// This file is part of the Discord Faucet library.
+//
+// You should have received a copy of the MIT License
+// along with the Discord Faucet library. If not, see <https://mit-license.org/>.
+
+//! Recognizing requests for funds in Discord messages.
+use anyhow::{bail, Context, Result};
+use ethers::types::Address;
+use regex::Regex;
+use std::str::FromStr;
+
+/// The pattern of the addresses in a message, unless a guild configures another one.
+pub const DEFAULT_ADDRESS_PATTERN: &str = "0x[a-fA-F0-9]{40}";
+
+/// Which channel messages are requests for funds.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Trigger {
+    /// Messages starting with a word, e.g. `faucet please send to 0x...`.
+    Prefix(String),
+    /// Messages consisting of exactly a command and addresses, e.g. `!faucet 0x...`.
+    Command(String),
+    /// Any message containing an address.
+    Address,
+}
+
+impl FromStr for Trigger {
+    type Err = anyhow::Error;
+
+    /// Parse a trigger from `prefix:WORD`, `command:COMMAND` or `address`.
+    fn from_str(s: &str) -> Result<Self> {
+        match s.split_once(':') {
+            Some(("prefix", word)) if !word.is_empty() => Ok(Self::Prefix(word.to_string())),
+            Some(("command", command)) if !command.is_empty() => {
+                Ok(Self::Command(command.to_string()))
+            }
+            None if s == "address" => Ok(Self::Address),
+            _ => bail!("expected prefix:WORD, command:COMMAND or address, got {s}"),
+        }
+    }
+}
+
+/// Recognizes requests for funds and extracts their addresses.
+#[derive(Clone, Debug)]
+pub struct Matcher {
+    /// Which channel messages are requests, or `None` if only commands are.
+    trigger: Option<Trigger>,
+    /// The pattern of an address. If it has a capture group, the address is the first group.
+    pattern: Regex,
+}
+
+impl Default for Matcher {
+    fn default() -> Self {
+        Self {
+            trigger: None,
+            pattern: Regex::new(DEFAULT_ADDRESS_PATTERN).unwrap(),
+        }
+    }
+}
+
+impl Matcher {
+    pub fn new(trigger: Option<Trigger>, pattern: Option<&str>) -> Result<Self> {
+        let pattern = Regex::new(pattern.unwrap_or(DEFAULT_ADDRESS_PATTERN))
+            .context("invalid address pattern")?;
+        Ok(Self { trigger, pattern })
+    }
+
+    /// Whether a channel message is a request for funds.
+    pub fn is_request(&self, message: &str) -> bool {
+        let message = message.trim();
+        match &self.trigger {
+            None => false,
+            Some(Trigger::Prefix(word)) => message
+                .split_whitespace()
+                .next()
+                .is_some_and(|first| first.eq_ignore_ascii_case(word)),
+            Some(Trigger::Command(command)) => {
+                let mut words = message.split_whitespace();
+                words.next() == Some(command.as_str())
+                    && words.all(|word| self.pattern.is_match(word))
+                    && !self.addresses(message).is_empty()
+            }
+            Some(Trigger::Address) => !self.addresses(message).is_empty(),
+        }
+    }
+
+    /// The distinct addresses in `text`, in the order they appear.
+    pub fn addresses(&self, text: &str) -> Vec<Address> {
+        let mut addresses: Vec<Address> = vec![];
+        for captures in self.pattern.captures_iter(text) {
+            let matched = captures.get(1).or_else(|| captures.get(0)).unwrap();
+            // Patterns may match text which is not an address, which is skipped.
+            let Ok(address) = matched.as_str().parse() else {
+                continue;
+            };
+            if !addresses.contains(&address) {
+                addresses.push(address);
+            }
+        }
+        addresses
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const ADDRESS: &str = "0x1234567890123456789012345678901234567890";
+
+    #[test]
+    fn test_parse_trigger() {
+        assert_eq!(
+            "prefix:faucet".parse::<Trigger>().unwrap(),
+            Trigger::Prefix("faucet".into())
+        );
+        assert_eq!(
+            "command:!faucet".parse::<Trigger>().unwrap(),
+            Trigger::Command("!faucet".into())
+        );
+        assert_eq!("address".parse::<Trigger>().unwrap(), Trigger::Address);
+        assert!("prefix:".parse::<Trigger>().is_err());
+        assert!("anything".parse::<Trigger>().is_err());
+    }
+
+    #[test]
+    fn test_triggers() {
+        let text = format!("please send to {ADDRESS}");
+        assert!(!Matcher::default().is_request(&text));
+
+        let matcher = Matcher::new(Some(Trigger::Address), None).unwrap();
+        assert!(matcher.is_request(&text));
+        assert!(!matcher.is_request("hello"));
+
+        let matcher = Matcher::new(Some(Trigger::Prefix("faucet".into())), None).unwrap();
+        assert!(matcher.is_request(&format!("Faucet {ADDRESS}")));
+        assert!(!matcher.is_request(&text));
+
+        let matcher = Matcher::new(Some(Trigger::Command("!faucet".into())), None).unwrap();
+        assert!(matcher.is_request(&format!("!faucet {ADDRESS}")));
+        assert!(!matcher.is_request(&format!("!faucet to {ADDRESS}")));
+        assert!(!matcher.is_request("!faucet"));
+        assert!(!matcher.is_request(&text));
+    }
+
+    #[test]
+    fn test_addresses() {
+        let other = "0xabcdefabcdefabcdefabcdefabcdefabcdefabcd";
+        let text = format!("{ADDRESS} and {other}, again {ADDRESS}");
+        let addresses = Matcher::default().addresses(&text);
+        assert_eq!(
+            addresses,
+            vec![ADDRESS.parse().unwrap(), other.parse().unwrap()]
+        );
+
+        // With a capture group, only the captured part is the address.
+        let matcher = Matcher::new(None, Some(r"to:(0x[a-fA-F0-9]{40})")).unwrap();
+        assert_eq!(
+            matcher.addresses(&format!("{other} to:{ADDRESS}")),
+            vec![ADDRESS.parse::<Address>().unwrap()]
+        );
+        assert!(Matcher::new(None, Some("(")).is_err());
+    }
+}
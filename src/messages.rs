@@ -0,0 +1,162 @@
+// Copyright (c) 2023 Espresso Systems (espressosys.com)
+// This file is part of the Discord Faucet library.
+//
+// You should have received a copy of the MIT License
+// along with the Discord Faucet library. If not, see <https://mit-license.org/>.
+
+//! Localized replies of the Discord bot.
+//!
+//! The English messages are built in. Other locales are loaded from TOML files with the same keys,
+//! and fall back to English for the keys they do not translate.
+use anyhow::{bail, Context, Result};
+use std::{collections::HashMap, fmt::Display, fs, path::Path, sync::Arc};
+
+const ENGLISH: &str = include_str!("messages.toml");
+
+/// The messages of one locale.
+#[derive(Clone, Debug)]
+pub struct Messages {
+    messages: Arc<HashMap<String, String>>,
+}
+
+impl From<HashMap<String, String>> for Messages {
+    fn from(messages: HashMap<String, String>) -> Self {
+        Self {
+            messages: Arc::new(messages),
+        }
+    }
+}
+
+impl Messages {
+    /// The message `key`, with each `{name}` placeholder replaced by the argument `name`.
+    pub fn get(&self, key: &str, args: &[(&str, &dyn Display)]) -> String {
+        let Some(template) = self.messages.get(key) else {
+            tracing::error!("Missing message {key}");
+            return key.to_string();
+        };
+        args.iter()
+            .fold(template.clone(), |message, (name, value)| {
+                message.replace(&format!("{{{name}}}"), &value.to_string())
+            })
+    }
+}
+
+/// The messages of all the available locales.
+#[derive(Clone, Debug)]
+pub struct Catalog {
+    locales: Arc<HashMap<String, Messages>>,
+    default_locale: String,
+}
+
+impl Default for Catalog {
+    /// A catalog with only English.
+    fn default() -> Self {
+        Self::new([], "en").expect("English is always available")
+    }
+}
+
+impl Catalog {
+    /// Load the translations in `dir`, if any, using `default_locale` for guilds without a locale.
+    pub fn load(dir: Option<&Path>, default_locale: &str) -> Result<Self> {
+        let mut translations = vec![];
+        if let Some(dir) = dir {
+            let entries =
+                fs::read_dir(dir).with_context(|| format!("reading {}", dir.display()))?;
+            for entry in entries {
+                let path = entry?.path();
+                if path.extension().and_then(|ext| ext.to_str()) != Some("toml") {
+                    continue;
+                }
+                let Some(locale) = path.file_stem().and_then(|stem| stem.to_str()) else {
+                    continue;
+                };
+                let contents = fs::read_to_string(&path)
+                    .with_context(|| format!("reading {}", path.display()))?;
+                let messages =
+                    parse(&contents).with_context(|| format!("parsing {}", path.display()))?;
+                tracing::info!("Loaded messages for locale {locale}");
+                translations.push((locale.to_string(), messages));
+            }
+        }
+        Self::new(translations, default_locale)
+    }
+
+    /// A catalog with English and `translations`, indexed by locale.
+    pub fn new(
+        translations: impl IntoIterator<Item = (String, HashMap<String, String>)>,
+        default_locale: &str,
+    ) -> Result<Self> {
+        let english = parse(ENGLISH).expect("built-in messages are valid");
+        let mut locales = HashMap::new();
+        for (locale, translation) in translations {
+            for key in translation.keys() {
+                if !english.contains_key(key) {
+                    tracing::warn!("Unknown message {key} for locale {locale}");
+                }
+            }
+            let mut messages = english.clone();
+            messages.extend(translation);
+            locales.insert(locale, Messages::from(messages));
+        }
+        locales
+            .entry("en".to_string())
+            .or_insert_with(|| Messages::from(english));
+        if !locales.contains_key(default_locale) {
+            bail!("no messages for the default locale {default_locale}");
+        }
+        Ok(Self {
+            locales: Arc::new(locales),
+            default_locale: default_locale.to_string(),
+        })
+    }
+
+    /// The messages of `locale`, or of the default locale if `locale` is unknown or not given.
+    pub fn messages(&self, locale: Option<&str>) -> Messages {
+        if let Some(messages) = locale.and_then(|locale| self.locales.get(locale)) {
+            return messages.clone();
+        }
+        if let Some(locale) = locale {
+            tracing::warn!(
+                "No messages for locale {locale}, using {}",
+                self.default_locale
+            );
+        }
+        self.locales[&self.default_locale].clone()
+    }
+}
+
+fn parse(contents: &str) -> Result<HashMap<String, String>> {
+    Ok(toml::from_str(contents)?)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_messages() {
+        let german = [(
+            "de".to_string(),
+            parse("user_ready = \"Du kannst jetzt Guthaben anfordern.\"").unwrap(),
+        )];
+        let catalog = Catalog::new(german.clone(), "en").unwrap();
+
+        let messages = catalog.messages(Some("de"));
+        assert_eq!(
+            messages.get("user_ready", &[]),
+            "Du kannst jetzt Guthaben anfordern."
+        );
+        // Missing translations fall back to English.
+        assert_eq!(messages.get("minute_other", &[("count", &5)]), "5 minutes");
+        // Unknown locales use the default one.
+        assert_eq!(
+            catalog.messages(Some("fr")).get("user_ready", &[]),
+            "You can request funds now."
+        );
+        assert_eq!(
+            Catalog::default().messages(None).get("user_ready", &[]),
+            "You can request funds now."
+        );
+        assert!(Catalog::new(german, "fr").is_err());
+    }
+}
@@ -0,0 +1,361 @@
+// Copyright (c) 2023 Espresso Systems (espressosys.com)
+// This file is part of the Discord Faucet library.
+//
+// You should have received a copy of the MIT License
+// along with the Discord Faucet library. If not, see <https://mit-license.org/>.
+
+//! Metrics of the faucet and the Discord bot.
+//!
+//! The metrics are collected as a list of [`Sample`]s, which are served at `/metrics` in the
+//! Prometheus text format and, if configured, pushed to StatsD. The metrics are few and simple, so
+//! they are rendered directly rather than with a metrics library.
+use crate::{CacheStats, FaucetStats};
+use async_std::sync::RwLock;
+use clap::ValueEnum;
+use std::{collections::BTreeMap, convert::Infallible, fmt::Write, sync::Arc};
+use tide_disco::metrics::Metrics;
+
+/// Where metrics are exported, in addition to the Prometheus endpoint.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum MetricsBackend {
+    /// Only serve the metrics at `/metrics`.
+    #[default]
+    Prometheus,
+    /// Push the metrics to StatsD, with labels in the metric names.
+    Statsd,
+    /// Push the metrics to the Datadog agent, with labels as DogStatsD tags.
+    Dogstatsd,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MetricKind {
+    Counter,
+    Gauge,
+}
+
+impl MetricKind {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Counter => "counter",
+            Self::Gauge => "gauge",
+        }
+    }
+}
+
+/// The value of a metric at the time it was collected.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Sample {
+    pub name: &'static str,
+    pub help: &'static str,
+    pub kind: MetricKind,
+    /// The label distinguishing the samples of the same metric, if any.
+    pub label: Option<(&'static str, String)>,
+    pub value: u64,
+}
+
+impl Sample {
+    fn new(name: &'static str, kind: MetricKind, help: &'static str, value: u64) -> Self {
+        Self {
+            name,
+            help,
+            kind,
+            label: None,
+            value,
+        }
+    }
+
+    fn with_label(mut self, name: &'static str, value: impl ToString) -> Self {
+        self.label = Some((name, value.to_string()));
+        self
+    }
+}
+
+/// Why a Discord request for funds was rejected.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Rejection {
+    Ban,
+    Role,
+    Account,
+    Registration,
+    Cooldown,
+    Quota,
+}
+
+impl Rejection {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Ban => "ban",
+            Self::Role => "role",
+            Self::Account => "account",
+            Self::Registration => "registration",
+            Self::Cooldown => "cooldown",
+            Self::Quota => "quota",
+        }
+    }
+}
+
+/// Counters of the interactions with the Discord bot.
+#[derive(Clone, Debug, Default)]
+pub struct DiscordMetrics {
+    commands: Arc<RwLock<BTreeMap<String, u64>>>,
+    rejections: Arc<RwLock<BTreeMap<Rejection, u64>>>,
+    /// The grants issued in each guild, with direct messages under guild 0.
+    grants: Arc<RwLock<BTreeMap<u64, u64>>>,
+}
+
+impl DiscordMetrics {
+    /// Count a command received by the bot.
+    pub async fn command(&self, name: &str) {
+        *self
+            .commands
+            .write()
+            .await
+            .entry(name.to_string())
+            .or_default() += 1;
+    }
+
+    /// Count a request rejected for `reason`.
+    pub async fn rejection(&self, reason: Rejection) {
+        *self.rejections.write().await.entry(reason).or_default() += 1;
+    }
+
+    /// Count `count` grants issued in `guild`, or in direct messages if `None`.
+    pub async fn grants(&self, guild: Option<u64>, count: usize) {
+        *self
+            .grants
+            .write()
+            .await
+            .entry(guild.unwrap_or(0))
+            .or_default() += count as u64;
+    }
+
+    async fn samples(&self, out: &mut Vec<Sample>) {
+        for (name, count) in self.commands.read().await.iter() {
+            out.push(
+                Sample::new(
+                    "discord_commands_total",
+                    MetricKind::Counter,
+                    "Discord commands received.",
+                    *count,
+                )
+                .with_label("command", name),
+            );
+        }
+        for (reason, count) in self.rejections.read().await.iter() {
+            out.push(
+                Sample::new(
+                    "discord_requests_rejected_total",
+                    MetricKind::Counter,
+                    "Discord requests for funds rejected, by reason.",
+                    *count,
+                )
+                .with_label("reason", reason.as_str()),
+            );
+        }
+        for (guild, count) in self.grants.read().await.iter() {
+            out.push(
+                Sample::new(
+                    "discord_grants_total",
+                    MetricKind::Counter,
+                    "Grants issued to Discord users, by guild. Direct messages are counted as guild 0.",
+                    *count,
+                )
+                .with_label("guild", guild),
+            );
+        }
+    }
+}
+
+/// Collect the metrics of the default faucet and, if it is enabled, the Discord bot, with the
+/// number of its gateway reconnects and of its requests not yet in the audit log.
+pub async fn collect_metrics(
+    faucet: Option<FaucetStats>,
+    caches: &[CacheStats],
+    discord: Option<(&DiscordMetrics, u64, usize)>,
+    self_test: Option<bool>,
+) -> Vec<Sample> {
+    let mut samples = vec![];
+    if let Some(stats) = faucet {
+        for (name, help, value) in [
+            (
+                "faucet_queue_length",
+                "Transfers waiting for a wallet.",
+                stats.queue_length as u64,
+            ),
+            (
+                "faucet_inflight",
+                "Transfers sent but not yet mined.",
+                stats.inflight as u64,
+            ),
+            (
+                "faucet_completed",
+                "Completed requests whose result is remembered.",
+                stats.completed as u64,
+            ),
+            (
+                "faucet_tarpitted",
+                "Transfers held back by the tarpit.",
+                stats.tarpitted as u64,
+            ),
+            (
+                "faucet_drips",
+                "Drip requests with installments still to be paid.",
+                stats.drips as u64,
+            ),
+            (
+                "faucet_relays",
+                "Relays which are not confirmed yet.",
+                stats.relays as u64,
+            ),
+            (
+                "faucet_queue_keys",
+                "Queued requests placed by their source or requester.",
+                stats.queue_keys as u64,
+            ),
+            (
+                "faucet_available_wallets",
+                "Wallets ready to send a transfer.",
+                stats.available_wallets as u64,
+            ),
+            (
+                "faucet_block_number",
+                "The latest block number of the chain.",
+                stats.block_number.as_u64(),
+            ),
+            (
+                "faucet_paused",
+                "Whether the faucet is paused.",
+                stats.paused as u64,
+            ),
+        ] {
+            samples.push(Sample::new(name, MetricKind::Gauge, help, value));
+        }
+    }
+    for stats in caches {
+        samples.push(
+            Sample::new(
+                "faucet_cache_hits_total",
+                MetricKind::Counter,
+                "Reads of chain data served from the cache, by cache.",
+                stats.hits,
+            )
+            .with_label("cache", stats.cache),
+        );
+    }
+    for stats in caches {
+        samples.push(
+            Sample::new(
+                "faucet_cache_misses_total",
+                MetricKind::Counter,
+                "Reads of chain data not found in the cache, by cache.",
+                stats.misses,
+            )
+            .with_label("cache", stats.cache),
+        );
+    }
+    if let Some((metrics, reconnects, requesters)) = discord {
+        metrics.samples(&mut samples).await;
+        samples.push(Sample::new(
+            "discord_gateway_reconnects_total",
+            MetricKind::Counter,
+            "Reconnections of the Discord shards to the gateway.",
+            reconnects,
+        ));
+        samples.push(Sample::new(
+            "discord_pending_requesters",
+            MetricKind::Gauge,
+            "Discord requests whose requester is not yet in the audit log.",
+            requesters as u64,
+        ));
+    }
+    if let Some(passed) = self_test {
+        samples.push(Sample::new(
+            "faucet_self_test_passed",
+            MetricKind::Gauge,
+            "Whether the startup self-test grant succeeded.",
+            passed as u64,
+        ));
+    }
+    samples
+}
+
+/// The metrics exported at `/metrics`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MetricsReport(String);
+
+impl MetricsReport {
+    /// Render `samples` in the Prometheus text format.
+    pub fn new(samples: &[Sample]) -> Self {
+        let mut out = String::new();
+        let mut previous = None;
+        for sample in samples {
+            // The samples of a metric are collected together, so the header is written once.
+            if previous != Some(sample.name) {
+                writeln!(out, "# HELP {} {}", sample.name, sample.help).unwrap();
+                writeln!(out, "# TYPE {} {}", sample.name, sample.kind.as_str()).unwrap();
+                previous = Some(sample.name);
+            }
+            match &sample.label {
+                Some((label, value)) => writeln!(
+                    out,
+                    "{}{{{label}=\"{value}\"}} {}",
+                    sample.name, sample.value
+                ),
+                None => writeln!(out, "{} {}", sample.name, sample.value),
+            }
+            .unwrap();
+        }
+        Self(out)
+    }
+}
+
+impl Metrics for MetricsReport {
+    type Error = Infallible;
+
+    fn export(&self) -> Result<String, Self::Error> {
+        Ok(self.0.clone())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[async_std::test]
+    async fn test_discord_metrics() {
+        let metrics = DiscordMetrics::default();
+        metrics.command("faucet").await;
+        metrics.command("faucet").await;
+        metrics.rejection(Rejection::Cooldown).await;
+        metrics.grants(Some(10), 2).await;
+        metrics.grants(None, 1).await;
+
+        let caches = [CacheStats {
+            cache: "receipts",
+            hits: 4,
+            misses: 1,
+        }];
+        let samples = collect_metrics(None, &caches, Some((&metrics, 3, 2)), Some(false)).await;
+        let report = MetricsReport::new(&samples).export().unwrap();
+        for line in [
+            "discord_commands_total{command=\"faucet\"} 2",
+            "discord_requests_rejected_total{reason=\"cooldown\"} 1",
+            "discord_grants_total{guild=\"10\"} 2",
+            "discord_grants_total{guild=\"0\"} 1",
+            "discord_gateway_reconnects_total 3",
+            "discord_pending_requesters 2",
+            "faucet_self_test_passed 0",
+            "faucet_cache_hits_total{cache=\"receipts\"} 4",
+            "faucet_cache_misses_total{cache=\"receipts\"} 1",
+        ] {
+            assert!(report.lines().any(|l| l == line), "missing {line}");
+        }
+        assert_eq!(
+            report
+                .lines()
+                .filter(|l| *l == "# TYPE discord_grants_total counter")
+                .count(),
+            1
+        );
+    }
+}
@@ -0,0 +1,165 @@
+// Copyright (c) 2023 Espresso Systems (espressosys.com)
+// This file is part of the Discord Faucet library.
+//
+// You should have received a copy of the MIT License
+// along with the Discord Faucet library. If not, see <https://mit-license.org/>.
+
+//! Versioned migrations of the database schemas, embedded in the binary.
+//!
+//! Migrations are applied automatically when a database is opened, or with `--migrate-only`. The
+//! applied versions are recorded in the `schema_migrations` table. A database migrated by a newer
+//! version of the faucet is refused, rather than risking corrupting its history.
+//!
+//! Never edit a released migration, add a new one instead.
+use anyhow::{bail, Context, Result};
+use rusqlite::Connection;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The migrations of the PostgreSQL schema, in order.
+const POSTGRES_MIGRATIONS: &[&str] = &[
+    include_str!("../migrations/postgres/0001_init.sql"),
+    include_str!("../migrations/postgres/0002_transfer_queue.sql"),
+    include_str!("../migrations/postgres/0003_leases.sql"),
+    include_str!("../migrations/postgres/0004_grants_chain.sql"),
+    include_str!("../migrations/postgres/0005_shared_completions.sql"),
+    include_str!("../migrations/postgres/0006_grants_millis.sql"),
+    include_str!("../migrations/postgres/0007_returns.sql"),
+    include_str!("../migrations/postgres/0008_registrations.sql"),
+    include_str!("../migrations/postgres/0009_rate_limit_hits.sql"),
+];
+
+/// The migrations of the SQLite schema, in order.
+const SQLITE_MIGRATIONS: &[&str] = &[
+    include_str!("../migrations/sqlite/0001_init.sql"),
+    include_str!("../migrations/sqlite/0002_grants_chain.sql"),
+    include_str!("../migrations/sqlite/0003_grants_millis.sql"),
+    include_str!("../migrations/sqlite/0004_returns.sql"),
+    include_str!("../migrations/sqlite/0005_registrations.sql"),
+    include_str!("../migrations/sqlite/0006_rate_limit_hits.sql"),
+];
+
+const CREATE_SCHEMA_MIGRATIONS: &str = "CREATE TABLE IF NOT EXISTS schema_migrations (
+    version BIGINT PRIMARY KEY,
+    applied_at BIGINT NOT NULL
+)";
+
+/// The migrations following version `applied`, numbered from 1.
+///
+/// Fails if the database has migrations this binary does not know about, that is if it was
+/// migrated by a newer version of the faucet.
+fn pending(
+    migrations: &'static [&'static str],
+    applied: i64,
+) -> Result<impl Iterator<Item = (i64, &'static str)>> {
+    let latest = migrations.len() as i64;
+    if applied > latest {
+        bail!(
+            "the database schema is at version {applied}, newer than the latest version {latest} \
+             known to this binary; refusing to downgrade, upgrade the faucet instead"
+        );
+    }
+    Ok(migrations
+        .iter()
+        .enumerate()
+        .map(|(index, migration)| (index as i64 + 1, *migration))
+        .skip(applied as usize))
+}
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as i64
+}
+
+/// Apply the migrations which were not applied to the PostgreSQL database yet.
+pub(crate) fn migrate_postgres(client: &mut postgres::Client) -> Result<()> {
+    client.batch_execute(CREATE_SCHEMA_MIGRATIONS)?;
+    let mut tx = client.transaction()?;
+    // Concurrent instances starting at once must not apply the same migration twice.
+    tx.batch_execute("LOCK TABLE schema_migrations IN EXCLUSIVE MODE")?;
+    let applied: i64 = tx
+        .query_one(
+            "SELECT COALESCE(MAX(version), 0) FROM schema_migrations",
+            &[],
+        )?
+        .get(0);
+    for (version, migration) in pending(POSTGRES_MIGRATIONS, applied)? {
+        tx.batch_execute(migration)
+            .with_context(|| format!("applying migration {version}"))?;
+        tx.execute(
+            "INSERT INTO schema_migrations (version, applied_at) VALUES ($1, $2)",
+            &[&version, &now()],
+        )?;
+        tracing::info!("Applied database migration {version}");
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+/// Apply the migrations which were not applied to the SQLite database yet.
+pub(crate) fn migrate_sqlite(conn: &mut Connection) -> Result<()> {
+    conn.execute_batch(CREATE_SCHEMA_MIGRATIONS)?;
+    // An immediate transaction locks the database, so that concurrent processes opening it do not
+    // apply the same migration twice.
+    let tx = conn.transaction_with_behavior(rusqlite::TransactionBehavior::Immediate)?;
+    let applied: i64 = tx.query_row(
+        "SELECT COALESCE(MAX(version), 0) FROM schema_migrations",
+        [],
+        |row| row.get(0),
+    )?;
+    for (version, migration) in pending(SQLITE_MIGRATIONS, applied)? {
+        tx.execute_batch(migration)
+            .with_context(|| format!("applying migration {version}"))?;
+        tx.execute(
+            "INSERT INTO schema_migrations (version, applied_at) VALUES (?1, ?2)",
+            (version, now()),
+        )?;
+        tracing::info!("Applied database migration {version}");
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn version(conn: &Connection) -> i64 {
+        conn.query_row("SELECT MAX(version) FROM schema_migrations", [], |row| {
+            row.get(0)
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_sqlite_migrations() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrate_sqlite(&mut conn).unwrap();
+        assert_eq!(version(&conn), SQLITE_MIGRATIONS.len() as i64);
+
+        // Migrating again is a no-op.
+        migrate_sqlite(&mut conn).unwrap();
+        assert_eq!(version(&conn), SQLITE_MIGRATIONS.len() as i64);
+
+        // A database migrated by a newer faucet is refused.
+        conn.execute(
+            "INSERT INTO schema_migrations (version, applied_at) VALUES (?1, 0)",
+            [SQLITE_MIGRATIONS.len() as i64 + 1],
+        )
+        .unwrap();
+        let err = migrate_sqlite(&mut conn).unwrap_err();
+        assert!(err.to_string().contains("refusing to downgrade"), "{err}");
+    }
+
+    #[test]
+    fn test_pending_migrations() {
+        let migrations = &["a", "b", "c"];
+        assert_eq!(
+            pending(migrations, 1).unwrap().collect::<Vec<_>>(),
+            vec![(2, "b"), (3, "c")]
+        );
+        assert_eq!(pending(migrations, 3).unwrap().count(), 0);
+        assert!(pending(migrations, 4).is_err());
+    }
+}
@@ -0,0 +1,349 @@
+// Copyright (c) 2023 Espresso Systems (espressosys.com)
+// This file is part of the Discord Faucet library.
+//
+// You should have received a copy of the MIT License
+// along with the Discord Faucet library. If not, see <https://mit-license.org/>.
+
+//! An in-memory chain, so that the transfer pipeline can be tested without anvil.
+//!
+//! The chain serves the RPC calls the faucet makes over HTTP. Native transfers are applied when
+//! they are mined, each with a fixed gas cost. Transactions are mined as soon as they are sent,
+//! unless automatic mining is disabled, in which case they stay pending until [MockChain::mine]
+//! is called.
+use async_trait::async_trait;
+use ethers::{
+    providers::{HttpClientError, JsonRpcClient, JsonRpcError},
+    types::{Address, Block, BlockNumber, Bytes, Transaction, TransactionReceipt, H256, U256, U64},
+    utils::{keccak256, rlp},
+};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::{json, Value};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Debug,
+    sync::{Arc, Mutex},
+};
+
+/// The chain ID of the mock chain, the same as anvil's.
+pub const MOCK_CHAIN_ID: u64 = 31337;
+/// The gas used by every transaction.
+const GAS_USED: u64 = 21000;
+/// The gas price of every transaction.
+const GAS_PRICE: u64 = 1_000_000_000;
+
+#[derive(Debug)]
+struct ChainState {
+    balances: HashMap<Address, U256>,
+    nonces: HashMap<Address, U256>,
+    blocks: Vec<Block<Transaction>>,
+    pending: Vec<Transaction>,
+    transactions: HashMap<H256, Transaction>,
+    receipts: HashMap<H256, TransactionReceipt>,
+    /// The next block reported by each block filter.
+    filters: HashMap<U256, usize>,
+    auto_mine: bool,
+    /// The addresses whose calls revert.
+    reverting: HashSet<Address>,
+}
+
+/// A chain simulated in memory.
+#[derive(Clone, Debug)]
+pub struct MockChain {
+    state: Arc<Mutex<ChainState>>,
+}
+
+impl Default for MockChain {
+    fn default() -> Self {
+        let mut state = ChainState {
+            balances: Default::default(),
+            nonces: Default::default(),
+            blocks: vec![],
+            pending: vec![],
+            transactions: Default::default(),
+            receipts: Default::default(),
+            filters: Default::default(),
+            auto_mine: true,
+            reverting: Default::default(),
+        };
+        state.mine();
+        Self {
+            state: Arc::new(Mutex::new(state)),
+        }
+    }
+}
+
+impl MockChain {
+    /// Set the balance of `address`.
+    pub fn fund(&self, address: Address, balance: U256) {
+        self.state.lock().unwrap().balances.insert(address, balance);
+    }
+
+    /// The balance of `address`.
+    pub fn balance(&self, address: Address) -> U256 {
+        self.state.lock().unwrap().balance(address)
+    }
+
+    /// Make the transactions to `address` revert, as if it were a contract rejecting them.
+    pub fn revert_calls_to(&self, address: Address) {
+        self.state.lock().unwrap().reverting.insert(address);
+    }
+
+    /// Whether transactions are mined as soon as they are sent.
+    pub fn set_auto_mine(&self, auto_mine: bool) {
+        self.state.lock().unwrap().auto_mine = auto_mine;
+    }
+
+    /// Mine a block with the pending transactions.
+    pub fn mine(&self) {
+        self.state.lock().unwrap().mine();
+    }
+
+    /// Drop the pending transactions, as if they were evicted from the mempool.
+    pub fn drop_pending(&self) {
+        let mut state = self.state.lock().unwrap();
+        for tx in std::mem::take(&mut state.pending) {
+            state.transactions.remove(&tx.hash);
+        }
+    }
+
+    /// Undo the mined transaction `tx_hash`, as if its block was reorged out, and return it to the
+    /// mempool.
+    pub fn reorg(&self, tx_hash: H256) {
+        self.state.lock().unwrap().reorg(tx_hash);
+    }
+
+    fn handle(&self, method: &str, params: Value) -> Result<Value, HttpClientError> {
+        let mut state = self.state.lock().unwrap();
+        Ok(match method {
+            "eth_chainId" => json!(U64::from(MOCK_CHAIN_ID)),
+            "eth_blockNumber" => json!(U64::from(state.blocks.len() - 1)),
+            "eth_gasPrice" => json!(U256::from(GAS_PRICE)),
+            "eth_estimateGas" => json!(U256::from(GAS_USED)),
+            "eth_getBalance" => json!(state.balance(param(&params, 0)?)),
+            "eth_getTransactionCount" => json!(state.nonce(param(&params, 0)?)),
+            "eth_sendRawTransaction" => json!(state.send(param(&params, 0)?)?),
+            "eth_getTransactionByHash" => json!(state.transactions.get(&param(&params, 0)?)),
+            "eth_getTransactionReceipt" => json!(state.receipts.get(&param(&params, 0)?)),
+            "eth_newBlockFilter" => {
+                let id = U256::from(state.filters.len());
+                let next = state.blocks.len();
+                state.filters.insert(id, next);
+                json!(id)
+            }
+            "eth_getFilterChanges" => {
+                let id: U256 = param(&params, 0)?;
+                let next = *state
+                    .filters
+                    .get(&id)
+                    .ok_or_else(|| rpc_error("filter not found"))?;
+                let hashes = state.blocks[next..]
+                    .iter()
+                    .map(|block| block.hash)
+                    .collect::<Vec<_>>();
+                let len = state.blocks.len();
+                state.filters.insert(id, len);
+                json!(hashes)
+            }
+            "eth_getBlockByHash" => {
+                let hash: H256 = param(&params, 0)?;
+                let block = state.blocks.iter().find(|block| block.hash == Some(hash));
+                render_block(block, param(&params, 1)?)
+            }
+            "eth_getBlockByNumber" => {
+                let number = match param::<BlockNumber>(&params, 0)? {
+                    BlockNumber::Number(number) => number.as_usize(),
+                    _ => state.blocks.len() - 1,
+                };
+                render_block(state.blocks.get(number), param(&params, 1)?)
+            }
+            _ => return Err(rpc_error(&format!("{method} is not supported by the mock"))),
+        })
+    }
+}
+
+impl ChainState {
+    fn balance(&self, address: Address) -> U256 {
+        self.balances.get(&address).copied().unwrap_or_default()
+    }
+
+    fn nonce(&self, address: Address) -> U256 {
+        self.nonces.get(&address).copied().unwrap_or_default()
+    }
+
+    fn send(&mut self, raw: Bytes) -> Result<H256, HttpClientError> {
+        let mut tx: Transaction = rlp::decode(raw.as_ref())
+            .map_err(|err| rpc_error(&format!("invalid transaction: {err}")))?;
+        tx.hash = keccak256(&raw).into();
+        tx.from = tx
+            .recover_from()
+            .map_err(|err| rpc_error(&format!("invalid signature: {err}")))?;
+
+        // Pending transactions are accounted for, as if they were all mined in order.
+        let nonce = self.nonce(tx.from) + self.pending_from(tx.from);
+        if tx.nonce < nonce {
+            return Err(rpc_error("nonce too low"));
+        }
+        if tx.nonce > nonce {
+            return Err(rpc_error("nonce too high"));
+        }
+        if self.balance(tx.from) < cost(&tx) {
+            return Err(rpc_error("insufficient funds for gas * price + value"));
+        }
+
+        let hash = tx.hash;
+        self.transactions.insert(hash, tx.clone());
+        self.pending.push(tx);
+        if self.auto_mine {
+            self.mine();
+        }
+        Ok(hash)
+    }
+
+    fn reorg(&mut self, tx_hash: H256) {
+        if self.receipts.remove(&tx_hash).is_none() {
+            return;
+        }
+        let mut tx = self.transactions[&tx_hash].clone();
+        for block in &mut self.blocks {
+            block.transactions.retain(|tx| tx.hash != tx_hash);
+        }
+        let balance = self.balance(tx.from) + cost(&tx);
+        self.balances.insert(tx.from, balance);
+        if let Some(to) = tx.to {
+            let balance = self.balance(to) - tx.value;
+            self.balances.insert(to, balance);
+        }
+        let nonce = self.nonce(tx.from) - 1;
+        self.nonces.insert(tx.from, nonce);
+
+        tx.block_hash = None;
+        tx.block_number = None;
+        tx.transaction_index = None;
+        self.transactions.insert(tx_hash, tx.clone());
+        self.pending.push(tx);
+    }
+
+    fn pending_from(&self, address: Address) -> U256 {
+        self.pending
+            .iter()
+            .filter(|tx| tx.from == address)
+            .count()
+            .into()
+    }
+
+    fn mine(&mut self) {
+        let number = U64::from(self.blocks.len());
+        let hash = H256::random();
+        let mut transactions = vec![];
+        for mut tx in std::mem::take(&mut self.pending) {
+            let balance = self.balance(tx.from);
+            // The balance was checked when the transaction was sent, but earlier transactions
+            // in the block may have spent it since.
+            let Some(remaining) = balance.checked_sub(cost(&tx)) else {
+                continue;
+            };
+            // A reverted transaction only pays for its gas.
+            let reverted = tx.to.is_some_and(|to| self.reverting.contains(&to));
+            match tx.to {
+                Some(_) if reverted => {
+                    self.balances.insert(tx.from, remaining + tx.value);
+                }
+                Some(to) => {
+                    self.balances.insert(tx.from, remaining);
+                    let balance = self.balance(to) + tx.value;
+                    self.balances.insert(to, balance);
+                }
+                None => {
+                    self.balances.insert(tx.from, remaining);
+                }
+            }
+            let nonce = self.nonce(tx.from) + 1;
+            self.nonces.insert(tx.from, nonce);
+
+            let index = transactions.len();
+            tx.block_hash = Some(hash);
+            tx.block_number = Some(number);
+            tx.transaction_index = Some(index.into());
+            self.receipts.insert(
+                tx.hash,
+                TransactionReceipt {
+                    transaction_hash: tx.hash,
+                    transaction_index: index.into(),
+                    block_hash: Some(hash),
+                    block_number: Some(number),
+                    from: tx.from,
+                    to: tx.to,
+                    gas_used: Some(GAS_USED.into()),
+                    effective_gas_price: tx.gas_price,
+                    status: Some(U64::from(!reverted as u64)),
+                    ..Default::default()
+                },
+            );
+            self.transactions.insert(tx.hash, tx.clone());
+            transactions.push(tx);
+        }
+        self.blocks.push(Block {
+            hash: Some(hash),
+            parent_hash: self
+                .blocks
+                .last()
+                .and_then(|block| block.hash)
+                .unwrap_or_default(),
+            number: Some(number),
+            transactions,
+            ..Default::default()
+        });
+    }
+}
+
+/// The amount a transaction takes from its sender.
+fn cost(tx: &Transaction) -> U256 {
+    tx.value + U256::from(GAS_USED) * tx.gas_price.unwrap_or(GAS_PRICE.into())
+}
+
+fn rpc_error(message: &str) -> HttpClientError {
+    HttpClientError::JsonRpcError(JsonRpcError {
+        code: -32000,
+        message: message.into(),
+        data: None,
+    })
+}
+
+fn param<P: DeserializeOwned>(params: &Value, index: usize) -> Result<P, HttpClientError> {
+    let value = params.get(index).cloned().unwrap_or(Value::Null);
+    serde_json::from_value(value).map_err(|err| rpc_error(&format!("invalid params: {err}")))
+}
+
+/// A block, with only the hashes of its transactions unless `full`.
+fn render_block(block: Option<&Block<Transaction>>, full: bool) -> Value {
+    let mut value = json!(block);
+    if let (Some(block), false) = (block, full) {
+        let hashes = block
+            .transactions
+            .iter()
+            .map(|tx| tx.hash)
+            .collect::<Vec<_>>();
+        value["transactions"] = json!(hashes);
+    }
+    value
+}
+
+#[async_trait]
+impl JsonRpcClient for MockChain {
+    // The mock reports errors the same way as a real HTTP RPC.
+    type Error = HttpClientError;
+
+    async fn request<T, R>(&self, method: &str, params: T) -> Result<R, Self::Error>
+    where
+        T: Debug + Serialize + Send + Sync,
+        R: DeserializeOwned + Send,
+    {
+        let params = serde_json::to_value(params).map_err(|err| HttpClientError::SerdeJson {
+            err,
+            text: String::new(),
+        })?;
+        let response = self.handle(method, params)?;
+        let text = response.to_string();
+        serde_json::from_value(response).map_err(|err| HttpClientError::SerdeJson { err, text })
+    }
+}
@@ -0,0 +1,31 @@
+// Copyright (c) 2023 Espresso Systems (espressosys.com)
+// This file is part of the Discord Faucet library.
+//
+// You should have received a copy of the MIT License
+// along with the Discord Faucet library. If not, see <https://mit-license.org/>.
+
+//! Test NFTs the faucet can mint to recipients.
+//!
+//! With `--nft-contract`, the faucet mints a token of an ERC-721 contract to the recipient of each
+//! request, by calling its `safeMint(address)` function. Minting is restricted to the owner of the
+//! contract, which must be one of the faucet wallets: the faucet looks it up on startup and sends
+//! every mint from it.
+use clap::ValueEnum;
+use ethers::contract::abigen;
+
+abigen!(
+    Erc721,
+    r#"[
+        function safeMint(address to) external
+    ]"#
+);
+
+/// How test NFTs are granted.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum NftMode {
+    /// Mint an NFT in addition to the requested currency or token.
+    #[default]
+    Alongside,
+    /// Only mint an NFT, without granting any currency or token.
+    Instead,
+}
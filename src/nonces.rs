@@ -0,0 +1,59 @@
+// Copyright (c) 2023 Espresso Systems (espressosys.com)
+// This file is part of the Discord Faucet library.
+//
+// You should have received a copy of the MIT License
+// along with the Discord Faucet library. If not, see <https://mit-license.org/>.
+
+//! Single-use nonces issued to web clients.
+use async_std::sync::Mutex;
+use ethers::types::H256;
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+/// How long a client has to use a nonce.
+const NONCE_TTL: Duration = Duration::from_secs(300);
+
+/// Maximum number of unused nonces kept in memory.
+const MAX_PENDING_NONCES: usize = 100_000;
+
+#[derive(Clone, Debug, Default)]
+pub struct NonceStore {
+    /// Unused nonces and the time they were issued.
+    issued: Arc<Mutex<HashMap<H256, Instant>>>,
+}
+
+impl NonceStore {
+    /// Issue a new random nonce.
+    ///
+    /// Returns `None` if too many nonces are outstanding.
+    pub async fn issue(&self) -> Option<H256> {
+        let mut issued = self.issued.lock().await;
+        issued.retain(|_, timestamp| timestamp.elapsed() < NONCE_TTL);
+        if issued.len() >= MAX_PENDING_NONCES {
+            tracing::warn!("Too many outstanding nonces");
+            return None;
+        }
+        let nonce = H256::random();
+        issued.insert(nonce, Instant::now());
+        Some(nonce)
+    }
+
+    /// Consume `nonce` if it was issued, has not expired and `valid` returns `true`.
+    ///
+    /// A nonce is not consumed by a failed attempt, so a client can retry after a mistake.
+    pub async fn consume_if(&self, nonce: H256, valid: impl FnOnce() -> bool) -> bool {
+        let mut issued = self.issued.lock().await;
+        match issued.get(&nonce) {
+            Some(timestamp) if timestamp.elapsed() < NONCE_TTL => {}
+            _ => return false,
+        }
+        if !valid() {
+            return false;
+        }
+        issued.remove(&nonce);
+        true
+    }
+}
@@ -0,0 +1,228 @@
+// Copyright (c) 2023 Espresso Systems (espressosys.com)
+// This file is part of the Discord Faucet library.
+//
+// You should have received a copy of the MIT License
+// along with the Discord Faucet library. If not, see <https://mit-license.org/>.
+
+//! OAuth login for the web faucet.
+//!
+//! The flow is:
+//! 1. The front-end fetches an authorization URL from the `oauth/login` endpoint and sends the user
+//!    there.
+//! 2. The provider redirects the user back to the configured redirect URL with a `code` and
+//!    `state`, which the front-end posts to the `oauth/session` endpoint.
+//! 3. The faucet exchanges the code for the user's identity and returns a session token, which is
+//!    passed to the `request` endpoint in the `X-Session-Token` header.
+use crate::NonceStore;
+use anyhow::{ensure, Context, Result};
+use async_std::sync::Mutex;
+use clap::ValueEnum;
+use ethers::types::H256;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fmt::{self, Display, Formatter},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use url::Url;
+
+/// How long a session token remains valid.
+const SESSION_TTL: Duration = Duration::from_secs(24 * 3600);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum OAuthProvider {
+    Github,
+    Discord,
+}
+
+impl OAuthProvider {
+    fn authorize_url(&self) -> &'static str {
+        match self {
+            Self::Github => "https://github.com/login/oauth/authorize",
+            Self::Discord => "https://discord.com/oauth2/authorize",
+        }
+    }
+
+    fn token_url(&self) -> &'static str {
+        match self {
+            Self::Github => "https://github.com/login/oauth/access_token",
+            Self::Discord => "https://discord.com/api/oauth2/token",
+        }
+    }
+
+    fn user_url(&self) -> &'static str {
+        match self {
+            Self::Github => "https://api.github.com/user",
+            Self::Discord => "https://discord.com/api/users/@me",
+        }
+    }
+
+    fn scope(&self) -> &'static str {
+        match self {
+            Self::Github => "read:user",
+            Self::Discord => "identify",
+        }
+    }
+}
+
+impl Display for OAuthProvider {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Github => write!(f, "github"),
+            Self::Discord => write!(f, "discord"),
+        }
+    }
+}
+
+/// An account authenticated with an OAuth provider, e.g. `github:1234`.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq, Hash)]
+pub struct OAuthIdentity(String);
+
+impl Display for OAuthIdentity {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct LoginRedirect {
+    /// The provider URL to send the user to.
+    pub url: Url,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct SessionRequest {
+    pub code: String,
+    pub state: H256,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct Session {
+    /// The token to pass in the `X-Session-Token` header.
+    pub token: H256,
+    pub identity: OAuthIdentity,
+}
+
+#[derive(Serialize)]
+struct TokenRequest<'a> {
+    client_id: &'a str,
+    client_secret: &'a str,
+    grant_type: &'a str,
+    code: &'a str,
+    redirect_uri: &'a str,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+}
+
+/// The user ID is a number for GitHub and a string for Discord.
+#[derive(Deserialize)]
+struct UserResponse {
+    id: serde_json::Value,
+}
+
+#[derive(Clone, Debug)]
+pub struct OAuth {
+    provider: OAuthProvider,
+    client_id: String,
+    client_secret: String,
+    redirect_url: Url,
+    /// `state` parameters of logins in progress, to protect against CSRF.
+    logins: NonceStore,
+    sessions: Arc<Mutex<HashMap<H256, (OAuthIdentity, Instant)>>>,
+}
+
+impl OAuth {
+    pub fn new(
+        provider: OAuthProvider,
+        client_id: String,
+        client_secret: String,
+        redirect_url: Url,
+    ) -> Self {
+        Self {
+            provider,
+            client_id,
+            client_secret,
+            redirect_url,
+            logins: Default::default(),
+            sessions: Default::default(),
+        }
+    }
+
+    /// Start a login, returning the provider URL to send the user to.
+    ///
+    /// Returns `None` if too many logins are in progress.
+    pub async fn login(&self) -> Option<LoginRedirect> {
+        let state = self.logins.issue().await?;
+        let mut url = Url::parse(self.provider.authorize_url()).unwrap();
+        url.query_pairs_mut()
+            .append_pair("client_id", &self.client_id)
+            .append_pair("redirect_uri", self.redirect_url.as_str())
+            .append_pair("response_type", "code")
+            .append_pair("scope", self.provider.scope())
+            .append_pair("state", &format!("{state:?}"));
+        Some(LoginRedirect { url })
+    }
+
+    /// Complete a login by exchanging the authorization code for the user's identity.
+    pub async fn create_session(&self, request: &SessionRequest) -> Result<Session> {
+        ensure!(
+            self.logins.consume_if(request.state, || true).await,
+            "unknown or expired login state"
+        );
+        let identity = self.identify(&request.code).await?;
+        tracing::info!("Created web session for {identity}");
+
+        let token = H256::random();
+        let mut sessions = self.sessions.lock().await;
+        sessions.retain(|_, (_, created)| created.elapsed() < SESSION_TTL);
+        sessions.insert(token, (identity.clone(), Instant::now()));
+        Ok(Session { token, identity })
+    }
+
+    /// The identity of the user holding a session token, if the session is valid.
+    pub async fn identity(&self, token: H256) -> Option<OAuthIdentity> {
+        match self.sessions.lock().await.get(&token) {
+            Some((identity, created)) if created.elapsed() < SESSION_TTL => Some(identity.clone()),
+            _ => None,
+        }
+    }
+
+    async fn identify(&self, code: &str) -> Result<OAuthIdentity> {
+        let body = surf::Body::from_form(&TokenRequest {
+            client_id: &self.client_id,
+            client_secret: &self.client_secret,
+            grant_type: "authorization_code",
+            code,
+            redirect_uri: self.redirect_url.as_str(),
+        })
+        .map_err(|err| err.into_inner())?;
+        let token: TokenResponse = surf::post(self.provider.token_url())
+            .header("Accept", "application/json")
+            .body(body)
+            .recv_json()
+            .await
+            .map_err(|err| err.into_inner())
+            .context("exchanging authorization code")?;
+
+        let user: UserResponse = surf::get(self.provider.user_url())
+            .header("Accept", "application/json")
+            .header("Authorization", format!("Bearer {}", token.access_token))
+            // Required by the GitHub API.
+            .header("User-Agent", "discord-faucet")
+            .recv_json()
+            .await
+            .map_err(|err| err.into_inner())
+            .context("fetching user")?;
+
+        let id = match user.id {
+            serde_json::Value::String(id) => id,
+            serde_json::Value::Number(id) => id.to_string(),
+            id => anyhow::bail!("unexpected user ID {id}"),
+        };
+        Ok(OAuthIdentity(format!("{}:{id}", self.provider)))
+    }
+}
@@ -0,0 +1,200 @@
+// Copyright (c) 2023 Espresso Systems (espressosys.com)
+// This file is part of the Discord Faucet library.
+//
+// You should have received a copy of the MIT License
+// along with the Discord Faucet library. If not, see <https://mit-license.org/>.
+
+//! OpenAPI description of the web API.
+//!
+//! The document is generated from the same `api.toml` that defines the routes, so it cannot drift
+//! from the routes actually served.
+use serde_json::{json, Map, Value};
+
+/// Generate an OpenAPI 3 document for the API module `module` specified by `api`.
+pub(crate) fn openapi_document(module: &str, api: &toml::Value) -> Value {
+    let meta = &api["meta"];
+    let mut paths = Map::new();
+
+    // Endpoints provided by tide-disco itself.
+    for path in ["/healthcheck".to_string(), format!("/{module}/healthcheck")] {
+        paths.insert(
+            path,
+            json!({
+                "get": {
+                    "summary": "Check that the service is running.",
+                    "responses": { "200": { "description": "The service is healthy." } },
+                },
+            }),
+        );
+    }
+
+    let routes = api
+        .get("route")
+        .and_then(|routes| routes.as_table())
+        .into_iter()
+        .flatten();
+    for (name, route) in routes {
+        let method = route
+            .get("METHOD")
+            .and_then(|method| method.as_str())
+            .unwrap_or("GET");
+        let doc = route
+            .get("DOC")
+            .and_then(|doc| doc.as_str())
+            .unwrap_or_default()
+            .trim();
+        let patterns = route
+            .get("PATH")
+            .and_then(|patterns| patterns.as_array())
+            .into_iter()
+            .flatten()
+            .filter_map(|pattern| pattern.as_str());
+
+        for (i, pattern) in patterns.enumerate() {
+            let mut path = format!("/{module}");
+            let mut parameters = vec![];
+            for segment in pattern.split('/').filter(|segment| !segment.is_empty()) {
+                if let Some(param) = segment.strip_prefix(':') {
+                    path += &format!("/{{{param}}}");
+                    let ty = route
+                        .get(segment)
+                        .and_then(|ty| ty.as_str())
+                        .unwrap_or("Literal");
+                    parameters.push(json!({
+                        "name": param,
+                        "in": "path",
+                        "required": true,
+                        "schema": { "type": param_type(ty) },
+                    }));
+                } else {
+                    path += &format!("/{segment}");
+                }
+            }
+
+            let operation_id = if i == 0 {
+                name.clone()
+            } else {
+                format!("{name}_{i}")
+            };
+            let mut operation = json!({
+                "operationId": operation_id,
+                "summary": doc.lines().next().unwrap_or_default(),
+                "description": doc,
+                "parameters": parameters,
+                "responses": {
+                    "200": { "description": "Success." },
+                    "default": {
+                        "description": "Error.",
+                        "content": {
+                            "application/json": {
+                                "schema": { "$ref": "#/components/schemas/FaucetError" },
+                            },
+                        },
+                    },
+                },
+            });
+            // OpenAPI cannot describe WebSockets. Socket routes are opened with a GET request and
+            // upgraded, so describe them as such.
+            let method = match method {
+                "SOCKET" => {
+                    operation["x-websocket"] = true.into();
+                    "get".to_string()
+                }
+                // Metrics routes are plain GET routes returning Prometheus text.
+                "METRICS" => "get".to_string(),
+                _ => method.to_lowercase(),
+            };
+            paths
+                .entry(path)
+                .or_insert_with(|| json!({}))
+                .as_object_mut()
+                .unwrap()
+                .insert(method, operation);
+        }
+    }
+
+    json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": meta.get("NAME").and_then(|name| name.as_str()).unwrap_or(module),
+            "description": meta.get("DESCRIPTION").and_then(|doc| doc.as_str()).unwrap_or_default(),
+            "version": env!("CARGO_PKG_VERSION"),
+        },
+        "paths": paths,
+        "components": {
+            "schemas": {
+                "FaucetError": {
+                    "description": "An error. Clients should branch on `code`, which is stable.",
+                    "type": "object",
+                    "properties": {
+                        "code": {
+                            "type": "string",
+                            "enum": [
+                                "BAD_REQUEST",
+                                "BAD_ADDRESS",
+                                "BAD_REQUEST_ID",
+                                "UNKNOWN_TOKEN",
+                                "NOT_FOUND",
+                                "CAPTCHA_FAILED",
+                                "PROOF_OF_WORK_FAILED",
+                                "OWNERSHIP_PROOF_FAILED",
+                                "SUSPICIOUS",
+                                "UNAUTHORIZED",
+                                "COOLDOWN",
+                                "QUOTA_EXCEEDED",
+                                "LIFETIME_CAP_REACHED",
+                                "QUEUE_FULL",
+                                "FAUCET_EMPTY",
+                                "PAUSED",
+                                "UNAVAILABLE",
+                                "INTERNAL",
+                            ],
+                        },
+                        "status": { "type": "integer" },
+                        "message": { "type": "string" },
+                        "retry_after_secs": { "type": "integer", "nullable": true },
+                    },
+                    "required": ["code", "status", "message"],
+                },
+            },
+        },
+    })
+}
+
+/// The JSON schema type of a tide-disco route parameter type.
+fn param_type(ty: &str) -> &'static str {
+    match ty {
+        "Boolean" => "boolean",
+        "Integer" => "integer",
+        _ => "string",
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_openapi_covers_all_routes() {
+        let api = toml::from_str::<toml::Value>(include_str!("api.toml")).unwrap();
+        let document = openapi_document("faucet", &api);
+        let operations = document["paths"]
+            .as_object()
+            .unwrap()
+            .values()
+            .flat_map(|path| path.as_object().unwrap().values())
+            .filter_map(|operation| operation.get("operationId")?.as_str())
+            .collect::<Vec<_>>();
+        for name in api["route"].as_table().unwrap().keys() {
+            assert!(operations.contains(&name.as_str()), "missing route {name}");
+        }
+
+        let request = &document["paths"]["/faucet/request/{address}"]["post"];
+        assert_eq!(request["operationId"], "request");
+        assert_eq!(request["parameters"][0]["name"], "address");
+        assert_eq!(
+            document["paths"]["/faucet/events"]["get"]["x-websocket"],
+            true
+        );
+    }
+}
@@ -0,0 +1,91 @@
+// Copyright (c) 2023 Espresso Systems (espressosys.com)
+// This file is part of the Discord Faucet library.
+//
+// You should have received a copy of the MIT License
+// along with the Discord Faucet library. If not, see <https://mit-license.org/>.
+
+//! Proofs that a requester controls the recipient address.
+//!
+//! The server issues a message containing the recipient address and a random nonce, which the
+//! requester signs with `personal_sign` (EIP-191) using the private key of the recipient address.
+//! This prevents users from spending the faucet's budget on addresses they do not own.
+use crate::NonceStore;
+use ethers::types::{Address, Signature, H256};
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct OwnershipChallenge {
+    pub nonce: H256,
+    /// The message to sign with `personal_sign`.
+    pub message: String,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct OwnershipProof {
+    nonces: NonceStore,
+}
+
+impl OwnershipProof {
+    /// Issue a message to be signed by the owner of `address`.
+    ///
+    /// Returns `None` if too many challenges are outstanding.
+    pub async fn challenge(&self, address: Address) -> Option<OwnershipChallenge> {
+        let nonce = self.nonces.issue().await?;
+        Some(OwnershipChallenge {
+            nonce,
+            message: message(address, nonce),
+        })
+    }
+
+    /// Verify and consume a signature of a previously issued challenge.
+    pub async fn verify(&self, address: Address, nonce: H256, signature: &Signature) -> bool {
+        self.nonces
+            .consume_if(nonce, || {
+                signature.verify(message(address, nonce), address).is_ok()
+            })
+            .await
+    }
+}
+
+fn message(address: Address, nonce: H256) -> String {
+    format!("I control {address:?} and request funds from the faucet.\n\nNonce: {nonce:?}")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ethers::signers::{LocalWallet, Signer};
+
+    #[async_std::test]
+    async fn test_ownership_proof() {
+        let proof = OwnershipProof::default();
+        let wallet = LocalWallet::new(&mut rand::thread_rng());
+        let challenge = proof.challenge(wallet.address()).await.unwrap();
+        let signature = wallet.sign_message(&challenge.message).await.unwrap();
+
+        // The signature must be made by the recipient.
+        assert!(
+            !proof
+                .verify(Address::random(), challenge.nonce, &signature)
+                .await
+        );
+        // Unknown nonces are rejected.
+        assert!(
+            !proof
+                .verify(wallet.address(), H256::random(), &signature)
+                .await
+        );
+
+        assert!(
+            proof
+                .verify(wallet.address(), challenge.nonce, &signature)
+                .await
+        );
+        // Nonces can only be used once.
+        assert!(
+            !proof
+                .verify(wallet.address(), challenge.nonce, &signature)
+                .await
+        );
+    }
+}
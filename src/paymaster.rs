@@ -0,0 +1,70 @@
+// Copyright (c) 2023 Espresso Systems (espressosys.com)
+// This file is part of the Discord Faucet library.
+//
+// You should have received a copy of the MIT License
+// along with the Discord Faucet library. If not, see <https://mit-license.org/>.
+
+//! Deposits in an ERC-4337 entry point, for smart accounts and paymasters.
+//!
+//! With `--entry-point`, the `deposit` endpoint grants funds as a deposit in the entry point for the
+//! recipient, rather than as a transfer to it, so that a smart account can pay for its user
+//! operations before it is even deployed. With `--paymaster`, the faucet also keeps the deposit of
+//! a paymaster above `--paymaster-min-deposit`, checking it every `--paymaster-check-interval`.
+use crate::{Faucet, RequestId, RequestStatus};
+use async_std::task::sleep;
+use ethers::{
+    contract::abigen,
+    types::{Address, U256},
+};
+use std::time::Duration;
+
+abigen!(
+    EntryPoint,
+    r#"[
+        function depositTo(address account) external payable
+        function balanceOf(address account) external view returns (uint256)
+    ]"#
+);
+
+/// Deposit `amount` for `paymaster` whenever its deposit in the entry point falls below `min`.
+///
+/// The deposit is not topped up again while the previous top-up is still pending. Top-ups are not
+/// grants, so they are not subject to the checks and the lifetime cap of the requests of users.
+pub async fn watch_paymaster(
+    faucet: Faucet,
+    paymaster: Address,
+    min: U256,
+    amount: U256,
+    interval: Duration,
+) {
+    let mut pending: Option<RequestId> = None;
+    loop {
+        if let Some(id) = pending {
+            if !matches!(
+                faucet.request_status(id).await,
+                None | Some(RequestStatus::Confirmed { .. })
+            ) {
+                sleep(interval).await;
+                continue;
+            }
+        }
+        match faucet.deposit_of(paymaster).await {
+            Ok(deposit) if deposit < min => match faucet.top_up_deposit(paymaster, amount).await {
+                Ok(id) => {
+                    tracing::info!(
+                        "Topping up the deposit of paymaster {paymaster:?} with request {id}"
+                    );
+                    pending = Some(id);
+                }
+                Err(err) => tracing::warn!(
+                    "Failed to top up the deposit of paymaster {paymaster:?}: {err:#}"
+                ),
+            },
+            Ok(_) => {}
+            Err(err) => {
+                tracing::warn!("Failed to get the deposit of paymaster {paymaster:?}: {err:#}")
+            }
+        }
+        sleep(interval).await;
+    }
+}
@@ -0,0 +1,120 @@
+// Copyright (c) 2023 Espresso Systems (espressosys.com)
+// This file is part of the Discord Faucet library.
+//
+// You should have received a copy of the MIT License
+// along with the Discord Faucet library. If not, see <https://mit-license.org/>.
+
+//! Proof-of-work challenges for anonymous web requests.
+//!
+//! A client fetches a challenge consisting of a random `nonce` and a `difficulty`. It then searches
+//! for a `solution` such that `keccak256(nonce || address || solution)` starts with at least
+//! `difficulty` zero bits, where `address` is the recipient of the request and `solution` is
+//! encoded as 8 big-endian bytes. Each challenge can only be used once.
+use crate::NonceStore;
+use ethers::{
+    types::{Address, H256},
+    utils::keccak256,
+};
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct Challenge {
+    pub nonce: H256,
+    pub difficulty: u32,
+}
+
+#[derive(Clone, Debug)]
+pub struct ProofOfWork {
+    difficulty: u32,
+    challenges: NonceStore,
+}
+
+impl ProofOfWork {
+    pub fn new(difficulty: u32) -> Self {
+        Self {
+            difficulty,
+            challenges: Default::default(),
+        }
+    }
+
+    /// Issue a new challenge.
+    ///
+    /// Returns `None` if too many challenges are outstanding.
+    pub async fn challenge(&self) -> Option<Challenge> {
+        Some(Challenge {
+            nonce: self.challenges.issue().await?,
+            difficulty: self.difficulty,
+        })
+    }
+
+    /// Verify and consume a solution to a previously issued challenge.
+    pub async fn verify(&self, nonce: H256, address: Address, solution: u64) -> bool {
+        self.challenges
+            .consume_if(nonce, || {
+                is_solution(nonce, address, solution, self.difficulty)
+            })
+            .await
+    }
+}
+
+fn is_solution(nonce: H256, address: Address, solution: u64, difficulty: u32) -> bool {
+    let hash = keccak256(
+        [
+            nonce.as_bytes(),
+            address.as_bytes(),
+            &solution.to_be_bytes(),
+        ]
+        .concat(),
+    );
+    leading_zero_bits(&hash) >= difficulty
+}
+
+fn leading_zero_bits(bytes: &[u8]) -> u32 {
+    let mut bits = 0;
+    for byte in bytes {
+        bits += byte.leading_zeros();
+        if *byte != 0 {
+            break;
+        }
+    }
+    bits
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn solve(challenge: Challenge, address: Address) -> u64 {
+        (0..)
+            .find(|solution| is_solution(challenge.nonce, address, *solution, challenge.difficulty))
+            .unwrap()
+    }
+
+    #[test]
+    fn test_leading_zero_bits() {
+        assert_eq!(leading_zero_bits(&[0xff]), 0);
+        assert_eq!(leading_zero_bits(&[0x01, 0x00]), 7);
+        assert_eq!(leading_zero_bits(&[0x00, 0x10]), 11);
+        assert_eq!(leading_zero_bits(&[0x00, 0x00]), 16);
+    }
+
+    #[async_std::test]
+    async fn test_proof_of_work() {
+        let pow = ProofOfWork::new(16);
+        let address = Address::random();
+        let challenge = pow.challenge().await.unwrap();
+        let solution = solve(challenge, address);
+
+        // The solution is bound to the address.
+        assert!(
+            !pow.verify(challenge.nonce, Address::random(), solution)
+                .await
+        );
+        // Unknown challenges are rejected.
+        assert!(!pow.verify(H256::random(), address, solution).await);
+
+        assert!(pow.verify(challenge.nonce, address, solution).await);
+        // Challenges can only be used once.
+        assert!(!pow.verify(challenge.nonce, address, solution).await);
+    }
+}
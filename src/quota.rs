@@ -0,0 +1,318 @@
+// Copyright (c) 2023 Espresso Systems (espressosys.com)
+// This file is part of the Discord Faucet library.
+//
+// You should have received a copy of the MIT License
+// along with the Discord Faucet library. If not, see <https://mit-license.org/>.
+
+//! API keys and usage quotas for partner integrations.
+//!
+//! Requests carrying a known key in the `X-Api-Key` header are exempt from the bot protection and
+//! OAuth login required of anonymous web requests. Instead, each key is limited to a number of
+//! requests per hour and per day and to a total amount of native currency granted.
+use crate::{from_unix_millis, to_unix_millis};
+use anyhow::{bail, Context, Error, Result};
+use async_std::sync::Mutex;
+use ethers::{types::U256, utils::parse_ether};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, VecDeque},
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use thiserror::Error;
+
+const HOUR: Duration = Duration::from_secs(3600);
+const DAY: Duration = Duration::from_secs(24 * 3600);
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ApiKey {
+    /// The name of the partner, used in logs and usage reports.
+    pub name: String,
+    pub key: String,
+    pub requests_per_hour: usize,
+    pub requests_per_day: usize,
+    /// The total amount of native currency which can be granted with this key, in wei.
+    pub max_total_granted: U256,
+}
+
+impl FromStr for ApiKey {
+    type Err = Error;
+
+    /// Parse a key from `NAME:KEY:REQUESTS_PER_HOUR:REQUESTS_PER_DAY:MAX_TOTAL`, where
+    /// `MAX_TOTAL` is in ethers.
+    fn from_str(s: &str) -> Result<Self> {
+        let [name, key, per_hour, per_day, max_total] = s.split(':').collect::<Vec<_>>()[..] else {
+            bail!("expected NAME:KEY:REQUESTS_PER_HOUR:REQUESTS_PER_DAY:MAX_TOTAL, got {s}");
+        };
+        if key.is_empty() {
+            bail!("API key for {name} is empty");
+        }
+        Ok(Self {
+            name: name.to_string(),
+            key: key.to_string(),
+            requests_per_hour: per_hour.parse().context("invalid requests per hour")?,
+            requests_per_day: per_day.parse().context("invalid requests per day")?,
+            max_total_granted: parse_ether(max_total).context("invalid maximum total")?,
+        })
+    }
+}
+
+#[derive(Clone, Debug, Error, PartialEq, Eq)]
+pub enum QuotaError {
+    #[error("unknown API key")]
+    UnknownKey,
+    #[error("quota of {limit} requests per {period} exceeded")]
+    RateExceeded {
+        limit: usize,
+        period: &'static str,
+        retry_after: Duration,
+    },
+    #[error("quota of {limit} total granted exceeded")]
+    TotalExceeded { limit: U256 },
+}
+
+/// Usage and quotas of an API key, as reported on the admin endpoint.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct ApiKeyUsage {
+    pub name: String,
+    pub requests_last_hour: usize,
+    pub requests_last_day: usize,
+    pub total_granted: U256,
+    pub requests_per_hour: usize,
+    pub requests_per_day: usize,
+    pub max_total_granted: U256,
+}
+
+/// The usage of an API key saved in a state snapshot.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub struct UsageSnapshot {
+    /// Times of the requests made in the last day, in milliseconds since the Unix epoch.
+    pub requests: Vec<u64>,
+    pub total_granted: U256,
+}
+
+#[derive(Debug, Default)]
+struct Usage {
+    /// Times of the requests made in the last day.
+    requests: VecDeque<Instant>,
+    total_granted: U256,
+}
+
+impl Usage {
+    fn forget_older_than(&mut self, period: Duration) {
+        while self
+            .requests
+            .front()
+            .is_some_and(|timestamp| timestamp.elapsed() >= period)
+        {
+            self.requests.pop_front();
+        }
+    }
+
+    fn requests_within(&self, period: Duration) -> usize {
+        self.requests
+            .iter()
+            .rev()
+            .take_while(|timestamp| timestamp.elapsed() < period)
+            .count()
+    }
+
+    /// The time until `count` more requests fit in a quota of `limit` requests per `period`.
+    fn retry_after(&self, limit: usize, count: usize, period: Duration) -> Option<Duration> {
+        let recent = self.requests_within(period);
+        if recent + count <= limit {
+            return None;
+        }
+        // Wait until enough of the recent requests have left the window. If the batch is larger
+        // than the quota itself, it will never fit; report the full period.
+        let index = self.requests.len() - recent + (recent + count - limit - 1);
+        Some(self.requests.get(index).map_or(period, |timestamp| {
+            period.saturating_sub(timestamp.elapsed())
+        }))
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct ApiKeys {
+    keys: Arc<HashMap<String, ApiKey>>,
+    usage: Arc<Mutex<HashMap<String, Usage>>>,
+}
+
+impl ApiKeys {
+    pub fn new(keys: impl IntoIterator<Item = ApiKey>) -> Self {
+        Self {
+            keys: Arc::new(keys.into_iter().map(|key| (key.key.clone(), key)).collect()),
+            usage: Default::default(),
+        }
+    }
+
+    /// The configuration of `key`, if it is a known key.
+    pub fn get(&self, key: &str) -> Option<&ApiKey> {
+        self.keys.get(key)
+    }
+
+    /// Record `count` grants of `value` each made with `key`.
+    ///
+    /// Fails without recording anything if the grants would exceed one of the quotas of `key`.
+    pub async fn charge(&self, key: &str, count: usize, value: U256) -> Result<(), QuotaError> {
+        let config = self.get(key).ok_or(QuotaError::UnknownKey)?;
+        let mut usage = self.usage.lock().await;
+        let usage = usage.entry(key.to_string()).or_default();
+        usage.forget_older_than(DAY);
+
+        for (limit, period, name) in [
+            (config.requests_per_hour, HOUR, "hour"),
+            (config.requests_per_day, DAY, "day"),
+        ] {
+            if let Some(retry_after) = usage.retry_after(limit, count, period) {
+                return Err(QuotaError::RateExceeded {
+                    limit,
+                    period: name,
+                    retry_after,
+                });
+            }
+        }
+        let total = usage.total_granted + value * count;
+        if total > config.max_total_granted {
+            return Err(QuotaError::TotalExceeded {
+                limit: config.max_total_granted,
+            });
+        }
+
+        let now = Instant::now();
+        usage.requests.extend(std::iter::repeat(now).take(count));
+        usage.total_granted = total;
+        Ok(())
+    }
+
+    /// Give back the last `count` grants of `value` each charged to `key`, which were not served.
+    pub async fn refund(&self, key: &str, count: usize, value: U256) {
+        let mut usage = self.usage.lock().await;
+        let Some(usage) = usage.get_mut(key) else {
+            return;
+        };
+        for _ in 0..count {
+            usage.requests.pop_back();
+        }
+        usage.total_granted = usage.total_granted.saturating_sub(value * count);
+    }
+
+    /// The usage of every key, to save in a state snapshot.
+    pub async fn snapshot(&self) -> HashMap<String, UsageSnapshot> {
+        let mut usage = self.usage.lock().await;
+        usage
+            .iter_mut()
+            .map(|(key, usage)| {
+                usage.forget_older_than(DAY);
+                let snapshot = UsageSnapshot {
+                    requests: usage.requests.iter().map(|t| to_unix_millis(*t)).collect(),
+                    total_granted: usage.total_granted,
+                };
+                (key.clone(), snapshot)
+            })
+            .collect()
+    }
+
+    /// Restore the usage of the keys from a state snapshot.
+    pub async fn restore(&self, snapshot: HashMap<String, UsageSnapshot>) {
+        let mut usage = self.usage.lock().await;
+        for (key, snapshot) in snapshot {
+            usage.insert(
+                key,
+                Usage {
+                    requests: snapshot
+                        .requests
+                        .into_iter()
+                        .map(from_unix_millis)
+                        .collect(),
+                    total_granted: snapshot.total_granted,
+                },
+            );
+        }
+    }
+
+    /// The usage of every configured key.
+    pub async fn usage(&self) -> Vec<ApiKeyUsage> {
+        let usage = self.usage.lock().await;
+        let mut report = self
+            .keys
+            .values()
+            .map(|config| {
+                let usage = usage.get(&config.key);
+                ApiKeyUsage {
+                    name: config.name.clone(),
+                    requests_last_hour: usage.map_or(0, |usage| usage.requests_within(HOUR)),
+                    requests_last_day: usage.map_or(0, |usage| usage.requests_within(DAY)),
+                    total_granted: usage.map_or(U256::zero(), |usage| usage.total_granted),
+                    requests_per_hour: config.requests_per_hour,
+                    requests_per_day: config.requests_per_day,
+                    max_total_granted: config.max_total_granted,
+                }
+            })
+            .collect::<Vec<_>>();
+        report.sort_by(|a, b| a.name.cmp(&b.name));
+        report
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_api_key() {
+        let key: ApiKey = "partner:secret:10:100:1.5".parse().unwrap();
+        assert_eq!(key.name, "partner");
+        assert_eq!(key.key, "secret");
+        assert_eq!(key.requests_per_hour, 10);
+        assert_eq!(key.requests_per_day, 100);
+        assert_eq!(key.max_total_granted, parse_ether("1.5").unwrap());
+
+        assert!("partner:secret:10:100".parse::<ApiKey>().is_err());
+        assert!("partner::10:100:1".parse::<ApiKey>().is_err());
+    }
+
+    #[async_std::test]
+    async fn test_quotas() {
+        let keys = ApiKeys::new(["partner:secret:3:100:10".parse().unwrap()]);
+        let one = parse_ether(1).unwrap();
+
+        assert_eq!(
+            keys.charge("other", 1, one).await,
+            Err(QuotaError::UnknownKey)
+        );
+
+        keys.charge("secret", 2, one).await.unwrap();
+        // A batch which does not fit is rejected as a whole.
+        assert!(matches!(
+            keys.charge("secret", 2, one).await,
+            Err(QuotaError::RateExceeded { period: "hour", retry_after, .. })
+                if retry_after > Duration::from_secs(3500)
+        ));
+        keys.charge("secret", 1, one).await.unwrap();
+
+        let usage = keys.usage().await;
+        assert_eq!(usage[0].name, "partner");
+        assert_eq!(usage[0].requests_last_hour, 3);
+        assert_eq!(usage[0].total_granted, one * 3);
+    }
+
+    #[async_std::test]
+    async fn test_total_quota() {
+        let keys = ApiKeys::new(["partner:secret:100:100:2".parse().unwrap()]);
+        let one = parse_ether(1).unwrap();
+        keys.charge("secret", 2, one).await.unwrap();
+        assert!(matches!(
+            keys.charge("secret", 1, one).await,
+            Err(QuotaError::TotalExceeded { .. })
+        ));
+        // Token grants do not count towards the total.
+        keys.charge("secret", 1, U256::zero()).await.unwrap();
+
+        // Grants which were not served are given back.
+        keys.refund("secret", 1, one).await;
+        keys.charge("secret", 1, one).await.unwrap();
+        assert_eq!(keys.usage().await[0].total_granted, one * 2);
+    }
+}
@@ -0,0 +1,130 @@
+// Copyright (c) 2023 Espresso Systems (espressosys.com)
+// This file is part of the Discord Faucet library.
+//
+// You should have received a copy of the MIT License
+// along with the Discord Faucet library. If not, see <https://mit-license.org/>.
+
+//! Rate limiting of grants shared by a group of users, e.g. the members of a Discord guild.
+use async_std::sync::Mutex;
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+const HOUR: Duration = Duration::from_secs(3600);
+
+/// A token bucket allowing a number of grants per hour, with bursts up to its capacity.
+#[derive(Clone, Debug)]
+pub struct TokenBucket {
+    per_hour: u32,
+    capacity: u32,
+    state: Arc<Mutex<BucketState>>,
+}
+
+#[derive(Debug)]
+struct BucketState {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    /// A bucket refilled with `per_hour` tokens per hour, holding at most `capacity` tokens.
+    ///
+    /// The bucket starts full.
+    pub fn new(per_hour: u32, capacity: u32) -> Self {
+        Self {
+            per_hour,
+            capacity,
+            state: Arc::new(Mutex::new(BucketState {
+                tokens: capacity as f64,
+                updated: Instant::now(),
+            })),
+        }
+    }
+
+    pub fn per_hour(&self) -> u32 {
+        self.per_hour
+    }
+
+    pub fn capacity(&self) -> u32 {
+        self.capacity
+    }
+
+    /// The time until `count` tokens are available, if they are not yet.
+    pub async fn remaining(&self, count: u32) -> Option<Duration> {
+        let mut state = self.state.lock().await;
+        self.refill(&mut state);
+        self.wait_time(&state, count)
+    }
+
+    /// Take `count` tokens from the bucket.
+    ///
+    /// Fails with the time until enough tokens are available if there are not enough left.
+    pub async fn take(&self, count: u32) -> Result<(), Duration> {
+        let mut state = self.state.lock().await;
+        self.refill(&mut state);
+        if let Some(remaining) = self.wait_time(&state, count) {
+            return Err(remaining);
+        }
+        state.tokens -= count as f64;
+        Ok(())
+    }
+
+    /// Put back `count` tokens taken for grants which were not served.
+    pub async fn refund(&self, count: u32) {
+        let mut state = self.state.lock().await;
+        self.refill(&mut state);
+        state.tokens = (state.tokens + count as f64).min(self.capacity as f64);
+    }
+
+    fn refill(&self, state: &mut BucketState) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(state.updated);
+        state.tokens =
+            (state.tokens + self.rate() * elapsed.as_secs_f64()).min(self.capacity as f64);
+        state.updated = now;
+    }
+
+    fn wait_time(&self, state: &BucketState, count: u32) -> Option<Duration> {
+        let missing = count as f64 - state.tokens;
+        if missing <= 0. {
+            return None;
+        }
+        // A request larger than the bucket never fits; report the time to refill it entirely.
+        if count > self.capacity || self.per_hour == 0 {
+            return Some(HOUR);
+        }
+        Some(Duration::from_secs_f64(missing / self.rate()).max(Duration::from_secs(1)))
+    }
+
+    /// Tokens per second.
+    fn rate(&self) -> f64 {
+        self.per_hour as f64 / HOUR.as_secs_f64()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[async_std::test]
+    async fn test_token_bucket() {
+        let bucket = TokenBucket::new(2, 3);
+        assert_eq!(bucket.remaining(3).await, None);
+        bucket.take(2).await.unwrap();
+        bucket.take(1).await.unwrap();
+
+        // The bucket is empty, and refills at 2 tokens per hour.
+        let remaining = bucket.take(1).await.unwrap_err();
+        assert!(remaining > Duration::from_secs(1790) && remaining <= Duration::from_secs(1800));
+        assert_eq!(bucket.remaining(4).await, Some(HOUR));
+
+        // Clones share the bucket.
+        assert!(bucket.clone().take(1).await.is_err());
+
+        // Tokens of grants which were not served are put back, up to the capacity.
+        bucket.refund(5).await;
+        bucket.take(3).await.unwrap();
+        assert!(bucket.take(1).await.is_err());
+    }
+}
@@ -0,0 +1,145 @@
+// Copyright (c) 2023 Espresso Systems (espressosys.com)
+// This file is part of the Discord Faucet library.
+//
+// You should have received a copy of the MIT License
+// along with the Discord Faucet library. If not, see <https://mit-license.org/>.
+
+//! Gasless meta-transactions relayed through a trusted forwarder (EIP-2771).
+//!
+//! With `--forwarder`, dApps can ask the faucet to relay a meta-transaction signed by a user instead
+//! of granting funds: the faucet submits it to the `execute` function of the forwarder and pays the
+//! gas, and the forwarder calls the target contract on behalf of the signer. The forwarder must
+//! implement the interface of OpenZeppelin's `MinimalForwarder`, which checks the signature and the
+//! nonce of each meta-transaction.
+//!
+//! Only calls allowed by `--relay-target` are relayed, so that the faucet does not pay for arbitrary
+//! calls to any contract. The forwarder does not revert when the call it relays fails, so a relay
+//! is only confirmed if replaying it on the state before its block succeeds.
+use anyhow::{bail, Context, Error, Result};
+use ethers::{
+    contract::abigen,
+    types::{Address, Bytes, U256},
+};
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+abigen!(
+    Forwarder,
+    r#"[
+        struct ForwardRequest { address from; address to; uint256 value; uint256 gas; uint256 nonce; bytes data; }
+        function verify(ForwardRequest req, bytes signature) external view returns (bool)
+        function execute(ForwardRequest req, bytes signature) external payable returns (bool, bytes)
+    ]"#
+);
+
+/// A meta-transaction signed by a user, to be relayed through the forwarder.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct MetaTransaction {
+    /// The signer, on whose behalf the target contract is called.
+    pub from: Address,
+    /// The target contract.
+    pub to: Address,
+    /// The native currency sent along with the call, which must be zero: the faucet only pays the
+    /// gas.
+    #[serde(default)]
+    pub value: U256,
+    /// The gas limit of the call to the target contract.
+    pub gas: U256,
+    /// The nonce of the signer in the forwarder.
+    pub nonce: U256,
+    /// The calldata of the call to the target contract.
+    pub data: Bytes,
+    /// The EIP-712 signature of the request by `from`.
+    pub signature: Bytes,
+}
+
+impl MetaTransaction {
+    /// The request to pass to the forwarder.
+    pub fn request(&self) -> ForwardRequest {
+        ForwardRequest {
+            from: self.from,
+            to: self.to,
+            value: self.value,
+            gas: self.gas,
+            nonce: self.nonce,
+            data: self.data.clone(),
+        }
+    }
+}
+
+/// A contract which meta-transactions can call, optionally restricted to one function.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RelayTarget {
+    pub contract: Address,
+    /// The selector of the function which can be called, or `None` for any function.
+    pub selector: Option<[u8; 4]>,
+}
+
+impl RelayTarget {
+    /// Whether `relay` calls this target.
+    pub fn allows(&self, relay: &MetaTransaction) -> bool {
+        relay.to == self.contract
+            && self
+                .selector
+                .map_or(true, |selector| relay.data.get(..4) == Some(&selector[..]))
+    }
+}
+
+impl FromStr for RelayTarget {
+    type Err = Error;
+
+    /// Parse a target from `CONTRACT` or `CONTRACT:SELECTOR`, e.g. `0x1234...:0xa9059cbb`.
+    fn from_str(s: &str) -> Result<Self> {
+        let (contract, selector) = match s.split_once(':') {
+            Some((contract, selector)) => (contract, Some(selector)),
+            None => (s, None),
+        };
+        let selector = selector
+            .map(|selector| -> Result<[u8; 4]> {
+                let bytes = Bytes::from_str(selector).context("invalid selector")?;
+                match bytes.to_vec().try_into() {
+                    Ok(selector) => Ok(selector),
+                    Err(_) => bail!("a selector has 4 bytes, got {selector}"),
+                }
+            })
+            .transpose()?;
+        Ok(Self {
+            contract: contract.parse().context("invalid contract address")?,
+            selector,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_relay_target() {
+        let contract = Address::random();
+        let relay = MetaTransaction {
+            from: Address::random(),
+            to: contract,
+            value: 0.into(),
+            gas: 100_000.into(),
+            nonce: 0.into(),
+            data: Bytes::from(vec![0xa9, 0x05, 0x9c, 0xbb, 0x00]),
+            signature: Bytes::new(),
+        };
+
+        let any = RelayTarget::from_str(&format!("{contract:?}")).unwrap();
+        assert_eq!(any.selector, None);
+        assert!(any.allows(&relay));
+
+        let transfer = RelayTarget::from_str(&format!("{contract:?}:0xa9059cbb")).unwrap();
+        assert!(transfer.allows(&relay));
+        let approve = RelayTarget::from_str(&format!("{contract:?}:0x095ea7b3")).unwrap();
+        assert!(!approve.allows(&relay));
+
+        let other = RelayTarget::from_str(&format!("{:?}", Address::random())).unwrap();
+        assert!(!other.allows(&relay));
+
+        RelayTarget::from_str(&format!("{contract:?}:0xa9")).unwrap_err();
+        RelayTarget::from_str("0x12:0xa9059cbb").unwrap_err();
+    }
+}
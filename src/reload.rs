@@ -0,0 +1,154 @@
+// Copyright (c) 2023 Espresso Systems (espressosys.com)
+// This file is part of the Discord Faucet library.
+//
+// You should have received a copy of the MIT License
+// along with the Discord Faucet library. If not, see <https://mit-license.org/>.
+
+//! Options which can be changed while the faucet runs.
+//!
+//! The settings file overrides a subset of the command line options. It is read at startup and
+//! again whenever the configuration is reloaded, on `SIGHUP` or with `POST /admin/reload`, without
+//! dropping the queue or the Discord connection. Settings removed from the file revert to the
+//! command line options.
+use crate::Options;
+use anyhow::{Context, Result};
+use ethers::utils::parse_ether;
+use serde::Deserialize;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+};
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SettingsFile {
+    /// The amount of each grant of the default faucet, in ethers.
+    grant_amount: Option<String>,
+    /// The channels where faucet commands are allowed in guilds without settings.
+    discord_channels: Option<Vec<u64>>,
+    /// The balance thresholds of the alerts, in ethers.
+    alert_balance: Option<Vec<String>>,
+    alert_queue_length: Option<usize>,
+    alert_rpc_failures: Option<usize>,
+}
+
+/// The current options, with the overrides of the settings file.
+#[derive(Clone, Debug)]
+pub struct LiveOptions {
+    path: Option<PathBuf>,
+    /// The options from the command line, before overrides.
+    base: Arc<Options>,
+    current: Arc<RwLock<Arc<Options>>>,
+}
+
+impl LiveOptions {
+    /// Override `opts` with the settings file, if there is one.
+    pub fn load(opts: Options) -> Result<Self> {
+        let path = opts.settings_file.clone();
+        let current = match &path {
+            Some(path) => apply(&opts, read(path)?)?,
+            None => opts.clone(),
+        };
+        Ok(Self {
+            path,
+            base: Arc::new(opts),
+            current: Arc::new(RwLock::new(Arc::new(current))),
+        })
+    }
+
+    /// The current options.
+    pub fn get(&self) -> Arc<Options> {
+        self.current.read().unwrap().clone()
+    }
+
+    /// Read the settings file again, returning the new options.
+    ///
+    /// The options are left unchanged if the file is invalid.
+    pub fn reload(&self) -> Result<Arc<Options>> {
+        let Some(path) = &self.path else {
+            return Ok(self.get());
+        };
+        let options = Arc::new(apply(&self.base, read(path)?)?);
+        *self.current.write().unwrap() = options.clone();
+        Ok(options)
+    }
+}
+
+impl From<Options> for LiveOptions {
+    /// Options without a settings file, which never change.
+    fn from(opts: Options) -> Self {
+        let opts = Arc::new(opts);
+        Self {
+            path: None,
+            base: opts.clone(),
+            current: Arc::new(RwLock::new(opts)),
+        }
+    }
+}
+
+fn apply(base: &Options, file: SettingsFile) -> Result<Options> {
+    let mut opts = base.clone();
+    if let Some(amount) = file.grant_amount {
+        opts.faucet_grant_amount = parse_ether(&amount).context("invalid grant_amount")?;
+    }
+    if let Some(channels) = file.discord_channels {
+        opts.discord_channels = channels;
+    }
+    if let Some(thresholds) = file.alert_balance {
+        opts.discord_alert_balance = thresholds
+            .iter()
+            .map(parse_ether)
+            .collect::<Result<_, _>>()
+            .context("invalid alert_balance")?;
+    }
+    if let Some(length) = file.alert_queue_length {
+        opts.discord_alert_queue_length = Some(length);
+    }
+    if let Some(failures) = file.alert_rpc_failures {
+        opts.discord_alert_rpc_failures = failures;
+    }
+    Ok(opts)
+}
+
+fn read(path: &Path) -> Result<SettingsFile> {
+    let contents =
+        fs::read_to_string(path).with_context(|| format!("reading {}", path.display()))?;
+    toml::from_str(&contents).with_context(|| format!("parsing {}", path.display()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_reload() {
+        let path =
+            std::env::temp_dir().join(format!("faucet-settings-{}.toml", rand::random::<u64>()));
+        fs::write(&path, "grant_amount = \"2\"\ndiscord_channels = [1, 2]\n").unwrap();
+        let mut opts = Options::default();
+        opts.settings_file = Some(path.clone());
+        opts.discord_alert_queue_length = Some(10);
+        let live = LiveOptions::load(opts).unwrap();
+        assert_eq!(live.get().faucet_grant_amount, parse_ether(2).unwrap());
+        assert_eq!(live.get().discord_channels, vec![1, 2]);
+        assert_eq!(live.get().discord_alert_queue_length, Some(10));
+
+        // Removed settings revert to the command line options.
+        fs::write(&path, "alert_queue_length = 5\n").unwrap();
+        live.reload().unwrap();
+        assert_eq!(
+            live.get().faucet_grant_amount,
+            Options::default().faucet_grant_amount
+        );
+        assert!(live.get().discord_channels.is_empty());
+        assert_eq!(live.get().discord_alert_queue_length, Some(5));
+
+        // Invalid files are rejected without changing the options.
+        fs::write(&path, "grant_amount = \"lots\"\n").unwrap();
+        live.reload().unwrap_err();
+        assert_eq!(live.get().discord_alert_queue_length, Some(5));
+
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,39 @@
+// Copyright (c) 2023 Espresso Systems (espressosys.com)
+// This file is part of the Discord Faucet library.
+//
+// You should have received a copy of the MIT License
+// along with the Discord Faucet library. If not, see <https://mit-license.org/>.
+
+//! The transport of the RPC provider of the faucet.
+use crate::HttpClient;
+#[cfg(test)]
+use crate::MockChain;
+use async_trait::async_trait;
+use ethers::providers::{JsonRpcClient, ProviderError};
+use serde::{de::DeserializeOwned, Serialize};
+use std::fmt::Debug;
+
+#[derive(Clone, Debug)]
+pub enum RpcClient {
+    Http(HttpClient),
+    /// An in-memory chain, for tests which do not spawn anvil.
+    #[cfg(test)]
+    Mock(MockChain),
+}
+
+#[async_trait]
+impl JsonRpcClient for RpcClient {
+    type Error = ProviderError;
+
+    async fn request<T, R>(&self, method: &str, params: T) -> Result<R, Self::Error>
+    where
+        T: Debug + Serialize + Send + Sync,
+        R: DeserializeOwned + Send,
+    {
+        match self {
+            Self::Http(client) => client.request(method, params).await.map_err(Into::into),
+            #[cfg(test)]
+            Self::Mock(chain) => chain.request(method, params).await.map_err(Into::into),
+        }
+    }
+}
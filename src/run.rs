@@ -0,0 +1,347 @@
+// Copyright (c) 2023 Espresso Systems (espressosys.com)
+// This file is part of the Discord Faucet library.
+//
+// You should have received a copy of the MIT License
+// along with the Discord Faucet library. If not, see <https://mit-license.org/>.
+
+//! Startup of the faucet, its front-ends and the background tasks.
+use crate::{
+    notify_systemd, open_cooldown_store, open_storage, record_history, save_snapshots, serve,
+    serve_events, serve_ui, serve_unix, setup_tracing, watch_paymaster, AlertWebhook, BanList,
+    Catalog, Command, Faucet, Guilds, LiveOptions, MemoryCooldownStore, MetricsBackend, Options,
+    SharedCooldownStore, Snapshot, TopUpList, WebState,
+};
+use async_compatibility_layer::logging::{setup_backtrace, setup_logging};
+use async_std::task::spawn;
+use clap::{Parser, ValueEnum};
+use futures::StreamExt;
+use signal_hook::consts::SIGHUP;
+use signal_hook_async_std::Signals;
+use std::{io, sync::Arc};
+
+/// The front-ends run by this process.
+///
+/// Running the HTTP API and the Discord bot in separate processes lets each be scaled and
+/// restarted on its own. Such processes share the queue of requests, with `--queue-url`, so that
+/// the wallets of every process serve the requests received by any of them. They must use
+/// different wallets, with `--wallet-shard`, so that their transactions do not conflict.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum RunMode {
+    /// Only serve requests from the HTTP API and the web page.
+    Web,
+    /// Only run the Discord bot. The API still serves the healthchecks, metrics and admin routes.
+    Discord,
+    /// Run both front-ends.
+    #[default]
+    Both,
+}
+
+impl RunMode {
+    /// Whether requests for funds are served over HTTP, gRPC and the web page.
+    pub fn serves_web(self) -> bool {
+        self != Self::Discord
+    }
+
+    /// Whether the Discord bot runs.
+    pub fn serves_discord(self) -> bool {
+        self != Self::Web
+    }
+}
+
+/// Reload the configuration of `state` whenever the process receives `SIGHUP`.
+async fn reload_on_sighup(state: WebState) {
+    let mut signals = match Signals::new([SIGHUP]) {
+        Ok(signals) => signals,
+        Err(err) => {
+            tracing::error!("Cannot listen for SIGHUP: {err}");
+            return;
+        }
+    };
+    while signals.next().await.is_some() {
+        tracing::info!("Received SIGHUP, reloading the configuration");
+        // Failures are logged, and the old configuration is kept.
+        state.reload().await.ok();
+    }
+}
+
+/// Apply the pending migrations to all the databases configured in `opts`.
+fn migrate(opts: &Options) -> anyhow::Result<()> {
+    for url in [&opts.database_url, &opts.queue_url, &opts.leader_lock_url]
+        .into_iter()
+        .flatten()
+    {
+        // Opening a database applies its migrations.
+        open_storage(url)?;
+    }
+    tracing::info!("Migrated the databases");
+    Ok(())
+}
+
+#[async_std::main]
+pub async fn main() -> io::Result<()> {
+    if Command::requested() {
+        setup_logging();
+        let succeeded = Command::parse().run().await;
+        std::process::exit(if succeeded { 0 } else { 1 });
+    }
+
+    // Configure the client with your Discord bot token in the environment.
+    let opts = Options::parse();
+    setup_tracing(&opts);
+    setup_backtrace();
+    if opts.migrate_only {
+        std::process::exit(match migrate(&opts) {
+            Ok(()) => 0,
+            Err(err) => {
+                tracing::error!("Failed to migrate the databases: {err:#}");
+                1
+            }
+        });
+    }
+    let live_options = LiveOptions::load(opts).expect("Failed to load the settings file");
+    let opts = Options::clone(&live_options.get());
+
+    // Create a new instance of the Client, logging in as a bot. This will
+    // automatically prepend your bot token with "Bot ", which is a requirement
+    // by Discord for bot users.
+    let (sender, receiver) = async_std::channel::bounded(opts.request_channel_capacity.get());
+    let faucet = Faucet::create(opts.clone(), receiver)
+        .await
+        .expect("Failed to create faucet");
+    let storage = opts
+        .database_url
+        .as_deref()
+        .map(|url| open_storage(url).expect("Failed to open the database"));
+    let snapshot = match &opts.state_file {
+        Some(path) => Snapshot::load(path).expect("Failed to load the state file"),
+        None => Snapshot::default(),
+    };
+    // Without a database or a cooldown store, the cooldowns are saved in the state file.
+    let snapshot_cooldowns = MemoryCooldownStore::new(snapshot.cooldowns);
+    let cooldowns = match &opts.cooldown_store_url {
+        Some(url) => Some(
+            open_cooldown_store(url)
+                .await
+                .expect("Failed to connect to the cooldown store"),
+        ),
+        None => match &storage {
+            Some(storage) => Some(storage.clone() as SharedCooldownStore),
+            None => opts
+                .state_file
+                .is_some()
+                .then(|| Arc::new(snapshot_cooldowns.clone()) as SharedCooldownStore),
+        },
+    };
+    let guilds = match opts.guild_config.clone() {
+        Some(path) => Guilds::load(path, &opts, cooldowns.clone())
+            .await
+            .expect("Failed to load guild settings"),
+        None => Guilds::default(),
+    };
+    if let Some(name) = &opts.dual_layer_chain {
+        assert!(
+            guilds.chain(name).is_some(),
+            "The dual-layer chain {name} is not defined in the guild settings"
+        );
+    }
+    for (_, chain) in guilds.chains() {
+        spawn(chain.faucet.clone().start());
+    }
+    if let Some(storage) = &storage {
+        if opts.max_lifetime_per_address.is_some() {
+            let totals = storage
+                .lifetime_grants("")
+                .await
+                .expect("Failed to load the lifetime grants");
+            faucet.load_lifetime_grants(totals).await;
+            let returns = storage
+                .lifetime_returns("")
+                .await
+                .expect("Failed to load the returns");
+            faucet.load_returns(returns).await;
+            for (name, chain) in guilds.chains() {
+                let totals = storage
+                    .lifetime_grants(name)
+                    .await
+                    .expect("Failed to load the lifetime grants");
+                chain.faucet.load_lifetime_grants(totals).await;
+                let returns = storage
+                    .lifetime_returns(name)
+                    .await
+                    .expect("Failed to load the returns");
+                chain.faucet.load_returns(returns).await;
+            }
+        }
+        spawn(record_history(
+            faucet.clone(),
+            storage.clone(),
+            String::new(),
+        ));
+        for (name, chain) in guilds.chains() {
+            spawn(record_history(
+                chain.faucet.clone(),
+                storage.clone(),
+                name.clone(),
+            ));
+        }
+    }
+    if let Some(webhook) = AlertWebhook::new(&opts) {
+        let source = opts
+            .network_name
+            .clone()
+            .unwrap_or_else(|| format!("chain {}", faucet.chain_id()));
+        webhook.alert_on_panic(source);
+        spawn(webhook.clone().watch(faucet.clone(), live_options.clone()));
+        for (_, chain) in guilds.chains() {
+            spawn(
+                webhook
+                    .clone()
+                    .watch(chain.faucet.clone(), live_options.clone()),
+            );
+        }
+    }
+    if opts.return_address.is_some() {
+        spawn(guilds.clone().credit_returns(faucet.clone()));
+        for (_, chain) in guilds.chains() {
+            spawn(guilds.clone().credit_returns(chain.faucet.clone()));
+        }
+    }
+    spawn(guilds.clone().watch());
+    let bans = match opts.discord_ban_list.clone() {
+        Some(path) => BanList::load(path).expect("Failed to load the ban list"),
+        None => BanList::default(),
+    };
+    let top_ups = match opts.top_up_file.clone() {
+        Some(path) => TopUpList::load(path).expect("Failed to load the top-up registrations"),
+        None => TopUpList::default(),
+    };
+    let catalog = Catalog::load(opts.discord_locales.as_deref(), &opts.discord_locale)
+        .expect("Failed to load Discord messages");
+    let state = WebState::new(sender, faucet.clone())
+        .with_guilds(guilds)
+        .with_catalog(catalog)
+        .with_bans(bans)
+        .with_top_ups(top_ups.clone())
+        .with_live_options(live_options);
+    let state = match storage {
+        Some(storage) => state.with_storage(storage),
+        None => state,
+    };
+    let state = match cooldowns {
+        Some(cooldowns) => state.with_cooldown_store(cooldowns),
+        None => state,
+    };
+    state
+        .load_registrations()
+        .await
+        .expect("Failed to load the Discord registrations");
+    spawn(reload_on_sighup(state.clone()));
+    spawn(state.leaderboard.clone().watch(faucet.clone()));
+    for (_, chain) in state.guilds.chains() {
+        spawn(state.leaderboard.clone().watch(chain.faucet.clone()));
+    }
+    if let Some(path) = opts.state_file.clone() {
+        state.api_keys.restore(snapshot.api_keys).await;
+        faucet
+            .restore(snapshot.faucet)
+            .await
+            .expect("Failed to restore the state");
+        spawn(save_snapshots(
+            path,
+            opts.state_snapshot_interval,
+            faucet.clone(),
+            state.clone(),
+            snapshot_cooldowns,
+        ));
+    }
+
+    // Do not attempt to start the discord bot if the token is missing or empty.
+    let discord_token = opts
+        .discord_token
+        .clone()
+        .filter(|token| !token.is_empty() && opts.mode.serves_discord());
+    if cfg!(not(feature = "discord")) && discord_token.is_some() {
+        tracing::warn!("Built without the `discord` feature, ignoring the Discord token");
+    }
+    #[cfg(feature = "discord")]
+    let state = if discord_token.is_some() {
+        let gateway = crate::Gateway::default();
+        if let Some(max) = opts.discord_max_disconnection {
+            spawn(crate::exit_when_disconnected(gateway.clone(), max));
+        }
+        state.with_gateway(gateway)
+    } else {
+        tracing::warn!("Discord bot disabled. For local testing this is fine.");
+        state
+    };
+    #[cfg(feature = "discord")]
+    let discord_state = state.clone();
+    if opts.metrics_backend != MetricsBackend::Prometheus {
+        spawn(state.clone().export_statsd());
+    }
+
+    if let Some(threshold) = opts.top_up_threshold {
+        spawn(top_ups.watch(state.clone(), threshold, opts.top_up_interval));
+    }
+    if let (Some(paymaster), Some(_)) = (opts.paymaster, opts.entry_point) {
+        spawn(watch_paymaster(
+            faucet.clone(),
+            paymaster,
+            opts.paymaster_min_deposit,
+            opts.paymaster_deposit_amount,
+            opts.paymaster_check_interval,
+        ));
+    }
+    spawn(notify_systemd(faucet.clone()));
+    if let (true, Some(canary)) = (opts.self_test, opts.self_test_address) {
+        spawn(
+            state
+                .self_test
+                .clone()
+                .run(faucet.clone(), state.faucet_queue.clone(), canary),
+        );
+    }
+    if opts.mode.serves_web() {
+        let port = opts.events_port;
+        let events = faucet.events();
+        spawn(async move {
+            if let Err(err) = serve_events(port, events).await {
+                tracing::error!("Server-Sent Events server failed: {err}");
+            }
+        });
+    }
+    let faucet_handle = spawn(faucet.start());
+    #[cfg(feature = "grpc")]
+    if let Some(port) = opts.grpc_port.filter(|_| opts.mode.serves_web()) {
+        let state = state.clone();
+        spawn(async move {
+            if let Err(err) = crate::serve_grpc(port, state).await {
+                tracing::error!("gRPC server failed: {err}");
+            }
+        });
+    }
+    let api_handle = match opts.listen_unix.clone() {
+        Some(path) => spawn(async move { serve_unix(&path, state).await }),
+        None => spawn(serve(opts.port, state)),
+    };
+    if let Some(port) = opts.ui_port.filter(|_| opts.mode.serves_web()) {
+        let opts = opts.clone();
+        spawn(async move {
+            if let Err(err) = serve_ui(port, &opts).await {
+                tracing::error!("Web page server failed: {err}");
+            }
+        });
+    }
+
+    #[cfg(feature = "discord")]
+    if let Some(token) = discord_token {
+        let _result = futures::join!(
+            faucet_handle,
+            api_handle,
+            crate::run_discord(token, discord_state, &opts)
+        );
+        return Ok(());
+    }
+    let _result = futures::join!(faucet_handle, api_handle);
+    Ok(())
+}
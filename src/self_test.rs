@@ -0,0 +1,101 @@
+// Copyright (c) 2023 Espresso Systems (espressosys.com)
+// This file is part of the Discord Faucet library.
+//
+// You should have received a copy of the MIT License
+// along with the Discord Faucet library. If not, see <https://mit-license.org/>.
+
+//! A real grant at startup, to check that a deployment works end to end.
+//!
+//! With `--self-test`, once the faucet is ready it sends one grant to `--self-test-address`, waits
+//! for the transfer to be confirmed and checks its receipt. The outcome is logged and exported as
+//! the `faucet_self_test_passed` metric.
+use crate::{Faucet, FaucetEvent, FaucetRequest, RequestSource, WebState};
+use anyhow::{anyhow, ensure, Context, Result};
+use async_std::{channel::Sender, future::timeout, sync::RwLock, task::sleep};
+use ethers::types::{Address, H256};
+use futures::StreamExt;
+use std::{sync::Arc, time::Duration};
+
+/// How often to check whether the faucet is ready.
+const READY_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The outcome of the self-test, shared with the metrics.
+#[derive(Clone, Debug, Default)]
+pub struct SelfTest {
+    passed: Arc<RwLock<Option<bool>>>,
+}
+
+impl SelfTest {
+    /// Whether the self-test passed, or `None` if it has not finished or is disabled.
+    pub async fn passed(&self) -> Option<bool> {
+        *self.passed.read().await
+    }
+
+    /// Grant funds to `canary` from `faucet`, and record whether the grant succeeded.
+    pub async fn run(self, faucet: Faucet, queue: Sender<FaucetRequest>, canary: Address) {
+        while !faucet.is_ready().await {
+            sleep(READY_CHECK_INTERVAL).await;
+        }
+        tracing::info!("Running the self-test, granting funds to {canary:?}");
+        let passed = match grant_and_confirm(&faucet, &queue, canary).await {
+            Ok(tx_hash) => {
+                tracing::info!("Self-test passed with transaction {tx_hash:?}");
+                true
+            }
+            Err(err) => {
+                tracing::error!("Self-test failed: {err:#}");
+                false
+            }
+        };
+        *self.passed.write().await = Some(passed);
+    }
+}
+
+/// Grant funds to `to` and check the receipt of the transfer.
+pub(crate) async fn grant_and_confirm(
+    faucet: &Faucet,
+    queue: &Sender<FaucetRequest>,
+    to: Address,
+) -> Result<H256> {
+    // Subscribe before submitting, so that the confirmation cannot be missed.
+    let mut events = faucet.events().subscribe().await;
+    let request = FaucetRequest::new(to, None).with_source(RequestSource::Admin);
+    let queued = WebState::submit(queue, faucet, request)
+        .await
+        .map_err(|err| anyhow!("{err}"))?;
+
+    // Failed transfers are retried, so wait for the confirmation until the transfer times out
+    // twice.
+    let confirmed = async {
+        while let Some(event) = events.next().await {
+            if let FaucetEvent::TransferConfirmed {
+                request, tx_hash, ..
+            } = event
+            {
+                if request.id() == Some(queued.id) {
+                    return Some(tx_hash);
+                }
+            }
+        }
+        None
+    };
+    let tx_hash = timeout(faucet.config().transaction_timeout * 2, confirmed)
+        .await
+        .context("timed out waiting for the transfer")?
+        .context("the faucet stopped publishing events")?;
+
+    let receipt = faucet
+        .receipt(tx_hash)
+        .await?
+        .with_context(|| format!("no receipt for transaction {tx_hash:?}"))?;
+    ensure!(
+        receipt.status == Some(1.into()),
+        "transaction {tx_hash:?} reverted"
+    );
+    ensure!(
+        receipt.to == Some(to),
+        "transaction {tx_hash:?} was sent to {:?} instead of {to:?}",
+        receipt.to
+    );
+    Ok(tx_hash)
+}
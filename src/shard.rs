@@ -0,0 +1,84 @@
+// Copyright (c) 2023 Espresso Systems (espressosys.com)
+// This file is part of the Discord Faucet library.
+//
+// You should have received a copy of the MIT License
+// along with the Discord Faucet library. If not, see <https://mit-license.org/>.
+
+//! Sharding of the wallets derived from a shared mnemonic between replicas.
+use anyhow::{ensure, Context};
+use std::{
+    fmt::{self, Display, Formatter},
+    str::FromStr,
+};
+
+/// The slice of wallets used by one of `count` replicas sharing a mnemonic, written `index/count`.
+///
+/// Shard `i` uses the `--num-clients` wallets following the `i * --num-clients`-th one, so that
+/// the shards of replicas with the same `--num-clients` are disjoint.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WalletShard {
+    index: u32,
+    count: u32,
+}
+
+impl WalletShard {
+    pub fn new(index: u32, count: u32) -> anyhow::Result<Self> {
+        ensure!(count > 0, "the number of shards must be positive");
+        ensure!(
+            index < count,
+            "shard index {index} must be less than the number of shards {count}"
+        );
+        Ok(Self { index, count })
+    }
+
+    pub fn index(&self) -> u32 {
+        self.index
+    }
+
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+
+    /// The offset of the first wallet of this shard, when each shard has `wallets_per_shard`.
+    pub fn offset(&self, wallets_per_shard: usize) -> u32 {
+        self.index * wallets_per_shard as u32
+    }
+}
+
+impl Display for WalletShard {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.index, self.count)
+    }
+}
+
+impl FromStr for WalletShard {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (index, count) = s
+            .split_once('/')
+            .context("expected a shard of the form `index/count`, e.g. `0/4`")?;
+        Self::new(
+            index.trim().parse().context("invalid shard index")?,
+            count.trim().parse().context("invalid number of shards")?,
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_wallet_shard() {
+        let shard: WalletShard = "2/4".parse().unwrap();
+        assert_eq!((shard.index(), shard.count()), (2, 4));
+        assert_eq!(shard.offset(10), 20);
+        assert_eq!(shard.to_string(), "2/4");
+        assert_eq!("0/1".parse::<WalletShard>().unwrap().offset(10), 0);
+
+        for invalid in ["4/4", "1/0", "1", "a/2", "1/b", ""] {
+            assert!(invalid.parse::<WalletShard>().is_err(), "{invalid}");
+        }
+    }
+}
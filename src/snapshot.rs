@@ -0,0 +1,240 @@
+// Copyright (c) 2023 Espresso Systems (espressosys.com)
+// This file is part of the Discord Faucet library.
+//
+// You should have received a copy of the MIT License
+// along with the Discord Faucet library. If not, see <https://mit-license.org/>.
+
+//! Snapshots of the state of the faucet, so that planned restarts are lossless without a database.
+//!
+//! With `--state-file`, the queue, the transfers in flight, the cooldowns and the usage of the API
+//! keys are saved periodically and when the process is asked to terminate, and restored on
+//! startup. The transfers in flight are reconciled with the chain when they are restored.
+use crate::{
+    CooldownEntry, Drip, Faucet, IncludedTransfer, MemoryCooldownStore, MetaTransaction, RequestId,
+    TransferRequest, UsageSnapshot, WebState,
+};
+use anyhow::{Context, Result};
+use async_std::future::timeout;
+use ethers::types::{Address, H256, U256, U64};
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use signal_hook::consts::{SIGINT, SIGTERM};
+use signal_hook_async_std::Signals;
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+/// The state of a faucet saved in a snapshot.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub struct FaucetSnapshot {
+    /// The queued faucet requests, in order.
+    pub queue: Vec<TransferRequest>,
+    pub inflight: Vec<InflightSnapshot>,
+    /// The UTC day, as days since the Unix epoch, and the number of requests confirmed on that day.
+    pub grants_today: (u64, usize),
+    /// The total amount of native currency granted to each address, with
+    /// `--max-lifetime-per-address`.
+    #[serde(default)]
+    pub lifetime_grants: HashMap<Address, U256>,
+    /// The total amount of native currency each address sent back to `--return-address`.
+    #[serde(default)]
+    pub returns: HashMap<Address, U256>,
+    /// The installments of drip requests which are still to be paid.
+    #[serde(default)]
+    pub drips: Vec<Drip>,
+    /// The meta-transactions of the relays which are not confirmed yet.
+    #[serde(default)]
+    pub relays: HashMap<RequestId, MetaTransaction>,
+    /// The grants which were mined but whose block was not final yet, with `--finality`.
+    #[serde(default)]
+    pub included: Vec<IncludedTransfer>,
+    /// The latest block when the snapshot was taken, after which the transfers submitted since the
+    /// snapshot are searched when it is restored.
+    #[serde(default)]
+    pub block_number: Option<U64>,
+}
+
+/// A transfer which was submitted but not mined when the snapshot was taken.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct InflightSnapshot {
+    pub tx_hash: H256,
+    pub sender: Address,
+    pub request: TransferRequest,
+    /// The other grants paid by the same transaction, with `--disperse-contract`.
+    #[serde(default)]
+    pub batch: Vec<TransferRequest>,
+    /// When the transfer was submitted, in milliseconds since the Unix epoch.
+    pub submitted_at: u64,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub struct Snapshot {
+    pub faucet: FaucetSnapshot,
+    pub cooldowns: HashMap<String, CooldownEntry>,
+    pub api_keys: HashMap<String, UsageSnapshot>,
+}
+
+impl Snapshot {
+    /// Load the snapshot at `path`, or an empty snapshot if there is none yet.
+    pub fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let contents =
+            fs::read_to_string(path).with_context(|| format!("reading {}", path.display()))?;
+        serde_json::from_str(&contents).with_context(|| format!("parsing {}", path.display()))
+    }
+
+    /// Save the snapshot to `path`.
+    ///
+    /// The snapshot is written to a temporary file first, so that a crash while saving does not
+    /// corrupt the previous snapshot.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, serde_json::to_vec(self)?)
+            .with_context(|| format!("writing {}", tmp.display()))?;
+        fs::rename(&tmp, path).with_context(|| format!("replacing {}", path.display()))?;
+        Ok(())
+    }
+
+    /// Take a snapshot of the faucet and its front-ends.
+    pub(crate) async fn take(
+        faucet: &Faucet,
+        state: &WebState,
+        cooldowns: &MemoryCooldownStore,
+    ) -> Self {
+        Self {
+            faucet: faucet.snapshot().await,
+            cooldowns: cooldowns.entries().await,
+            api_keys: state.api_keys.snapshot().await,
+        }
+    }
+}
+
+/// Save snapshots to `path` every `interval`, and when the process is asked to terminate.
+pub(crate) async fn save_snapshots(
+    path: PathBuf,
+    interval: Duration,
+    faucet: Faucet,
+    state: WebState,
+    cooldowns: MemoryCooldownStore,
+) {
+    let mut signals = match Signals::new([SIGTERM, SIGINT]) {
+        Ok(signals) => Some(signals),
+        Err(err) => {
+            tracing::error!(
+                "Cannot listen for SIGTERM, the state will not be saved on exit: {err}"
+            );
+            None
+        }
+    };
+    loop {
+        let terminating = match &mut signals {
+            Some(signals) => timeout(interval, signals.next()).await.is_ok(),
+            None => {
+                async_std::task::sleep(interval).await;
+                false
+            }
+        };
+        let snapshot = Snapshot::take(&faucet, &state, &cooldowns).await;
+        match snapshot.save(&path) {
+            Ok(()) => tracing::debug!("Saved the state to {}", path.display()),
+            Err(err) => tracing::error!("Failed to save the state: {err:#}"),
+        }
+        if terminating {
+            tracing::info!("Saved the state to {}, exiting", path.display());
+            std::process::exit(0);
+        }
+    }
+}
+
+/// The number of milliseconds since the Unix epoch at `instant`.
+pub(crate) fn to_unix_millis(instant: Instant) -> u64 {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    now.saturating_sub(instant.elapsed()).as_millis() as u64
+}
+
+/// The instant at `millis` milliseconds since the Unix epoch, or now if it is in the future.
+pub(crate) fn from_unix_millis(millis: u64) -> Instant {
+    let elapsed = SystemTime::now()
+        .duration_since(UNIX_EPOCH + Duration::from_millis(millis))
+        .unwrap_or_default();
+    Instant::now()
+        .checked_sub(elapsed)
+        .unwrap_or_else(Instant::now)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::CorrelationId;
+
+    #[test]
+    fn test_snapshot_file() {
+        let dir = std::env::temp_dir().join(format!("snapshot-{}", rand::random::<u64>()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("state.json");
+        assert_eq!(Snapshot::load(&path).unwrap(), Snapshot::default());
+
+        let request = TransferRequest::faucet(RequestId::random(), Address::random(), 1.into());
+        let snapshot = Snapshot {
+            faucet: FaucetSnapshot {
+                queue: vec![request],
+                inflight: vec![InflightSnapshot {
+                    tx_hash: H256::random(),
+                    sender: Address::random(),
+                    request,
+                    batch: vec![],
+                    submitted_at: 1000,
+                }],
+                grants_today: (19000, 3),
+                lifetime_grants: [(request.to(), 1.into())].into(),
+                returns: [(request.to(), 1.into())].into(),
+                drips: vec![Drip {
+                    id: request.id().unwrap(),
+                    correlation_id: CorrelationId::random(),
+                    to: request.to(),
+                    amount: 1.into(),
+                    remaining: 2,
+                    next_at: 2000,
+                }],
+                relays: Default::default(),
+                included: vec![IncludedTransfer {
+                    request,
+                    tx_hash: H256::random(),
+                    block_number: 7.into(),
+                    sent_with: Some((Address::random(), 3.into())),
+                }],
+                block_number: Some(8.into()),
+            },
+            cooldowns: [(
+                "oauth:1".to_string(),
+                CooldownEntry {
+                    started_at: 1,
+                    expires_at: 2,
+                },
+            )]
+            .into(),
+            api_keys: Default::default(),
+        };
+        snapshot.save(&path).unwrap();
+        assert_eq!(Snapshot::load(&path).unwrap(), snapshot);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_unix_millis() {
+        let instant = Instant::now() - Duration::from_secs(10);
+        let restored = from_unix_millis(to_unix_millis(instant));
+        let drift = restored
+            .checked_duration_since(instant)
+            .unwrap_or_else(|| instant.duration_since(restored));
+        assert!(drift < Duration::from_millis(100));
+    }
+}
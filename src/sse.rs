@@ -0,0 +1,92 @@
+// Copyright (c) 2023 Espresso Systems (espressosys.com)
+// This file is part of the Discord Faucet library.
+//
+// You should have received a copy of the MIT License
+// along with the Discord Faucet library. If not, see <https://mit-license.org/>.
+
+//! Faucet activity as Server-Sent Events.
+//!
+//! The API only streams over WebSockets, which browsers cannot reconnect to by themselves, so the
+//! events are also served as Server-Sent Events at `GET /faucet/events` and `GET /v1/events`, for
+//! dashboards using an `EventSource`. The API framework cannot serve them next to the WebSocket
+//! route, so they are served by a separate, plain HTTP server on `--events-port`, enabled by
+//! default.
+use crate::EventBus;
+use futures::StreamExt;
+use std::io;
+use tide::{
+    security::{CorsMiddleware, Origin},
+    sse, Request,
+};
+
+pub(crate) async fn serve_events(port: u16, events: EventBus) -> io::Result<()> {
+    app(events).listen(format!("0.0.0.0:{port}")).await
+}
+
+fn app(events: EventBus) -> tide::Server<EventBus> {
+    let mut app = tide::with_state(events);
+    // Dashboards are usually served from another origin.
+    app.with(CorsMiddleware::new().allow_origin(Origin::from("*")));
+    for path in ["/faucet/events", "/v1/events"] {
+        app.at(path)
+            .get(sse::endpoint(|req: Request<EventBus>, sender| async move {
+                let mut events = req.state().subscribe().await;
+                while let Some(event) = events.next().await {
+                    sender
+                        .send(event.name(), serde_json::to_string(&event)?, None)
+                        .await?;
+                }
+                Ok(())
+            }));
+    }
+    app
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{FaucetEvent, RequestId, TransferRequest};
+    use async_std::{
+        io::{prelude::BufReadExt, BufReader},
+        task::{sleep, spawn},
+    };
+    use ethers::types::Address;
+    use std::time::Duration;
+    use tide::listener::Listener;
+
+    #[async_std::test]
+    async fn test_serve_events() {
+        let events = EventBus::default();
+        let port = portpicker::pick_unused_port().unwrap();
+        let mut listener = app(events.clone())
+            .bind(format!("127.0.0.1:{port}"))
+            .await
+            .unwrap();
+        spawn(async move { listener.accept().await });
+
+        let res = surf::get(format!("http://127.0.0.1:{port}/v1/events"))
+            .await
+            .unwrap();
+        assert_eq!(res.content_type().unwrap().essence(), "text/event-stream");
+
+        // Keep publishing until the stream has subscribed.
+        let event = FaucetEvent::RequestQueued {
+            request: TransferRequest::faucet(RequestId::random(), Address::random(), 1.into()),
+        };
+        spawn({
+            let event = event.clone();
+            async move {
+                loop {
+                    events.publish(event.clone()).await;
+                    sleep(Duration::from_millis(100)).await;
+                }
+            }
+        });
+        let mut lines = BufReader::new(res).lines();
+        assert_eq!(lines.next().await.unwrap().unwrap(), "event:request_queued");
+        assert_eq!(
+            lines.next().await.unwrap().unwrap(),
+            format!("data:{}", serde_json::to_string(&event).unwrap())
+        );
+    }
+}
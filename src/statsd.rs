@@ -0,0 +1,136 @@
+// Copyright (c) 2023 Espresso Systems (espressosys.com)
+// This file is part of the Discord Faucet library.
+//
+// You should have received a copy of the MIT License
+// along with the Discord Faucet library. If not, see <https://mit-license.org/>.
+
+//! Export of the metrics to StatsD or DogStatsD.
+//!
+//! The metrics are pushed over UDP at a fixed interval. StatsD counters are increments, so each
+//! push sends how much every counter grew since the previous push.
+use crate::{MetricKind, MetricsBackend, Sample};
+use async_std::net::UdpSocket;
+use std::{collections::HashMap, io};
+
+pub struct StatsdExporter {
+    socket: UdpSocket,
+    /// Whether labels are sent as DogStatsD tags rather than in the metric names.
+    tags: bool,
+    prefix: String,
+    /// The value of each counter at the previous push, by metric name and label.
+    counters: HashMap<String, u64>,
+}
+
+impl StatsdExporter {
+    /// Send metrics to the StatsD server at `address`, e.g. `localhost:8125`.
+    pub async fn connect(
+        address: &str,
+        backend: MetricsBackend,
+        prefix: String,
+    ) -> io::Result<Self> {
+        let socket = UdpSocket::bind("0.0.0.0:0").await?;
+        socket.connect(address).await?;
+        Ok(Self {
+            socket,
+            tags: backend == MetricsBackend::Dogstatsd,
+            prefix,
+            counters: Default::default(),
+        })
+    }
+
+    /// Push `samples` to the server.
+    pub async fn send(&mut self, samples: &[Sample]) -> io::Result<()> {
+        // Each line is sent in its own datagram, to stay below the size limit of the server.
+        for line in self.lines(samples) {
+            self.socket.send(line.as_bytes()).await?;
+        }
+        Ok(())
+    }
+
+    fn lines(&mut self, samples: &[Sample]) -> Vec<String> {
+        let mut lines = vec![];
+        for sample in samples {
+            let mut name = format!("{}{}", self.prefix, sample.name);
+            let mut tags = String::new();
+            if let Some((label, value)) = &sample.label {
+                if self.tags {
+                    tags = format!("|#{label}:{value}");
+                } else {
+                    name = format!("{name}.{label}.{value}");
+                }
+            }
+            let (value, kind) = match sample.kind {
+                MetricKind::Gauge => (sample.value, "g"),
+                MetricKind::Counter => {
+                    let previous = self
+                        .counters
+                        .insert(format!("{name}{tags}"), sample.value)
+                        .unwrap_or_default();
+                    let increment = sample.value.saturating_sub(previous);
+                    if increment == 0 {
+                        continue;
+                    }
+                    (increment, "c")
+                }
+            };
+            lines.push(format!("{name}:{value}|{kind}{tags}"));
+        }
+        lines
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{collect_metrics, DiscordMetrics};
+
+    #[async_std::test]
+    async fn test_statsd_lines() {
+        let metrics = DiscordMetrics::default();
+        metrics.grants(Some(10), 2).await;
+
+        let mut statsd =
+            StatsdExporter::connect("127.0.0.1:8125", MetricsBackend::Statsd, "faucet.".into())
+                .await
+                .unwrap();
+        let mut dogstatsd = StatsdExporter::connect(
+            "127.0.0.1:8125",
+            MetricsBackend::Dogstatsd,
+            "faucet.".into(),
+        )
+        .await
+        .unwrap();
+
+        let samples = collect_metrics(None, &[], Some((&metrics, 0, 1)), None).await;
+        assert_eq!(
+            statsd.lines(&samples),
+            [
+                "faucet.discord_grants_total.guild.10:2|c",
+                "faucet.discord_pending_requesters:1|g"
+            ]
+        );
+        assert_eq!(
+            dogstatsd.lines(&samples),
+            [
+                "faucet.discord_grants_total:2|c|#guild:10",
+                "faucet.discord_pending_requesters:1|g"
+            ]
+        );
+
+        // Counters are sent as increments since the previous push.
+        metrics.grants(Some(10), 3).await;
+        let samples = collect_metrics(None, &[], Some((&metrics, 0, 1)), None).await;
+        assert_eq!(
+            statsd.lines(&samples),
+            [
+                "faucet.discord_grants_total.guild.10:3|c",
+                "faucet.discord_pending_requesters:1|g"
+            ]
+        );
+        // Gauges are sent every time.
+        assert_eq!(
+            statsd.lines(&samples),
+            ["faucet.discord_pending_requesters:1|g"]
+        );
+    }
+}
@@ -0,0 +1,144 @@
+// Copyright (c) 2023 Espresso Systems (espressosys.com)
+// This file is part of the Discord Faucet library.
+//
+// You should have received a copy of the MIT License
+// along with the Discord Faucet library. If not, see <https://mit-license.org/>.
+
+//! The `status` command, a summary of the state of a running faucet.
+use crate::{DeepHealth, FaucetError};
+use clap::Args;
+use ethers::utils::format_ether;
+use std::fmt::Write;
+use surf_disco::Client;
+use url::Url;
+
+#[derive(Args, Clone, Debug)]
+pub struct Status {
+    /// The URL of the API of the running faucet.
+    #[arg(
+        long,
+        env = "ESPRESSO_DISCORD_FAUCET_STATUS_URL",
+        default_value = "http://localhost:8111"
+    )]
+    url: Url,
+}
+
+impl Status {
+    /// Print the state of the faucet, returning whether it is healthy.
+    pub async fn run(self) -> bool {
+        let client = Client::<FaucetError>::new(self.url.clone());
+        match client.get::<DeepHealth>("v1/healthcheck/deep").send().await {
+            Ok(health) => {
+                print!("{}", render(&health));
+                true
+            }
+            Err(err) => {
+                println!("The faucet at {} is unhealthy: {err}", self.url);
+                false
+            }
+        }
+    }
+}
+
+fn yes_no(value: bool) -> &'static str {
+    if value {
+        "yes"
+    } else {
+        "no"
+    }
+}
+
+/// Render `health` as a table.
+fn render(health: &DeepHealth) -> String {
+    let faucet = &health.faucet;
+    let mut rows = vec![
+        ("Faucet", String::new()),
+        ("  Paused", yes_no(faucet.paused).to_string()),
+        ("  Queue length", faucet.queue_length.to_string()),
+        ("  In flight", faucet.inflight.to_string()),
+        ("  Available wallets", faucet.available_wallets.to_string()),
+        (
+            "  Total balance",
+            format!("{} ETH", format_ether(faucet.total_balance)),
+        ),
+        ("  Block", faucet.block_number.to_string()),
+    ];
+    let mut shards = vec![];
+    match &health.discord {
+        Some(discord) => {
+            rows.push(("Discord", String::new()));
+            rows.push(("  Connected", yes_no(discord.connected).to_string()));
+            rows.push(("  Reconnects", discord.reconnects.to_string()));
+            for shard in &discord.shards {
+                let state = if shard.connected {
+                    "connected"
+                } else {
+                    "disconnected"
+                };
+                shards.push((
+                    format!("  Shard {}", shard.id),
+                    format!("{state} for {}s", shard.since_secs),
+                ));
+            }
+        }
+        None => rows.push(("Discord", "disabled".into())),
+    }
+
+    let rows = rows
+        .into_iter()
+        .map(|(name, value)| (name.to_string(), value))
+        .chain(shards)
+        .collect::<Vec<_>>();
+    let width = rows.iter().map(|(name, _)| name.len()).max().unwrap_or(0);
+    let mut out = String::new();
+    for (name, value) in rows {
+        writeln!(out, "{name:width$}  {value}").unwrap();
+    }
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{FaucetStats, GatewayHealth, ShardHealth};
+    use ethers::utils::parse_ether;
+
+    #[test]
+    fn test_render_status() {
+        let health = DeepHealth {
+            faucet: FaucetStats {
+                queue_length: 3,
+                inflight: 1,
+                completed: 5,
+                tarpitted: 0,
+                drips: 0,
+                relays: 0,
+                queue_keys: 0,
+                available_wallets: 9,
+                total_balance: parse_ether(100).unwrap(),
+                block_number: 42.into(),
+                paused: false,
+                velocity_spike: None,
+            },
+            discord: Some(GatewayHealth {
+                connected: true,
+                disconnected_secs: None,
+                reconnects: 2,
+                shards: vec![ShardHealth {
+                    id: 0,
+                    connected: true,
+                    since_secs: 60,
+                }],
+            }),
+        };
+        let table = render(&health);
+        for line in [
+            "  Queue length       3",
+            "  Total balance      100.000000000000000000 ETH",
+            "  Reconnects         2",
+            "  Shard 0            connected for 60s",
+        ] {
+            assert!(table.lines().any(|l| l == line), "missing {line}");
+        }
+    }
+}
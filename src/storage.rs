@@ -0,0 +1,995 @@
+// Copyright (c) 2023 Espresso Systems (espressosys.com)
+// This file is part of the Discord Faucet library.
+//
+// You should have received a copy of the MIT License
+// along with the Discord Faucet library. If not, see <https://mit-license.org/>.
+
+//! Persistent storage of the requests, the grants, the returns, the Discord registrations and the
+//! cooldowns.
+//!
+//! Without storage, the history is not kept and cooldowns are forgotten when the faucet restarts.
+//! With `--database-url`, every request and grant is recorded, and cooldowns are written through to
+//! the database so that they survive restarts, unless a separate cooldown store is configured.
+//! Transfers the faucet gives up on are recorded in a dead-letter table for operators to inspect.
+//!
+//! A local SQLite database is enough for a single instance. Deployments running several instances
+//! share a PostgreSQL database instead.
+use crate::{
+    migrate_sqlite, CooldownStore, Faucet, FaucetEvent, PgConnection, RequestId, TransferRequest,
+};
+use anyhow::{Context, Result};
+use async_std::task::spawn_blocking;
+use async_trait::async_trait;
+use ethers::types::{Address, H256, U256, U64};
+use futures::StreamExt;
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fmt::Debug,
+    path::Path,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// The most grants returned by a history query.
+pub const MAX_HISTORY: usize = 100;
+
+/// A grant which was mined successfully.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GrantRecord {
+    /// The chain of `--guild-config` the grant was made on, or empty for the default chain.
+    #[serde(default)]
+    pub chain: String,
+    pub request_id: RequestId,
+    pub to: Address,
+    /// The ERC-20 token granted, or `None` for the native currency.
+    pub token: Option<Address>,
+    pub amount: U256,
+    pub tx_hash: H256,
+    pub block_number: Option<U64>,
+    /// When the grant was confirmed, in seconds since the Unix epoch.
+    pub timestamp: u64,
+}
+
+#[async_trait]
+pub trait Storage: CooldownStore {
+    /// Record a faucet request when it is queued.
+    async fn record_request(&self, request: &TransferRequest) -> Result<()>;
+
+    /// Record a grant when it is mined.
+    async fn record_grant(&self, grant: &GrantRecord) -> Result<()>;
+
+    /// Record a failed transfer in the dead-letter table.
+    async fn record_failure(
+        &self,
+        request: &TransferRequest,
+        tx_hash: Option<H256>,
+        reason: &str,
+    ) -> Result<()>;
+
+    /// The latest grants to `to`, most recent first.
+    async fn history(&self, to: Address, limit: usize) -> Result<Vec<GrantRecord>>;
+
+    /// The total amount of native currency granted to each address on `chain`, empty for the
+    /// default chain.
+    async fn lifetime_grants(&self, chain: &str) -> Result<HashMap<Address, U256>>;
+
+    /// Record `amount` sent back to `--return-address` on `chain` by `from` in `tx_hash`.
+    async fn record_return(
+        &self,
+        chain: &str,
+        from: Address,
+        amount: U256,
+        tx_hash: H256,
+    ) -> Result<()>;
+
+    /// The total amount of native currency each address sent back to `--return-address` on
+    /// `chain`.
+    async fn lifetime_returns(&self, chain: &str) -> Result<HashMap<Address, U256>>;
+
+    /// Record `address` as the address registered by the Discord user `user`, replacing any
+    /// previous one.
+    async fn record_registration(&self, user: u64, address: Address) -> Result<()>;
+
+    /// The address registered by each Discord user.
+    async fn registrations(&self) -> Result<HashMap<u64, Address>>;
+}
+
+/// The storage shared by the faucet, its front-ends and the cooldowns.
+pub type SharedStorage = Arc<dyn Storage>;
+
+/// Open the database at `url`.
+///
+/// PostgreSQL databases are given by a `postgres://` URL. SQLite databases are given by path,
+/// optionally prefixed with `sqlite://`.
+pub fn open_storage(url: &str) -> Result<SharedStorage> {
+    if url.starts_with("postgres://") || url.starts_with("postgresql://") {
+        return Ok(Arc::new(PostgresStorage::connect(url)?));
+    }
+    let path = url
+        .strip_prefix("sqlite://")
+        .or_else(|| url.strip_prefix("sqlite:"))
+        .unwrap_or(url);
+    Ok(Arc::new(SqliteStorage::open(Path::new(path))?))
+}
+
+/// The token and amount granted by a faucet request, or `None` for funding transfers, relays and
+/// paymaster top-ups.
+fn grant_of(request: &TransferRequest) -> Option<(RequestId, Address, Option<Address>, U256)> {
+    match *request {
+        TransferRequest::Faucet { id, to, amount, .. }
+        | TransferRequest::Deposit {
+            id,
+            to,
+            amount,
+            top_up: false,
+            ..
+        } => Some((id, to, None, amount)),
+        TransferRequest::Erc20 {
+            id,
+            to,
+            token,
+            amount,
+            ..
+        } => Some((id, to, Some(token), amount)),
+        // A mint grants one token of the NFT contract.
+        TransferRequest::Nft {
+            id, to, contract, ..
+        } => Some((id, to, Some(contract), U256::one())),
+        TransferRequest::Erc1155 {
+            id,
+            to,
+            token,
+            amount,
+            ..
+        } => Some((id, to, Some(token), amount)),
+        TransferRequest::Funding { .. }
+        | TransferRequest::Relay { .. }
+        | TransferRequest::Deposit { top_up: true, .. } => None,
+    }
+}
+
+/// The recipient of any transfer.
+fn recipient(request: &TransferRequest) -> Address {
+    match *request {
+        TransferRequest::Faucet { to, .. }
+        | TransferRequest::Funding { to, .. }
+        | TransferRequest::Erc20 { to, .. }
+        | TransferRequest::Nft { to, .. }
+        | TransferRequest::Erc1155 { to, .. }
+        | TransferRequest::Deposit { to, .. }
+        | TransferRequest::Relay { to, .. } => to,
+    }
+}
+
+/// The columns of a row of the `grants` table, other than the recipient.
+type GrantRow = (
+    String,
+    String,
+    Option<String>,
+    String,
+    String,
+    Option<i64>,
+    i64,
+);
+
+fn grant_from_row(
+    to: Address,
+    (chain, request_id, token, amount, tx_hash, block_number, timestamp): GrantRow,
+) -> Result<GrantRecord> {
+    Ok(GrantRecord {
+        chain,
+        request_id: request_id.parse()?,
+        to,
+        token: token.map(|token| token.parse()).transpose()?,
+        amount: U256::from_dec_str(&amount)?,
+        tx_hash: tx_hash.parse()?,
+        block_number: block_number.map(|number| U64::from(number as u64)),
+        timestamp: timestamp as u64 / 1000,
+    })
+}
+
+/// Add up the amounts of `(address, amount)` rows by address.
+fn sum_grants(
+    rows: impl IntoIterator<Item = Result<(String, String)>>,
+) -> Result<HashMap<Address, U256>> {
+    let mut totals = HashMap::<Address, U256>::new();
+    for row in rows {
+        let (to, amount) = row?;
+        *totals.entry(to.parse()?).or_default() += U256::from_dec_str(&amount)?;
+    }
+    Ok(totals)
+}
+
+fn unix_millis(time: SystemTime) -> i64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as i64
+}
+
+fn from_unix_millis(millis: i64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_millis(millis as u64)
+}
+
+/// Parse `(user, address)` rows of the registrations table.
+fn registrations_from_rows(
+    rows: impl IntoIterator<Item = Result<(i64, String)>>,
+) -> Result<HashMap<u64, Address>> {
+    rows.into_iter()
+        .map(|row| {
+            let (user, address) = row?;
+            Ok((user as u64, address.parse()?))
+        })
+        .collect()
+}
+
+/// The time left at `now` in a cooldown of `period` which started at `started_at`.
+pub(crate) fn remaining_cooldown(started_at: i64, period: Duration, now: i64) -> Duration {
+    period.saturating_sub(Duration::from_millis(now.saturating_sub(started_at) as u64))
+}
+
+/// Record the requests and grants of `faucet` on `chain` in `storage` as long as it runs.
+///
+/// `chain` is the name of a chain of `--guild-config`, or empty for the default chain.
+pub async fn record_history(faucet: Faucet, storage: SharedStorage, chain: String) {
+    let mut events = faucet.events().subscribe().await;
+    while let Some(event) = events.next().await {
+        let result = match event {
+            FaucetEvent::RequestQueued { request } if request.id().is_some() => {
+                storage.record_request(&request).await
+            }
+            // Transfers which are sent again are not dead letters yet.
+            FaucetEvent::TransferFailed {
+                request,
+                tx_hash,
+                reason,
+                retried: false,
+            } => storage.record_failure(&request, tx_hash, &reason).await,
+            FaucetEvent::FundsReturned {
+                from,
+                amount,
+                tx_hash,
+            } => storage.record_return(&chain, from, amount, tx_hash).await,
+            FaucetEvent::TransferConfirmed {
+                request,
+                tx_hash,
+                block_number,
+            } => match grant_of(&request) {
+                Some((request_id, to, token, amount)) => {
+                    let grant = GrantRecord {
+                        chain: chain.clone(),
+                        request_id,
+                        to,
+                        token,
+                        amount,
+                        tx_hash,
+                        block_number,
+                        timestamp: SystemTime::now()
+                            .duration_since(UNIX_EPOCH)
+                            .unwrap_or_default()
+                            .as_secs(),
+                    };
+                    storage.record_grant(&grant).await
+                }
+                None => Ok(()),
+            },
+            _ => Ok(()),
+        };
+        if let Err(err) = result {
+            tracing::error!("Failed to record faucet history: {err:#}");
+        }
+    }
+}
+
+/// Storage in a local SQLite database.
+#[derive(Clone, Debug)]
+pub struct SqliteStorage {
+    conn: Arc<Mutex<Connection>>,
+}
+
+impl SqliteStorage {
+    /// Open the database at `path`, creating it if needed.
+    pub fn open(path: &Path) -> Result<Self> {
+        let mut conn = Connection::open(path)
+            .with_context(|| format!("opening database {}", path.display()))?;
+        migrate_sqlite(&mut conn)?;
+        Ok(Self {
+            conn: Arc::new(Mutex::new(conn)),
+        })
+    }
+
+    /// Run `f` with the connection, off the async executor.
+    async fn with_conn<T: Send + 'static>(
+        &self,
+        f: impl FnOnce(&Connection) -> Result<T> + Send + 'static,
+    ) -> Result<T> {
+        let conn = self.conn.clone();
+        spawn_blocking(move || f(&conn.lock().unwrap())).await
+    }
+}
+
+#[async_trait]
+impl Storage for SqliteStorage {
+    async fn record_request(&self, request: &TransferRequest) -> Result<()> {
+        let Some((id, to, token, amount)) = grant_of(request) else {
+            return Ok(());
+        };
+        let now = unix_millis(SystemTime::now());
+        self.with_conn(move |conn| {
+            conn.execute(
+                "INSERT OR IGNORE INTO requests (id, recipient, token, amount, queued_at)
+                 VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![
+                    id.to_string(),
+                    format!("{to:?}"),
+                    token.map(|token| format!("{token:?}")),
+                    amount.to_string(),
+                    now
+                ],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn record_grant(&self, grant: &GrantRecord) -> Result<()> {
+        let grant = grant.clone();
+        self.with_conn(move |conn| {
+            conn.execute(
+                "INSERT OR REPLACE INTO grants
+                 (request_id, recipient, token, amount, tx_hash, block_number, confirmed_at, chain)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+                params![
+                    grant.request_id.to_string(),
+                    format!("{:?}", grant.to),
+                    grant.token.map(|token| format!("{token:?}")),
+                    grant.amount.to_string(),
+                    format!("{:?}", grant.tx_hash),
+                    grant.block_number.map(|number| number.as_u64() as i64),
+                    grant.timestamp as i64 * 1000,
+                    grant.chain
+                ],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn record_failure(
+        &self,
+        request: &TransferRequest,
+        tx_hash: Option<H256>,
+        reason: &str,
+    ) -> Result<()> {
+        let request_id = request.id().map(|id| id.to_string());
+        let to = recipient(request);
+        let reason = reason.to_string();
+        let now = unix_millis(SystemTime::now());
+        self.with_conn(move |conn| {
+            conn.execute(
+                "INSERT INTO dead_letters (request_id, recipient, tx_hash, reason, failed_at)
+                 VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![
+                    request_id,
+                    format!("{to:?}"),
+                    tx_hash.map(|hash| format!("{hash:?}")),
+                    reason,
+                    now
+                ],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn history(&self, to: Address, limit: usize) -> Result<Vec<GrantRecord>> {
+        self.with_conn(move |conn| {
+            let mut stmt = conn.prepare(
+                "SELECT chain, request_id, token, amount, tx_hash, block_number, confirmed_at
+                 FROM grants WHERE recipient = ?1 ORDER BY confirmed_at DESC LIMIT ?2",
+            )?;
+            let rows = stmt.query_map(params![format!("{to:?}"), limit as i64], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, Option<String>>(2)?,
+                    row.get::<_, String>(3)?,
+                    row.get::<_, String>(4)?,
+                    row.get::<_, Option<i64>>(5)?,
+                    row.get::<_, i64>(6)?,
+                ))
+            })?;
+            rows.map(|row| grant_from_row(to, row?)).collect()
+        })
+        .await
+    }
+
+    async fn lifetime_grants(&self, chain: &str) -> Result<HashMap<Address, U256>> {
+        let chain = chain.to_string();
+        self.with_conn(move |conn| {
+            // Amounts in wei overflow the 64-bit integers of SQLite, so they are added up here.
+            let mut stmt = conn.prepare(
+                "SELECT recipient, amount FROM grants WHERE token IS NULL AND chain = ?1",
+            )?;
+            let rows = stmt.query_map([chain], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+            })?;
+            sum_grants(rows.map(|row| Ok(row?)))
+        })
+        .await
+    }
+
+    async fn record_return(
+        &self,
+        chain: &str,
+        from: Address,
+        amount: U256,
+        tx_hash: H256,
+    ) -> Result<()> {
+        let chain = chain.to_string();
+        let now = unix_millis(SystemTime::now());
+        self.with_conn(move |conn| {
+            conn.execute(
+                "INSERT OR IGNORE INTO returns (tx_hash, chain, sender, amount, returned_at)
+                 VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![
+                    format!("{tx_hash:?}"),
+                    chain,
+                    format!("{from:?}"),
+                    amount.to_string(),
+                    now
+                ],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn lifetime_returns(&self, chain: &str) -> Result<HashMap<Address, U256>> {
+        let chain = chain.to_string();
+        self.with_conn(move |conn| {
+            let mut stmt = conn.prepare("SELECT sender, amount FROM returns WHERE chain = ?1")?;
+            let rows = stmt.query_map([chain], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+            })?;
+            sum_grants(rows.map(|row| Ok(row?)))
+        })
+        .await
+    }
+
+    async fn record_registration(&self, user: u64, address: Address) -> Result<()> {
+        let now = unix_millis(SystemTime::now());
+        self.with_conn(move |conn| {
+            conn.execute(
+                "INSERT INTO registrations (user_id, address, registered_at) VALUES (?1, ?2, ?3)
+                 ON CONFLICT (user_id) DO UPDATE
+                 SET address = excluded.address, registered_at = excluded.registered_at",
+                params![user as i64, format!("{address:?}"), now],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn registrations(&self) -> Result<HashMap<u64, Address>> {
+        self.with_conn(move |conn| {
+            let mut stmt = conn.prepare("SELECT user_id, address FROM registrations")?;
+            let rows = stmt.query_map([], |row| {
+                Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?))
+            })?;
+            registrations_from_rows(rows.map(|row| Ok(row?)))
+        })
+        .await
+    }
+}
+
+#[async_trait]
+impl CooldownStore for SqliteStorage {
+    async fn cooldown(&self, key: &str) -> Result<Option<SystemTime>> {
+        let key = key.to_string();
+        self.with_conn(move |conn| {
+            let started = conn
+                .query_row(
+                    "SELECT started_at FROM cooldowns WHERE key = ?1",
+                    [key],
+                    |row| row.get::<_, i64>(0),
+                )
+                .optional()?;
+            Ok(started.map(from_unix_millis))
+        })
+        .await
+    }
+
+    async fn start_cooldown(&self, key: &str, period: Duration) -> Result<Option<Duration>> {
+        let key = key.to_string();
+        let now = unix_millis(SystemTime::now());
+        let expired = now - period.as_millis() as i64;
+        self.with_conn(move |conn| {
+            // Only restart the cooldown if it expired, atomically.
+            let started = conn.execute(
+                "INSERT INTO cooldowns (key, started_at) VALUES (?1, ?2)
+                 ON CONFLICT (key) DO UPDATE SET started_at = excluded.started_at
+                 WHERE cooldowns.started_at <= ?3",
+                params![key, now, expired],
+            )?;
+            if started > 0 {
+                return Ok(None);
+            }
+            let started_at = conn.query_row(
+                "SELECT started_at FROM cooldowns WHERE key = ?1",
+                [key],
+                |row| row.get::<_, i64>(0),
+            )?;
+            Ok(Some(remaining_cooldown(started_at, period, now)))
+        })
+        .await
+    }
+
+    async fn reset_cooldown(&self, key: &str) -> Result<()> {
+        let key = key.to_string();
+        self.with_conn(move |conn| {
+            conn.execute("DELETE FROM cooldowns WHERE key = ?1", [key])?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn hits(&self, key: &str, period: Duration) -> Result<(usize, Option<SystemTime>)> {
+        let key = key.to_string();
+        let now = unix_millis(SystemTime::now());
+        let since = now - period.as_millis() as i64;
+        self.with_conn(move |conn| {
+            // Forget expired hits, so that the table does not grow forever.
+            conn.execute("DELETE FROM rate_limit_hits WHERE expires_at <= ?1", [now])?;
+            let (count, oldest) = conn.query_row(
+                "SELECT COUNT(*), MIN(hit_at) FROM rate_limit_hits WHERE key = ?1 AND hit_at > ?2",
+                params![key, since],
+                |row| Ok((row.get::<_, i64>(0)?, row.get::<_, Option<i64>>(1)?)),
+            )?;
+            Ok((count as usize, oldest.map(from_unix_millis)))
+        })
+        .await
+    }
+
+    async fn hit(&self, key: &str, period: Duration) -> Result<()> {
+        let key = key.to_string();
+        let now = unix_millis(SystemTime::now());
+        let expires_at = now + period.as_millis() as i64;
+        self.with_conn(move |conn| {
+            conn.execute(
+                "INSERT INTO rate_limit_hits (key, hit_at, expires_at) VALUES (?1, ?2, ?3)",
+                params![key, now, expires_at],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+}
+
+/// Storage in a PostgreSQL database, which can be shared by several faucet instances.
+#[derive(Clone)]
+pub struct PostgresStorage {
+    conn: PgConnection,
+}
+
+impl Debug for PostgresStorage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PostgresStorage").finish_non_exhaustive()
+    }
+}
+
+impl PostgresStorage {
+    /// Connect to the database at `url` and apply any pending migrations.
+    pub fn connect(url: &str) -> Result<Self> {
+        Ok(Self {
+            conn: PgConnection::connect(url).context("connecting to database")?,
+        })
+    }
+
+    /// Run `f` with the client, off the async executor, reconnecting if the connection dropped.
+    async fn with_client<T: Send + 'static>(
+        &self,
+        f: impl FnOnce(&mut postgres::Client) -> Result<T> + Send + 'static,
+    ) -> Result<T> {
+        self.conn.run(f).await
+    }
+}
+
+#[async_trait]
+impl Storage for PostgresStorage {
+    async fn record_request(&self, request: &TransferRequest) -> Result<()> {
+        let Some((id, to, token, amount)) = grant_of(request) else {
+            return Ok(());
+        };
+        let now = unix_millis(SystemTime::now());
+        self.with_client(move |client| {
+            client.execute(
+                "INSERT INTO requests (id, recipient, token, amount, queued_at)
+                 VALUES ($1, $2, $3, $4, $5) ON CONFLICT DO NOTHING",
+                &[
+                    &id.to_string(),
+                    &format!("{to:?}"),
+                    &token.map(|token| format!("{token:?}")),
+                    &amount.to_string(),
+                    &now,
+                ],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn record_grant(&self, grant: &GrantRecord) -> Result<()> {
+        let grant = grant.clone();
+        self.with_client(move |client| {
+            client.execute(
+                "INSERT INTO grants
+                 (request_id, recipient, token, amount, tx_hash, block_number, confirmed_at, chain)
+                 VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+                 ON CONFLICT (request_id) DO UPDATE SET
+                    tx_hash = excluded.tx_hash,
+                    block_number = excluded.block_number,
+                    confirmed_at = excluded.confirmed_at",
+                &[
+                    &grant.request_id.to_string(),
+                    &format!("{:?}", grant.to),
+                    &grant.token.map(|token| format!("{token:?}")),
+                    &grant.amount.to_string(),
+                    &format!("{:?}", grant.tx_hash),
+                    &grant.block_number.map(|number| number.as_u64() as i64),
+                    &(grant.timestamp as i64 * 1000),
+                    &grant.chain,
+                ],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn record_failure(
+        &self,
+        request: &TransferRequest,
+        tx_hash: Option<H256>,
+        reason: &str,
+    ) -> Result<()> {
+        let request_id = request.id().map(|id| id.to_string());
+        let to = recipient(request);
+        let reason = reason.to_string();
+        let now = unix_millis(SystemTime::now());
+        self.with_client(move |client| {
+            client.execute(
+                "INSERT INTO dead_letters (request_id, recipient, tx_hash, reason, failed_at)
+                 VALUES ($1, $2, $3, $4, $5)",
+                &[
+                    &request_id,
+                    &format!("{to:?}"),
+                    &tx_hash.map(|hash| format!("{hash:?}")),
+                    &reason,
+                    &now,
+                ],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn history(&self, to: Address, limit: usize) -> Result<Vec<GrantRecord>> {
+        self.with_client(move |client| {
+            client
+                .query(
+                    "SELECT chain, request_id, token, amount, tx_hash, block_number, confirmed_at
+                     FROM grants WHERE recipient = $1 ORDER BY confirmed_at DESC LIMIT $2",
+                    &[&format!("{to:?}"), &(limit as i64)],
+                )?
+                .into_iter()
+                .map(|row| {
+                    grant_from_row(
+                        to,
+                        (
+                            row.try_get(0)?,
+                            row.try_get(1)?,
+                            row.try_get(2)?,
+                            row.try_get(3)?,
+                            row.try_get(4)?,
+                            row.try_get(5)?,
+                            row.try_get(6)?,
+                        ),
+                    )
+                })
+                .collect()
+        })
+        .await
+    }
+
+    async fn lifetime_grants(&self, chain: &str) -> Result<HashMap<Address, U256>> {
+        let chain = chain.to_string();
+        self.with_client(move |client| {
+            let rows = client.query(
+                "SELECT recipient, SUM(amount::NUMERIC)::TEXT FROM grants
+                 WHERE token IS NULL AND chain = $1 GROUP BY recipient",
+                &[&chain],
+            )?;
+            sum_grants(
+                rows.into_iter()
+                    .map(|row| Ok((row.try_get(0)?, row.try_get(1)?))),
+            )
+        })
+        .await
+    }
+
+    async fn record_return(
+        &self,
+        chain: &str,
+        from: Address,
+        amount: U256,
+        tx_hash: H256,
+    ) -> Result<()> {
+        let chain = chain.to_string();
+        let now = unix_millis(SystemTime::now());
+        self.with_client(move |client| {
+            client.execute(
+                "INSERT INTO returns (tx_hash, chain, sender, amount, returned_at)
+                 VALUES ($1, $2, $3, $4, $5) ON CONFLICT DO NOTHING",
+                &[
+                    &format!("{tx_hash:?}"),
+                    &chain,
+                    &format!("{from:?}"),
+                    &amount.to_string(),
+                    &now,
+                ],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn lifetime_returns(&self, chain: &str) -> Result<HashMap<Address, U256>> {
+        let chain = chain.to_string();
+        self.with_client(move |client| {
+            let rows = client.query(
+                "SELECT sender, SUM(amount::NUMERIC)::TEXT FROM returns
+                 WHERE chain = $1 GROUP BY sender",
+                &[&chain],
+            )?;
+            sum_grants(
+                rows.into_iter()
+                    .map(|row| Ok((row.try_get(0)?, row.try_get(1)?))),
+            )
+        })
+        .await
+    }
+
+    async fn record_registration(&self, user: u64, address: Address) -> Result<()> {
+        let now = unix_millis(SystemTime::now());
+        self.with_client(move |client| {
+            client.execute(
+                "INSERT INTO registrations (user_id, address, registered_at) VALUES ($1, $2, $3)
+                 ON CONFLICT (user_id) DO UPDATE
+                 SET address = excluded.address, registered_at = excluded.registered_at",
+                &[&(user as i64), &format!("{address:?}"), &now],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn registrations(&self) -> Result<HashMap<u64, Address>> {
+        self.with_client(move |client| {
+            let rows = client.query("SELECT user_id, address FROM registrations", &[])?;
+            registrations_from_rows(
+                rows.into_iter()
+                    .map(|row| Ok((row.try_get(0)?, row.try_get(1)?))),
+            )
+        })
+        .await
+    }
+}
+
+#[async_trait]
+impl CooldownStore for PostgresStorage {
+    async fn cooldown(&self, key: &str) -> Result<Option<SystemTime>> {
+        let key = key.to_string();
+        self.with_client(move |client| {
+            let started = client
+                .query_opt("SELECT started_at FROM cooldowns WHERE key = $1", &[&key])?
+                .map(|row| row.try_get::<_, i64>(0))
+                .transpose()?;
+            Ok(started.map(from_unix_millis))
+        })
+        .await
+    }
+
+    async fn start_cooldown(&self, key: &str, period: Duration) -> Result<Option<Duration>> {
+        let key = key.to_string();
+        let now = unix_millis(SystemTime::now());
+        let expired = now - period.as_millis() as i64;
+        self.with_client(move |client| {
+            // Only restart the cooldown if it expired, atomically across instances.
+            let started = client.execute(
+                "INSERT INTO cooldowns (key, started_at) VALUES ($1, $2)
+                 ON CONFLICT (key) DO UPDATE SET started_at = excluded.started_at
+                 WHERE cooldowns.started_at <= $3",
+                &[&key, &now, &expired],
+            )?;
+            if started > 0 {
+                return Ok(None);
+            }
+            let started_at: i64 = client
+                .query_one("SELECT started_at FROM cooldowns WHERE key = $1", &[&key])?
+                .try_get(0)?;
+            Ok(Some(remaining_cooldown(started_at, period, now)))
+        })
+        .await
+    }
+
+    async fn reset_cooldown(&self, key: &str) -> Result<()> {
+        let key = key.to_string();
+        self.with_client(move |client| {
+            client.execute("DELETE FROM cooldowns WHERE key = $1", &[&key])?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn hits(&self, key: &str, period: Duration) -> Result<(usize, Option<SystemTime>)> {
+        let key = key.to_string();
+        let now = unix_millis(SystemTime::now());
+        let since = now - period.as_millis() as i64;
+        self.with_client(move |client| {
+            // Forget expired hits, so that the table does not grow forever.
+            client.execute(
+                "DELETE FROM rate_limit_hits WHERE expires_at <= $1",
+                &[&now],
+            )?;
+            let row = client.query_one(
+                "SELECT COUNT(*), MIN(hit_at) FROM rate_limit_hits WHERE key = $1 AND hit_at > $2",
+                &[&key, &since],
+            )?;
+            let count: i64 = row.try_get(0)?;
+            let oldest: Option<i64> = row.try_get(1)?;
+            Ok((count as usize, oldest.map(from_unix_millis)))
+        })
+        .await
+    }
+
+    async fn hit(&self, key: &str, period: Duration) -> Result<()> {
+        let key = key.to_string();
+        let now = unix_millis(SystemTime::now());
+        let expires_at = now + period.as_millis() as i64;
+        self.with_client(move |client| {
+            client.execute(
+                "INSERT INTO rate_limit_hits (key, hit_at, expires_at) VALUES ($1, $2, $3)",
+                &[&key, &now, &expires_at],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::check_cooldown_store;
+
+    /// Check the behavior common to all the storage backends.
+    ///
+    /// Keys and addresses are random, so that the check can run against a shared database.
+    async fn check_storage(storage: &dyn Storage) {
+        let to = Address::random();
+        let id = RequestId::random();
+        storage
+            .record_request(&TransferRequest::faucet(id, to, 1.into()))
+            .await
+            .unwrap();
+        assert!(storage.history(to, MAX_HISTORY).await.unwrap().is_empty());
+
+        let grant = GrantRecord {
+            chain: String::new(),
+            request_id: id,
+            to,
+            token: None,
+            amount: 1.into(),
+            tx_hash: H256::random(),
+            block_number: Some(7.into()),
+            timestamp: 1000,
+        };
+        storage.record_grant(&grant).await.unwrap();
+        assert_eq!(storage.history(to, MAX_HISTORY).await.unwrap(), vec![grant]);
+
+        // Only grants of the native currency count towards the lifetime totals.
+        let token_grant = GrantRecord {
+            request_id: RequestId::random(),
+            token: Some(Address::random()),
+            amount: 5.into(),
+            tx_hash: H256::random(),
+            ..grant.clone()
+        };
+        storage.record_grant(&token_grant).await.unwrap();
+        let more = GrantRecord {
+            request_id: RequestId::random(),
+            amount: 2.into(),
+            tx_hash: H256::random(),
+            timestamp: 2000,
+            ..grant.clone()
+        };
+        storage.record_grant(&more).await.unwrap();
+        assert_eq!(storage.lifetime_grants("").await.unwrap()[&to], 3.into());
+
+        // Lifetime totals are kept per chain.
+        let rollup = GrantRecord {
+            chain: "rollup".to_string(),
+            request_id: RequestId::random(),
+            amount: 4.into(),
+            tx_hash: H256::random(),
+            ..grant.clone()
+        };
+        storage.record_grant(&rollup).await.unwrap();
+        assert_eq!(storage.lifetime_grants("").await.unwrap()[&to], 3.into());
+        assert_eq!(
+            storage.lifetime_grants("rollup").await.unwrap()[&to],
+            4.into()
+        );
+        assert!(storage
+            .history(Address::random(), MAX_HISTORY)
+            .await
+            .unwrap()
+            .is_empty());
+
+        // Returns are kept per chain, and each return is only counted once.
+        let tx_hash = H256::random();
+        for _ in 0..2 {
+            storage
+                .record_return("", to, 2.into(), tx_hash)
+                .await
+                .unwrap();
+        }
+        storage
+            .record_return("", to, 1.into(), H256::random())
+            .await
+            .unwrap();
+        assert_eq!(storage.lifetime_returns("").await.unwrap()[&to], 3.into());
+        assert!(!storage
+            .lifetime_returns("rollup")
+            .await
+            .unwrap()
+            .contains_key(&to));
+
+        // Registering another address replaces the previous one.
+        let user = rand::random::<u32>() as u64;
+        storage
+            .record_registration(user, Address::random())
+            .await
+            .unwrap();
+        storage.record_registration(user, to).await.unwrap();
+        assert_eq!(storage.registrations().await.unwrap()[&user], to);
+
+        storage
+            .record_failure(
+                &TransferRequest::faucet(id, to, 1.into()),
+                None,
+                "nonce too low",
+            )
+            .await
+            .unwrap();
+
+        check_cooldown_store(storage).await;
+    }
+
+    #[async_std::test]
+    async fn test_sqlite_storage() {
+        check_storage(&SqliteStorage::open(Path::new(":memory:")).unwrap()).await;
+    }
+
+    /// Runs against the database given by `ESPRESSO_DISCORD_FAUCET_TEST_DATABASE_URL`, with `cargo test -- --ignored`.
+    #[async_std::test]
+    #[ignore]
+    async fn test_postgres_storage() {
+        let url = std::env::var("ESPRESSO_DISCORD_FAUCET_TEST_DATABASE_URL")
+            .expect("ESPRESSO_DISCORD_FAUCET_TEST_DATABASE_URL is not set");
+        check_storage(&PostgresStorage::connect(&url).unwrap()).await;
+        // Migrations are only applied once.
+        PostgresStorage::connect(&url).unwrap();
+    }
+}
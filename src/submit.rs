@@ -0,0 +1,137 @@
+// Copyright (c) 2023 Espresso Systems (espressosys.com)
+// This file is part of the Discord Faucet library.
+//
+// You should have received a copy of the MIT License
+// along with the Discord Faucet library. If not, see <https://mit-license.org/>.
+
+//! Classification of the errors of the RPC when submitting a transfer.
+//!
+//! Nodes report most submission failures with the generic JSON-RPC error code `-32000`, so the
+//! errors are mostly told apart by their message, which is similar across the common clients.
+use ethers::providers::JsonRpcError;
+use serde::{Deserialize, Serialize};
+use std::fmt::{self, Display, Formatter};
+
+/// The class of a submission error, which decides how the faucet recovers from it.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum SubmitErrorKind {
+    /// The wallet cannot pay for the transfer: its balance is read again, and the transfer is sent
+    /// by a wallet which can pay for it.
+    InsufficientFunds,
+    /// The nonce of the transaction was already used, or is used by a pending transaction: the
+    /// transfer is sent again with a nonce read again from the chain.
+    NonceConflict,
+    /// The node already has the transaction, which was submitted by an earlier attempt: it is
+    /// tracked like a successful submission.
+    AlreadyKnown,
+    /// The node rejected the transaction itself, e.g. because it reverts or is invalid: sending it
+    /// again would fail the same way, so the request is dropped.
+    Rejected,
+    /// Any other error, e.g. a timeout, a rate limit or a base fee spike above the max fee of the
+    /// transaction: the transfer is sent again, with fees estimated again.
+    Transient,
+}
+
+impl SubmitErrorKind {
+    /// Classify the JSON-RPC error `err`, if the node returned one, with the error `message`.
+    pub fn classify(err: Option<&JsonRpcError>, message: &str) -> Self {
+        let message = err
+            .map_or(message, |err| err.message.as_str())
+            .to_lowercase();
+        let has = |patterns: &[&str]| patterns.iter().any(|pattern| message.contains(pattern));
+        if has(&["insufficient funds", "insufficient balance"]) {
+            Self::InsufficientFunds
+        } else if has(&[
+            "nonce too low",
+            "nonce has already been used",
+            "oldnonce",
+            "replacement transaction underpriced",
+            "replacement fee too low",
+        ]) {
+            Self::NonceConflict
+        } else if has(&["already known", "known transaction", "alreadyknown"]) {
+            Self::AlreadyKnown
+        } else if has(&[
+            "execution reverted",
+            "intrinsic gas too low",
+            "exceeds block gas limit",
+            "invalid sender",
+            "invalid transaction",
+        ]) || matches!(err, Some(err) if err.code == -32602 || err.code == 3)
+        {
+            Self::Rejected
+        } else {
+            Self::Transient
+        }
+    }
+}
+
+impl Display for SubmitErrorKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let kind = match self {
+            Self::InsufficientFunds => "insufficient funds",
+            Self::NonceConflict => "nonce conflict",
+            Self::AlreadyKnown => "already known",
+            Self::Rejected => "rejected",
+            Self::Transient => "transient",
+        };
+        write!(f, "{kind}")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn rpc_error(code: i64, message: &str) -> JsonRpcError {
+        JsonRpcError {
+            code,
+            message: message.to_string(),
+            data: None,
+        }
+    }
+
+    #[test]
+    fn test_classify_submit_errors() {
+        for (message, kind) in [
+            (
+                "insufficient funds for gas * price + value",
+                SubmitErrorKind::InsufficientFunds,
+            ),
+            ("nonce too low", SubmitErrorKind::NonceConflict),
+            (
+                "replacement transaction underpriced",
+                SubmitErrorKind::NonceConflict,
+            ),
+            ("already known", SubmitErrorKind::AlreadyKnown),
+            ("execution reverted", SubmitErrorKind::Rejected),
+            ("intrinsic gas too low", SubmitErrorKind::Rejected),
+            ("header not found", SubmitErrorKind::Transient),
+            (
+                "max fee per gas less than block base fee",
+                SubmitErrorKind::Transient,
+            ),
+        ] {
+            let err = rpc_error(-32000, message);
+            assert_eq!(SubmitErrorKind::classify(Some(&err), ""), kind, "{message}");
+        }
+
+        // Invalid parameters are rejected whatever the message.
+        let err = rpc_error(-32602, "invalid argument 0");
+        assert_eq!(
+            SubmitErrorKind::classify(Some(&err), ""),
+            SubmitErrorKind::Rejected
+        );
+
+        // Errors which are not JSON-RPC errors are classified by their message.
+        assert_eq!(
+            SubmitErrorKind::classify(None, "Nonce too low (injected)"),
+            SubmitErrorKind::NonceConflict
+        );
+        assert_eq!(
+            SubmitErrorKind::classify(None, "connection reset by peer"),
+            SubmitErrorKind::Transient
+        );
+    }
+}
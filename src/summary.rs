@@ -0,0 +1,118 @@
+// Copyright (c) 2023 Espresso Systems (espressosys.com)
+// This file is part of the Discord Faucet library.
+//
+// You should have received a copy of the MIT License
+// along with the Discord Faucet library. If not, see <https://mit-license.org/>.
+
+//! Periodic summaries of the activity of the faucet.
+//!
+//! The events of the faucet are accumulated over each period and the summary is reset once it is
+//! reported, so each report only covers its own period.
+use crate::{FaucetEvent, TransferRequest};
+use ethers::types::{Address, U256};
+use std::{collections::HashSet, time::Duration};
+
+/// The activity of a faucet during one period.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Summary {
+    /// Grants confirmed, in native currency and in ERC-20 tokens.
+    pub grants: usize,
+    /// The distinct addresses which received a grant.
+    pub recipients: HashSet<Address>,
+    /// The total native currency granted.
+    pub value: U256,
+    /// Transfers which failed and had to be retried.
+    pub failures: usize,
+}
+
+impl Summary {
+    /// Account for `event` in the summary.
+    pub fn record(&mut self, event: &FaucetEvent) {
+        match event {
+            FaucetEvent::TransferConfirmed { request, .. } => match request {
+                TransferRequest::Faucet { to, amount, .. }
+                | TransferRequest::Deposit {
+                    to,
+                    amount,
+                    top_up: false,
+                    ..
+                } => {
+                    self.grants += 1;
+                    self.recipients.insert(*to);
+                    self.value += *amount;
+                }
+                TransferRequest::Erc20 { to, .. }
+                | TransferRequest::Nft { to, .. }
+                | TransferRequest::Erc1155 { to, .. } => {
+                    self.grants += 1;
+                    self.recipients.insert(*to);
+                }
+                // Funding transfers between the faucet wallets, relays and paymaster top-ups are not
+                // grants.
+                TransferRequest::Funding { .. }
+                | TransferRequest::Relay { .. }
+                | TransferRequest::Deposit { top_up: true, .. } => {}
+            },
+            FaucetEvent::TransferFailed { .. } => self.failures += 1,
+            _ => {}
+        }
+    }
+
+    /// How long `balance` lasts if the faucet keeps granting as much every `period`.
+    ///
+    /// Returns `None` if nothing was granted during the period.
+    pub fn runway(&self, balance: U256, period: Duration) -> Option<Duration> {
+        if self.value.is_zero() {
+            return None;
+        }
+        let secs = balance.saturating_mul(period.as_secs().into()) / self.value;
+        Some(Duration::from_secs(secs.min(u64::MAX.into()).as_u64()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::RequestId;
+
+    fn confirmed(request: TransferRequest) -> FaucetEvent {
+        FaucetEvent::TransferConfirmed {
+            request,
+            tx_hash: Default::default(),
+            block_number: None,
+        }
+    }
+
+    #[test]
+    fn test_summary() {
+        let mut summary = Summary::default();
+        let alice = Address::random();
+        let bob = Address::random();
+        summary.record(&confirmed(TransferRequest::faucet(
+            RequestId::random(),
+            alice,
+            100.into(),
+        )));
+        summary.record(&confirmed(TransferRequest::faucet(
+            RequestId::random(),
+            alice,
+            100.into(),
+        )));
+        summary.record(&confirmed(TransferRequest::funding(bob, 1000.into())));
+        summary.record(&FaucetEvent::TransferFailed {
+            request: TransferRequest::faucet(RequestId::random(), bob, 100.into()),
+            tx_hash: None,
+            reason: "nonce too low".into(),
+            retried: true,
+        });
+
+        assert_eq!(summary.grants, 2);
+        assert_eq!(summary.recipients, [alice].into_iter().collect());
+        assert_eq!(summary.value, 200.into());
+        assert_eq!(summary.failures, 1);
+
+        let day = Duration::from_secs(24 * 3600);
+        assert_eq!(summary.runway(1000.into(), day), Some(day * 5));
+        assert_eq!(Summary::default().runway(1000.into(), day), None);
+    }
+}
@@ -0,0 +1,496 @@
+// Copyright (c) 2023 Espresso Systems (espressosys.com)
+// This file is part of the Discord Faucet library.
+//
+// You should have received a copy of the MIT License
+// along with the Discord Faucet library. If not, see <https://mit-license.org/>.
+
+//! On-chain heuristics to screen out recipients which look like Sybil addresses.
+//!
+//! Each recipient is scored on what the chain knows about it: an address which never sent a
+//! transaction and was never funded is more likely to be freshly generated, and an address funded
+//! by another address the faucet has already served many times is likely part of a farm. Requests
+//! for recipients scoring at or above `--sybil-threshold` are rejected.
+//!
+//! Farmers often split faucet funds across hundreds of addresses funded from the same wallet. The
+//! recipients the faucet sends funds to are clustered by their first funder, and with
+//! `--max-cluster-size`, a recipient joining a cluster which is already that large is rejected. At
+//! most [`MAX_CLUSTERS`] clusters are tracked, forgetting the ones joined least recently.
+//!
+//! Looking up the first funder of an address scans recent blocks, so recipient profiles are cached
+//! for `--sybil-cache-ttl`. Requests are screened after their cooldowns and bot protection, and a
+//! lookup which does not complete within [`FUNDER_LOOKUP_TIMEOUT`] lets the recipient through, so
+//! that uncached recipients cannot make each request cost arbitrarily many calls to the provider.
+use crate::{Faucet, FaucetEvent, Options, RpcClient, TransferRequest};
+use anyhow::{Context, Result};
+use async_std::{
+    future::timeout,
+    sync::{Mutex, RwLock},
+};
+use ethers::{
+    providers::{Middleware as _, Provider},
+    types::Address,
+};
+use futures::{stream, StreamExt, TryStreamExt};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+/// The longest time spent looking up the first funder of a recipient.
+pub const FUNDER_LOOKUP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How many blocks are fetched at once when looking up the first funder of a recipient.
+const SCAN_CONCURRENCY: usize = 16;
+
+/// The most clusters tracked at once.
+pub const MAX_CLUSTERS: usize = 10_000;
+
+/// What the chain knows about a recipient.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RecipientProfile {
+    /// The number of transactions sent by the recipient.
+    pub nonce: u64,
+    /// The sender of the first transfer of value to the recipient within the scanned blocks.
+    pub funder: Option<Address>,
+}
+
+impl RecipientProfile {
+    /// Whether the recipient has any transaction history.
+    pub fn has_history(&self) -> bool {
+        self.nonce > 0 || self.funder.is_some()
+    }
+}
+
+/// The weights of the heuristics.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SybilScoring {
+    /// A recipient scoring at least this much is rejected.
+    pub threshold: u32,
+    /// The score of a recipient without any transaction history.
+    pub no_history: u32,
+    /// The score of a recipient funded by an address which was served many times.
+    pub served_funder: u32,
+    /// The number of grants after which a funder counts as served many times.
+    pub served_funder_grants: usize,
+}
+
+impl SybilScoring {
+    /// The score of `profile`, given the number of grants served to its funder.
+    pub fn score(&self, profile: &RecipientProfile, funder_grants: usize) -> u32 {
+        let mut score = 0;
+        if !profile.has_history() {
+            score += self.no_history;
+        }
+        if profile.funder.is_some() && funder_grants >= self.served_funder_grants {
+            score += self.served_funder;
+        }
+        score
+    }
+}
+
+/// Recipients sent funds by the faucet sharing the same first funder.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct Cluster {
+    pub funder: Address,
+    pub size: usize,
+}
+
+#[derive(Clone, Debug)]
+pub struct SybilScreen {
+    provider: Provider<RpcClient>,
+    scoring: SybilScoring,
+    max_cluster_size: Option<usize>,
+    /// Addresses which are not counted as funders, such as the faucet wallets.
+    exempt: Arc<HashSet<Address>>,
+    /// How many blocks back to look for the first funder of a recipient.
+    scan_blocks: u64,
+    cache_ttl: Duration,
+    profiles: Arc<Mutex<HashMap<Address, (Instant, RecipientProfile)>>>,
+    /// The number of grants confirmed to each address, and when the latest one was.
+    served: Arc<RwLock<HashMap<Address, (Instant, usize)>>>,
+    /// How long an address which is not served again is remembered, `--velocity-window`.
+    served_window: Duration,
+    /// The recipients funded by each funder, and when a recipient last joined the cluster.
+    clusters: Arc<RwLock<HashMap<Address, (Instant, HashSet<Address>)>>>,
+}
+
+impl SybilScreen {
+    /// A screen configured by `options`, or `None` if Sybil checks are disabled.
+    ///
+    /// Transfers from the `exempt` addresses do not count towards clusters.
+    pub fn new(
+        provider: Provider<RpcClient>,
+        options: &Options,
+        exempt: impl IntoIterator<Item = Address>,
+    ) -> Option<Self> {
+        if options.sybil_threshold.is_none() && options.max_cluster_size.is_none() {
+            return None;
+        }
+        Some(Self {
+            provider,
+            scoring: SybilScoring {
+                // Without a threshold, only clusters are limited.
+                threshold: options.sybil_threshold.unwrap_or(u32::MAX),
+                no_history: options.sybil_no_history_score,
+                served_funder: options.sybil_served_funder_score,
+                served_funder_grants: options.sybil_served_funder_grants,
+            },
+            scan_blocks: options.sybil_scan_blocks,
+            cache_ttl: options.sybil_cache_ttl,
+            max_cluster_size: options.max_cluster_size,
+            exempt: Arc::new(exempt.into_iter().collect()),
+            profiles: Default::default(),
+            served: Default::default(),
+            served_window: options.velocity_window,
+            clusters: Default::default(),
+        })
+    }
+
+    /// The score of a recipient with `profile`, which is rejected if it is at least the threshold.
+    pub async fn score(&self, profile: &RecipientProfile) -> u32 {
+        let funder_grants = match profile.funder {
+            Some(funder) => self.grants(funder).await,
+            None => 0,
+        };
+        self.scoring.score(profile, funder_grants)
+    }
+
+    /// Whether `score` is high enough to reject the recipient.
+    pub fn rejects(&self, score: u32) -> bool {
+        score >= self.scoring.threshold
+    }
+
+    /// The number of grants confirmed to `address`, until it is not served for a velocity window.
+    pub async fn grants(&self, address: Address) -> usize {
+        match self.served.read().await.get(&address) {
+            Some((last, grants)) if last.elapsed() < self.served_window => *grants,
+            _ => 0,
+        }
+    }
+
+    /// Check that `recipient` may join the cluster of addresses funded by `funder`.
+    ///
+    /// Returns the size of the cluster as an error if it is full. Recipients already in the cluster
+    /// are always accepted.
+    pub async fn check_cluster(&self, funder: Address, recipient: Address) -> Result<(), usize> {
+        let Some(max) = self.max_cluster_size else {
+            return Ok(());
+        };
+        if self.exempt.contains(&funder) {
+            return Ok(());
+        }
+        match self.clusters.read().await.get(&funder) {
+            Some((_, cluster)) if cluster.len() >= max && !cluster.contains(&recipient) => {
+                Err(cluster.len())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Add `recipient` to the cluster of addresses funded by `funder`, once it is sent funds.
+    ///
+    /// Beyond [`MAX_CLUSTERS`], the cluster joined least recently is forgotten.
+    pub async fn join_cluster(&self, funder: Address, recipient: Address) {
+        if self.exempt.contains(&funder) {
+            return;
+        }
+        let mut clusters = self.clusters.write().await;
+        if clusters.len() >= MAX_CLUSTERS && !clusters.contains_key(&funder) {
+            let oldest = clusters
+                .iter()
+                .min_by_key(|(_, (joined, _))| *joined)
+                .map(|(funder, _)| *funder);
+            if let Some(oldest) = oldest {
+                clusters.remove(&oldest);
+            }
+        }
+        let (joined, cluster) = clusters
+            .entry(funder)
+            .or_insert_with(|| (Instant::now(), HashSet::new()));
+        *joined = Instant::now();
+        cluster.insert(recipient);
+    }
+
+    /// The clusters of recipients sent funds, largest first.
+    pub async fn clusters(&self) -> Vec<Cluster> {
+        let mut clusters = self
+            .clusters
+            .read()
+            .await
+            .iter()
+            .map(|(funder, (_, recipients))| Cluster {
+                funder: *funder,
+                size: recipients.len(),
+            })
+            .collect::<Vec<_>>();
+        clusters.sort_by(|a, b| b.size.cmp(&a.size));
+        clusters
+    }
+
+    /// Count a grant confirmed to `address`.
+    ///
+    /// The addresses which were not served within a velocity window are forgotten.
+    pub async fn record_grant(&self, address: Address) {
+        let now = Instant::now();
+        let mut served = self.served.write().await;
+        served.retain(|_, (last, _)| now.duration_since(*last) < self.served_window);
+        let (last, grants) = served.entry(address).or_insert((now, 0));
+        *last = now;
+        *grants += 1;
+    }
+
+    /// The first funder of `address`, if its profile is cached.
+    async fn cached_funder(&self, address: Address) -> Option<Address> {
+        self.profiles
+            .lock()
+            .await
+            .get(&address)
+            .and_then(|(_, profile)| profile.funder)
+    }
+
+    /// The profile of `address`, from the cache if it was looked up recently.
+    pub async fn profile(&self, address: Address) -> Result<RecipientProfile> {
+        if let Some((looked_up, profile)) = self.profiles.lock().await.get(&address) {
+            if looked_up.elapsed() < self.cache_ttl {
+                return Ok(*profile);
+            }
+        }
+        let profile = RecipientProfile {
+            nonce: self
+                .provider
+                .get_transaction_count(address, None)
+                .await?
+                .as_u64(),
+            funder: timeout(FUNDER_LOOKUP_TIMEOUT, self.funder(address))
+                .await
+                .context("timed out looking up the funder")??,
+        };
+        let mut profiles = self.profiles.lock().await;
+        profiles.retain(|_, (looked_up, _)| looked_up.elapsed() < self.cache_ttl);
+        profiles.insert(address, (Instant::now(), profile));
+        Ok(profile)
+    }
+
+    /// The sender of the earliest transfer of value to `address` in the scanned blocks.
+    pub async fn funder(&self, address: Address) -> Result<Option<Address>> {
+        let latest = self.provider.get_block_number().await?.as_u64();
+        let mut blocks = stream::iter(latest.saturating_sub(self.scan_blocks)..=latest)
+            .map(|number| self.provider.get_block_with_txs(number))
+            .buffered(SCAN_CONCURRENCY);
+        while let Some(block) = blocks.try_next().await? {
+            let Some(block) = block else {
+                continue;
+            };
+            if let Some(tx) = block
+                .transactions
+                .iter()
+                .find(|tx| tx.to == Some(address) && !tx.value.is_zero())
+            {
+                return Ok(Some(tx.from));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Count the grants confirmed by `faucet`, and cluster the recipients of its transfers.
+    ///
+    /// Recipients join their cluster when a transfer to them is submitted, so that requests which
+    /// are rejected or never sent do not count. Only recipients which were screened have a known
+    /// funder.
+    pub async fn watch(self, faucet: Faucet) {
+        let mut events = faucet.events().subscribe().await;
+        while let Some(event) = events.next().await {
+            match event {
+                FaucetEvent::TransferSubmitted { request, .. }
+                    if !matches!(request, TransferRequest::Funding { .. }) =>
+                {
+                    let to = request.to();
+                    if let Some(funder) = self.cached_funder(to).await {
+                        self.join_cluster(funder, to).await;
+                    }
+                }
+                FaucetEvent::TransferConfirmed { request, .. }
+                    if !matches!(request, TransferRequest::Funding { .. }) =>
+                {
+                    self.record_grant(request.to()).await;
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Middleware, MockChain};
+    use async_compatibility_layer::logging::{setup_backtrace, setup_logging};
+    use ethers::{
+        signers::{LocalWallet, Signer},
+        types::{TransactionRequest, U256},
+    };
+
+    #[test]
+    fn test_sybil_scoring() {
+        let scoring = SybilScoring {
+            threshold: 100,
+            no_history: 50,
+            served_funder: 100,
+            served_funder_grants: 3,
+        };
+        let fresh = RecipientProfile {
+            nonce: 0,
+            funder: None,
+        };
+        assert_eq!(scoring.score(&fresh, 0), 50);
+
+        let funded = RecipientProfile {
+            nonce: 0,
+            funder: Some(Address::random()),
+        };
+        assert_eq!(scoring.score(&funded, 2), 0);
+        assert_eq!(scoring.score(&funded, 3), 100);
+
+        let active = RecipientProfile {
+            nonce: 5,
+            funder: None,
+        };
+        assert_eq!(scoring.score(&active, 0), 0);
+    }
+
+    #[async_std::test]
+    async fn test_mock_sybil_screen() {
+        setup_logging();
+        setup_backtrace();
+
+        let chain = MockChain::default();
+        let provider = Provider::new(RpcClient::Mock(chain.clone()));
+        let options = Options {
+            sybil_threshold: Some(100),
+            ..Options::default()
+        };
+        let screen = SybilScreen::new(provider.clone(), &options, []).unwrap();
+
+        // A fresh address has no history.
+        let recipient = Address::random();
+        let profile = screen.profile(recipient).await.unwrap();
+        assert_eq!(screen.score(&profile).await, 50);
+
+        // Fund the recipient from another address.
+        let chain_id = provider.get_chainid().await.unwrap().as_u64();
+        let wallet = LocalWallet::new(&mut rand::thread_rng()).with_chain_id(chain_id);
+        chain.fund(wallet.address(), U256::exp10(18));
+        let funder = Middleware::new(provider.clone(), wallet);
+        funder
+            .send_transaction(TransactionRequest::pay(recipient, 1000), None)
+            .await
+            .unwrap();
+
+        // The fresh profile is still cached.
+        assert_eq!(screen.profile(recipient).await.unwrap(), profile);
+
+        // A new recipient funded by the same address has history, until the funder has been served
+        // too many times.
+        let other = Address::random();
+        funder
+            .send_transaction(TransactionRequest::pay(other, 1000), None)
+            .await
+            .unwrap();
+        let profile = screen.profile(other).await.unwrap();
+        assert_eq!(profile.funder, Some(funder.address()));
+        assert_eq!(screen.score(&profile).await, 0);
+        for _ in 0..3 {
+            screen.record_grant(funder.address()).await;
+        }
+        let score = screen.score(&profile).await;
+        assert_eq!(score, 100);
+        assert!(screen.rejects(score));
+    }
+
+    #[async_std::test]
+    async fn test_sybil_clusters() {
+        let options = Options {
+            max_cluster_size: Some(2),
+            ..Options::default()
+        };
+        let exempt = Address::random();
+        let provider = Provider::new(RpcClient::Mock(MockChain::default()));
+        let screen = SybilScreen::new(provider, &options, [exempt]).unwrap();
+
+        let funder = Address::random();
+        let recipients = [Address::random(), Address::random()];
+        for recipient in recipients {
+            screen.check_cluster(funder, recipient).await.unwrap();
+            screen.join_cluster(funder, recipient).await;
+        }
+        // Recipients already in the full cluster can still request.
+        screen.check_cluster(funder, recipients[0]).await.unwrap();
+        assert_eq!(
+            screen.check_cluster(funder, Address::random()).await,
+            Err(2)
+        );
+
+        // Exempt funders are not clustered.
+        for _ in 0..3 {
+            let recipient = Address::random();
+            screen.check_cluster(exempt, recipient).await.unwrap();
+            screen.join_cluster(exempt, recipient).await;
+        }
+        let other = Address::random();
+        screen.join_cluster(other, recipients[0]).await;
+        assert_eq!(
+            screen.clusters().await,
+            [
+                Cluster { funder, size: 2 },
+                Cluster {
+                    funder: other,
+                    size: 1
+                },
+            ]
+        );
+
+        // The cluster joined least recently is forgotten beyond the limit.
+        for _ in 2..MAX_CLUSTERS {
+            screen
+                .join_cluster(Address::random(), Address::random())
+                .await;
+        }
+        screen.join_cluster(other, Address::random()).await;
+        screen
+            .join_cluster(Address::random(), Address::random())
+            .await;
+        let clusters = screen.clusters().await;
+        assert_eq!(clusters.len(), MAX_CLUSTERS);
+        assert!(clusters.iter().all(|cluster| cluster.funder != funder));
+        assert!(clusters.contains(&Cluster {
+            funder: other,
+            size: 2
+        }));
+    }
+
+    #[async_std::test]
+    async fn test_sybil_served_window() {
+        let options = Options {
+            max_cluster_size: Some(2),
+            velocity_window: Duration::from_millis(10),
+            ..Options::default()
+        };
+        let provider = Provider::new(RpcClient::Mock(MockChain::default()));
+        let screen = SybilScreen::new(provider, &options, []).unwrap();
+
+        let funder = Address::random();
+        screen.record_grant(funder).await;
+        screen.record_grant(funder).await;
+        assert_eq!(screen.grants(funder).await, 2);
+
+        // An address which is not served again within the window is forgotten.
+        async_std::task::sleep(Duration::from_millis(20)).await;
+        assert_eq!(screen.grants(funder).await, 0);
+        let other = Address::random();
+        screen.record_grant(other).await;
+        assert_eq!(screen.grants(other).await, 1);
+        assert!(!screen.served.read().await.contains_key(&funder));
+    }
+}
@@ -0,0 +1,115 @@
+// Copyright (c) 2023 Espresso Systems (espressosys.com)
+// This file is part of the Discord Faucet library.
+//
+// You should have received a copy of the MIT License
+// along with the Discord Faucet library. If not, see <https://mit-license.org/>.
+
+//! Readiness and watchdog notifications for systemd.
+//!
+//! With `Type=notify`, systemd considers the service started once the faucet is monitoring the
+//! chain. With `WatchdogSec=`, the faucet pets the watchdog as long as its transfer loop keeps
+//! running, so that systemd restarts it if the loop gets stuck. Outside of systemd, when
+//! `NOTIFY_SOCKET` is not set, nothing is sent.
+use crate::Faucet;
+use async_std::task::sleep;
+use std::{env, io, os::unix::net::UnixDatagram, process, time::Duration};
+
+/// How often to check whether the faucet is ready.
+const READY_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Send `state` to systemd, e.g. `READY=1`.
+///
+/// Returns `false` without sending anything if the process is not run by systemd.
+pub fn sd_notify(state: &str) -> io::Result<bool> {
+    let Some(path) = env::var_os("NOTIFY_SOCKET") else {
+        return Ok(false);
+    };
+    let socket = UnixDatagram::unbound()?;
+    let path = path.to_string_lossy();
+    match path.strip_prefix('@') {
+        // Abstract sockets are named with a leading `@`.
+        Some(name) => send_abstract(&socket, name, state)?,
+        None => {
+            socket.send_to(state.as_bytes(), &*path)?;
+        }
+    }
+    Ok(true)
+}
+
+#[cfg(target_os = "linux")]
+fn send_abstract(socket: &UnixDatagram, name: &str, state: &str) -> io::Result<()> {
+    use std::os::{linux::net::SocketAddrExt, unix::net::SocketAddr};
+    let addr = SocketAddr::from_abstract_name(name)?;
+    socket.send_to_addr(state.as_bytes(), &addr)?;
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn send_abstract(_socket: &UnixDatagram, _name: &str, _state: &str) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "abstract sockets are only supported on Linux",
+    ))
+}
+
+/// The watchdog timeout systemd expects this process to respect, if any.
+pub fn watchdog_timeout() -> Option<Duration> {
+    // The watchdog is meant for the main process only.
+    if let Ok(pid) = env::var("WATCHDOG_PID") {
+        if pid.parse::<u32>().ok() != Some(process::id()) {
+            return None;
+        }
+    }
+    let usec = env::var("WATCHDOG_USEC").ok()?.parse().ok()?;
+    Some(Duration::from_micros(usec))
+}
+
+/// Tell systemd when `faucet` is ready, then pet the watchdog while it is running.
+pub async fn notify_systemd(faucet: Faucet) {
+    while !faucet.is_monitoring().await {
+        sleep(READY_CHECK_INTERVAL).await;
+    }
+    match sd_notify("READY=1") {
+        Ok(true) => tracing::info!("Notified systemd that the faucet is ready"),
+        Ok(false) => return,
+        Err(err) => tracing::error!("Failed to notify systemd: {err}"),
+    }
+
+    let Some(timeout) = watchdog_timeout() else {
+        return;
+    };
+    loop {
+        // Pet the watchdog twice per timeout, so that a late wake up does not trigger it.
+        sleep(timeout / 2).await;
+        if faucet.is_alive(timeout).await {
+            if let Err(err) = sd_notify("WATCHDOG=1") {
+                tracing::error!("Failed to pet the systemd watchdog: {err}");
+            }
+        } else {
+            tracing::error!("The faucet stopped executing transfers, not petting the watchdog");
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_sd_notify() {
+        let dir = env::temp_dir().join(format!("faucet-notify-{}", rand::random::<u64>()));
+        std::fs::create_dir(&dir).unwrap();
+        let path = dir.join("notify.sock");
+        let server = UnixDatagram::bind(&path).unwrap();
+
+        env::set_var("NOTIFY_SOCKET", &path);
+        assert!(sd_notify("READY=1").unwrap());
+        let mut buf = [0; 16];
+        let len = server.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"READY=1");
+
+        env::remove_var("NOTIFY_SOCKET");
+        assert!(!sd_notify("READY=1").unwrap());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
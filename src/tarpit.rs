@@ -0,0 +1,114 @@
+// Copyright (c) 2023 Espresso Systems (espressosys.com)
+// This file is part of the Discord Faucet library.
+//
+// You should have received a copy of the MIT License
+// along with the Discord Faucet library. If not, see <https://mit-license.org/>.
+
+//! Tarpitting of flagged requesters.
+//!
+//! Rejecting a flagged requester tells them they were caught, and teaches them to rotate their
+//! identity. With `--tarpit SIGNAL:DELAY`, requests flagged by `SIGNAL` are accepted as usual
+//! instead, but held back for `DELAY` before they join the back of the queue.
+use anyhow::{anyhow, bail, Error, Result};
+use std::{
+    fmt::{self, Display, Formatter},
+    str::FromStr,
+    time::Duration,
+};
+
+/// A heuristic flagging abusive requesters.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum AbuseSignal {
+    /// The recipient has a high Sybil score.
+    Sybil,
+    /// The recipient belongs to a large cluster of addresses sharing a funder.
+    Cluster,
+    /// The requester is throttled after a spike of requests.
+    Velocity,
+    /// The request exceeds a `--rate-limit` rule.
+    RateLimit,
+}
+
+impl FromStr for AbuseSignal {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "sybil" => Ok(Self::Sybil),
+            "cluster" => Ok(Self::Cluster),
+            "velocity" => Ok(Self::Velocity),
+            "rate-limit" => Ok(Self::RateLimit),
+            _ => bail!("unknown signal {s}, expected sybil, cluster, velocity or rate-limit"),
+        }
+    }
+}
+
+impl Display for AbuseSignal {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Self::Sybil => write!(f, "sybil"),
+            Self::Cluster => write!(f, "cluster"),
+            Self::Velocity => write!(f, "velocity"),
+            Self::RateLimit => write!(f, "rate-limit"),
+        }
+    }
+}
+
+/// Hold back the requests flagged by `signal` for `delay`, instead of rejecting them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TarpitRule {
+    pub signal: AbuseSignal,
+    pub delay: Duration,
+}
+
+impl FromStr for TarpitRule {
+    type Err = Error;
+
+    /// Parse a rule from `SIGNAL:DELAY`, e.g. `sybil:1h`.
+    fn from_str(s: &str) -> Result<Self> {
+        let Some((signal, delay)) = s.split_once(':') else {
+            bail!("expected SIGNAL:DELAY, got {s}");
+        };
+        Ok(Self {
+            signal: signal.parse()?,
+            delay: duration_str::parse(delay).map_err(|err| anyhow!("invalid delay: {err}"))?,
+        })
+    }
+}
+
+/// The delay of the requests flagged by `signal` under `rules`, or `None` to reject them.
+pub fn tarpit_delay(rules: &[TarpitRule], signal: AbuseSignal) -> Option<Duration> {
+    rules
+        .iter()
+        .filter(|rule| rule.signal == signal)
+        .map(|rule| rule.delay)
+        .max()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_tarpit_rules() {
+        let rules = ["sybil:1h", "rate-limit:10m"]
+            .into_iter()
+            .map(|rule| rule.parse().unwrap())
+            .collect::<Vec<TarpitRule>>();
+        assert_eq!(
+            rules[1],
+            TarpitRule {
+                signal: AbuseSignal::RateLimit,
+                delay: Duration::from_secs(600),
+            }
+        );
+        assert_eq!(
+            tarpit_delay(&rules, AbuseSignal::Sybil),
+            Some(Duration::from_secs(3600))
+        );
+        assert_eq!(tarpit_delay(&rules, AbuseSignal::Cluster), None);
+
+        "sybil".parse::<TarpitRule>().unwrap_err();
+        "spam:1h".parse::<TarpitRule>().unwrap_err();
+    }
+}
@@ -0,0 +1,195 @@
+// Copyright (c) 2023 Espresso Systems (espressosys.com)
+// This file is part of the Discord Faucet library.
+//
+// You should have received a copy of the MIT License
+// along with the Discord Faucet library. If not, see <https://mit-license.org/>.
+
+//! Tracing of faucet requests, optionally exported with OpenTelemetry.
+//!
+//! Each request has a `faucet_request` span lasting from intake to confirmation, with a child span
+//! for each stage it goes through, so that exported traces show where the latency of a request
+//! accumulates. A request goes back to `queue_wait` whenever its transfer is retried. A request
+//! handed over to the shared queue ends its span on the instance which received it, and the
+//! instance taking it from the shared queue starts a span with the same request and correlation
+//! IDs.
+use crate::{CorrelationId, Options, RequestId, RotatingFile, TransferRequest};
+use async_compatibility_layer::logging::setup_logging;
+use async_std::sync::Mutex;
+use std::{collections::HashMap, sync::Arc};
+use tracing::{field, info_span, Span, Subscriber};
+use tracing_subscriber::{
+    layer::SubscriberExt, registry::LookupSpan, util::SubscriberInitExt, EnvFilter, Layer,
+};
+
+/// A stage in the life of a faucet request.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stage {
+    /// Received by a front-end, waiting to be picked up by the faucet.
+    Intake,
+    /// Waiting for a wallet to send the transfer.
+    QueueWait,
+    /// Sending the transfer to the RPC.
+    Submission,
+    /// Waiting for the transfer to be mined.
+    Confirmation,
+    /// Waiting for the block of the transfer to be final, with `--finality`.
+    Finality,
+}
+
+#[derive(Debug)]
+struct RequestSpan {
+    root: Span,
+    /// The span of the current stage, which ends when it is replaced.
+    stage: Span,
+}
+
+/// The spans of the requests in progress.
+#[derive(Clone, Debug, Default)]
+pub struct RequestSpans {
+    spans: Arc<Mutex<HashMap<RequestId, RequestSpan>>>,
+}
+
+impl RequestSpans {
+    /// Start tracing a request at intake.
+    pub async fn start(&self, id: RequestId, correlation_id: CorrelationId) {
+        let root = info_span!(
+            "faucet_request",
+            request_id = %id,
+            %correlation_id,
+            outcome = field::Empty
+        );
+        let stage = stage_span(&root, Stage::Intake);
+        self.spans
+            .lock()
+            .await
+            .insert(id, RequestSpan { root, stage });
+    }
+
+    /// Move a request to `stage`, ending its previous stage.
+    pub async fn stage(&self, id: RequestId, stage: Stage) {
+        if let Some(span) = self.spans.lock().await.get_mut(&id) {
+            span.stage = stage_span(&span.root, stage);
+        }
+    }
+
+    /// The span of the request served by `transfer`, to log within.
+    ///
+    /// The span carries the request and correlation IDs. It is disabled for transfers which do not
+    /// serve a traced request.
+    pub async fn span(&self, transfer: &TransferRequest) -> Span {
+        let Some(id) = transfer.id() else {
+            return Span::none();
+        };
+        match self.spans.lock().await.get(&id) {
+            Some(span) => span.root.clone(),
+            None => Span::none(),
+        }
+    }
+
+    /// Stop tracing a request, which ended with `outcome`.
+    pub async fn finish(&self, id: RequestId, outcome: &str) {
+        if let Some(span) = self.spans.lock().await.remove(&id) {
+            span.root.record("outcome", outcome);
+        }
+    }
+}
+
+fn stage_span(root: &Span, stage: Stage) -> Span {
+    match stage {
+        Stage::Intake => info_span!(parent: root, "intake"),
+        Stage::QueueWait => info_span!(parent: root, "queue_wait"),
+        Stage::Submission => info_span!(parent: root, "submission"),
+        Stage::Confirmation => info_span!(parent: root, "confirmation"),
+        Stage::Finality => info_span!(parent: root, "finality"),
+    }
+}
+
+/// Log to the console and, if configured, to a log file and export traces with OTLP.
+pub fn setup_tracing(opts: &Options) {
+    let log_file = opts.log_file.as_ref().map(|path| {
+        RotatingFile::open(
+            path,
+            Some(opts.log_file_max_size).filter(|size| *size > 0),
+            opts.log_file_max_age,
+            opts.log_file_keep,
+        )
+        .expect("Failed to open the log file")
+    });
+
+    #[cfg(feature = "otlp")]
+    if let Some(endpoint) = &opts.otlp_endpoint {
+        setup_otlp(endpoint, &opts.otlp_service_name, log_file)
+            .expect("Failed to set up OTLP export");
+        return;
+    }
+    match log_file {
+        Some(file) => tracing_subscriber::registry()
+            .with(EnvFilter::from_default_env())
+            .with(tracing_subscriber::fmt::layer())
+            .with(file_layer(file))
+            .init(),
+        None => setup_logging(),
+    }
+}
+
+/// Write the logs to `file`, without the terminal colors.
+fn file_layer<S>(file: RotatingFile) -> impl Layer<S>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    tracing_subscriber::fmt::layer()
+        .with_ansi(false)
+        .with_writer(move || file.clone())
+}
+
+#[cfg(feature = "otlp")]
+fn setup_otlp(
+    endpoint: &url::Url,
+    service_name: &str,
+    log_file: Option<RotatingFile>,
+) -> anyhow::Result<()> {
+    use opentelemetry::KeyValue;
+    use opentelemetry_otlp::WithExportConfig;
+    use opentelemetry_sdk::{runtime::AsyncStd, trace, Resource};
+
+    let tracer = opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(
+            opentelemetry_otlp::new_exporter()
+                .tonic()
+                .with_endpoint(endpoint.as_str()),
+        )
+        .with_trace_config(trace::config().with_resource(Resource::new([KeyValue::new(
+            "service.name",
+            service_name.to_string(),
+        )])))
+        .install_batch(AsyncStd)?;
+    tracing_subscriber::registry()
+        .with(EnvFilter::from_default_env())
+        .with(tracing_subscriber::fmt::layer())
+        .with(log_file.map(file_layer))
+        .with(tracing_opentelemetry::layer().with_tracer(tracer))
+        .try_init()?;
+    tracing::info!("Exporting traces to {endpoint}");
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[async_std::test]
+    async fn test_request_spans() {
+        let spans = RequestSpans::default();
+        let id = RequestId::random();
+        spans.start(id, CorrelationId::random()).await;
+        spans.stage(id, Stage::QueueWait).await;
+        assert!(spans.spans.lock().await.contains_key(&id));
+
+        spans.finish(id, "confirmed").await;
+        assert!(spans.spans.lock().await.is_empty());
+        // Requests which are not traced are ignored.
+        spans.stage(id, Stage::Submission).await;
+        assert!(spans.spans.lock().await.is_empty());
+    }
+}
@@ -0,0 +1,123 @@
+// Copyright (c) 2023 Espresso Systems (espressosys.com)
+// This file is part of the Discord Faucet library.
+//
+// You should have received a copy of the MIT License
+// along with the Discord Faucet library. If not, see <https://mit-license.org/>.
+
+//! Helpers for the integration tests of other projects which need a faucet.
+//!
+//! Only built with the `test_utils` feature. The faucet grants funds from the accounts anvil funds
+//! by default, and serves its HTTP API on a free local port.
+//!
+//! ```ignore
+//! let faucet = TestFaucet::start(anvil.url()).await?;
+//! faucet.fund(address).await?;
+//! faucet.shutdown().await;
+//! ```
+use crate::{grant_and_confirm, serve, Faucet, FaucetRequest, Options, WebState};
+use anyhow::{Context, Result};
+use async_std::{
+    channel::{unbounded, Sender},
+    task::{sleep, spawn, JoinHandle},
+};
+use ethers::types::{Address, H256};
+use std::time::Duration;
+use url::Url;
+
+/// How often to check whether the faucet is ready.
+const READY_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// A faucet running in the background of a test.
+pub struct TestFaucet {
+    faucet: Faucet,
+    queue: Sender<FaucetRequest>,
+    url: Url,
+    pipeline: JoinHandle<(Result<()>, Result<()>, Result<()>, Result<()>)>,
+    server: JoinHandle<()>,
+}
+
+impl TestFaucet {
+    /// Start a faucet on the anvil chain at `rpc_url`, with the default options.
+    pub async fn start(rpc_url: Url) -> Result<Self> {
+        Self::start_with(Options {
+            provider_url_http: rpc_url,
+            provider_url_ws: None,
+            ..Default::default()
+        })
+        .await
+    }
+
+    /// Start a faucet with `options`, serving its API on a free port instead of `options.port`.
+    ///
+    /// Returns once the faucet is ready to serve requests.
+    pub async fn start_with(mut options: Options) -> Result<Self> {
+        options.port = portpicker::pick_unused_port().context("no free port")?;
+        let url = format!("http://localhost:{}", options.port).parse()?;
+
+        let (queue, receiver) = unbounded();
+        let faucet = Faucet::create(options.clone(), receiver).await?;
+        let pipeline = faucet.clone().start().await;
+        let state = WebState::new(queue.clone(), faucet.clone());
+        let server = spawn(async move {
+            if let Err(err) = serve(options.port, state).await {
+                tracing::error!("Test faucet server failed: {err}");
+            }
+        });
+
+        while !faucet.is_ready().await {
+            sleep(READY_CHECK_INTERVAL).await;
+        }
+        Ok(Self {
+            faucet,
+            queue,
+            url,
+            pipeline,
+            server,
+        })
+    }
+
+    /// The URL of the HTTP API of the faucet.
+    pub fn url(&self) -> &Url {
+        &self.url
+    }
+
+    /// The running faucet.
+    pub fn faucet(&self) -> &Faucet {
+        &self.faucet
+    }
+
+    /// Grant funds to `address`, returning once the transfer is mined.
+    pub async fn fund(&self, address: Address) -> Result<H256> {
+        grant_and_confirm(&self.faucet, &self.queue, address).await
+    }
+
+    /// Stop the faucet and its API.
+    pub async fn shutdown(self) {
+        self.queue.close();
+        // Dropping the handles would detach the tasks rather than stopping them.
+        self.server.cancel().await;
+        self.pipeline.cancel().await;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use sequencer_utils::AnvilOptions;
+
+    #[async_std::test]
+    async fn test_test_faucet() -> Result<()> {
+        let anvil = AnvilOptions::default().spawn().await;
+        let faucet = TestFaucet::start(anvil.url()).await?;
+
+        let recipient = Address::random();
+        faucet.fund(recipient).await?;
+        assert_eq!(
+            faucet.faucet().balance(recipient).await?,
+            faucet.faucet().config().faucet_grant_amount
+        );
+
+        faucet.shutdown().await;
+        Ok(())
+    }
+}
@@ -0,0 +1,448 @@
+// Copyright (c) 2023 Espresso Systems (espressosys.com)
+// This file is part of the Discord Faucet library.
+//
+// You should have received a copy of the MIT License
+// along with the Discord Faucet library. If not, see <https://mit-license.org/>.
+
+//! Tokens the faucet can grant in addition to the native currency.
+//!
+//! ERC-20 grants are paid from the token balances of the faucet wallets, so every wallet that
+//! should serve token requests must be funded with the token. ERC-1155 grants are either paid from
+//! the balances of the faucet wallets in the same way, or minted by the owner of the contract, which
+//! must then be one of the faucet wallets. Each ERC-1155 token ID is configured as a token of its
+//! own, and its grants are only confirmed once their `TransferSingle` event is found in the receipt.
+//!
+//! Tokens are configured with `--token`, or in the registry file passed with `--tokens-file`, which
+//! can restrict each token to some chains and is reloaded while the faucet runs.
+use anyhow::{bail, Context, Error, Result};
+use ethers::{
+    contract::{abigen, parse_log},
+    types::{Address, TransactionReceipt, U256},
+    utils::parse_units,
+};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, fs, path::Path, str::FromStr};
+
+abigen!(
+    Erc20,
+    r#"[
+        function transfer(address to, uint256 amount) external returns (bool)
+        function balanceOf(address account) external view returns (uint256)
+    ]"#
+);
+
+abigen!(
+    Erc1155,
+    r#"[
+        function safeTransferFrom(address from, address to, uint256 id, uint256 amount, bytes data) external
+        function mint(address account, uint256 id, uint256 amount, bytes data) external
+        function balanceOf(address account, uint256 id) external view returns (uint256)
+        function owner() external view returns (address)
+        event TransferSingle(address indexed operator, address indexed from, address indexed to, uint256 id, uint256 value)
+    ]"#
+);
+
+/// The token standard of a configured token.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TokenStandard {
+    #[default]
+    Erc20,
+    /// The token `id` of an ERC-1155 contract, transferred from the faucet wallets or minted with
+    /// the `mint` function of the contract.
+    Erc1155 { id: U256, mint: bool },
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct Token {
+    pub symbol: String,
+    pub address: Address,
+    pub decimals: u8,
+    /// The amount granted per request, in the smallest unit of the token.
+    pub grant_amount: U256,
+    #[serde(default)]
+    pub standard: TokenStandard,
+}
+
+impl FromStr for Token {
+    type Err = Error;
+
+    /// Parse a token from `SYMBOL:ADDRESS:DECIMALS:AMOUNT`, where `AMOUNT` is the grant amount in
+    /// whole tokens, or an ERC-1155 token ID from `SYMBOL:ADDRESS:erc1155:ID:AMOUNT[:mint]`.
+    fn from_str(s: &str) -> Result<Self> {
+        match s.split(':').collect::<Vec<_>>()[..] {
+            [symbol, address, decimals, amount] => {
+                let decimals = decimals.parse().context("invalid decimals")?;
+                Ok(Self {
+                    symbol: symbol.to_string(),
+                    address: address.parse().context("invalid token address")?,
+                    decimals,
+                    grant_amount: parse_units(amount, decimals as u32)
+                        .context("invalid grant amount")?
+                        .into(),
+                    standard: TokenStandard::Erc20,
+                })
+            }
+            [symbol, address, "erc1155", id, amount, ref mint @ ..] => {
+                let mint = match mint {
+                    [] => false,
+                    ["mint"] => true,
+                    _ => bail!("expected SYMBOL:ADDRESS:erc1155:ID:AMOUNT[:mint], got {s}"),
+                };
+                Ok(Self {
+                    symbol: symbol.to_string(),
+                    address: address.parse().context("invalid token address")?,
+                    decimals: 0,
+                    grant_amount: U256::from_dec_str(amount).context("invalid grant amount")?,
+                    standard: TokenStandard::Erc1155 {
+                        id: U256::from_dec_str(id).context("invalid token ID")?,
+                        mint,
+                    },
+                })
+            }
+            _ => bail!(
+                "expected SYMBOL:ADDRESS:DECIMALS:AMOUNT or SYMBOL:ADDRESS:erc1155:ID:AMOUNT[:mint], \
+                 got {s}"
+            ),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TokensFile {
+    #[serde(default, rename = "token")]
+    tokens: Vec<TokenEntry>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TokenEntry {
+    symbol: String,
+    address: Address,
+    #[serde(default)]
+    decimals: u8,
+    /// The amount granted per request, in whole tokens.
+    grant_amount: String,
+    /// The names or IDs of the chains the token is granted on, or all chains if empty.
+    #[serde(default)]
+    chains: Vec<String>,
+    /// The token ID, for a token of an ERC-1155 contract.
+    erc1155_id: Option<String>,
+    /// Whether the ERC-1155 token is minted rather than transferred.
+    #[serde(default)]
+    mint: bool,
+}
+
+impl TokenEntry {
+    fn enabled_on(&self, chain_id: u64, network_name: Option<&str>) -> bool {
+        self.chains.is_empty()
+            || self
+                .chains
+                .iter()
+                .any(|chain| Some(chain.as_str()) == network_name || *chain == chain_id.to_string())
+    }
+
+    fn token(self) -> Result<Token> {
+        let standard = match &self.erc1155_id {
+            Some(id) => {
+                if self.decimals != 0 {
+                    bail!("ERC-1155 token {} cannot have decimals", self.symbol);
+                }
+                TokenStandard::Erc1155 {
+                    id: U256::from_dec_str(id).context("invalid token ID")?,
+                    mint: self.mint,
+                }
+            }
+            None if self.mint => bail!("only ERC-1155 tokens can be minted"),
+            None => TokenStandard::Erc20,
+        };
+        Ok(Token {
+            grant_amount: parse_units(&self.grant_amount, self.decimals as u32)
+                .context("invalid grant amount")?
+                .into(),
+            symbol: self.symbol,
+            address: self.address,
+            decimals: self.decimals,
+            standard,
+        })
+    }
+}
+
+/// Read the tokens of the registry file at `path` which are enabled on the chain `chain_id`, named
+/// `network_name`.
+pub fn read_tokens(path: &Path, chain_id: u64, network_name: Option<&str>) -> Result<Vec<Token>> {
+    let contents =
+        fs::read_to_string(path).with_context(|| format!("reading {}", path.display()))?;
+    parse_tokens(&contents, chain_id, network_name)
+        .with_context(|| format!("parsing {}", path.display()))
+}
+
+fn parse_tokens(contents: &str, chain_id: u64, network_name: Option<&str>) -> Result<Vec<Token>> {
+    let file: TokensFile = toml::from_str(contents)?;
+    file.tokens
+        .into_iter()
+        .filter(|entry| entry.enabled_on(chain_id, network_name))
+        .map(|entry| {
+            let symbol = entry.symbol.clone();
+            entry
+                .token()
+                .with_context(|| format!("invalid token {symbol}"))
+        })
+        .collect()
+}
+
+/// Whether `receipt` has a `TransferSingle` event of the ERC-1155 contract `token` moving `amount`
+/// of the token `id` from `from`, the zero address for mints, to `to`.
+pub fn has_transfer_single(
+    receipt: &TransactionReceipt,
+    token: Address,
+    from: Address,
+    to: Address,
+    id: U256,
+    amount: U256,
+) -> bool {
+    receipt
+        .logs
+        .iter()
+        .filter(|log| log.address == token)
+        .filter_map(|log| parse_log::<TransferSingleFilter>(log.clone()).ok())
+        .any(|event| {
+            event.from == from && event.to == to && event.id == id && event.value == amount
+        })
+}
+
+/// The configured tokens, indexed by case-insensitive symbol.
+#[derive(Clone, Debug, Default)]
+pub struct TokenRegistry {
+    tokens: BTreeMap<String, Token>,
+}
+
+impl TokenRegistry {
+    pub fn new(tokens: impl IntoIterator<Item = Token>) -> Self {
+        Self {
+            tokens: tokens
+                .into_iter()
+                .map(|token| (token.symbol.to_lowercase(), token))
+                .collect(),
+        }
+    }
+
+    pub fn get(&self, symbol: &str) -> Option<&Token> {
+        self.tokens.get(&symbol.to_lowercase())
+    }
+
+    /// The configured ERC-20 token with contract `address`, if any.
+    pub fn by_address(&self, address: Address) -> Option<&Token> {
+        self.tokens
+            .values()
+            .find(|token| token.address == address && token.standard == TokenStandard::Erc20)
+    }
+
+    /// The configured token `id` of the ERC-1155 contract `address`, if any.
+    pub fn erc1155(&self, address: Address, id: U256) -> Option<&Token> {
+        self.tokens.values().find(|token| {
+            token.address == address
+                && matches!(token.standard, TokenStandard::Erc1155 { id: other, .. } if other == id)
+        })
+    }
+
+    /// The ERC-1155 contracts with tokens minted by the faucet.
+    pub fn minted_contracts(&self) -> Vec<Address> {
+        let mut contracts = self
+            .tokens
+            .values()
+            .filter(|token| matches!(token.standard, TokenStandard::Erc1155 { mint: true, .. }))
+            .map(|token| token.address)
+            .collect::<Vec<_>>();
+        contracts.sort();
+        contracts.dedup();
+        contracts
+    }
+
+    /// The symbols of all configured tokens.
+    pub fn symbols(&self) -> Vec<String> {
+        self.tokens
+            .values()
+            .map(|token| token.symbol.clone())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ethers::{
+        abi::{encode, Token as AbiToken},
+        contract::EthEvent,
+        types::{Log, H256},
+    };
+
+    #[test]
+    fn test_parse_token() {
+        let token: Token = "USDC:0x1234567890123456789012345678901234567890:6:100"
+            .parse()
+            .unwrap();
+        assert_eq!(token.symbol, "USDC");
+        assert_eq!(token.decimals, 6);
+        assert_eq!(token.grant_amount, U256::from(100_000_000));
+
+        assert!("USDC:0x1234:6:100".parse::<Token>().is_err());
+        assert!("USDC:6:100".parse::<Token>().is_err());
+    }
+
+    #[test]
+    fn test_parse_erc1155_token() {
+        let token: Token = "SWORD:0x1234567890123456789012345678901234567890:erc1155:7:2:mint"
+            .parse()
+            .unwrap();
+        assert_eq!(token.grant_amount, U256::from(2));
+        assert_eq!(
+            token.standard,
+            TokenStandard::Erc1155 {
+                id: 7.into(),
+                mint: true
+            }
+        );
+
+        let registry = TokenRegistry::new([token.clone()]);
+        assert_eq!(registry.erc1155(token.address, 7.into()), Some(&token));
+        assert_eq!(registry.erc1155(token.address, 8.into()), None);
+        assert_eq!(registry.by_address(token.address), None);
+        assert_eq!(registry.minted_contracts(), [token.address]);
+
+        assert!(
+            "SWORD:0x1234567890123456789012345678901234567890:erc1155:7:2:burn"
+                .parse::<Token>()
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_transfer_single_receipt() {
+        let token = Address::random();
+        let to = Address::random();
+        let log = Log {
+            address: token,
+            topics: vec![
+                TransferSingleFilter::signature(),
+                H256::from(Address::random()),
+                H256::zero(),
+                H256::from(to),
+            ],
+            data: encode(&[AbiToken::Uint(7.into()), AbiToken::Uint(2.into())]).into(),
+            ..Default::default()
+        };
+        let receipt = TransactionReceipt {
+            logs: vec![log],
+            ..Default::default()
+        };
+        let from = Address::zero();
+        assert!(has_transfer_single(
+            &receipt,
+            token,
+            from,
+            to,
+            7.into(),
+            2.into()
+        ));
+        assert!(!has_transfer_single(
+            &receipt,
+            token,
+            from,
+            to,
+            7.into(),
+            1.into()
+        ));
+        assert!(!has_transfer_single(
+            &receipt,
+            Address::random(),
+            from,
+            to,
+            7.into(),
+            2.into()
+        ));
+        assert!(!has_transfer_single(
+            &receipt,
+            token,
+            Address::random(),
+            to,
+            7.into(),
+            2.into()
+        ));
+    }
+
+    #[test]
+    fn test_registry_lookup_is_case_insensitive() {
+        let token: Token = "USDC:0x1234567890123456789012345678901234567890:6:100"
+            .parse()
+            .unwrap();
+        let registry = TokenRegistry::new([token.clone()]);
+        assert_eq!(registry.get("usdc"), Some(&token));
+        assert_eq!(registry.get("dai"), None);
+        assert_eq!(registry.symbols(), vec!["USDC".to_string()]);
+    }
+
+    #[test]
+    fn test_parse_tokens_file() {
+        let contents = r#"
+            [[token]]
+            symbol = "USDC"
+            address = "0x1234567890123456789012345678901234567890"
+            decimals = 6
+            grant_amount = "100"
+
+            [[token]]
+            symbol = "DAI"
+            address = "0x1234567890123456789012345678901234567891"
+            decimals = 18
+            grant_amount = "0.5"
+            chains = ["sepolia", "5"]
+
+            [[token]]
+            symbol = "SWORD"
+            address = "0x1234567890123456789012345678901234567892"
+            grant_amount = "2"
+            erc1155_id = "7"
+            mint = true
+        "#;
+
+        let tokens = parse_tokens(contents, 1, None).unwrap();
+        let registry = TokenRegistry::new(tokens);
+        assert_eq!(registry.symbols(), ["SWORD", "USDC"]);
+        assert_eq!(
+            registry.get("usdc").unwrap().grant_amount,
+            U256::from(100_000_000)
+        );
+        assert_eq!(
+            registry.get("sword").unwrap().standard,
+            TokenStandard::Erc1155 {
+                id: 7.into(),
+                mint: true
+            }
+        );
+
+        // Tokens restricted to some chains are matched by name or by chain ID.
+        for (chain_id, name) in [(1, Some("sepolia")), (5, None)] {
+            let registry = TokenRegistry::new(parse_tokens(contents, chain_id, name).unwrap());
+            assert_eq!(
+                registry.get("dai").unwrap().grant_amount,
+                U256::exp10(17) * 5
+            );
+        }
+
+        assert!(parse_tokens(
+            r#"
+                [[token]]
+                symbol = "USDC"
+                address = "0x1234567890123456789012345678901234567890"
+                decimals = 6
+                grant_amount = "100"
+                mint = true
+            "#,
+            1,
+            None
+        )
+        .is_err());
+    }
+}
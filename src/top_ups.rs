@@ -0,0 +1,256 @@
+// Copyright (c) 2023 Espresso Systems (espressosys.com)
+// This file is part of the Discord Faucet library.
+//
+// You should have received a copy of the MIT License
+// along with the Discord Faucet library. If not, see <https://mit-license.org/>.
+
+//! Addresses registered for automatic top-ups.
+//!
+//! Long-running testnet accounts, such as validators or demo accounts, need funds regularly. With
+//! `--top-up-threshold`, Discord users and admins can register addresses, and every
+//! `--top-up-interval` the faucet requests a grant for each registered address whose balance fell
+//! below the threshold. Registrations are saved to a JSON file, so that they survive restarts.
+//!
+//! The top-ups of an address registered by a Discord user are requests by that user, subject to
+//! the same bans, registration, cooldowns and quota as the user's web requests with a Discord
+//! token. Banning a user cancels their registrations.
+use crate::{
+    DiscordWebToken, FaucetRequest, RequestId, RequestSource, RequestStatus, Requester, WebState,
+};
+use anyhow::{Context, Result};
+use async_std::{fs, sync::RwLock, task::sleep};
+use ethers::types::{Address, U256};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// An address registered for automatic top-ups.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct TopUp {
+    pub address: Address,
+    /// The Discord user who registered the address, or `None` if an admin did.
+    pub registered_by: Option<u64>,
+    /// The guild whose cooldowns and quota apply to the top-ups, if registered by a Discord user
+    /// with a token issued in a guild.
+    #[serde(default)]
+    pub guild: Option<u64>,
+    /// Whether the Discord user who registered the address held `--discord-verified-role`, which
+    /// gives the top-ups the priority of the user's Discord requests.
+    #[serde(default)]
+    pub verified: bool,
+    /// When the address was registered, in seconds since the Unix epoch.
+    pub timestamp: u64,
+}
+
+impl TopUp {
+    /// Register `address` on behalf of the Discord user holding the web token `owner`, or of an
+    /// admin if `None`.
+    pub fn new(address: Address, owner: Option<DiscordWebToken>) -> Self {
+        Self {
+            address,
+            registered_by: owner.map(|owner| owner.user),
+            guild: owner.and_then(|owner| owner.guild),
+            verified: owner.is_some_and(|owner| owner.verified),
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct TopUpList {
+    /// The file the registrations are saved to, or `None` to keep them in memory only.
+    path: Option<PathBuf>,
+    top_ups: Arc<RwLock<BTreeMap<Address, TopUp>>>,
+}
+
+impl TopUpList {
+    /// Load the registrations saved at `path`, which is created on the first registration if it
+    /// does not exist.
+    pub fn load(path: PathBuf) -> Result<Self> {
+        let top_ups = if path.exists() { read(&path)? } else { vec![] };
+        Ok(Self {
+            path: Some(path),
+            top_ups: Arc::new(RwLock::new(
+                top_ups
+                    .into_iter()
+                    .map(|top_up| (top_up.address, top_up))
+                    .collect(),
+            )),
+        })
+    }
+
+    /// The registration of `address`, if it is registered.
+    pub async fn get(&self, address: Address) -> Option<TopUp> {
+        self.top_ups.read().await.get(&address).cloned()
+    }
+
+    /// All the registrations, ordered by address.
+    pub async fn list(&self) -> Vec<TopUp> {
+        self.top_ups.read().await.values().cloned().collect()
+    }
+
+    /// The number of addresses registered by the Discord user `user`.
+    pub async fn count_by(&self, user: u64) -> usize {
+        self.top_ups
+            .read()
+            .await
+            .values()
+            .filter(|top_up| top_up.registered_by == Some(user))
+            .count()
+    }
+
+    /// Register an address, replacing any previous registration of the same address.
+    pub async fn register(&self, top_up: TopUp) -> Result<()> {
+        let mut top_ups = self.top_ups.write().await;
+        let mut updated = top_ups.clone();
+        updated.insert(top_up.address, top_up);
+        self.save(&updated).await?;
+        *top_ups = updated;
+        Ok(())
+    }
+
+    /// Stop topping up `address`, returning whether it was registered.
+    pub async fn unregister(&self, address: Address) -> Result<bool> {
+        let mut top_ups = self.top_ups.write().await;
+        if !top_ups.contains_key(&address) {
+            return Ok(false);
+        }
+        let mut updated = top_ups.clone();
+        updated.remove(&address);
+        self.save(&updated).await?;
+        *top_ups = updated;
+        Ok(true)
+    }
+
+    /// Request a grant every `interval` for each registered address with less than `threshold`.
+    ///
+    /// An address is not topped up again while its previous top-up is still pending.
+    pub async fn watch(self, state: WebState, threshold: U256, interval: Duration) {
+        let faucet = &state.faucet;
+        let mut pending = HashMap::<Address, RequestId>::new();
+        loop {
+            sleep(interval).await;
+            for top_up in self.list().await {
+                let address = top_up.address;
+                if let Some(user) = top_up.registered_by {
+                    if state.bans.get(user).await.is_some() {
+                        tracing::info!("Unregistering {address:?} of banned user {user}");
+                        if let Err(err) = self.unregister(address).await {
+                            tracing::warn!("Failed to unregister {address:?}: {err:#}");
+                        }
+                        continue;
+                    }
+                }
+                if let Some(id) = pending.get(&address) {
+                    if !matches!(
+                        faucet.request_status(*id).await,
+                        None | Some(RequestStatus::Confirmed { .. })
+                    ) {
+                        continue;
+                    }
+                }
+                match faucet.balance(address).await {
+                    Ok(balance) if balance < threshold => {}
+                    Ok(_) => continue,
+                    Err(err) => {
+                        tracing::warn!("Failed to get the balance of {address:?}: {err:#}");
+                        continue;
+                    }
+                }
+                let request = FaucetRequest::new(address, None);
+                // Top-ups registered by Discord users have the priority of their Discord requests.
+                let result = match top_up.registered_by {
+                    Some(user) => {
+                        let owner = DiscordWebToken {
+                            user,
+                            guild: top_up.guild,
+                            expires: 0,
+                            verified: top_up.verified,
+                        };
+                        let request = request
+                            .with_requester(Some(Requester::Discord(user)))
+                            .with_source(owner.source());
+                        state.discord_web_request(owner, request).await
+                    }
+                    None => {
+                        let request = request.with_source(RequestSource::Admin);
+                        WebState::submit(&state.faucet_queue, faucet, request).await
+                    }
+                };
+                match result {
+                    Ok(queued) => {
+                        tracing::info!("Topping up {address:?} with request {}", queued.id);
+                        pending.insert(address, queued.id);
+                    }
+                    Err(err) => tracing::warn!("Failed to top up {address:?}: {}", err.message),
+                }
+            }
+        }
+    }
+
+    async fn save(&self, top_ups: &BTreeMap<Address, TopUp>) -> Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let contents = serde_json::to_string_pretty(&top_ups.values().collect::<Vec<_>>())?;
+        // Write to a temporary file first, so that the list is never left half written.
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, contents)
+            .await
+            .with_context(|| format!("writing {}", tmp.display()))?;
+        fs::rename(&tmp, path)
+            .await
+            .with_context(|| format!("writing {}", path.display()))
+    }
+}
+
+fn read(path: &Path) -> Result<Vec<TopUp>> {
+    let contents =
+        std::fs::read_to_string(path).with_context(|| format!("reading {}", path.display()))?;
+    serde_json::from_str(&contents).with_context(|| format!("parsing {}", path.display()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[async_std::test]
+    async fn test_top_up_list() {
+        let path =
+            std::env::temp_dir().join(format!("faucet-top-ups-{}.json", rand::random::<u64>()));
+        let top_ups = TopUpList::load(path.clone()).unwrap();
+        let address = Address::random();
+        assert_eq!(top_ups.get(address).await, None);
+
+        let owner = DiscordWebToken {
+            user: 1,
+            guild: Some(2),
+            expires: 0,
+            verified: true,
+        };
+        let top_up = TopUp::new(address, Some(owner));
+        assert_eq!(top_up.registered_by, Some(1));
+        assert_eq!(top_up.guild, Some(2));
+        assert!(top_up.verified);
+        top_ups.register(top_up.clone()).await.unwrap();
+        let other = Address::random();
+        top_ups.register(TopUp::new(other, None)).await.unwrap();
+        assert_eq!(top_ups.get(address).await, Some(top_up.clone()));
+        assert_eq!(top_ups.count_by(1).await, 1);
+
+        assert!(top_ups.unregister(other).await.unwrap());
+        assert!(!top_ups.unregister(other).await.unwrap());
+
+        // The registrations are persisted.
+        let reloaded = TopUpList::load(path.clone()).unwrap();
+        assert_eq!(reloaded.list().await, vec![top_up]);
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,91 @@
+// Copyright (c) 2023 Espresso Systems (espressosys.com)
+// This file is part of the Discord Faucet library.
+//
+// You should have received a copy of the MIT License
+// along with the Discord Faucet library. If not, see <https://mit-license.org/>.
+
+//! A minimal web page for requesting funds.
+//!
+//! This lets a testnet offer a faucet to humans without deploying a separate front-end. The page is
+//! a single static HTML file which calls the `v1` API from the browser, so it is served by a
+//! separate, plain HTTP server.
+use crate::Options;
+use serde::Serialize;
+use std::io;
+use tide::{http::mime, Response};
+
+/// The page, with a `{{CONFIG}}` placeholder for the JSON serialized [`UiConfig`].
+const INDEX_HTML: &str = include_str!("ui.html");
+
+/// The settings the page needs to talk to the API.
+#[derive(Clone, Debug, Serialize)]
+struct UiConfig {
+    /// The URL of the API, or `None` to use the host of the page.
+    api_url: Option<String>,
+    api_port: u16,
+    captcha_script: Option<&'static str>,
+    captcha_class: Option<&'static str>,
+    captcha_site_key: Option<String>,
+    tokens: Vec<String>,
+}
+
+pub(crate) async fn serve_ui(port: u16, options: &Options) -> io::Result<()> {
+    let page = render(options);
+    let mut app = tide::new();
+    app.at("/").get(move |_req| {
+        let page = page.clone();
+        async move {
+            Ok(Response::builder(200)
+                .content_type(mime::HTML)
+                .body(page)
+                .build())
+        }
+    });
+    app.listen(format!("0.0.0.0:{port}")).await
+}
+
+fn render(options: &Options) -> String {
+    let captcha = options
+        .captcha_provider
+        .filter(|_| options.captcha_site_key.is_some());
+    let config = UiConfig {
+        api_url: options
+            .ui_api_url
+            .as_ref()
+            .map(|url| url.as_str().trim_end_matches('/').to_string()),
+        api_port: options.port,
+        captcha_script: captcha.map(|provider| provider.script_url()),
+        captcha_class: captcha.map(|provider| provider.widget_class()),
+        captcha_site_key: options.captcha_site_key.clone(),
+        tokens: options
+            .tokens
+            .iter()
+            .map(|token| token.symbol.clone())
+            .collect(),
+    };
+    // The config is embedded in a <script> element, which must not be closed by a string inside
+    // the config.
+    let config = serde_json::to_string(&config)
+        .unwrap()
+        .replace("</", "<\\/");
+    INDEX_HTML.replace("{{CONFIG}}", &config)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::CaptchaProvider;
+
+    #[test]
+    fn test_render_config() {
+        let options = Options {
+            captcha_provider: Some(CaptchaProvider::Turnstile),
+            captcha_site_key: Some("</script>".to_string()),
+            ..Default::default()
+        };
+        let page = render(&options);
+        assert!(!page.contains("{{CONFIG}}"));
+        assert!(page.contains(CaptchaProvider::Turnstile.script_url()));
+        assert!(page.contains("<\\/script>"));
+    }
+}
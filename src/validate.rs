@@ -0,0 +1,228 @@
+// Copyright (c) 2023 Espresso Systems (espressosys.com)
+// This file is part of the Discord Faucet library.
+//
+// You should have received a copy of the MIT License
+// along with the Discord Faucet library. If not, see <https://mit-license.org/>.
+
+//! The `validate-config` command, a preflight check of a deployment.
+//!
+//! Every check runs even if an earlier one failed, so that a single run reports all the problems
+//! with the configuration.
+use crate::{BanList, Catalog, Guilds, LiveOptions, Options};
+use anyhow::{anyhow, bail, Result};
+use clap::Args;
+use ethers::{
+    providers::{Http, Middleware, Provider, Ws},
+    signers::{coins_bip39::English, MnemonicBuilder, Signer},
+    utils::format_ether,
+};
+use serde::Deserialize;
+use std::fmt::{self, Display, Formatter};
+use url::Url;
+
+#[derive(Args, Clone, Debug)]
+pub struct ValidateConfig {
+    #[command(flatten)]
+    options: Options,
+
+    /// Do not check the Discord token with Discord.
+    #[arg(long)]
+    skip_discord: bool,
+}
+
+/// The outcome of each check.
+#[derive(Debug, Default)]
+pub struct Report {
+    checks: Vec<(&'static str, Result<String>)>,
+}
+
+impl Report {
+    fn check(&mut self, name: &'static str, result: Result<String>) {
+        self.checks.push((name, result));
+    }
+
+    /// Whether all the checks passed.
+    pub fn passed(&self) -> bool {
+        self.checks.iter().all(|(_, result)| result.is_ok())
+    }
+}
+
+impl Display for Report {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        for (name, result) in &self.checks {
+            match result {
+                Ok(detail) => writeln!(f, "PASS  {name}: {detail}")?,
+                Err(err) => writeln!(f, "FAIL  {name}: {err:#}")?,
+            }
+        }
+        let failed = self.checks.iter().filter(|(_, r)| r.is_err()).count();
+        if failed == 0 {
+            write!(f, "All {} checks passed", self.checks.len())
+        } else {
+            write!(f, "{failed} of {} checks failed", self.checks.len())
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct DiscordUser {
+    username: String,
+}
+
+impl ValidateConfig {
+    /// Run all the checks.
+    pub async fn run(self) -> Report {
+        let mut report = Report::default();
+
+        let opts = match LiveOptions::load(self.options.clone()) {
+            Ok(live) => {
+                report.check(
+                    "settings file",
+                    Ok(match &self.options.settings_file {
+                        Some(path) => format!("{} is valid", path.display()),
+                        None => "not configured".into(),
+                    }),
+                );
+                Options::clone(&live.get())
+            }
+            Err(err) => {
+                report.check("settings file", Err(err));
+                self.options.clone()
+            }
+        };
+
+        let mut chains = vec![];
+        report.check(
+            "guild config",
+            match &opts.guild_config {
+                Some(path) => Guilds::validate(path).await.map(|(guilds, urls)| {
+                    chains = urls;
+                    format!("{guilds} guilds and {} chains", chains.len())
+                }),
+                None => Ok("not configured".into()),
+            },
+        );
+        report.check(
+            "ban list",
+            match &opts.discord_ban_list {
+                Some(path) => match BanList::load(path.clone()) {
+                    Ok(bans) => Ok(format!("{} bans", bans.list().await.len())),
+                    Err(err) => Err(err),
+                },
+                None => Ok("not configured, bans are kept in memory".into()),
+            },
+        );
+        report.check(
+            "messages",
+            Catalog::load(opts.discord_locales.as_deref(), &opts.discord_locale)
+                .map(|_| format!("default locale {}", opts.discord_locale)),
+        );
+
+        let http_chain_id = chain_id(&opts.provider_url_http).await;
+        report.check(
+            "RPC",
+            http_chain_id
+                .as_ref()
+                .map(|id| format!("{} is on chain {id}", opts.provider_url_http))
+                .map_err(|err| anyhow!("{err:#}")),
+        );
+        if let Some(url) = &opts.provider_url_ws {
+            report.check(
+                "WebSocket RPC",
+                ws_chain_id(url).await.and_then(|id| match &http_chain_id {
+                    Ok(http_id) if *http_id != id => {
+                        bail!("{url} is on chain {id}, but the HTTP RPC is on chain {http_id}")
+                    }
+                    _ => Ok(format!("{url} is on chain {id}")),
+                }),
+            );
+        }
+        for (name, url) in &chains {
+            report.check(
+                "guild chain RPC",
+                chain_id(url)
+                    .await
+                    .map(|id| format!("{name}: {url} is on chain {id}"))
+                    .map_err(|err| err.context(format!("chain {name}"))),
+            );
+        }
+
+        report.check("first wallet", first_wallet(&opts).await);
+
+        if self.skip_discord {
+            report.check("Discord token", Ok("skipped".into()));
+        } else {
+            report.check(
+                "Discord token",
+                match opts
+                    .discord_token
+                    .as_deref()
+                    .filter(|token| !token.is_empty())
+                {
+                    Some(token) => discord_user(token)
+                        .await
+                        .map(|user| format!("logs in as {user}")),
+                    None => Ok("not configured, the Discord bot is disabled".into()),
+                },
+            );
+        }
+
+        report
+    }
+}
+
+async fn chain_id(url: &Url) -> Result<u64> {
+    let provider = Provider::<Http>::try_from(url.to_string())?;
+    Ok(provider.get_chainid().await?.as_u64())
+}
+
+async fn ws_chain_id(url: &Url) -> Result<u64> {
+    let provider = Provider::<Ws>::connect(url.clone()).await?;
+    Ok(provider.get_chainid().await?.as_u64())
+}
+
+/// Derive the first faucet wallet and get its balance.
+async fn first_wallet(opts: &Options) -> Result<String> {
+    let wallet = MnemonicBuilder::<English>::default()
+        .phrase(opts.mnemonic.as_str())
+        .index(opts.first_wallet_index())?
+        .build()?;
+    let provider = Provider::<Http>::try_from(opts.provider_url_http.to_string())?;
+    let balance = provider.get_balance(wallet.address(), None).await?;
+    Ok(format!(
+        "{:?} has {} ETH",
+        wallet.address(),
+        format_ether(balance)
+    ))
+}
+
+/// The name of the Discord bot which logs in with `token`.
+async fn discord_user(token: &str) -> Result<String> {
+    let mut res = surf::get("https://discord.com/api/v10/users/@me")
+        .header("Authorization", format!("Bot {token}"))
+        .await
+        .map_err(|err| err.into_inner())?;
+    if !res.status().is_success() {
+        bail!("Discord rejected the token: {}", res.status());
+    }
+    let user: DiscordUser = res.body_json().await.map_err(|err| err.into_inner())?;
+    Ok(user.username)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_report() {
+        let mut report = Report::default();
+        report.check("first", Ok("fine".into()));
+        assert!(report.passed());
+        report.check("second", Err(anyhow!("broken")));
+        assert!(!report.passed());
+        assert_eq!(
+            report.to_string(),
+            "PASS  first: fine\nFAIL  second: broken\n1 of 2 checks failed"
+        );
+    }
+}
@@ -0,0 +1,291 @@
+// Copyright (c) 2023 Espresso Systems (espressosys.com)
+// This file is part of the Discord Faucet library.
+//
+// You should have received a copy of the MIT License
+// along with the Discord Faucet library. If not, see <https://mit-license.org/>.
+
+//! Detection of spikes in the rate of requests.
+//!
+//! Requests are counted in windows of `--velocity-window`, globally, per client IP and per Discord
+//! user. The faucet learns a baseline for each of these scopes: a moving average of the global
+//! count, and of the count of the busiest IP and the busiest Discord user, of each window. A scope
+//! spikes when its count exceeds `--velocity-factor` times its baseline, and at least
+//! `--velocity-min-requests`. Windows with a spike are not learned, so that an attack does not
+//! raise its own baseline.
+//!
+//! For `--velocity-throttle-period` after the last spike, every IP and Discord user is held to
+//! `--velocity-cooldown` between requests, and with `--bot-protection-on-spike`, web requests must
+//! pass the CAPTCHA or proof of work.
+use crate::{Cooldown, Options};
+use async_std::sync::Mutex;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fmt::{self, Display, Formatter},
+    net::IpAddr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+/// The weight of the latest window in the baselines.
+const BASELINE_WEIGHT: f64 = 0.1;
+
+/// The most idle windows learned at once, after which the baselines are practically zero anyway.
+const MAX_IDLE_WINDOWS: u32 = 100;
+
+/// What requests are counted by.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum VelocityKey {
+    /// All requests.
+    Global,
+    /// The requests from a client IP.
+    Ip(IpAddr),
+    /// The requests from a Discord user.
+    DiscordUser(u64),
+}
+
+impl VelocityKey {
+    /// The index of the baseline of this key.
+    fn scope(&self) -> usize {
+        match self {
+            Self::Global => 0,
+            Self::Ip(_) => 1,
+            Self::DiscordUser(_) => 2,
+        }
+    }
+}
+
+impl Display for VelocityKey {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Self::Global => write!(f, "all requesters"),
+            Self::Ip(ip) => write!(f, "IP {ip}"),
+            Self::DiscordUser(user) => write!(f, "Discord user {user}"),
+        }
+    }
+}
+
+/// A window in which requests exceeded their baseline.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct VelocitySpike {
+    pub key: VelocityKey,
+    /// The number of requests in the window.
+    pub requests: usize,
+    /// The baseline of the scope of `key`, rounded.
+    pub baseline: usize,
+}
+
+#[derive(Clone, Debug)]
+pub struct VelocityMonitor {
+    factor: f64,
+    window: Duration,
+    min_requests: usize,
+    throttle_period: Duration,
+    state: Arc<Mutex<VelocityState>>,
+    /// The cooldown of each IP and Discord user while throttled.
+    cooldown: Cooldown<VelocityKey>,
+}
+
+#[derive(Debug)]
+struct VelocityState {
+    window_start: Instant,
+    counts: HashMap<VelocityKey, usize>,
+    /// The baseline of each scope, once a window has been learned.
+    baselines: [Option<f64>; 3],
+    /// Whether a spike was detected in the current window.
+    spiked: bool,
+    /// The latest spike, and when it was last detected.
+    spike: Option<(VelocitySpike, Instant)>,
+}
+
+impl VelocityMonitor {
+    /// A monitor configured by `options`, or `None` if spike detection is disabled.
+    pub fn new(options: &Options) -> Option<Self> {
+        let factor = options.velocity_factor?;
+        Some(Self {
+            factor,
+            window: options.velocity_window,
+            min_requests: options.velocity_min_requests,
+            throttle_period: options.velocity_throttle_period,
+            state: Arc::new(Mutex::new(VelocityState {
+                window_start: Instant::now(),
+                counts: Default::default(),
+                baselines: Default::default(),
+                spiked: false,
+                spike: None,
+            })),
+            cooldown: Cooldown::new(options.velocity_cooldown),
+        })
+    }
+
+    /// Count a request from `key`, if the requester is known, and throttle it during a spike.
+    ///
+    /// Fails with the time until the requester may request again if it is throttled.
+    pub async fn check(&self, key: Option<VelocityKey>) -> Result<(), Duration> {
+        let now = Instant::now();
+        let throttled = {
+            let mut state = self.state.lock().await;
+            self.record(&mut state, VelocityKey::Global, now);
+            if let Some(key) = key {
+                self.record(&mut state, key, now);
+            }
+            self.spike_at(&state, now).is_some()
+        };
+        match key {
+            Some(key) if throttled => self.cooldown.start(key).await,
+            _ => Ok(()),
+        }
+    }
+
+    /// The latest spike, if the faucet is still throttled because of it.
+    pub async fn spike(&self) -> Option<VelocitySpike> {
+        self.spike_at(&*self.state.lock().await, Instant::now())
+    }
+
+    fn spike_at(&self, state: &VelocityState, now: Instant) -> Option<VelocitySpike> {
+        let (spike, detected) = state.spike?;
+        (now.duration_since(detected) < self.throttle_period).then_some(spike)
+    }
+
+    /// Count a request from `key` at `now`, and detect a spike.
+    fn record(&self, state: &mut VelocityState, key: VelocityKey, now: Instant) {
+        self.roll(state, now);
+        let requests = state.counts.entry(key).or_default();
+        *requests += 1;
+        let requests = *requests;
+
+        // Before the first window is learned, only the minimum applies.
+        let baseline = state.baselines[key.scope()];
+        if requests < self.min_requests
+            || baseline.is_some_and(|baseline| requests as f64 <= self.factor * baseline)
+        {
+            return;
+        }
+        let spike = VelocitySpike {
+            key,
+            requests,
+            baseline: baseline.unwrap_or_default().round() as usize,
+        };
+        if self.spike_at(state, now).is_none() {
+            tracing::warn!(
+                "Request spike from {key}: {requests} requests in a window, baseline {}",
+                spike.baseline
+            );
+        }
+        state.spike = Some((spike, now));
+        state.spiked = true;
+    }
+
+    /// Learn the windows which ended before `now`.
+    fn roll(&self, state: &mut VelocityState, now: Instant) {
+        let elapsed = now.duration_since(state.window_start);
+        let windows = (elapsed.as_secs_f64() / self.window.as_secs_f64()) as u32;
+        if windows == 0 {
+            return;
+        }
+        let counts = std::mem::take(&mut state.counts);
+        if !state.spiked {
+            for (scope, baseline) in state.baselines.iter_mut().enumerate() {
+                let peak = counts
+                    .iter()
+                    .filter(|(key, _)| key.scope() == scope)
+                    .map(|(_, requests)| *requests)
+                    .max()
+                    .unwrap_or(0);
+                learn(baseline, peak as f64);
+            }
+        }
+        for _ in 1..windows.min(MAX_IDLE_WINDOWS) {
+            for baseline in &mut state.baselines {
+                learn(baseline, 0.0);
+            }
+        }
+        state.spiked = false;
+        state.window_start += self.window * windows;
+    }
+}
+
+/// Update `baseline` with the count of a new window.
+fn learn(baseline: &mut Option<f64>, requests: f64) {
+    *baseline = Some(match *baseline {
+        Some(baseline) => baseline + BASELINE_WEIGHT * (requests - baseline),
+        None => requests,
+    });
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[async_std::test]
+    async fn test_velocity_spike() {
+        let options = Options {
+            velocity_factor: Some(3.0),
+            velocity_window: Duration::from_secs(60),
+            velocity_min_requests: 5,
+            ..Options::default()
+        };
+        let monitor = VelocityMonitor::new(&options).unwrap();
+        let mut state = monitor.state.lock().await;
+        let start = state.window_start;
+        let ip = VelocityKey::Ip("127.0.0.1".parse().unwrap());
+
+        // Learn a baseline of 4 requests per window from the same IP.
+        for window in 0..3 {
+            let now = start + Duration::from_secs(60 * window);
+            for _ in 0..4 {
+                monitor.record(&mut state, VelocityKey::Global, now);
+                monitor.record(&mut state, ip, now);
+            }
+        }
+        assert_eq!(monitor.spike_at(&state, start), None);
+        let now = start + Duration::from_secs(180);
+        monitor.roll(&mut state, now);
+        assert_eq!(state.baselines, [Some(4.0), Some(4.0), Some(0.0)]);
+
+        // Up to 3 times the baseline is fine.
+        for _ in 0..12 {
+            monitor.record(&mut state, ip, now);
+        }
+        assert_eq!(monitor.spike_at(&state, now), None);
+        monitor.record(&mut state, ip, now);
+        let spike = VelocitySpike {
+            key: ip,
+            requests: 13,
+            baseline: 4,
+        };
+        assert_eq!(monitor.spike_at(&state, now), Some(spike));
+
+        // The spike is not learned, and the throttle lasts after it subsides.
+        let later = now + Duration::from_secs(60);
+        monitor.roll(&mut state, later);
+        assert_eq!(state.baselines[1], Some(4.0));
+        assert_eq!(monitor.spike_at(&state, later), Some(spike));
+        assert_eq!(
+            monitor.spike_at(&state, now + options.velocity_throttle_period),
+            None
+        );
+    }
+
+    #[async_std::test]
+    async fn test_velocity_throttle() {
+        let options = Options {
+            velocity_factor: Some(3.0),
+            velocity_min_requests: 2,
+            ..Options::default()
+        };
+        let monitor = VelocityMonitor::new(&options).unwrap();
+        let user = Some(VelocityKey::DiscordUser(1));
+
+        // Requests are not throttled until the spike.
+        monitor.check(user).await.unwrap();
+        monitor.check(None).await.unwrap();
+        assert_eq!(monitor.spike().await.unwrap().key, VelocityKey::Global);
+
+        // During the spike, each requester is held to the cooldown.
+        monitor.check(user).await.unwrap();
+        monitor.check(user).await.unwrap_err();
+        monitor.check(None).await.unwrap();
+    }
+}
@@ -10,106 +10,1719 @@
 //! 1. Provide a healthcheck endpoint for the discord bot, so it can be automatically
 //!    restarted if it fails.
 //! 2. Test and use the faucet locally without connecting to Discord.
-use async_std::channel::Sender;
+//! 3. Stream faucet activity to dashboards.
+use crate::openapi::openapi_document;
+use crate::{
+    collect_metrics, AbuseSignal, ApiKeys, BanList, CaptchaVerifier, Catalog, ChainFaucet,
+    CompletedTransfer, Cooldown, CorrelationId, DiscordMetrics, DiscordWebToken, ErrorCode, Faucet,
+    FaucetError, FaucetEvent, FaucetRequest, FaucetStats, Gateway, GatewayHealth, GuildSettings,
+    Guilds, Leaderboard, LimitKeys, LiveOptions, MetaTransaction, MetricsReport, OAuth,
+    OAuthIdentity, OwnershipProof, ProofOfWork, RateLimiter, RequestId, RequestSource,
+    RequestStatus, Requester, Sample, SelfTest, SessionRequest, SharedCooldownStore, SharedStorage,
+    StatsdExporter, Token, TopUp, TopUpList, VelocityKey, WebTokens, MAX_HISTORY,
+};
+use async_std::channel::{Sender, TrySendError};
 use async_std::sync::RwLock;
-use ethers::types::Address;
-use futures::FutureExt;
+use async_std::task::sleep;
+use ethers::types::{Address, H256, U256};
+use futures::{future::ready, stream, FutureExt, StreamExt, TryFutureExt};
 use serde::{Deserialize, Serialize};
+#[cfg(feature = "discord")]
+use serenity::prelude::Context;
+use std::borrow::Cow;
+use std::collections::HashMap;
 use std::env;
+use std::fs;
+use std::future::Future;
 use std::io;
-use thiserror::Error;
-use tide_disco::RequestError;
-use tide_disco::{http::StatusCode, Api, App, Error};
-
-#[derive(Clone, Debug, Deserialize, Serialize, Error)]
-pub enum FaucetError {
-    #[error("faucet error {status}: {msg}")]
-    FaucetError { status: StatusCode, msg: String },
-    #[error("unable to parse Ethereum address: {input}")]
-    BadAddress { status: StatusCode, input: String },
+use std::net::{IpAddr, SocketAddr};
+use std::os::unix::fs::FileTypeExt;
+use std::path::Path;
+#[cfg(feature = "discord")]
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use std::time::Duration;
+use tide_disco::{http::StatusCode, Api, App, RequestParams};
+
+/// Maximum number of addresses in a batch request.
+pub const MAX_BATCH_SIZE: usize = 100;
+
+/// How often to check whether a request served by another instance of the shared queue is
+/// confirmed.
+const SHARED_COMPLETION_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// The response to a faucet request.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct QueuedRequest {
+    pub id: RequestId,
+    /// Ties the logs related to this request together, for support.
+    pub correlation_id: CorrelationId,
+    /// The expected time until the transfer is mined.
+    pub eta_secs: u64,
+    /// Cancels the request, in the `X-Cancel-Token` header of the cancel route.
+    pub cancel_token: H256,
+}
+
+/// The response to a dual-layer faucet request.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct DualQueuedRequest {
+    /// The grant on the default chain.
+    pub l1: QueuedRequest,
+    /// The grant on `--dual-layer-chain`, or `None` if it could not be queued once the L1 grant was
+    /// already being sent.
+    pub rollup: Option<QueuedRequest>,
+}
+
+/// The response of the deep healthcheck.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct DeepHealth {
+    pub faucet: FaucetStats,
+    /// The connection state of the Discord bot, if it is enabled.
+    pub discord: Option<GatewayHealth>,
+}
+
+/// The ID of the request made for each address of a batch request.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct BatchRequestId {
+    pub address: Address,
+    pub id: RequestId,
+    pub correlation_id: CorrelationId,
+    /// The expected time until the transfer is mined.
+    pub eta_secs: u64,
+    /// Cancels the request, in the `X-Cancel-Token` header of the cancel route.
+    pub cancel_token: H256,
+}
+
+pub(crate) async fn serve(port: u16, state: WebState) -> io::Result<()> {
+    app(state)?.serve(format!("0.0.0.0:{}", port)).await
+}
+
+/// Serve the API on a Unix domain socket at `path` instead of a TCP port.
+pub(crate) async fn serve_unix(path: &Path, state: WebState) -> io::Result<()> {
+    // A socket file left behind by a previous run would make binding fail.
+    if fs::symlink_metadata(path).is_ok_and(|metadata| metadata.file_type().is_socket()) {
+        fs::remove_file(path)?;
+    }
+    app(state)?
+        .serve(format!("http+unix://{}", path.display()))
+        .await
+}
+
+fn app(state: WebState) -> io::Result<App<RwLock<WebState>, FaucetError>> {
+    let mut app = App::<_, FaucetError>::with_state(RwLock::new(state));
+    app.with_version(env!("CARGO_PKG_VERSION").parse().unwrap());
+
+    // Breaking changes to the API must be introduced in a new module (`v2`), leaving the existing
+    // modules unchanged.
+    app.register_module("v1", define_api("v1")?).unwrap();
+    // The unversioned paths predate `v1` and are kept as aliases for existing integrations.
+    app.register_module("faucet", define_api("faucet")?)
+        .unwrap();
+
+    Ok(app)
+}
+
+/// Define the routes of version 1 of the API, to be registered as `module`.
+fn define_api(module: &str) -> io::Result<Api<RwLock<WebState>, FaucetError>> {
+    // Include API specification in binary
+    let toml = toml::from_str::<toml::value::Value>(include_str!("api.toml"))
+        .map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
+
+    let openapi = openapi_document(module, &toml);
+
+    let mut api = Api::<RwLock<WebState>, FaucetError>::new(toml).unwrap();
+    api.with_version(env!("CARGO_PKG_VERSION").parse().unwrap());
+
+    // Can invoke with
+    //    `curl http://0.0.0.0:8111/v1/openapi.json`
+    api.get("openapi", move |_req, _state| {
+        let openapi = openapi.clone();
+        async move { Ok(openapi) }.boxed()
+    })
+    .unwrap();
+
+    // Can invoke with
+    //    `curl -i -X POST http://0.0.0.0:8111/v1/request/0x1234567890123456789012345678901234567890`
+    // or, to request an ERC-20 token,
+    //    `curl -i -X POST http://0.0.0.0:8111/v1/request/0x1234567890123456789012345678901234567890/usdc`
+    api.post("request", |req, state| {
+        request_funds(req, state, Grant::Once).boxed()
+    })
+    .unwrap();
+
+    // Can invoke with
+    //    `curl -i -X POST http://0.0.0.0:8111/v1/drip/0x1234567890123456789012345678901234567890`
+    api.post("drip", |req, state| {
+        request_funds(req, state, Grant::Drip).boxed()
+    })
+    .unwrap();
+
+    // Can invoke with
+    //    `curl -i -X POST http://0.0.0.0:8111/v1/dual/0x1234567890123456789012345678901234567890`
+    api.post("dual", |req, state| {
+        async move {
+            let rollup = state.dual_layer_chain()?;
+            let address = req.string_param("address")?;
+            let address: Address = address
+                .parse()
+                .map_err(|_| FaucetError::bad_address(address))?;
+            let request = FaucetRequest::new(address, None).with_source(RequestSource::Web);
+            let l1 = request_funds(req, state, Grant::Once);
+            WebState::submit_dual(&state.faucet, l1, rollup, request).await
+        }
+        .boxed()
+    })
+    .unwrap();
+
+    // Can invoke with
+    //    `curl -i -X POST http://0.0.0.0:8111/v1/deposit/0x1234567890123456789012345678901234567890`
+    api.post("deposit", |req, state| {
+        request_funds(req, state, Grant::Deposit).boxed()
+    })
+    .unwrap();
+
+    // Can invoke with
+    //    `curl -i -X POST -H 'Content-Type: application/json' -d '{"from": "0x...", "to": "0x...", "gas": "0x30d40", "nonce": "0x0", "data": "0x...", "signature": "0x..."}' http://0.0.0.0:8111/v1/relay`
+    api.post("relay", |req, state| {
+        async move {
+            let relay = req.body_json::<MetaTransaction>()?;
+            request_funds(req, state, Grant::Relay(relay)).await
+        }
+        .boxed()
+    })
+    .unwrap();
+
+    // Can invoke with
+    //    `curl -X POST -H 'Content-Type: application/json' -d '["0x1234567890123456789012345678901234567890"]' http://0.0.0.0:8111/v1/request/batch`
+    api.post("request_batch", |req, state| {
+        async move {
+            state.check_web_mode()?;
+            let addresses = req.body_json::<Vec<Address>>()?;
+            if addresses.is_empty() || addresses.len() > MAX_BATCH_SIZE {
+                return Err(FaucetError::new(
+                    ErrorCode::BadRequest,
+                    StatusCode::BadRequest,
+                    format!("batch must contain between 1 and {MAX_BATCH_SIZE} addresses"),
+                ));
+            }
+            if state.ownership.is_some() {
+                return Err(FaucetError::new(
+                    ErrorCode::OwnershipProofFailed,
+                    StatusCode::Forbidden,
+                    "batch requests are not available when ownership proofs are required",
+                ));
+            }
+            let token = req
+                .opt_string_param("token")?
+                .map(|symbol| state.token(symbol))
+                .transpose()?;
+            // All the requests of a batch share a correlation ID.
+            let correlation_id = correlation_id(&req)?;
+            tracing::info!(
+                %correlation_id,
+                "Received batch faucet request for {} addresses {:?}",
+                addresses.len(),
+                token
+            );
+            let delay = state.check_velocity(&req).await?;
+            let api_key = state.api_key(&req)?;
+            // A single CAPTCHA, proof of work or OAuth login must not unlock a whole batch of
+            // grants, so batches then need an API key, which is charged per address.
+            if api_key.is_none()
+                && (state.captcha.is_some() || state.pow.is_some() || state.oauth.is_some())
+            {
+                return Err(FaucetError::unauthorized(
+                    "batch requests require an API key when bot protection or OAuth is enabled",
+                ));
+            }
+            // The requests of partners are queued in order, like those of a single requester.
+            let requester = match api_key {
+                Some(_) => None,
+                None => client_ip(&req).map(Requester::Ip),
+            };
+            let mut delays = vec![delay; addresses.len()];
+            if api_key.is_none() {
+                let ip = client_ip(&req);
+                for (address, delay) in addresses.iter().zip(&mut delays) {
+                    let limited = state
+                        .check_rate_limits(LimitKeys {
+                            ip,
+                            address: Some(*address),
+                            user: None,
+                        })
+                        .await?;
+                    *delay = (*delay).max(limited);
+                }
+            }
+            let count = addresses.len();
+            if let Some(key) = api_key {
+                state.charge_api_key(key, count, &token).await?;
+            }
+            let mut ids = vec![];
+            for (address, delay) in addresses.into_iter().zip(delays) {
+                let request = FaucetRequest::new(address, token.clone())
+                    .with_correlation_id(correlation_id)
+                    .with_delay(delay)
+                    .with_requester(requester)
+                    .with_source(RequestSource::Web);
+                let QueuedRequest {
+                    id,
+                    eta_secs,
+                    cancel_token,
+                    ..
+                } = match state.request(request).await {
+                    Ok(queued) => queued,
+                    Err(err) => {
+                        // The requests queued so far are served, the others are not charged.
+                        if let Some(key) = api_key {
+                            state.refund_api_key(key, count - ids.len(), &token).await;
+                        }
+                        return Err(err);
+                    }
+                };
+                ids.push(BatchRequestId {
+                    address,
+                    id,
+                    correlation_id,
+                    eta_secs,
+                    cancel_token,
+                });
+            }
+            Ok(ids)
+        }
+        .boxed()
+    })
+    .unwrap();
+
+    // Can invoke with
+    //    `curl http://0.0.0.0:8111/v1/status/<request_id>`
+    api.get("status", |req, state| {
+        async move {
+            let input = req.string_param("request_id")?;
+            let id = input
+                .parse()
+                .map_err(|_| FaucetError::bad_request_id(input))?;
+            state.request_status(id).await.ok_or_else(|| {
+                FaucetError::new(
+                    ErrorCode::NotFound,
+                    StatusCode::NotFound,
+                    format!("unknown request {id}"),
+                )
+            })
+        }
+        .boxed()
+    })
+    .unwrap();
+
+    // Can invoke with
+    //    `curl -X DELETE -H 'X-Cancel-Token: ...' http://0.0.0.0:8111/v1/request/<request_id>`
+    api.delete("cancel", |req, state| {
+        async move {
+            let input = req.string_param("request_id")?;
+            let id = input
+                .parse()
+                .map_err(|_| FaucetError::bad_request_id(input))?;
+            let token = header(&req, "X-Cancel-Token").and_then(|token| token.parse::<H256>().ok());
+            if token != Some(state.faucet.cancel_token(id)) {
+                return Err(FaucetError::unauthorized(
+                    "missing or invalid X-Cancel-Token header",
+                ));
+            }
+            Ok(state.faucet.cancel_request(id).await)
+        }
+        .boxed()
+    })
+    .unwrap();
+
+    // Can invoke with
+    //    `curl http://0.0.0.0:8111/v1/history/0x1234567890123456789012345678901234567890`
+    api.get("history", |req, state| {
+        async move {
+            let address = req.string_param("address")?;
+            let address = address
+                .parse()
+                .map_err(|_| FaucetError::bad_address(address))?;
+            let Some(storage) = &state.storage else {
+                return Err(FaucetError::not_enabled("grant history"));
+            };
+            storage.history(address, MAX_HISTORY).await.map_err(|err| {
+                FaucetError::new(
+                    ErrorCode::Internal,
+                    StatusCode::InternalServerError,
+                    format!("failed to load the history: {err:#}"),
+                )
+            })
+        }
+        .boxed()
+    })
+    .unwrap();
+
+    // Can invoke with
+    //    `curl http://0.0.0.0:8111/v1/challenge`
+    api.get("challenge", |_req, state| {
+        async move {
+            let Some(pow) = &state.pow else {
+                return Err(FaucetError::not_enabled("proof of work"));
+            };
+            pow.challenge().await.ok_or_else(|| {
+                FaucetError::unavailable("too many outstanding challenges, try again later")
+            })
+        }
+        .boxed()
+    })
+    .unwrap();
+
+    // Can invoke with
+    //    `curl http://0.0.0.0:8111/v1/ownership/0x1234567890123456789012345678901234567890`
+    api.get("ownership", |req, state| {
+        async move {
+            let address = req.string_param("address")?;
+            let address = address
+                .parse()
+                .map_err(|_| FaucetError::bad_address(address))?;
+            let Some(ownership) = &state.ownership else {
+                return Err(FaucetError::not_enabled("ownership proofs"));
+            };
+            ownership.challenge(address).await.ok_or_else(|| {
+                FaucetError::unavailable("too many outstanding challenges, try again later")
+            })
+        }
+        .boxed()
+    })
+    .unwrap();
+
+    // Can invoke with
+    //    `curl http://0.0.0.0:8111/v1/oauth/login`
+    api.get("oauth_login", |_req, state| {
+        async move {
+            state.oauth()?.login().await.ok_or_else(|| {
+                FaucetError::unavailable("too many logins in progress, try again later")
+            })
+        }
+        .boxed()
+    })
+    .unwrap();
+
+    // Can invoke with
+    //    `curl -X POST -H 'Content-Type: application/json' -d '{"code":"...","state":"0x..."}' http://0.0.0.0:8111/v1/oauth/session`
+    api.post("oauth_session", |req, state| {
+        async move {
+            let request = req.body_json::<SessionRequest>()?;
+            state
+                .oauth()?
+                .create_session(&request)
+                .await
+                .map_err(|err| FaucetError::unauthorized(format!("login failed: {err:#}")))
+        }
+        .boxed()
+    })
+    .unwrap();
+
+    // Can invoke with
+    //    `curl -H 'X-Admin-Token: ...' http://0.0.0.0:8111/v1/admin/api-keys`
+    api.get("api_keys", |req, state| {
+        async move {
+            state.verify_admin(&req)?;
+            Ok(state.api_keys.usage().await)
+        }
+        .boxed()
+    })
+    .unwrap();
+
+    // Can invoke with
+    //    `curl http://0.0.0.0:8111/v1/healthcheck/deep`
+    api.get("deep_healthcheck", |_req, state| {
+        async move {
+            let faucet =
+                state.faucet.stats().await.map_err(|err| {
+                    FaucetError::unavailable(format!("chain unreachable: {err:#}"))
+                })?;
+            let discord = match &state.gateway {
+                Some(gateway) => Some(gateway.health().await),
+                None => None,
+            };
+            if let Some(GatewayHealth {
+                disconnected_secs: Some(secs),
+                ..
+            }) = &discord
+            {
+                return Err(FaucetError::unavailable(format!(
+                    "Discord bot disconnected for {secs} seconds"
+                )));
+            }
+            Ok(DeepHealth { faucet, discord })
+        }
+        .boxed()
+    })
+    .unwrap();
+
+    // Can invoke with
+    //    `curl http://0.0.0.0:8111/v1/readiness`
+    api.get("readiness", |_req, state| {
+        async move {
+            if state.faucet.is_ready().await {
+                Ok(true)
+            } else {
+                Err(FaucetError::unavailable(
+                    "the faucet is starting or on standby",
+                ))
+            }
+        }
+        .boxed()
+    })
+    .unwrap();
+
+    // Can invoke with
+    //    `curl http://0.0.0.0:8111/v1/metrics`
+    api.metrics("metrics", |_req, state| {
+        async move { Ok(Cow::Owned(MetricsReport::new(&state.metrics().await))) }.boxed()
+    })
+    .unwrap();
+
+    // Can invoke with
+    //    `curl -H 'X-Admin-Token: ...' http://0.0.0.0:8111/v1/admin/clusters`
+    api.get("clusters", |req, state| {
+        async move {
+            state.verify_admin(&req)?;
+            Ok(state.faucet.sybil_clusters().await)
+        }
+        .boxed()
+    })
+    .unwrap();
+
+    // Can invoke with
+    //    `curl -H 'X-Admin-Token: ...' http://0.0.0.0:8111/v1/admin/quarantine`
+    api.get("quarantine", |req, state| {
+        async move {
+            state.verify_admin(&req)?;
+            Ok(state.faucet.quarantined().await)
+        }
+        .boxed()
+    })
+    .unwrap();
+
+    // Can invoke with
+    //    `curl -X POST -H 'X-Admin-Token: ...' http://0.0.0.0:8111/v1/admin/reload`
+    api.post("reload", |req, state| {
+        async move {
+            state.verify_admin(&req)?;
+            state.reload().await.map_err(|err| {
+                FaucetError::new(
+                    ErrorCode::BadRequest,
+                    StatusCode::BadRequest,
+                    format!("{err:#}"),
+                )
+            })
+        }
+        .boxed()
+    })
+    .unwrap();
+
+    // Can invoke with
+    //    `curl -X POST -H 'X-Kill-Switch-Token: ...' http://0.0.0.0:8111/v1/admin/kill-switch`
+    api.post("engage_kill_switch", |req, state| {
+        async move {
+            state.verify_kill_switch(&req)?;
+            state.faucet.engage_kill_switch();
+            Ok(state.faucet.is_killed())
+        }
+        .boxed()
+    })
+    .unwrap();
+
+    // Can invoke with
+    //    `curl -X DELETE -H 'X-Kill-Switch-Token: ...' http://0.0.0.0:8111/v1/admin/kill-switch`
+    api.delete("release_kill_switch", |req, state| {
+        async move {
+            state.verify_kill_switch(&req)?;
+            state.faucet.release_kill_switch();
+            Ok(state.faucet.is_killed())
+        }
+        .boxed()
+    })
+    .unwrap();
+
+    // Can invoke with
+    //    `curl -H 'X-Admin-Token: ...' http://0.0.0.0:8111/v1/admin/bans`
+    api.get("bans", |req, state| {
+        async move {
+            state.verify_admin(&req)?;
+            Ok(state.bans.list().await)
+        }
+        .boxed()
+    })
+    .unwrap();
+
+    // Can invoke with
+    //    `curl -X POST -H 'X-Discord-Token: ...' http://0.0.0.0:8111/v1/top-up/0x1234567890123456789012345678901234567890`
+    api.post("top_up", |req, state| {
+        async move {
+            let address = req.string_param("address")?;
+            let address = address
+                .parse()
+                .map_err(|_| FaucetError::bad_address(address))?;
+            let owner = state.verify_top_up_owner(&req).await?;
+            let user = owner.as_ref().map(|owner| owner.user);
+            if state.top_ups.get(address).await.is_some() {
+                return Ok(false);
+            }
+            if let Some(user) = user {
+                let max = state.faucet.config().max_top_ups_per_user;
+                if state.top_ups.count_by(user).await >= max {
+                    return Err(FaucetError::new(
+                        ErrorCode::QuotaExceeded,
+                        StatusCode::Forbidden,
+                        format!("a Discord user can register at most {max} addresses for top-ups"),
+                    ));
+                }
+            }
+            state
+                .top_ups
+                .register(TopUp::new(address, owner))
+                .await
+                .map_err(|err| {
+                    tracing::error!("Failed to register {address:?} for top-ups: {err:#}");
+                    FaucetError::unavailable("failed to save the registration")
+                })?;
+            Ok(true)
+        }
+        .boxed()
+    })
+    .unwrap();
+
+    // Can invoke with
+    //    `curl -X DELETE -H 'X-Discord-Token: ...' http://0.0.0.0:8111/v1/top-up/0x1234567890123456789012345678901234567890`
+    api.delete("cancel_top_up", |req, state| {
+        async move {
+            let address = req.string_param("address")?;
+            let address = address
+                .parse()
+                .map_err(|_| FaucetError::bad_address(address))?;
+            let user = state
+                .verify_top_up_owner(&req)
+                .await?
+                .map(|owner| owner.user);
+            let Some(top_up) = state.top_ups.get(address).await else {
+                return Ok(false);
+            };
+            // Users can only cancel their own registrations.
+            if user.is_some() && top_up.registered_by != user {
+                return Err(FaucetError::unauthorized(
+                    "the address was registered by another user",
+                ));
+            }
+            state.top_ups.unregister(address).await.map_err(|err| {
+                tracing::error!("Failed to unregister {address:?} from top-ups: {err:#}");
+                FaucetError::unavailable("failed to save the registration")
+            })
+        }
+        .boxed()
+    })
+    .unwrap();
+
+    // Can invoke with
+    //    `curl -H 'X-Admin-Token: ...' http://0.0.0.0:8111/v1/admin/top-ups`
+    api.get("top_ups", |req, state| {
+        async move {
+            state.verify_admin(&req)?;
+            Ok(state.top_ups.list().await)
+        }
+        .boxed()
+    })
+    .unwrap();
+
+    // Can subscribe with
+    //    `websocat ws://0.0.0.0:8111/v1/events`
+    api.stream("events", |_req, state| {
+        async move {
+            let events = state.read().await.faucet.events().subscribe().await;
+            Ok(events.map(Ok))
+        }
+        .try_flatten_stream()
+        .boxed()
+    })
+    .unwrap();
+
+    // Can subscribe with
+    //    `websocat ws://0.0.0.0:8111/v1/await/<request_id>`
+    api.stream("await", |req, state| {
+        async move {
+            let input = req.string_param("request_id")?;
+            let id = input
+                .parse()
+                .map_err(|_| FaucetError::bad_request_id(input))?;
+            let faucet = state.read().await.faucet.clone();
+            await_transfer(faucet, id).await
+        }
+        .try_flatten_stream()
+        .boxed()
+    })
+    .unwrap();
+
+    Ok(api)
+}
+
+/// What a web request for funds asks for.
+enum Grant {
+    /// A grant to the address in the path.
+    Once,
+    /// A grant to the address in the path, paid in installments.
+    Drip,
+    /// A grant deposited in the ERC-4337 entry point for the address in the path.
+    Deposit,
+    /// The relay of a meta-transaction, on behalf of its signer.
+    Relay(MetaTransaction),
+}
+
+/// Handle a web request for funds.
+async fn request_funds(
+    req: RequestParams,
+    state: &WebState,
+    grant: Grant,
+) -> Result<QueuedRequest, FaucetError> {
+    state.check_web_mode()?;
+    if matches!(grant, Grant::Deposit) && state.faucet.config().entry_point.is_none() {
+        return Err(FaucetError::not_enabled("deposits"));
+    }
+    let address = match &grant {
+        Grant::Relay(relay) => {
+            state.faucet.verify_relay(relay).await?;
+            relay.from
+        }
+        _ => {
+            let address = req.string_param("address")?;
+            address
+                .parse()
+                .map_err(|_| FaucetError::bad_address(address))?
+        }
+    };
+    let token = req
+        .opt_string_param("token")?
+        .map(|symbol| state.token(symbol))
+        .transpose()?;
+    if matches!(grant, Grant::Drip) && token.is_some() {
+        return Err(FaucetError::new(
+            ErrorCode::BadRequest,
+            StatusCode::BadRequest,
+            "drip grants are only available for the native currency",
+        ));
+    }
+    let correlation_id = correlation_id(&req)?;
+    tracing::info!(
+        %correlation_id,
+        "Received faucet request for {:?} {:?}",
+        address,
+        token
+    );
+    let mut delay = state.check_velocity(&req).await?;
+    let api_key = state.api_key(&req)?;
+    let discord = state.discord_web_token(&req)?;
+    let identity = match (api_key, discord) {
+        // Requests from partners are authenticated by their API key.
+        (Some(_), _) => None,
+        // Requests with a Discord token are authenticated by the token.
+        (None, Some(_)) => {
+            state.verify_bot_protection(&req, address).await?;
+            None
+        }
+        (None, None) => {
+            let identity = state.authenticate(&req).await?;
+            state.verify_bot_protection(&req, address).await?;
+            identity
+        }
+    };
+    state.verify_ownership(&req, address).await?;
+    // Partners send the requests of many users, so they are only limited by their quota.
+    if api_key.is_none() {
+        let limited = state
+            .check_rate_limits(LimitKeys {
+                ip: client_ip(&req),
+                address: Some(address),
+                user: discord.map(|discord| discord.user),
+            })
+            .await?;
+        delay = delay.max(limited);
+    }
+    if let Some(key) = api_key {
+        state.charge_api_key(key, 1, &token).await?;
+    }
+    if let Some(identity) = identity.clone() {
+        state.start_oauth_cooldown(identity).await?;
+    }
+    let (requester, source) = match (api_key, discord) {
+        (Some(_), _) => (None, RequestSource::Web),
+        (None, Some(discord)) => (Some(Requester::Discord(discord.user)), discord.source()),
+        (None, None) => (client_ip(&req).map(Requester::Ip), RequestSource::Web),
+    };
+    let mut request = FaucetRequest::new(address, token.clone())
+        .with_correlation_id(correlation_id)
+        .with_delay(delay)
+        .with_requester(requester)
+        .with_source(source);
+    request = match grant {
+        Grant::Once => request,
+        Grant::Drip => request.with_drip(),
+        Grant::Deposit => request.with_deposit(),
+        Grant::Relay(relay) => request.with_relay(relay),
+    };
+    let result = match discord {
+        Some(discord) if api_key.is_none() => state.discord_web_request(discord, request).await,
+        _ => state.request(request).await,
+    };
+    // Requests which are not served are not charged.
+    if result.is_err() {
+        state.roll_back(api_key, identity, &token).await;
+    }
+    result
+}
+
+/// The last value of the header `name`, if present.
+fn header<'a>(req: &'a RequestParams, name: &'static str) -> Option<&'a str> {
+    req.header(name).map(|values| values.last().as_str())
+}
+
+/// The IP of the client, as reported by the reverse proxy in the `X-Forwarded-For` header, or else
+/// the address of the peer.
+///
+/// The last `X-Forwarded-For` entry is trusted, as the one appended by the proxy in front of the
+/// faucet. Without a proxy, clients could choose their IP by setting the header themselves, so the
+/// API must only be exposed through one.
+fn client_ip(req: &RequestParams) -> Option<IpAddr> {
+    // The proxy appends the address it received the request from.
+    let forwarded = header(req, "X-Forwarded-For")
+        .and_then(|forwarded| forwarded.rsplit(',').next())
+        .and_then(|ip| ip.trim().parse().ok());
+    forwarded.or_else(|| {
+        let remote = req.remote()?;
+        // The peer address comes with a port.
+        remote
+            .parse::<SocketAddr>()
+            .map(|addr| addr.ip())
+            .or_else(|_| remote.parse())
+            .ok()
+    })
+}
+
+/// The correlation ID passed by the client in the `X-Correlation-Id` header, or a new one.
+fn correlation_id(req: &RequestParams) -> Result<CorrelationId, FaucetError> {
+    match header(req, "X-Correlation-Id") {
+        Some(input) => input.parse().map_err(|_| {
+            FaucetError::new(
+                ErrorCode::BadRequest,
+                StatusCode::BadRequest,
+                format!("invalid X-Correlation-Id {input}, expected up to 32 hex digits"),
+            )
+        }),
+        None => Ok(CorrelationId::random()),
+    }
+}
+
+/// A stream which yields a single message once the request `id` has been mined.
+pub(crate) async fn await_transfer(
+    faucet: Faucet,
+    id: RequestId,
+) -> Result<stream::BoxStream<'static, Result<CompletedTransfer, FaucetError>>, FaucetError> {
+    // Subscribe before checking for a completed transfer, so that we cannot miss a confirmation
+    // which happens in between.
+    let events = faucet.events().subscribe().await;
+    if let Some(completed) = faucet.completed_transfer(id).await {
+        return Ok(stream::once(ready(Ok(completed))).boxed());
+    }
+    // Requests in the shared queue may be confirmed by another instance, which only records them
+    // in the shared queue.
+    let shared = stream::unfold(faucet.clone(), move |faucet| async move {
+        if !faucet.has_shared_queue() {
+            return None;
+        }
+        sleep(SHARED_COMPLETION_POLL_INTERVAL).await;
+        let completed = faucet.completed_transfer(id).await;
+        Some((completed, faucet))
+    })
+    .filter_map(|completed| ready(completed.map(Ok)));
+    let confirmed = events.filter_map(move |event| {
+        ready(match event {
+            FaucetEvent::TransferConfirmed {
+                request,
+                tx_hash,
+                block_number,
+            } if request.id() == Some(id) => Some(Ok(CompletedTransfer {
+                tx_hash,
+                block_number,
+            })),
+            _ => None,
+        })
+    });
+    Ok(stream::select(confirmed, shared).take(1).boxed())
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct WebState {
+    pub(crate) faucet_queue: Sender<FaucetRequest>,
+    pub(crate) faucet: Faucet,
+    captcha: Option<CaptchaVerifier>,
+    pow: Option<ProofOfWork>,
+    ownership: Option<OwnershipProof>,
+    oauth: Option<OAuth>,
+    oauth_cooldown: Cooldown<OAuthIdentity>,
+    /// The `--rate-limit` rules over the client IP, the recipient address and the Discord user.
+    pub(crate) rate_limiter: RateLimiter,
+    pub(crate) api_keys: ApiKeys,
+    pub(crate) guilds: Guilds,
+    /// The last address each Discord user requested funds to.
+    pub(crate) discord_addresses: Arc<RwLock<HashMap<u64, Address>>>,
+    /// The recent returns and grants, for `/leaderboard`.
+    pub(crate) leaderboard: Leaderboard,
+    /// The latest context of each Discord gateway shard, whose presence is being updated.
+    ///
+    /// The context is replaced when the client is restarted.
+    #[cfg(feature = "discord")]
+    pub(crate) shard_contexts: Arc<RwLock<HashMap<u64, Context>>>,
+    /// Whether the Discord bot is posting alerts and the audit log.
+    #[cfg(feature = "discord")]
+    pub(crate) posting_started: Arc<AtomicBool>,
+    /// Signs the tokens linking web requests to Discord users.
+    pub(crate) web_tokens: WebTokens,
+    /// The Discord users banned from the faucet.
+    pub(crate) bans: BanList,
+    /// The addresses registered for automatic top-ups.
+    pub(crate) top_ups: TopUpList,
+    /// The address registered by each Discord user.
+    pub(crate) discord_registrations: Arc<RwLock<HashMap<u64, Address>>>,
+    /// The cooldown between changes of the registered address of a Discord user.
+    #[cfg(feature = "discord")]
+    pub(crate) registration_cooldown: Cooldown<u64>,
+    /// The Discord users who made the pending requests, credited when a grant is posted in the
+    /// audit log.
+    pub(crate) discord_requesters: Arc<RwLock<HashMap<RequestId, u64>>>,
+    /// The replies of the Discord bot.
+    pub(crate) catalog: Catalog,
+    /// The connection state of the Discord bot, or `None` if it is disabled.
+    pub(crate) gateway: Option<Gateway>,
+    /// Counters of the interactions with the Discord bot.
+    pub(crate) discord_metrics: DiscordMetrics,
+    /// The options which can be reloaded while the faucet runs.
+    pub(crate) live_options: LiveOptions,
+    /// The outcome of the startup self-test.
+    pub(crate) self_test: SelfTest,
+    /// Where the grant history, the Discord registrations and the cooldowns are persisted, if
+    /// anywhere.
+    pub(crate) storage: Option<SharedStorage>,
 }
 
-impl tide_disco::Error for FaucetError {
-    fn catch_all(status: StatusCode, msg: String) -> Self {
-        Self::FaucetError { status, msg }
+impl WebState {
+    pub fn new(faucet_queue: Sender<FaucetRequest>, faucet: Faucet) -> Self {
+        let config = faucet.config();
+        let captcha = config
+            .captcha_provider
+            .zip(config.captcha_secret.clone())
+            .map(|(provider, secret)| CaptchaVerifier::new(provider, secret));
+        let pow = config.pow_difficulty.map(ProofOfWork::new);
+        let ownership = config.require_ownership_proof.then(OwnershipProof::default);
+        let oauth = match (
+            config.oauth_provider,
+            &config.oauth_client_id,
+            &config.oauth_client_secret,
+            &config.oauth_redirect_url,
+        ) {
+            (Some(provider), Some(client_id), Some(client_secret), Some(redirect_url)) => {
+                Some(OAuth::new(
+                    provider,
+                    client_id.clone(),
+                    client_secret.clone(),
+                    redirect_url.clone(),
+                ))
+            }
+            _ => None,
+        };
+        let oauth_cooldown = Cooldown::new(config.oauth_cooldown);
+        let web_tokens = WebTokens::new(
+            config.discord_web_token_secret,
+            config.discord_web_token_ttl,
+        );
+        let api_keys = ApiKeys::new(config.api_keys.clone());
+        Self {
+            faucet_queue,
+            faucet,
+            captcha,
+            pow,
+            ownership,
+            oauth,
+            oauth_cooldown,
+            rate_limiter: RateLimiter::new(config.rate_limits.clone()),
+            api_keys,
+            guilds: Guilds::default(),
+            discord_addresses: Default::default(),
+            leaderboard: Leaderboard::new(config.discord_leaderboard_period),
+            #[cfg(feature = "discord")]
+            shard_contexts: Default::default(),
+            #[cfg(feature = "discord")]
+            posting_started: Default::default(),
+            web_tokens,
+            bans: BanList::default(),
+            top_ups: TopUpList::default(),
+            discord_registrations: Default::default(),
+            #[cfg(feature = "discord")]
+            registration_cooldown: Cooldown::new(config.discord_registration_cooldown),
+            discord_requesters: Default::default(),
+            catalog: Catalog::default(),
+            gateway: None,
+            discord_metrics: DiscordMetrics::default(),
+            live_options: LiveOptions::from(config.clone()),
+            self_test: SelfTest::default(),
+            storage: None,
+        }
+    }
+
+    /// Serve the grant history from `storage`.
+    pub fn with_storage(mut self, storage: SharedStorage) -> Self {
+        self.storage = Some(storage);
+        self
+    }
+
+    /// Load the addresses registered by Discord users from the storage, if any.
+    pub async fn load_registrations(&self) -> anyhow::Result<()> {
+        if let Some(storage) = &self.storage {
+            let registrations = storage.registrations().await?;
+            *self.discord_registrations.write().await = registrations;
+        }
+        Ok(())
+    }
+
+    /// Persist the cooldowns and the rate limits of the web and Discord front-ends in `store`.
+    pub fn with_cooldown_store(mut self, store: SharedCooldownStore) -> Self {
+        self.oauth_cooldown = self.oauth_cooldown.with_storage(store.clone(), "oauth");
+        self.rate_limiter = self.rate_limiter.with_storage(store.clone());
+        #[cfg(feature = "discord")]
+        {
+            self.registration_cooldown = self
+                .registration_cooldown
+                .with_storage(store, "discord:registration");
+        }
+        self
+    }
+
+    /// Serve Discord guilds according to `guilds`.
+    pub fn with_guilds(mut self, guilds: Guilds) -> Self {
+        self.guilds = guilds;
+        self
+    }
+
+    /// Enforce the Discord bans in `bans`.
+    pub fn with_bans(mut self, bans: BanList) -> Self {
+        self.bans = bans;
+        self
+    }
+
+    /// Save the addresses registered for top-ups in `top_ups`.
+    pub fn with_top_ups(mut self, top_ups: TopUpList) -> Self {
+        self.top_ups = top_ups;
+        self
+    }
+
+    /// Apply the options in `options`, which can be reloaded while the faucet runs.
+    pub fn with_live_options(mut self, options: LiveOptions) -> Self {
+        self.live_options = options;
+        self
+    }
+
+    /// Report the connection state of the Discord bot in the deep healthcheck.
+    pub fn with_gateway(mut self, gateway: Gateway) -> Self {
+        self.gateway = Some(gateway);
+        self
+    }
+
+    /// Reply on Discord with the messages in `catalog`.
+    pub fn with_catalog(mut self, catalog: Catalog) -> Self {
+        self.catalog = catalog;
+        self
+    }
+
+    /// Fail if this process does not serve requests for funds over HTTP.
+    fn check_web_mode(&self) -> Result<(), FaucetError> {
+        if self.faucet.config().mode.serves_web() {
+            Ok(())
+        } else {
+            Err(FaucetError::not_enabled("web requests"))
+        }
+    }
+
+    /// Look up a configured token by symbol.
+    pub(crate) fn token(&self, symbol: &str) -> Result<Token, FaucetError> {
+        let tokens = self.faucet.tokens();
+        tokens
+            .get(symbol)
+            .cloned()
+            .ok_or_else(|| FaucetError::unknown_token(symbol, &tokens.symbols()))
+    }
+
+    fn oauth(&self) -> Result<&OAuth, FaucetError> {
+        self.oauth
+            .as_ref()
+            .ok_or_else(|| FaucetError::not_enabled("OAuth login"))
+    }
+
+    /// The API key of a web request, if it carries one.
+    fn api_key<'a>(&self, req: &'a RequestParams) -> Result<Option<&'a str>, FaucetError> {
+        let Some(key) = header(req, "X-Api-Key") else {
+            return Ok(None);
+        };
+        if self.api_keys.get(key).is_none() {
+            return Err(FaucetError::unauthorized("unknown API key"));
+        }
+        Ok(Some(key))
+    }
+
+    /// Charge `count` grants of `token` to the quotas of an API key.
+    pub(crate) async fn charge_api_key(
+        &self,
+        key: &str,
+        count: usize,
+        token: &Option<Token>,
+    ) -> Result<(), FaucetError> {
+        // The total quota only limits the native currency.
+        let value = match token {
+            Some(_) => U256::zero(),
+            None => self.faucet.grant_amount().await,
+        };
+        Ok(self.api_keys.charge(key, count, value).await?)
+    }
+
+    /// Give back `count` grants of `token` charged to an API key, which were not served.
+    pub(crate) async fn refund_api_key(&self, key: &str, count: usize, token: &Option<Token>) {
+        let value = match token {
+            Some(_) => U256::zero(),
+            None => self.faucet.grant_amount().await,
+        };
+        self.api_keys.refund(key, count, value).await;
+    }
+
+    /// Check that a web request carries the admin token.
+    fn verify_admin(&self, req: &RequestParams) -> Result<(), FaucetError> {
+        let Some(admin_token) = &self.faucet.config().admin_token else {
+            return Err(FaucetError::not_enabled("the admin API"));
+        };
+        if header(req, "X-Admin-Token") != Some(admin_token.as_str()) {
+            return Err(FaucetError::unauthorized(
+                "missing or invalid X-Admin-Token header",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Check that a web request may manage top-up registrations.
+    ///
+    /// Returns the `X-Discord-Token` of a Discord user who is not banned, or `None` for an admin.
+    async fn verify_top_up_owner(
+        &self,
+        req: &RequestParams,
+    ) -> Result<Option<DiscordWebToken>, FaucetError> {
+        if self.faucet.config().top_up_threshold.is_none() {
+            return Err(FaucetError::not_enabled("top-ups"));
+        }
+        if let Some(discord) = self.discord_web_token(req)? {
+            if self.bans.get(discord.user).await.is_some() {
+                return Err(FaucetError::unauthorized("banned from the faucet"));
+            }
+            return Ok(Some(discord));
+        }
+        self.verify_admin(req).map_err(|_| {
+            FaucetError::unauthorized("missing X-Discord-Token or X-Admin-Token header")
+        })?;
+        Ok(None)
+    }
+
+    /// Check that a web request carries the kill switch token.
+    fn verify_kill_switch(&self, req: &RequestParams) -> Result<(), FaucetError> {
+        let Some(token) = &self.faucet.config().kill_switch_token else {
+            return Err(FaucetError::not_enabled("the kill switch endpoint"));
+        };
+        if header(req, "X-Kill-Switch-Token") != Some(token.as_str()) {
+            return Err(FaucetError::unauthorized(
+                "missing or invalid X-Kill-Switch-Token header",
+            ));
+        }
+        Ok(())
+    }
+
+    /// The Discord user on whose behalf a web request is made, if it carries a Discord token.
+    fn discord_web_token(
+        &self,
+        req: &RequestParams,
+    ) -> Result<Option<DiscordWebToken>, FaucetError> {
+        let Some(token) = header(req, "X-Discord-Token") else {
+            return Ok(None);
+        };
+        self.web_tokens
+            .verify(token)
+            .map(Some)
+            .map_err(|err| FaucetError::unauthorized(format!("invalid X-Discord-Token: {err}")))
+    }
+
+    /// The identity of the user making a web request, if OAuth login is enabled.
+    async fn authenticate(
+        &self,
+        req: &RequestParams,
+    ) -> Result<Option<OAuthIdentity>, FaucetError> {
+        let Some(oauth) = &self.oauth else {
+            return Ok(None);
+        };
+        let unauthorized = FaucetError::unauthorized;
+        let token = header(req, "X-Session-Token")
+            .ok_or_else(|| unauthorized("missing X-Session-Token header"))?
+            .parse()
+            .map_err(|_| unauthorized("invalid X-Session-Token header"))?;
+        let identity = oauth
+            .identity(token)
+            .await
+            .ok_or_else(|| unauthorized("unknown or expired session"))?;
+        Ok(Some(identity))
+    }
+
+    async fn start_oauth_cooldown(&self, identity: OAuthIdentity) -> Result<(), FaucetError> {
+        self.oauth_cooldown
+            .start(identity)
+            .await
+            .map_err(FaucetError::cooldown)
+    }
+
+    /// Undo the charges of a web request which was not served: its API key is refunded and the
+    /// cooldown of its user ended.
+    async fn roll_back(
+        &self,
+        api_key: Option<&str>,
+        identity: Option<OAuthIdentity>,
+        token: &Option<Token>,
+    ) {
+        if let Some(key) = api_key {
+            self.refund_api_key(key, 1, token).await;
+        }
+        if let Some(identity) = identity {
+            self.oauth_cooldown.reset(&identity).await;
+        }
+    }
+
+    /// Count a request towards the `--rate-limit` rules which apply to it.
+    ///
+    /// Returns the delay of the request if it exceeds a rule and rate limits are tarpitted.
+    pub(crate) async fn check_rate_limits(
+        &self,
+        keys: LimitKeys,
+    ) -> Result<Option<Duration>, FaucetError> {
+        let delay = self
+            .tarpit(AbuseSignal::RateLimit, self.rate_limiter.check(keys).await)
+            .await?;
+        if delay.is_some() {
+            // Tarpitted requests still count, so that they do not reset the limit.
+            self.rate_limiter.count(keys).await;
+        }
+        Ok(delay)
+    }
+
+    /// Count a web request towards the request rates, and throttle it during a spike.
+    ///
+    /// Returns the delay of the request if it is throttled and spikes are tarpitted.
+    async fn check_velocity(&self, req: &RequestParams) -> Result<Option<Duration>, FaucetError> {
+        let Some(velocity) = self.faucet.velocity() else {
+            return Ok(None);
+        };
+        let result = velocity.check(client_ip(req).map(VelocityKey::Ip)).await;
+        self.tarpit(AbuseSignal::Velocity, result).await
+    }
+
+    /// Hold back a request flagged by `signal` instead of rejecting it, if `signal` is tarpitted.
+    pub(crate) async fn tarpit(
+        &self,
+        signal: AbuseSignal,
+        result: Result<(), Duration>,
+    ) -> Result<Option<Duration>, FaucetError> {
+        match result {
+            Ok(()) => Ok(None),
+            Err(remaining) => match self.faucet.tarpit_delay(signal).await {
+                Some(delay) => {
+                    tracing::info!("Tarpitting request flagged by {signal}");
+                    Ok(Some(delay))
+                }
+                None => Err(FaucetError::cooldown(remaining)),
+            },
+        }
+    }
+
+    /// Check that a web request passes the configured bot protection.
+    ///
+    /// If both proof of work and CAPTCHA are enabled, either one is sufficient.
+    async fn verify_bot_protection(
+        &self,
+        req: &RequestParams,
+        address: Address,
+    ) -> Result<(), FaucetError> {
+        if self.faucet.config().bot_protection_on_spike {
+            let spike = match self.faucet.velocity() {
+                Some(velocity) => velocity.spike().await,
+                None => None,
+            };
+            if spike.is_none() {
+                return Ok(());
+            }
+        }
+        match (&self.pow, &self.captcha, header(req, "X-Pow-Nonce")) {
+            (Some(pow), _, Some(nonce)) => {
+                Self::verify_pow(pow, nonce, header(req, "X-Pow-Solution"), address).await
+            }
+            (_, Some(captcha), _) => {
+                Self::verify_captcha(captcha, header(req, "X-Captcha-Token")).await
+            }
+            (Some(_), None, None) => Err(FaucetError::new(
+                ErrorCode::ProofOfWorkFailed,
+                StatusCode::Forbidden,
+                "missing X-Pow-Nonce header",
+            )),
+            (None, None, _) => Ok(()),
+        }
+    }
+
+    /// Check that a web request proves ownership of `address`, if ownership proofs are required.
+    async fn verify_ownership(
+        &self,
+        req: &RequestParams,
+        address: Address,
+    ) -> Result<(), FaucetError> {
+        let Some(ownership) = &self.ownership else {
+            return Ok(());
+        };
+        let bad_request = |msg: &str| {
+            FaucetError::new(ErrorCode::OwnershipProofFailed, StatusCode::BadRequest, msg)
+        };
+        let nonce = header(req, "X-Ownership-Nonce")
+            .ok_or_else(|| bad_request("missing X-Ownership-Nonce header"))?
+            .parse()
+            .map_err(|_| bad_request("invalid X-Ownership-Nonce header"))?;
+        let signature = header(req, "X-Ownership-Signature")
+            .ok_or_else(|| bad_request("missing X-Ownership-Signature header"))?
+            .parse()
+            .map_err(|_| bad_request("invalid X-Ownership-Signature header"))?;
+        if ownership.verify(address, nonce, &signature).await {
+            Ok(())
+        } else {
+            Err(FaucetError::new(
+                ErrorCode::OwnershipProofFailed,
+                StatusCode::Forbidden,
+                "invalid signature or expired nonce",
+            ))
+        }
+    }
+
+    async fn verify_pow(
+        pow: &ProofOfWork,
+        nonce: &str,
+        solution: Option<&str>,
+        address: Address,
+    ) -> Result<(), FaucetError> {
+        let bad_request =
+            |msg: &str| FaucetError::new(ErrorCode::ProofOfWorkFailed, StatusCode::BadRequest, msg);
+        let nonce = nonce
+            .parse()
+            .map_err(|_| bad_request("invalid X-Pow-Nonce header"))?;
+        let solution = solution
+            .ok_or_else(|| bad_request("missing X-Pow-Solution header"))?
+            .parse()
+            .map_err(|_| bad_request("invalid X-Pow-Solution header"))?;
+        if pow.verify(nonce, address, solution).await {
+            Ok(())
+        } else {
+            Err(FaucetError::new(
+                ErrorCode::ProofOfWorkFailed,
+                StatusCode::Forbidden,
+                "invalid or expired solution",
+            ))
+        }
+    }
+
+    async fn verify_captcha(
+        captcha: &CaptchaVerifier,
+        token: Option<&str>,
+    ) -> Result<(), FaucetError> {
+        let Some(token) = token else {
+            return Err(FaucetError::new(
+                ErrorCode::CaptchaFailed,
+                StatusCode::Forbidden,
+                "missing X-Captcha-Token header",
+            ));
+        };
+        match captcha.verify(token).await {
+            Ok(true) => Ok(()),
+            Ok(false) => Err(FaucetError::new(
+                ErrorCode::CaptchaFailed,
+                StatusCode::Forbidden,
+                "invalid CAPTCHA token",
+            )),
+            Err(err) => {
+                tracing::error!("Failed to verify CAPTCHA token: {err:#}");
+                Err(FaucetError::unavailable("CAPTCHA provider unavailable"))
+            }
+        }
+    }
+
+    pub async fn request(&self, request: FaucetRequest) -> Result<QueuedRequest, FaucetError> {
+        Self::submit(&self.faucet_queue, &self.faucet, request).await
+    }
+
+    /// Submit `request` to `faucet` through its `queue`.
+    pub(crate) async fn submit(
+        queue: &Sender<FaucetRequest>,
+        faucet: &Faucet,
+        request: FaucetRequest,
+    ) -> Result<QueuedRequest, FaucetError> {
+        let request = Self::prepare(faucet, request).await?;
+        Self::enqueue(queue, faucet, request).await
     }
 
-    fn status(&self) -> StatusCode {
-        match self {
-            Self::FaucetError { status, .. } => *status,
-            Self::BadAddress { status, .. } => *status,
+    /// Submit the L1 grant of a dual-layer request with `l1`, to `faucet`, and the grant `request`
+    /// to the `--dual-layer-chain` `rollup`, so that both grants are served or neither.
+    ///
+    /// The rollup grant is checked before `l1` charges the requester for the L1 grant. If the
+    /// rollup grant still cannot be queued and the L1 grant is already being sent, the L1 grant is
+    /// served alone, without a rollup grant in the response.
+    pub(crate) async fn submit_dual(
+        faucet: &Faucet,
+        l1: impl Future<Output = Result<QueuedRequest, FaucetError>>,
+        rollup: &ChainFaucet,
+        request: FaucetRequest,
+    ) -> Result<DualQueuedRequest, FaucetError> {
+        let request = Self::prepare(&rollup.faucet, request).await?;
+        let l1 = match l1.await {
+            Ok(l1) => l1,
+            Err(err) => {
+                rollup.faucet.release_lifetime_grant(request.id).await;
+                return Err(err);
+            }
+        };
+        let request = request.with_correlation_id(l1.correlation_id);
+        match Self::enqueue(&rollup.queue, &rollup.faucet, request).await {
+            Ok(rollup) => Ok(DualQueuedRequest {
+                l1,
+                rollup: Some(rollup),
+            }),
+            Err(err) if faucet.cancel_request(l1.id).await => Err(err),
+            Err(err) => {
+                tracing::warn!(
+                    %l1.correlation_id,
+                    "Serving L1 grant {} without its rollup grant: {}",
+                    l1.id,
+                    err
+                );
+                Ok(DualQueuedRequest { l1, rollup: None })
+            }
         }
     }
-}
 
-impl From<RequestError> for FaucetError {
-    fn from(err: RequestError) -> Self {
-        Self::catch_all(StatusCode::BadRequest, err.to_string())
+    /// Check that `faucet` can serve `request` now, and reserve its grant.
+    async fn prepare(
+        faucet: &Faucet,
+        mut request: FaucetRequest,
+    ) -> Result<FaucetRequest, FaucetError> {
+        // Do not queue requests which cannot be served until the faucet has started.
+        if !faucet.is_ready().await {
+            return Err(FaucetError::unavailable(
+                "the faucet is starting or on standby, try again later",
+            ));
+        }
+        if faucet.is_killed() {
+            return Err(FaucetError::new(
+                ErrorCode::Paused,
+                StatusCode::ServiceUnavailable,
+                "the faucet has been stopped by its operators, try again later",
+            ));
+        }
+        if faucet.is_paused().await {
+            return Err(FaucetError::new(
+                ErrorCode::Paused,
+                StatusCode::ServiceUnavailable,
+                "the faucet is paused",
+            ));
+        }
+        let delay = faucet.screen_recipient(request.to).await?;
+        request = request.with_delay(delay);
+        // The lifetime cap only applies to grants of the native currency.
+        if request.token.is_none() && request.relay.is_none() {
+            let amount = match request.amount {
+                Some(amount) => amount,
+                None => faucet.grant_amount().await,
+            };
+            faucet
+                .reserve_lifetime_grant(request.id, request.to, amount)
+                .await?;
+        }
+        Ok(request)
     }
-}
 
-pub(crate) async fn serve(port: u16, state: WebState) -> io::Result<()> {
-    let mut app = App::<_, FaucetError>::with_state(RwLock::new(state));
-    app.with_version(env!("CARGO_PKG_VERSION").parse().unwrap());
+    /// Queue `request`, checked by [`prepare`](Self::prepare), on `faucet` through its `queue`.
+    async fn enqueue(
+        queue: &Sender<FaucetRequest>,
+        faucet: &Faucet,
+        request: FaucetRequest,
+    ) -> Result<QueuedRequest, FaucetError> {
+        let FaucetRequest {
+            id, correlation_id, ..
+        } = request;
+        let eta = faucet.estimate_wait().await;
+        faucet.spans().start(id, correlation_id).await;
+        if let Err(err) = queue.try_send(request) {
+            faucet.release_lifetime_grant(id).await;
+            faucet.spans().finish(id, "rejected").await;
+            return Err(match err {
+                TrySendError::Full(_) => FaucetError::new(
+                    ErrorCode::QueueFull,
+                    StatusCode::ServiceUnavailable,
+                    "too many pending requests, try again later",
+                ),
+                TrySendError::Closed(_) => FaucetError::unavailable("faucet is not running"),
+            });
+        }
+        Ok(QueuedRequest {
+            id,
+            correlation_id,
+            eta_secs: eta.as_secs(),
+            cancel_token: faucet.cancel_token(id),
+        })
+    }
 
-    // Include API specification in binary
-    let toml = toml::from_str::<toml::value::Value>(include_str!("api.toml"))
-        .map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
+    /// The status of the request `id` on the default chain or any chain of the guild settings.
+    pub(crate) async fn request_status(&self, id: RequestId) -> Option<RequestStatus> {
+        if let Some(status) = self.faucet.request_status(id).await {
+            return Some(status);
+        }
+        for (_, chain) in self.guilds.chains() {
+            if let Some(status) = chain.faucet.request_status(id).await {
+                return Some(status);
+            }
+        }
+        None
+    }
 
-    let mut api = Api::<RwLock<WebState>, FaucetError>::new(toml).unwrap();
-    api.with_version(env!("CARGO_PKG_VERSION").parse().unwrap());
+    /// The chain on which dual-layer requests are granted along with the default chain.
+    pub(crate) fn dual_layer_chain(&self) -> Result<&ChainFaucet, FaucetError> {
+        self.faucet
+            .config()
+            .dual_layer_chain
+            .as_deref()
+            .and_then(|name| self.guilds.chain(name))
+            .ok_or_else(|| FaucetError::not_enabled("dual-layer grants"))
+    }
 
-    // Can invoke with
-    //    `curl -i -X POST http://0.0.0.0:8111/faucet/request/0x1234567890123456789012345678901234567890`
-    api.post("request", |req, state| {
-        async move {
-            let address = req.string_param("address")?;
-            let address = address.parse().map_err(|_| FaucetError::BadAddress {
-                status: StatusCode::BadRequest,
-                input: address.to_string(),
-            })?;
-            tracing::info!("Received faucet request for {:?}", address);
-            state.request(address).await?;
-            Ok(())
+    /// The queue and faucet serving a guild with `settings`.
+    pub(crate) fn chain(
+        &self,
+        settings: Option<&GuildSettings>,
+    ) -> (&Sender<FaucetRequest>, &Faucet) {
+        match settings
+            .and_then(|settings| settings.chain.as_deref())
+            .and_then(|chain| self.guilds.chain(chain))
+        {
+            Some(chain) => (&chain.queue, &chain.faucet),
+            None => (&self.faucet_queue, &self.faucet),
         }
-        .boxed()
-    })
-    .unwrap();
+    }
 
-    app.register_module("faucet", api).unwrap();
-    app.serve(format!("0.0.0.0:{}", port)).await
-}
+    /// Handle a web request for funds carrying a token issued with `/faucet-web-token`.
+    ///
+    /// The request is subject to the same ban, registration and cooldowns as a request by the
+    /// Discord user in the guild the token was issued in, and granted the amount of that guild.
+    pub(crate) async fn discord_web_request(
+        &self,
+        discord: DiscordWebToken,
+        mut request: FaucetRequest,
+    ) -> Result<QueuedRequest, FaucetError> {
+        let address = request.to;
+        if self.bans.get(discord.user).await.is_some() {
+            return Err(FaucetError::unauthorized("banned from the faucet"));
+        }
+        if self.faucet.config().discord_require_registration {
+            let registered = self
+                .discord_registrations
+                .read()
+                .await
+                .get(&discord.user)
+                .copied();
+            if registered != Some(address) {
+                return Err(FaucetError::unauthorized(
+                    "only the registered address of the Discord user can receive funds",
+                ));
+            }
+        }
+        let settings = match discord.guild {
+            Some(guild) => self.guilds.get(guild).await,
+            None => None,
+        };
+        let quota_exhausted = |remaining| {
+            FaucetError::quota_exhausted("the quota of the Discord server is used up", remaining)
+        };
+        if let Some(settings) = &settings {
+            if let Some(remaining) = match &settings.quota {
+                Some(quota) => quota.remaining(1).await,
+                None => None,
+            } {
+                return Err(quota_exhausted(remaining));
+            }
+            settings
+                .start_cooldowns(discord.user, &[address])
+                .await
+                .map_err(|cooling_down| FaucetError::cooldown(cooling_down.remaining()))?;
+            if let Some(quota) = &settings.quota {
+                if let Err(remaining) = quota.take(1).await {
+                    settings
+                        .reset_cooldowns(Some(discord.user), &[address])
+                        .await;
+                    return Err(quota_exhausted(remaining));
+                }
+            }
+        }
 
-#[derive(Clone, Debug)]
-pub(crate) struct WebState {
-    faucet_queue: Sender<Address>,
-}
+        let (queue, faucet) = self.chain(settings.as_ref());
+        if request.token.is_none() {
+            if let Some(amount) = settings.as_ref().and_then(|settings| settings.grant_amount) {
+                request = request.with_amount(amount);
+            }
+        }
+        let queued = match Self::submit(queue, faucet, request).await {
+            Ok(queued) => queued,
+            Err(err) => {
+                // Requests which are not served do not count against the guild.
+                if let Some(settings) = &settings {
+                    settings
+                        .reset_cooldowns(Some(discord.user), &[address])
+                        .await;
+                    if let Some(quota) = &settings.quota {
+                        quota.refund(1).await;
+                    }
+                }
+                return Err(err);
+            }
+        };
+        self.discord_addresses
+            .write()
+            .await
+            .insert(discord.user, address);
+        if self.faucet.config().discord_audit_channel_id.is_some() {
+            self.discord_requesters
+                .write()
+                .await
+                .insert(queued.id, discord.user);
+        }
+        Ok(queued)
+    }
 
-impl WebState {
-    pub fn new(faucet_queue: Sender<Address>) -> Self {
-        Self { faucet_queue }
+    /// Reload the settings file, the guild settings, the ban list and the tokens registry.
+    ///
+    /// Each file is reloaded independently, and the tokens registry of every chain, so an invalid
+    /// file does not prevent the others from being reloaded, and the error lists every failure. The
+    /// queue and the Discord connection are not affected. The faucets of the chains grant the amount
+    /// of their guilds, or else the amount of the settings file.
+    pub(crate) async fn reload(&self) -> anyhow::Result<()> {
+        let mut errors = vec![];
+        let previous = self.live_options.get();
+        match self.live_options.reload() {
+            Ok(options) => {
+                if options.faucet_grant_amount != previous.faucet_grant_amount {
+                    self.faucet
+                        .set_grant_amount(options.faucet_grant_amount)
+                        .await;
+                    for (name, chain) in self.guilds.chains() {
+                        if self.guilds.grant_amount(name).await.is_none() {
+                            chain
+                                .faucet
+                                .set_grant_amount(options.faucet_grant_amount)
+                                .await;
+                        }
+                    }
+                }
+            }
+            Err(err) => errors.push(err.context("reloading the settings file")),
+        }
+        if let Err(err) = self.guilds.reload().await {
+            errors.push(err.context("reloading the guild settings"));
+        }
+        if let Err(err) = self.bans.reload().await {
+            errors.push(err.context("reloading the ban list"));
+        }
+        if let Err(err) = self.faucet.reload_tokens() {
+            errors.push(err.context("reloading the tokens registry"));
+        }
+        for (name, chain) in self.guilds.chains() {
+            if let Err(err) = chain.faucet.reload_tokens() {
+                errors.push(err.context(format!("reloading the tokens registry of chain {name}")));
+            }
+        }
+        if errors.is_empty() {
+            tracing::info!("Reloaded the configuration");
+            return Ok(());
+        }
+        let errors = errors
+            .iter()
+            .map(|err| format!("{err:#}"))
+            .collect::<Vec<_>>()
+            .join("; ");
+        tracing::error!("Failed to reload the configuration: {errors}");
+        Err(anyhow::anyhow!(errors))
     }
 
-    pub async fn request(&self, address: Address) -> Result<(), FaucetError> {
-        self.faucet_queue
-            .send(address)
-            .await
-            .map_err(|err| FaucetError::FaucetError {
-                status: StatusCode::InternalServerError,
-                msg: err.to_string(),
-            })?;
-        Ok(())
+    /// The current metrics of the default faucet and, if it is enabled, the Discord bot.
+    pub(crate) async fn metrics(&self) -> Vec<Sample> {
+        // Report the Discord metrics even if the chain cannot be reached.
+        let faucet = match self.faucet.stats().await {
+            Ok(stats) => Some(stats),
+            Err(err) => {
+                tracing::warn!("Failed to get the faucet status for the metrics: {err:#}");
+                None
+            }
+        };
+        let requesters = self.discord_requesters.read().await.len();
+        let discord = self
+            .gateway
+            .as_ref()
+            .map(|gateway| (&self.discord_metrics, gateway.reconnects(), requesters));
+        collect_metrics(
+            faucet,
+            &self.faucet.cache_stats(),
+            discord,
+            self.self_test.passed().await,
+        )
+        .await
+    }
+
+    /// Push the metrics to StatsD every `--statsd-interval`.
+    pub(crate) async fn export_statsd(self) {
+        let config = self.faucet.config();
+        let mut exporter = match StatsdExporter::connect(
+            &config.statsd_address,
+            config.metrics_backend,
+            config.statsd_prefix.clone(),
+        )
+        .await
+        {
+            Ok(exporter) => exporter,
+            Err(err) => {
+                tracing::error!("Cannot export metrics to {}: {err}", config.statsd_address);
+                return;
+            }
+        };
+        loop {
+            if let Err(err) = exporter.send(&self.metrics().await).await {
+                tracing::warn!("Failed to export metrics to StatsD: {err}");
+            }
+            sleep(config.statsd_interval).await;
+        }
     }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::faucet::{Faucet, Middleware, Options, TEST_MNEMONIC};
+    use crate::faucet::{Faucet, Options, RequestStatus, TransferRequest, TEST_MNEMONIC};
     use anyhow::Result;
     use async_compatibility_layer::logging::{setup_backtrace, setup_logging};
     use async_std::task::spawn;
     use ethers::{
+        middleware::SignerMiddleware,
         providers::{Http, Middleware as _, Provider},
         signers::{coins_bip39::English, MnemonicBuilder, Signer},
         types::{TransactionRequest, U256},
@@ -119,19 +1732,27 @@ mod test {
     use std::{sync::Arc, time::Duration};
     use surf_disco::Client;
 
+    /// Wait until the faucet can serve requests, which are rejected until then.
+    async fn wait_until_ready(client: &Client<FaucetError>) {
+        while client.get::<bool>("faucet/readiness").send().await.is_err() {
+            async_std::task::sleep(Duration::from_millis(100)).await;
+        }
+    }
+
     async fn run_faucet_test(options: Options, num_transfers: usize) -> Result<()> {
         let client =
             Client::<FaucetError>::new(format!("http://localhost:{}", options.port).parse()?);
         // Avoids waiting 10 seconds for the retry in `connect`.
         async_std::task::sleep(Duration::from_millis(100)).await;
         client.connect(None).await;
+        wait_until_ready(&client).await;
 
         let recipient = Address::random();
         let mut total_transfer_amount = U256::zero();
 
         for _ in 0..num_transfers {
             client
-                .post(&format!("faucet/request/{recipient:?}"))
+                .post::<QueuedRequest>(&format!("faucet/request/{recipient:?}"))
                 .send()
                 .await?;
 
@@ -190,15 +1811,380 @@ mod test {
 
         // Start the faucet
         let faucet = Faucet::create(options.clone(), receiver).await?;
-        let _handle = faucet.start().await;
+        let _handle = faucet.clone().start().await;
 
         // Start the web server
-        spawn(async move { serve(options.port, WebState::new(sender)).await });
+        spawn(async move { serve(options.port, WebState::new(sender, faucet)).await });
 
         run_faucet_test(options, 30).await?;
         Ok(())
     }
 
+    #[async_std::test]
+    async fn test_faucet_events() -> Result<()> {
+        setup_logging();
+        setup_backtrace();
+
+        let anvil = AnvilOptions::default().spawn().await;
+
+        let options = Options {
+            num_clients: 1,
+            faucet_grant_amount: parse_ether(1).unwrap(),
+            provider_url_ws: None,
+            provider_url_http: anvil.url(),
+            port: portpicker::pick_unused_port().unwrap(),
+            ..Default::default()
+        };
+
+        let (sender, receiver) = async_std::channel::unbounded();
+
+        // Start the faucet
+        let faucet = Faucet::create(options.clone(), receiver).await?;
+        let _handle = faucet.clone().start().await;
+
+        // Start the web server
+        spawn(async move { serve(options.port, WebState::new(sender, faucet)).await });
+
+        let client =
+            Client::<FaucetError>::new(format!("http://localhost:{}", options.port).parse()?);
+        client.connect(None).await;
+        wait_until_ready(&client).await;
+        let mut events = client
+            .socket("faucet/events")
+            .subscribe::<FaucetEvent>()
+            .await?;
+
+        let recipient = Address::random();
+        let correlation_id = CorrelationId::random();
+        let QueuedRequest {
+            id,
+            correlation_id: returned,
+            ..
+        } = client
+            .post::<QueuedRequest>(&format!("faucet/request/{recipient:?}"))
+            .header("X-Correlation-Id", correlation_id.to_string())
+            .send()
+            .await?;
+        assert_eq!(returned, correlation_id);
+        let request = TransferRequest::faucet(id, recipient, options.faucet_grant_amount)
+            .with_correlation_id(correlation_id);
+
+        assert_eq!(
+            events.next().await.unwrap()?,
+            FaucetEvent::RequestQueued { request }
+        );
+        assert!(matches!(
+            events.next().await.unwrap()?,
+            FaucetEvent::TransferSubmitted { request: submitted, .. } if submitted == request
+        ));
+        assert!(matches!(
+            events.next().await.unwrap()?,
+            FaucetEvent::TransferConfirmed { request: confirmed, .. } if confirmed == request
+        ));
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn test_batch_request() -> Result<()> {
+        setup_logging();
+        setup_backtrace();
+
+        let anvil = AnvilOptions::default().spawn().await;
+
+        let options = Options {
+            num_clients: 2,
+            faucet_grant_amount: parse_ether(1).unwrap(),
+            provider_url_ws: None,
+            provider_url_http: anvil.url(),
+            port: portpicker::pick_unused_port().unwrap(),
+            ..Default::default()
+        };
+
+        let (sender, receiver) = async_std::channel::unbounded();
+
+        // Start the faucet
+        let faucet = Faucet::create(options.clone(), receiver).await?;
+        let _handle = faucet.clone().start().await;
+
+        // Start the web server
+        spawn(async move { serve(options.port, WebState::new(sender, faucet)).await });
+
+        let client =
+            Client::<FaucetError>::new(format!("http://localhost:{}", options.port).parse()?);
+        client.connect(None).await;
+        wait_until_ready(&client).await;
+
+        // Batches that are too large are rejected.
+        let too_many = vec![Address::random(); MAX_BATCH_SIZE + 1];
+        client
+            .post::<Vec<BatchRequestId>>("faucet/request/batch")
+            .body_json(&too_many)?
+            .send()
+            .await
+            .unwrap_err();
+
+        let recipients = (0..5).map(|_| Address::random()).collect::<Vec<_>>();
+        let ids = client
+            .post::<Vec<BatchRequestId>>("faucet/request/batch")
+            .body_json(&recipients)?
+            .send()
+            .await?;
+        assert_eq!(
+            ids.iter().map(|id| id.address).collect::<Vec<_>>(),
+            recipients
+        );
+
+        for BatchRequestId { address, id, .. } in ids {
+            let completed = client
+                .socket(&format!("faucet/await/{id}"))
+                .subscribe::<CompletedTransfer>()
+                .await?
+                .next()
+                .await
+                .unwrap()?;
+            tracing::info!("Funded {address:?} in {:?}", completed.tx_hash);
+        }
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn test_await_request() -> Result<()> {
+        setup_logging();
+        setup_backtrace();
+
+        let anvil = AnvilOptions::default().spawn().await;
+
+        let options = Options {
+            num_clients: 1,
+            faucet_grant_amount: parse_ether(1).unwrap(),
+            provider_url_ws: None,
+            provider_url_http: anvil.url(),
+            port: portpicker::pick_unused_port().unwrap(),
+            ..Default::default()
+        };
+
+        let (sender, receiver) = async_std::channel::unbounded();
+
+        // Start the faucet
+        let faucet = Faucet::create(options.clone(), receiver).await?;
+        let _handle = faucet.clone().start().await;
+
+        // Start the web server
+        spawn(async move { serve(options.port, WebState::new(sender, faucet)).await });
+
+        let client =
+            Client::<FaucetError>::new(format!("http://localhost:{}", options.port).parse()?);
+        client.connect(None).await;
+        wait_until_ready(&client).await;
+
+        // Use the versioned paths; the other tests use the legacy aliases.
+        let recipient = Address::random();
+        let QueuedRequest { id, .. } = client
+            .post::<QueuedRequest>(&format!("v1/request/{recipient:?}"))
+            .send()
+            .await?;
+        let completed = client
+            .socket(&format!("v1/await/{id}"))
+            .subscribe::<CompletedTransfer>()
+            .await?
+            .next()
+            .await
+            .unwrap()?;
+
+        let provider = Provider::<Http>::try_from(options.provider_url_http.to_string())?;
+        let receipt = provider
+            .get_transaction_receipt(completed.tx_hash)
+            .await?
+            .unwrap();
+        assert_eq!(receipt.to, Some(recipient));
+        assert_eq!(receipt.block_number, completed.block_number);
+        assert_eq!(
+            client
+                .get::<RequestStatus>(&format!("v1/status/{id}"))
+                .send()
+                .await?,
+            RequestStatus::Confirmed {
+                tx_hash: completed.tx_hash,
+                block_number: completed.block_number,
+            }
+        );
+
+        // Awaiting a request that has already completed returns immediately.
+        let again = client
+            .socket(&format!("v1/await/{id}"))
+            .subscribe::<CompletedTransfer>()
+            .await?
+            .next()
+            .await
+            .unwrap()?;
+        assert_eq!(again, completed);
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn test_dual_request() -> Result<()> {
+        setup_logging();
+        setup_backtrace();
+
+        let l1_anvil = AnvilOptions::default().spawn().await;
+        let rollup_anvil = AnvilOptions::default().spawn().await;
+        let path =
+            std::env::temp_dir().join(format!("faucet-guilds-{}.toml", rand::random::<u64>()));
+        std::fs::write(
+            &path,
+            format!(
+                "[chain.rollup]\nprovider_url_http = \"{}\"\n",
+                rollup_anvil.url()
+            ),
+        )?;
+
+        let options = Options {
+            num_clients: 1,
+            faucet_grant_amount: parse_ether(1).unwrap(),
+            provider_url_ws: None,
+            provider_url_http: l1_anvil.url(),
+            port: portpicker::pick_unused_port().unwrap(),
+            dual_layer_chain: Some("rollup".into()),
+            ..Default::default()
+        };
+
+        let (sender, receiver) = async_std::channel::unbounded();
+        let faucet = Faucet::create(options.clone(), receiver).await?;
+        let _handle = faucet.clone().start().await;
+        let guilds = Guilds::load(path.clone(), &options, None).await?;
+        std::fs::remove_file(&path)?;
+        let rollup = guilds.chain("rollup").unwrap().clone();
+        let _rollup_handle = rollup.faucet.clone().start().await;
+
+        let state = WebState::new(sender.clone(), faucet.clone()).with_guilds(guilds);
+        spawn(async move { serve(options.port, state).await });
+        let client =
+            Client::<FaucetError>::new(format!("http://localhost:{}", options.port).parse()?);
+        client.connect(None).await;
+        wait_until_ready(&client).await;
+        while !rollup.faucet.is_ready().await {
+            async_std::task::sleep(Duration::from_millis(100)).await;
+        }
+
+        let recipient = Address::random();
+        let request_dual = || async {
+            let dual = client
+                .post::<DualQueuedRequest>(&format!("v1/dual/{recipient:?}"))
+                .send()
+                .await?;
+            let rollup_grant = dual.rollup.unwrap();
+            assert_eq!(dual.l1.correlation_id, rollup_grant.correlation_id);
+            await_transfer(faucet.clone(), dual.l1.id)
+                .await?
+                .next()
+                .await
+                .unwrap()?;
+            await_transfer(rollup.faucet.clone(), rollup_grant.id)
+                .await?
+                .next()
+                .await
+                .unwrap()?;
+            Ok::<_, anyhow::Error>(())
+        };
+
+        // Both grants are served.
+        request_dual().await?;
+
+        // When the rollup grant is refused, the L1 grant is not requested, on the web as well as
+        // on Discord.
+        rollup.faucet.pause().await;
+        client
+            .post::<DualQueuedRequest>(&format!("v1/dual/{recipient:?}"))
+            .send()
+            .await
+            .unwrap_err();
+        let request = FaucetRequest::new(recipient, None);
+        let id = request.id;
+        let l1 = WebState::submit(&sender, &faucet, request);
+        WebState::submit_dual(&faucet, l1, &rollup, FaucetRequest::new(recipient, None))
+            .await
+            .unwrap_err();
+        assert_eq!(faucet.request_status(id).await, None);
+
+        // The grants are served in order, so no other L1 grant was paid once the next one is.
+        rollup.faucet.resume().await;
+        request_dual().await?;
+        let provider = Provider::<Http>::try_from(options.provider_url_http.to_string())?;
+        assert_eq!(
+            provider.get_balance(recipient, None).await?,
+            parse_ether(2).unwrap()
+        );
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn test_pause_and_grant_amount() -> Result<()> {
+        setup_logging();
+        setup_backtrace();
+
+        let anvil = AnvilOptions::default().spawn().await;
+
+        let options = Options {
+            num_clients: 1,
+            faucet_grant_amount: parse_ether(1).unwrap(),
+            provider_url_ws: None,
+            provider_url_http: anvil.url(),
+            port: portpicker::pick_unused_port().unwrap(),
+            ..Default::default()
+        };
+
+        let (sender, receiver) = async_std::channel::unbounded();
+
+        // Start the faucet
+        let faucet = Faucet::create(options.clone(), receiver).await?;
+        let _handle = faucet.clone().start().await;
+
+        // Start the web server
+        let state = WebState::new(sender, faucet.clone());
+        spawn(async move { serve(options.port, state).await });
+
+        let client =
+            Client::<FaucetError>::new(format!("http://localhost:{}", options.port).parse()?);
+        client.connect(None).await;
+        wait_until_ready(&client).await;
+
+        // A paused faucet rejects requests.
+        faucet.pause().await;
+        let recipient = Address::random();
+        let err = client
+            .post::<QueuedRequest>(&format!("v1/request/{recipient:?}"))
+            .send()
+            .await
+            .unwrap_err();
+        assert_eq!(err.code, ErrorCode::Paused);
+
+        // After resuming, requests are granted the amount set by the operator.
+        faucet.resume().await;
+        let amount = parse_ether("0.5").unwrap();
+        faucet.set_grant_amount(amount).await;
+        let QueuedRequest { id, .. } = client
+            .post::<QueuedRequest>(&format!("v1/request/{recipient:?}"))
+            .send()
+            .await?;
+        client
+            .socket(&format!("v1/await/{id}"))
+            .subscribe::<CompletedTransfer>()
+            .await?
+            .next()
+            .await
+            .unwrap()?;
+
+        let provider = Provider::<Http>::try_from(options.provider_url_http.to_string())?;
+        assert_eq!(provider.get_balance(recipient, None).await?, amount);
+        assert_eq!(faucet.wallets().await.len(), 1);
+
+        Ok(())
+    }
+
     #[async_std::test]
     async fn test_node_restart_ws() -> Result<()> {
         test_node_restart(true).await
@@ -239,10 +2225,10 @@ mod test {
 
         // Start the faucet
         let faucet = Faucet::create(options.clone(), receiver).await?;
-        let _handle = faucet.start().await;
+        let _handle = faucet.clone().start().await;
 
         // Start the web server
-        spawn(async move { serve(options.port, WebState::new(sender)).await });
+        spawn(async move { serve(options.port, WebState::new(sender, faucet)).await });
 
         run_faucet_test(options.clone(), 3).await?;
 
@@ -288,7 +2274,7 @@ mod test {
             .index(0u32)?
             .build()?
             .with_chain_id(chain_id);
-        let funded_client = Arc::new(Middleware::new(provider.clone(), funded_wallet));
+        let funded_client = Arc::new(SignerMiddleware::new(provider.clone(), funded_wallet));
 
         // An unfunded mnemonic
         let mnemonic =
@@ -313,10 +2299,10 @@ mod test {
 
         // Start the faucet
         let faucet = Faucet::create(options.clone(), receiver).await?;
-        let _handle = faucet.start().await;
+        let _handle = faucet.clone().start().await;
 
         // Start the web server
-        spawn(async move { serve(options.port, WebState::new(sender)).await });
+        spawn(async move { serve(options.port, WebState::new(sender, faucet)).await });
 
         // Transfer some funds to the faucet
         funded_client
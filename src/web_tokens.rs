@@ -0,0 +1,150 @@
+// Copyright (c) 2023 Espresso Systems (espressosys.com)
+// This file is part of the Discord Faucet library.
+//
+// You should have received a copy of the MIT License
+// along with the Discord Faucet library. If not, see <https://mit-license.org/>.
+
+//! Short-lived tokens linking web requests to a Discord user.
+//!
+//! The bot issues a token to a Discord user with the `/faucet-web-token` command. Web requests
+//! carrying the token are treated as requests by that user, in the guild where the token was
+//! issued, and share the priority of the user's Discord requests. Tokens are signed with a secret,
+//! so they do not need to be stored.
+use crate::RequestSource;
+use anyhow::{bail, ensure, Context, Result};
+use ethers::{types::H256, utils::keccak256};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// The Discord user a web token was issued to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DiscordWebToken {
+    pub user: u64,
+    /// The guild where the token was issued, or `None` for direct messages.
+    pub guild: Option<u64>,
+    /// When the token expires, in seconds since the Unix epoch.
+    pub expires: u64,
+    /// Whether the user held `--discord-verified-role` when the token was issued.
+    pub verified: bool,
+}
+
+impl DiscordWebToken {
+    /// The source of the requests made with the token.
+    pub fn source(&self) -> RequestSource {
+        if self.verified {
+            RequestSource::DiscordVerified
+        } else {
+            RequestSource::Discord
+        }
+    }
+}
+
+/// Issues and verifies web tokens.
+#[derive(Clone, Debug)]
+pub struct WebTokens {
+    secret: H256,
+    ttl: Duration,
+}
+
+impl WebTokens {
+    /// Sign tokens valid for `ttl` with `secret`.
+    ///
+    /// Without a secret, a random one is used, so tokens are only valid until the faucet restarts
+    /// and only on this instance.
+    pub fn new(secret: Option<H256>, ttl: Duration) -> Self {
+        Self {
+            secret: secret.unwrap_or_else(H256::random),
+            ttl,
+        }
+    }
+
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+
+    /// Issue a token to `user` in `guild`, `verified` if the user holds `--discord-verified-role`.
+    pub fn issue(&self, user: u64, guild: Option<u64>, verified: bool) -> String {
+        self.issue_at(user, guild, verified, now())
+    }
+
+    fn issue_at(&self, user: u64, guild: Option<u64>, verified: bool, now: u64) -> String {
+        let payload = format!(
+            "{user}.{}.{}.{}",
+            guild.unwrap_or(0),
+            now + self.ttl.as_secs(),
+            u8::from(verified)
+        );
+        format!("{payload}.{:?}", self.sign(&payload))
+    }
+
+    /// The Discord user holding `token`, if it is valid and has not expired.
+    pub fn verify(&self, token: &str) -> Result<DiscordWebToken> {
+        self.verify_at(token, now())
+    }
+
+    fn verify_at(&self, token: &str, now: u64) -> Result<DiscordWebToken> {
+        let Some((payload, signature)) = token.rsplit_once('.') else {
+            bail!("malformed token");
+        };
+        let signature: H256 = signature.parse().context("malformed signature")?;
+        ensure!(signature == self.sign(payload), "invalid signature");
+        let [user, guild, expires, verified] = payload.split('.').collect::<Vec<_>>()[..] else {
+            bail!("malformed token");
+        };
+        let token = DiscordWebToken {
+            user: user.parse().context("malformed user")?,
+            guild: Some(guild.parse().context("malformed guild")?).filter(|guild| *guild != 0),
+            expires: expires.parse().context("malformed expiry")?,
+            verified: verified.parse::<u8>().context("malformed verification")? != 0,
+        };
+        ensure!(token.expires > now, "expired token");
+        Ok(token)
+    }
+
+    fn sign(&self, payload: &str) -> H256 {
+        // Keccak is not vulnerable to length extension, so hashing the secret with the payload is a
+        // sound MAC.
+        keccak256([self.secret.as_bytes(), payload.as_bytes()].concat()).into()
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_web_tokens() {
+        let tokens = WebTokens::new(None, Duration::from_secs(60));
+        let token = tokens.issue_at(1, Some(2), false, 1000);
+        assert_eq!(
+            tokens.verify_at(&token, 1000).unwrap(),
+            DiscordWebToken {
+                user: 1,
+                guild: Some(2),
+                expires: 1060,
+                verified: false,
+            }
+        );
+        assert!(tokens.verify_at(&token, 1060).is_err());
+
+        let dm_token = tokens.issue_at(1, None, false, 1000);
+        assert_eq!(tokens.verify_at(&dm_token, 1000).unwrap().guild, None);
+
+        // Tokens of verified users give their requests the same priority as on Discord.
+        let verified = tokens.verify_at(&tokens.issue_at(1, Some(2), true, 1000), 1000);
+        assert_eq!(verified.unwrap().source(), RequestSource::DiscordVerified);
+
+        // Tokens cannot be altered or verified with another secret.
+        let forged = token.replacen('1', "3", 1);
+        assert!(tokens.verify_at(&forged, 1000).is_err());
+        let other = WebTokens::new(None, Duration::from_secs(60));
+        assert!(other.verify_at(&token, 1000).is_err());
+        assert!(tokens.verify_at("garbage", 1000).is_err());
+    }
+}
@@ -0,0 +1,324 @@
+// Copyright (c) 2023 Espresso Systems (espressosys.com)
+// This file is part of the Discord Faucet library.
+//
+// You should have received a copy of the MIT License
+// along with the Discord Faucet library. If not, see <https://mit-license.org/>.
+
+//! Alerts sent to a generic webhook, for operators who do not watch Discord.
+//!
+//! The body of the webhook is either a Slack message, which is also understood by most chat
+//! services, or a PagerDuty Events v2 event. Alerts are raised when the balance is low, when the
+//! RPC keeps failing, when a transfer fails for good and is recorded as a dead letter, and when a
+//! task panics. An alert which is raised again before it recovers is only sent once per
+//! deduplication window, and failed sends are retried a few times.
+use crate::{Alert, Alerts, Faucet, FaucetEvent, LiveOptions, Options};
+use anyhow::{bail, Result};
+use async_std::{
+    sync::Mutex,
+    task::{sleep, spawn},
+};
+use clap::ValueEnum;
+use futures::StreamExt;
+use serde_json::{json, Value};
+use std::{
+    collections::HashMap,
+    panic,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use url::Url;
+
+/// How often the state of the faucet is checked for alerts.
+const CHECK_INTERVAL: Duration = Duration::from_secs(60);
+/// How many times a webhook is attempted before giving up.
+const ATTEMPTS: u32 = 3;
+/// The delay before the first retry, doubled for each further retry.
+const RETRY_DELAY: Duration = Duration::from_secs(1);
+/// The most alerts remembered for deduplication, the oldest being forgotten first.
+const MAX_SENT: usize = 1000;
+
+/// The body format of the alert webhook.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum WebhookFormat {
+    /// A Slack incoming webhook message, `{"text": ...}`.
+    Slack,
+    /// A PagerDuty Events API v2 event.
+    PagerDuty,
+}
+
+#[derive(Clone, Debug)]
+pub struct AlertWebhook {
+    url: Url,
+    format: WebhookFormat,
+    routing_key: Option<String>,
+    dedup_window: Duration,
+    /// When each alert which has not recovered was last sent, by deduplication key.
+    sent: Arc<Mutex<HashMap<String, Instant>>>,
+}
+
+impl AlertWebhook {
+    /// The webhook configured in `opts`, if any.
+    pub fn new(opts: &Options) -> Option<Self> {
+        Some(Self {
+            url: opts.alert_webhook_url.clone()?,
+            format: opts.alert_webhook_format,
+            routing_key: opts.alert_webhook_routing_key.clone(),
+            dedup_window: opts.alert_webhook_dedup_window,
+            sent: Default::default(),
+        })
+    }
+
+    /// Send `alert` about `source`, unless it was already sent recently.
+    pub async fn send(&self, source: &str, alert: &Alert) {
+        let key = format!("{source}:{}", alert.key());
+        {
+            let mut sent = self.sent.lock().await;
+            if alert.is_recovery() {
+                sent.remove(&key);
+            } else {
+                match sent.get(&key) {
+                    Some(at) if at.elapsed() < self.dedup_window => {
+                        tracing::debug!("Not sending repeated alert {key}");
+                        return;
+                    }
+                    _ => {
+                        // Alerts outside the window would be sent again anyway, and alerts such as
+                        // dead letters never recover, so they are forgotten.
+                        sent.retain(|_, at| at.elapsed() < self.dedup_window);
+                        if sent.len() >= MAX_SENT {
+                            if let Some(oldest) = sent
+                                .iter()
+                                .min_by_key(|(_, at)| **at)
+                                .map(|(key, _)| key.clone())
+                            {
+                                sent.remove(&oldest);
+                            }
+                        }
+                        sent.insert(key.clone(), Instant::now());
+                    }
+                }
+            }
+        }
+
+        let body = self.body(source, &key, alert);
+        let mut delay = RETRY_DELAY;
+        for attempt in 1..=ATTEMPTS {
+            match self.post(&body).await {
+                Ok(()) => return,
+                Err(err) if attempt < ATTEMPTS => {
+                    tracing::warn!("Alert webhook failed (attempt {attempt}): {err:#}");
+                    sleep(delay).await;
+                    delay *= 2;
+                }
+                Err(err) => tracing::error!("Cannot send alert {key} to webhook: {err:#}"),
+            }
+        }
+    }
+
+    fn body(&self, source: &str, key: &str, alert: &Alert) -> Value {
+        match self.format {
+            WebhookFormat::Slack => json!({ "text": format!("[{source}] {alert}") }),
+            WebhookFormat::PagerDuty if alert.is_recovery() => json!({
+                "routing_key": self.routing_key,
+                "event_action": "resolve",
+                "dedup_key": key,
+            }),
+            WebhookFormat::PagerDuty => json!({
+                "routing_key": self.routing_key,
+                "event_action": "trigger",
+                "dedup_key": key,
+                "payload": {
+                    "summary": alert.to_string(),
+                    "source": source,
+                    "severity": if alert.is_critical() { "critical" } else { "warning" },
+                },
+            }),
+        }
+    }
+
+    async fn post(&self, body: &Value) -> Result<()> {
+        let res = surf::post(self.url.as_str())
+            .body_json(body)
+            .map_err(|err| err.into_inner())?
+            .await
+            .map_err(|err| err.into_inner())?;
+        if !res.status().is_success() {
+            bail!("webhook returned {}", res.status());
+        }
+        Ok(())
+    }
+
+    /// Send alerts about `faucet` as long as it runs, with the thresholds in `options`.
+    pub async fn watch(self, faucet: Faucet, options: LiveOptions) {
+        let source = match &faucet.config().network_name {
+            Some(name) => name.clone(),
+            None => format!("chain {}", faucet.chain_id()),
+        };
+
+        // Dead letters are reported as they happen. Transfers which are sent again are not.
+        let mut events = faucet.events().subscribe().await;
+        spawn({
+            let webhook = self.clone();
+            let source = source.clone();
+            async move {
+                while let Some(event) = events.next().await {
+                    if let FaucetEvent::TransferFailed {
+                        request,
+                        reason,
+                        retried: false,
+                        ..
+                    } = event
+                    {
+                        let alert = Alert::DeadLetter { request, reason };
+                        webhook.send(&source, &alert).await;
+                    }
+                }
+            }
+        });
+
+        let mut alerts = Alerts::new(faucet.config());
+        loop {
+            alerts.configure(&options.get());
+            let stats = faucet.stats().await.map_err(|err| format!("{err:#}"));
+            for alert in alerts.check(stats.as_ref().map_err(Clone::clone)) {
+                self.send(&source, &alert).await;
+            }
+            sleep(CHECK_INTERVAL).await;
+        }
+    }
+
+    /// Send an alert whenever a thread or task panics, in addition to the usual panic output.
+    pub fn alert_on_panic(&self, source: String) {
+        let webhook = self.clone();
+        let previous = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            previous(info);
+            let alert = Alert::TaskPanicked {
+                message: info.to_string(),
+            };
+            let webhook = webhook.clone();
+            let source = source.clone();
+            spawn(async move { webhook.send(&source, &alert).await });
+        }));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{RequestId, TransferRequest};
+    use ethers::types::Address;
+    use tide::listener::Listener;
+
+    type Received = Arc<Mutex<Vec<Value>>>;
+
+    fn webhook(url: Url, format: WebhookFormat) -> AlertWebhook {
+        let mut opts = Options::default();
+        opts.alert_webhook_url = Some(url);
+        opts.alert_webhook_format = format;
+        opts.alert_webhook_routing_key = Some("key".into());
+        AlertWebhook::new(&opts).unwrap()
+    }
+
+    /// A local webhook, recording the bodies it receives.
+    async fn mock_webhook() -> (Url, Received) {
+        let received = Received::default();
+        let mut app = tide::with_state(received.clone());
+        app.at("/")
+            .post(|mut req: tide::Request<Received>| async move {
+                let body = req.body_json::<Value>().await?;
+                req.state().lock().await.push(body);
+                Ok(tide::StatusCode::Ok)
+            });
+        let port = portpicker::pick_unused_port().unwrap();
+        let mut listener = app.bind(format!("127.0.0.1:{port}")).await.unwrap();
+        spawn(async move { listener.accept().await });
+        (
+            format!("http://127.0.0.1:{port}").parse().unwrap(),
+            received,
+        )
+    }
+
+    #[test]
+    fn test_webhook_body() {
+        let alert = Alert::RpcFailing {
+            failures: 3,
+            error: "timeout".into(),
+        };
+        let url: Url = "http://127.0.0.1:1".parse().unwrap();
+        assert_eq!(
+            webhook(url.clone(), WebhookFormat::Slack).body(
+                "testnet",
+                "testnet:rpc_failing",
+                &alert
+            ),
+            json!({ "text": "[testnet] The RPC failed 3 times in a row: timeout" })
+        );
+
+        let pager_duty = webhook(url, WebhookFormat::PagerDuty);
+        assert_eq!(
+            pager_duty.body("testnet", "testnet:rpc_failing", &alert),
+            json!({
+                "routing_key": "key",
+                "event_action": "trigger",
+                "dedup_key": "testnet:rpc_failing",
+                "payload": {
+                    "summary": "The RPC failed 3 times in a row: timeout",
+                    "source": "testnet",
+                    "severity": "critical",
+                },
+            })
+        );
+        assert_eq!(
+            pager_duty.body("testnet", "testnet:rpc_failing", &Alert::RpcRecovered),
+            json!({
+                "routing_key": "key",
+                "event_action": "resolve",
+                "dedup_key": "testnet:rpc_failing",
+            })
+        );
+    }
+
+    #[async_std::test]
+    async fn test_webhook_dedup() {
+        let (url, received) = mock_webhook().await;
+        let webhook = webhook(url, WebhookFormat::Slack);
+        let alert = Alert::LowBalance {
+            balance: 1.into(),
+            threshold: 10.into(),
+        };
+        let text = json!({ "text": format!("[testnet] {alert}") });
+        webhook.send("testnet", &alert).await;
+        assert_eq!(*received.lock().await, [text.clone()]);
+
+        // Repeated alerts are not sent again within the window.
+        webhook.send("testnet", &alert).await;
+        assert_eq!(received.lock().await.len(), 1);
+
+        // Recovering clears the alert, so it is sent again if it recurs.
+        let recovered = Alert::BalanceRecovered {
+            balance: 100.into(),
+        };
+        webhook.send("testnet", &recovered).await;
+        assert!(webhook.sent.lock().await.is_empty());
+        webhook.send("testnet", &alert).await;
+        assert_eq!(received.lock().await.len(), 3);
+        assert_eq!(received.lock().await[2], text);
+    }
+
+    #[async_std::test]
+    async fn test_webhook_bounded() {
+        let (url, received) = mock_webhook().await;
+        let webhook = webhook(url, WebhookFormat::Slack);
+        let dead_letter = |_| Alert::DeadLetter {
+            request: TransferRequest::faucet(RequestId::random(), Address::random(), 1.into()),
+            reason: "reverted".into(),
+        };
+        for alert in (0..MAX_SENT + 1).map(dead_letter) {
+            webhook.send("testnet", &alert).await;
+        }
+        // Every dead letter is sent, but only the latest are remembered.
+        assert_eq!(received.lock().await.len(), MAX_SENT + 1);
+        assert_eq!(webhook.sent.lock().await.len(), MAX_SENT);
+    }
+}